@@ -0,0 +1,28 @@
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+// Value noise, so the dissolve eats away in blobs rather than single pixels.
+fn noise(p: vec2<f32>) -> f32 {
+    let cell = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let a = hash(cell);
+    let b = hash(cell + vec2<f32>(1.0, 0.0));
+    let c = hash(cell + vec2<f32>(0.0, 1.0));
+    let d = hash(cell + vec2<f32>(1.0, 1.0));
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let n = noise(in.tex_coords * 8.0);
+    if n < material.amount.x {
+        discard;
+    }
+    if n < material.amount.x + material.edge_width.x {
+        return vec4<f32>(material.edge_color.rgb, color.a * material.edge_color.a);
+    }
+    return color;
+}
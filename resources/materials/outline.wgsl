@@ -0,0 +1,12 @@
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let w = material.width.x;
+    var neighbours = 0.0;
+    neighbours = max(neighbours, textureSample(t_diffuse, s_diffuse, in.tex_coords + vec2<f32>(w, 0.0)).a);
+    neighbours = max(neighbours, textureSample(t_diffuse, s_diffuse, in.tex_coords - vec2<f32>(w, 0.0)).a);
+    neighbours = max(neighbours, textureSample(t_diffuse, s_diffuse, in.tex_coords + vec2<f32>(0.0, w)).a);
+    neighbours = max(neighbours, textureSample(t_diffuse, s_diffuse, in.tex_coords - vec2<f32>(0.0, w)).a);
+    let outline = vec4<f32>(material.color.rgb, material.color.a * neighbours);
+    return mix(outline, color, color.a);
+}
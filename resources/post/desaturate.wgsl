@@ -0,0 +1,8 @@
+// A custom post-processing pass: fades the frame to gray.
+// post.params.x: amount, 0 to 1.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let gray = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return vec4<f32>(mix(color.rgb, vec3<f32>(gray), post.params.x), color.a);
+}
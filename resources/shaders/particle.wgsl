@@ -0,0 +1,65 @@
+// Vertex shader
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+// One particle, in world space.
+struct InstanceInput {
+    @location(2) center: vec2<f32>,
+    @location(3) size_rotation: vec2<f32>,
+    @location(4) color: vec4<f32>,
+    // left, top, right, bottom
+    @location(5) uv: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let size = instance.size_rotation.x;
+    let c = cos(instance.size_rotation.y);
+    let s = sin(instance.size_rotation.y);
+    let corner = model.position.xy * size;
+    let rotated = vec2<f32>(corner.x * c - corner.y * s, corner.x * s + corner.y * c);
+
+    var out: VertexOutput;
+    out.tex_coords = mix(instance.uv.xy, instance.uv.zw, model.tex_coords);
+    out.color = instance.color;
+    out.clip_position = camera.view_proj * vec4<f32>(instance.center + rotated, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader, both write premultiplied alpha.
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.color;
+    return vec4<f32>(color.rgb * color.a, color.a);
+}
+
+// For textures that are already premultiplied.
+@fragment
+fn fs_premultiplied(in: VertexOutput) -> @location(0) vec4<f32> {
+    let tint = vec4<f32>(in.color.rgb * in.color.a, in.color.a);
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords) * tint;
+}
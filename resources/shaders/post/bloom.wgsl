@@ -0,0 +1,32 @@
+// post.params: threshold, intensity, radius in pixels.
+// post.extra.xy: the blur direction, set by the engine.
+
+@fragment
+fn fs_extract(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let amount = max(brightness - post.params.x, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * amount, 1.0);
+}
+
+// A 9 tap gaussian along post.extra.xy.
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let step = post.extra.xy * post.params.z / 4.0 / post.frame.xy;
+    var color = textureSample(source, source_sampler, in.uv).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = step * f32(i);
+        color += textureSample(source, source_sampler, in.uv + offset).rgb * weights[i];
+        color += textureSample(source, source_sampler, in.uv - offset).rgb * weights[i];
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// `second` is the blurred bright parts.
+@fragment
+fn fs_combine(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv).rgb;
+    let bloom = textureSample(second, second_sampler, in.uv).rgb;
+    return vec4<f32>(color + bloom * post.params.y, 1.0);
+}
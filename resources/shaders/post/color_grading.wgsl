@@ -0,0 +1,22 @@
+// `second` is the LUT: `size` slices of size x size side by side, blue growing to the right.
+// post.params: strength, LUT size.
+
+fn lut_uv(color: vec3<f32>, slice: f32, size: f32) -> vec2<f32> {
+    let x = (slice + (color.r * (size - 1.0) + 0.5) / size) / size;
+    let y = (color.g * (size - 1.0) + 0.5) / size;
+    return vec2<f32>(x, y);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let size = post.params.y;
+    let graded_in = clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    let blue = graded_in.b * (size - 1.0);
+    let low = floor(blue);
+    let high = min(low + 1.0, size - 1.0);
+    let a = textureSample(second, second_sampler, lut_uv(graded_in, low, size)).rgb;
+    let b = textureSample(second, second_sampler, lut_uv(graded_in, high, size)).rgb;
+    let graded = mix(a, b, blue - low);
+    return vec4<f32>(mix(color.rgb, graded, post.params.x), color.a);
+}
@@ -0,0 +1,5 @@
+// Writes the processed frame to the window.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(source, source_sampler, in.uv).rgb, 1.0);
+}
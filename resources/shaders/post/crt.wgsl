@@ -0,0 +1,15 @@
+// post.params: scanline intensity, scanline count, curvature.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Bends the picture like the glass of a tube.
+    let centered = in.uv * 2.0 - 1.0;
+    let bent = centered * (1.0 + post.params.z * dot(centered, centered));
+    let uv = bent * 0.5 + 0.5;
+    let color = textureSample(source, source_sampler, uv).rgb;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let scanline = 0.5 + 0.5 * sin(uv.y * post.params.y * 6.2831853);
+    let shade = 1.0 - post.params.x * (1.0 - scanline);
+    return vec4<f32>(color * shade, 1.0);
+}
@@ -0,0 +1,9 @@
+// post.params: intensity, radius, softness.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let aspect = post.frame.x / post.frame.y;
+    let distance = length((in.uv - vec2<f32>(0.5)) * vec2<f32>(aspect, 1.0));
+    let shade = 1.0 - smoothstep(post.params.y - post.params.z, post.params.y, distance);
+    return vec4<f32>(color.rgb * mix(1.0 - post.params.x, 1.0, shade), color.a);
+}
@@ -0,0 +1,188 @@
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::Context;
+
+use super::AssetLoader;
+use crate::engine::app::{audio::decoder::SoundData, renderer::{material::{Material, MaterialSource}, texture::{Texture, TextureSettings}, State}, vfs};
+
+fn read(path: &str) -> anyhow::Result<Vec<u8>>{
+    vfs::read(path).with_context(|| format!("Could not read {}", path))
+}
+
+/// Decodes images on the workers and uploads them in `finish`.
+/// Settings come from `load_with`, else from the `<image>.meta` sidecar, else the defaults.
+pub struct TextureLoader{
+    state: Rc<RefCell<State>>
+}
+
+impl TextureLoader{
+    pub fn new(state: Rc<RefCell<State>>) -> Self{
+        Self { state }
+    }
+}
+
+pub struct DecodedTexture{
+    label: String,
+    levels: Vec<image::RgbaImage>,
+    settings: TextureSettings
+}
+
+impl AssetLoader for TextureLoader{
+    type Asset = Texture;
+    type Decoded = DecodedTexture;
+    type Settings = TextureSettings;
+
+    fn decoder(&self) -> impl Fn(&str, Option<&TextureSettings>) -> anyhow::Result<DecodedTexture> + Send + Sync + 'static{
+        |path: &str, settings: Option<&TextureSettings>| decode_texture(path, settings)
+    }
+
+    fn finish(&self, texture: DecodedTexture) -> anyhow::Result<Texture>{
+        self.state.borrow().create_texture(&texture.levels, texture.settings, &texture.label)
+    }
+
+    fn dependencies(&self, path: &str) -> Vec<String>{
+        vec![sidecar_path(path)]
+    }
+}
+
+fn decode_texture(path: &str, settings: Option<&TextureSettings>) -> anyhow::Result<DecodedTexture>{
+    let settings = match settings{
+        Some(settings) => *settings,
+        None => sidecar_settings(path)?
+    };
+    let image = image::load_from_memory(&read(path)?)?;
+    Ok(DecodedTexture { label: path.to_string(), levels: settings.prepare(&image), settings })
+}
+
+fn sidecar_path(path: &str) -> String{
+    format!("{}.meta", path)
+}
+
+fn sidecar_settings(path: &str) -> anyhow::Result<TextureSettings>{
+    let sidecar = sidecar_path(path);
+    if !vfs::vfs().read().unwrap().exists(&sidecar){
+        return Ok(TextureSettings::default());
+    }
+    let text = vfs::read_to_string(&sidecar)?;
+    TextureSettings::parse(&text).with_context(|| format!("Invalid {}", sidecar))
+}
+
+/// Reads a `.material` file with its shader and decodes its textures on the workers,
+/// then uploads the textures and compiles the shader in `finish`.
+pub struct MaterialLoader{
+    state: Rc<RefCell<State>>
+}
+
+impl MaterialLoader{
+    pub fn new(state: Rc<RefCell<State>>) -> Self{
+        Self { state }
+    }
+}
+
+pub struct DecodedMaterial{
+    label: String,
+    source: MaterialSource,
+    fragment: String,
+    textures: Vec<DecodedTexture>
+}
+
+impl AssetLoader for MaterialLoader{
+    type Asset = Material;
+    type Decoded = DecodedMaterial;
+    type Settings = ();
+
+    fn decoder(&self) -> impl Fn(&str, Option<&()>) -> anyhow::Result<DecodedMaterial> + Send + Sync + 'static{
+        |path: &str, _: Option<&()>|{
+            let source = MaterialSource::parse(path, &String::from_utf8(read(path)?)?).with_context(|| format!("Invalid {}", path))?;
+            let fragment = String::from_utf8(read(&source.shader)?)?;
+            let textures = source.textures.iter().map(|(_, texture)| decode_texture(texture, None)).collect::<anyhow::Result<_>>()?;
+            Ok(DecodedMaterial { label: path.to_string(), source, fragment, textures })
+        }
+    }
+
+    fn finish(&self, material: DecodedMaterial) -> anyhow::Result<Material>{
+        let state = self.state.borrow();
+        let textures = material.textures.iter()
+            .map(|texture| state.create_texture(&texture.levels, texture.settings, &texture.label))
+            .collect::<anyhow::Result<_>>()?;
+        state.create_material(&material.source, &material.fragment, textures, &material.label)
+    }
+
+    fn dependencies(&self, path: &str) -> Vec<String>{
+        vfs::read_to_string(path).ok()
+            .and_then(|text| MaterialSource::parse(path, &text).ok())
+            .map(|source| source.files())
+            .unwrap_or_default()
+    }
+}
+
+/// Decodes sounds at the mixer's sample rate.
+pub struct SoundLoader{
+    sample_rate: u32
+}
+
+impl SoundLoader{
+    pub fn new(sample_rate: u32) -> Self{
+        Self { sample_rate }
+    }
+}
+
+impl AssetLoader for SoundLoader{
+    type Asset = SoundData;
+    type Decoded = SoundData;
+    type Settings = ();
+
+    fn decoder(&self) -> impl Fn(&str, Option<&()>) -> anyhow::Result<SoundData> + Send + Sync + 'static{
+        let sample_rate = self.sample_rate;
+        move |path: &str, _: Option<&()>|{
+            let extension = path.rsplit_once('.').map(|(_, extension)| extension.to_string());
+            SoundData::decode(read(path)?, extension.as_deref(), sample_rate)
+        }
+    }
+
+    fn finish(&self, sound: SoundData) -> anyhow::Result<SoundData>{
+        Ok(sound)
+    }
+}
+
+/// Source text of a Lua script.
+pub struct ScriptSource{
+    pub source: String
+}
+
+pub struct ScriptLoader;
+
+impl AssetLoader for ScriptLoader{
+    type Asset = ScriptSource;
+    type Decoded = ScriptSource;
+    type Settings = ();
+
+    fn decoder(&self) -> impl Fn(&str, Option<&()>) -> anyhow::Result<ScriptSource> + Send + Sync + 'static{
+        |path: &str, _: Option<&()>| Ok(ScriptSource { source: String::from_utf8(read(path)?)? })
+    }
+
+    fn finish(&self, script: ScriptSource) -> anyhow::Result<ScriptSource>{
+        Ok(script)
+    }
+}
+
+/// A TTF or OTF file, e.g. for `egui::FontDefinitions`.
+pub struct Font{
+    pub data: Vec<u8>
+}
+
+pub struct FontLoader;
+
+impl AssetLoader for FontLoader{
+    type Asset = Font;
+    type Decoded = Font;
+    type Settings = ();
+
+    fn decoder(&self) -> impl Fn(&str, Option<&()>) -> anyhow::Result<Font> + Send + Sync + 'static{
+        |path: &str, _: Option<&()>| Ok(Font { data: read(path)? })
+    }
+
+    fn finish(&self, font: Font) -> anyhow::Result<Font>{
+        Ok(font)
+    }
+}
@@ -0,0 +1,506 @@
+pub mod loaders;
+mod workers;
+
+use std::{any::{Any, TypeId}, collections::HashMap, fmt::Debug, path::{Path, PathBuf}, rc::Rc, sync::{mpsc, Arc, RwLock, Weak}};
+
+use anyhow::{anyhow, Context};
+
+use crate::engine::app::vfs;
+use workers::Workers;
+
+/// Where a handle's asset is in the loading process.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadState{
+    Pending,
+    Ready,
+    Failed(String)
+}
+
+enum SlotState<T>{
+    Pending,
+    Ready(Arc<T>),
+    Failed(String)
+}
+
+/// A shared reference to an asset that may still be loading.
+/// Handles to the same path point to the same slot, so a reload is seen by all of them.
+/// The asset is unloaded when its last handle is dropped.
+pub struct Handle<T>{
+    slot: Arc<Slot<T>>
+}
+
+struct Slot<T>{
+    path: String,
+    settings: Option<Settings>,
+    state: RwLock<SlotState<T>>
+}
+
+impl<T> Handle<T>{
+    /// A handle to an asset made at runtime instead of loaded from a file,
+    /// e.g. a procedural texture. `name` only identifies it; it's never reloaded.
+    pub fn from_asset(name: &str, asset: T) -> Self{
+        let slot = Slot { path: name.to_string(), settings: None, state: RwLock::new(SlotState::Ready(Arc::new(asset))) };
+        Self { slot: Arc::new(slot) }
+    }
+
+    /// A handle that never finishes loading, for tests that don't need the asset.
+    #[cfg(test)]
+    pub fn pending(name: &str) -> Self{
+        let slot = Slot { path: name.to_string(), settings: None, state: RwLock::new(SlotState::Pending) };
+        Self { slot: Arc::new(slot) }
+    }
+
+    /// The asset, once it's loaded.
+    pub fn get(&self) -> Option<Arc<T>>{
+        match &*self.slot.state.read().unwrap(){
+            SlotState::Ready(asset) => Some(asset.clone()),
+            _ => None
+        }
+    }
+
+    pub fn state(&self) -> LoadState{
+        match &*self.slot.state.read().unwrap(){
+            SlotState::Pending => LoadState::Pending,
+            SlotState::Ready(_) => LoadState::Ready,
+            SlotState::Failed(e) => LoadState::Failed(e.clone())
+        }
+    }
+
+    pub fn is_ready(&self) -> bool{
+        matches!(&*self.slot.state.read().unwrap(), SlotState::Ready(_))
+    }
+
+    pub fn is_failed(&self) -> bool{
+        matches!(&*self.slot.state.read().unwrap(), SlotState::Failed(_))
+    }
+
+    /// Path the asset was loaded from, as passed to `AssetManager::load`.
+    pub fn path(&self) -> &str{
+        &self.slot.path
+    }
+
+    /// `true` when no other handle to the asset is left.
+    pub fn is_unique(&self) -> bool{
+        Arc::strong_count(&self.slot) == 1
+    }
+
+    pub fn ptr_eq(&self, other: &Handle<T>) -> bool{
+        Arc::ptr_eq(&self.slot, &other.slot)
+    }
+}
+
+impl<T> Clone for Handle<T>{
+    fn clone(&self) -> Self{
+        Self { slot: self.slot.clone() }
+    }
+}
+
+/// Turns a file into an asset of one type, in two steps:
+/// `decode` runs on a worker thread, `finish` on the main thread (e.g. to upload to the GPU).
+pub trait AssetLoader{
+    type Asset: Send + Sync + 'static;
+    type Decoded: Send + 'static;
+    /// Options passed to `AssetManager::load_with`, `()` for assets without any.
+    type Settings: Debug + Send + Sync + 'static;
+
+    /// The decoding step. It's called once when the loader is registered and
+    /// shared by the workers, so it can only capture `Send` settings.
+    /// `settings` is `None` for a plain `AssetManager::load`.
+    fn decoder(&self) -> impl Fn(&str, Option<&Self::Settings>) -> anyhow::Result<Self::Decoded> + Send + Sync + 'static;
+
+    fn finish(&self, decoded: Self::Decoded) -> anyhow::Result<Self::Asset>;
+
+    /// Other files the asset is read from, e.g. a sidecar; changing them reloads the asset.
+    fn dependencies(&self, _path: &str) -> Vec<String>{
+        Vec::new()
+    }
+}
+
+type Decoded = Box<dyn Any + Send>;
+type Settings = Arc<dyn Any + Send + Sync>;
+type DecodeFn = Arc<dyn Fn(&str, Option<&Settings>) -> anyhow::Result<Decoded> + Send + Sync>;
+type FinishFn<T> = Box<dyn Fn(Decoded) -> anyhow::Result<T>>;
+type DependenciesFn = Box<dyn Fn(&str) -> Vec<String>>;
+
+struct Store<T>{
+    decode: DecodeFn,
+    finish: FinishFn<T>,
+    dependencies: DependenciesFn,
+    /// Keyed by path, plus the settings if they were given.
+    slots: HashMap<String, Weak<Slot<T>>>
+}
+
+impl<T> Store<T>{
+    fn load_now(&self, path: &str, settings: Option<&Settings>) -> anyhow::Result<T>{
+        (self.finish)((self.decode)(path, settings)?)
+    }
+}
+
+/// A decoded file coming back from a worker.
+struct Finished{
+    type_id: TypeId,
+    key: String,
+    result: anyhow::Result<Decoded>
+}
+
+fn slot_key(path: &str, settings: Option<&dyn Debug>) -> String{
+    match settings{
+        Some(settings) => format!("{}#{:?}", path, settings),
+        None => path.to_string()
+    }
+}
+
+/// The type-erased part of a `Store`, so the manager can reload and clean up
+/// every asset type without knowing it.
+trait AnyStore{
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn as_any(&self) -> &dyn Any;
+    fn finish(&mut self, key: &str, result: anyhow::Result<Decoded>);
+    fn reload_file(&mut self, file: &Path, errors: &mut Vec<anyhow::Error>) -> usize;
+    fn paths(&self) -> Vec<String>;
+    fn collect_garbage(&mut self);
+    fn len(&self) -> usize;
+}
+
+impl<T: Send + Sync + 'static> AnyStore for Store<T>{
+    fn as_any_mut(&mut self) -> &mut dyn Any{
+        self
+    }
+
+    fn as_any(&self) -> &dyn Any{
+        self
+    }
+
+    fn finish(&mut self, key: &str, result: anyhow::Result<Decoded>){
+        // Nobody is waiting for it anymore.
+        let Some(slot) = self.slots.get(key).and_then(|slot| slot.upgrade()) else{
+            return;
+        };
+        let state = match result.and_then(|decoded| (self.finish)(decoded)){
+            Ok(asset) => SlotState::Ready(Arc::new(asset)),
+            Err(e) => {
+                log::error!("Could not load {}: {:#}", slot.path, e);
+                SlotState::Failed(format!("{:#}", e))
+            }
+        };
+        *slot.state.write().unwrap() = state;
+    }
+
+    fn reload_file(&mut self, file: &Path, errors: &mut Vec<anyhow::Error>) -> usize{
+        let mut reloaded = 0;
+        for slot in self.slots.values(){
+            let Some(slot) = slot.upgrade() else{
+                continue;
+            };
+            let files = std::iter::once(slot.path.clone()).chain((self.dependencies)(&slot.path));
+            if !files.filter_map(|path| vfs::writable_path(&path)).any(|path| path == file){
+                continue;
+            }
+            match self.load_now(&slot.path, slot.settings.as_ref()).with_context(|| format!("Could not reload {}", slot.path)){
+                Ok(asset) => {
+                    *slot.state.write().unwrap() = SlotState::Ready(Arc::new(asset));
+                    reloaded += 1;
+                },
+                Err(e) => errors.push(e)
+            }
+        }
+        reloaded
+    }
+
+    fn paths(&self) -> Vec<String>{
+        self.slots.values()
+            .filter_map(|slot| slot.upgrade())
+            .flat_map(|slot| std::iter::once(slot.path.clone()).chain((self.dependencies)(&slot.path)))
+            .collect()
+    }
+
+    fn collect_garbage(&mut self){
+        self.slots.retain(|_, slot| slot.strong_count() > 0);
+    }
+
+    fn len(&self) -> usize{
+        self.slots.values().filter(|slot| slot.strong_count() > 0).count()
+    }
+}
+
+/// Loads assets of any registered type through typed handles.
+/// Loading a path that is already loaded (or loading) returns the existing handle.
+pub struct AssetManager{
+    stores: HashMap<TypeId, Box<dyn AnyStore>>,
+    workers: Workers,
+    sender: mpsc::Sender<Finished>,
+    receiver: mpsc::Receiver<Finished>,
+    requested: usize,
+    finished: usize
+}
+
+impl AssetManager{
+    pub fn new() -> Self{
+        let (sender, receiver) = mpsc::channel();
+        Self { stores: HashMap::new(), workers: Workers::default(), sender, receiver, requested: 0, finished: 0 }
+    }
+
+    /// Registers the loader for `L::Asset`, replacing a previous one.
+    pub fn register<L: AssetLoader + 'static>(&mut self, loader: L){
+        let decoder = loader.decoder();
+        let decode: DecodeFn = Arc::new(move |path: &str, settings: Option<&Settings>|{
+            let settings = match settings{
+                Some(settings) => Some(settings.downcast_ref::<L::Settings>()
+                    .ok_or_else(|| anyhow!("{} takes {} settings", std::any::type_name::<L::Asset>(), std::any::type_name::<L::Settings>()))?),
+                None => None
+            };
+            decoder(path, settings).map(|decoded| Box::new(decoded) as Decoded)
+        });
+        let loader = Rc::new(loader);
+        let finish_loader = loader.clone();
+        let finish: FinishFn<L::Asset> = Box::new(move |decoded: Decoded|{
+            let decoded = decoded.downcast::<L::Decoded>().map_err(|_| anyhow!("Decoded asset has the wrong type"))?;
+            finish_loader.finish(*decoded)
+        });
+        let dependencies: DependenciesFn = Box::new(move |path: &str| loader.dependencies(path));
+        let store = Store { decode, finish, dependencies, slots: HashMap::new() };
+        self.stores.insert(TypeId::of::<L::Asset>(), Box::new(store));
+    }
+
+    /// Starts loading `path` on a worker thread and returns a pending handle.
+    /// Only fails when no loader is registered for `T`.
+    pub fn load<T: Send + Sync + 'static>(&mut self, path: &str) -> anyhow::Result<Handle<T>>{
+        self.start_load(path, None::<()>)
+    }
+
+    /// Like `load`, with options for the loader (e.g. `TextureSettings`) instead of its defaults.
+    /// The same path loaded with different settings gives separate assets.
+    pub fn load_with<T: Send + Sync + 'static, S: Debug + Send + Sync + 'static>(&mut self, path: &str, settings: S) -> anyhow::Result<Handle<T>>{
+        self.start_load(path, Some(settings))
+    }
+
+    fn start_load<T: Send + Sync + 'static, S: Debug + Send + Sync + 'static>(&mut self, path: &str, settings: Option<S>) -> anyhow::Result<Handle<T>>{
+        let key = slot_key(path, settings.as_ref().map(|settings| settings as &dyn Debug));
+        if let Some(handle) = self.get_by_key(&key){
+            return Ok(handle);
+        }
+        let store = store_mut::<T>(&mut self.stores)?;
+        let settings = settings.map(|settings| Arc::new(settings) as Settings);
+        let slot = Arc::new(Slot { path: path.to_string(), settings: settings.clone(), state: RwLock::new(SlotState::Pending) });
+        store.slots.insert(key.clone(), Arc::downgrade(&slot));
+
+        if self.requested == self.finished{
+            self.requested = 0;
+            self.finished = 0;
+        }
+        self.requested += 1;
+
+        let decode = store.decode.clone();
+        let sender = self.sender.clone();
+        let path = path.to_string();
+        self.workers.spawn(move ||{
+            let result = decode(&path, settings.as_ref());
+            let _ = sender.send(Finished { type_id: TypeId::of::<T>(), key, result });
+        });
+        Ok(Handle { slot })
+    }
+
+    /// Loads `path` on the calling thread; the handle is ready or the error is returned.
+    pub fn load_blocking<T: Send + Sync + 'static>(&mut self, path: &str) -> anyhow::Result<Handle<T>>{
+        if let Some(handle) = self.get::<T>(path)
+            && handle.is_ready(){
+            return Ok(handle);
+        }
+        let store = store_mut::<T>(&mut self.stores)?;
+        let asset = store.load_now(path, None).with_context(|| format!("Could not load {}", path))?;
+        let slot = match store.slots.get(path).and_then(|slot| slot.upgrade()){
+            Some(slot) => slot,
+            None => {
+                let slot = Arc::new(Slot { path: path.to_string(), settings: None, state: RwLock::new(SlotState::Pending) });
+                store.slots.insert(path.to_string(), Arc::downgrade(&slot));
+                slot
+            }
+        };
+        *slot.state.write().unwrap() = SlotState::Ready(Arc::new(asset));
+        Ok(Handle { slot })
+    }
+
+    /// The handle of an asset that is loaded or loading with default settings.
+    pub fn get<T: Send + Sync + 'static>(&self, path: &str) -> Option<Handle<T>>{
+        self.get_by_key(path)
+    }
+
+    fn get_by_key<T: Send + Sync + 'static>(&self, key: &str) -> Option<Handle<T>>{
+        let store = self.stores.get(&TypeId::of::<T>())?.as_any().downcast_ref::<Store<T>>()?;
+        let slot = store.slots.get(key)?.upgrade()?;
+        Some(Handle { slot })
+    }
+
+    /// Finishes the loads the workers are done with. Call once per frame on the main thread.
+    pub fn update(&mut self){
+        while let Ok(finished) = self.receiver.try_recv(){
+            self.finished += 1;
+            if let Some(store) = self.stores.get_mut(&finished.type_id){
+                store.finish(&finished.key, finished.result);
+            }
+        }
+    }
+
+    /// Share of the loads started since the manager was last idle that are done, from 0 to 1.
+    pub fn progress(&self) -> f32{
+        if self.requested == 0{
+            return 1.0;
+        }
+        self.finished as f32 / self.requested as f32
+    }
+
+    pub fn pending_count(&self) -> usize{
+        self.requested - self.finished
+    }
+
+    /// Reloads every asset loaded from `file`. Handles keep the old asset if loading fails.
+    /// Returns how many were reloaded and the errors of the others.
+    pub fn reload_file(&mut self, file: &Path) -> (usize, Vec<anyhow::Error>){
+        let mut errors = Vec::new();
+        let reloaded = self.stores.values_mut().map(|store| store.reload_file(file, &mut errors)).sum();
+        (reloaded, errors)
+    }
+
+    /// Resolved file paths of all loaded assets, for the file watcher.
+    /// Files that don't exist yet, like a missing sidecar, are watched where they'd be created.
+    pub fn watched_paths(&self) -> Vec<PathBuf>{
+        self.stores.values().flat_map(|store| store.paths()).filter_map(|path| vfs::writable_path(&path)).collect()
+    }
+
+    /// Forgets assets whose handles were all dropped.
+    pub fn collect_garbage(&mut self){
+        for store in self.stores.values_mut(){
+            store.collect_garbage();
+        }
+    }
+
+    /// Number of assets that still have handles.
+    pub fn loaded_count(&self) -> usize{
+        self.stores.values().map(|store| store.len()).sum()
+    }
+}
+
+impl Default for AssetManager{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+fn store_mut<T: Send + Sync + 'static>(stores: &mut HashMap<TypeId, Box<dyn AnyStore>>) -> anyhow::Result<&mut Store<T>>{
+    stores.get_mut(&TypeId::of::<T>())
+        .and_then(|store| store.as_any_mut().downcast_mut::<Store<T>>())
+        .ok_or_else(|| anyhow!("No loader registered for {}", std::any::type_name::<T>()))
+}
+
+#[cfg(test)]
+mod tests{
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// Turns the path into its upper case, without touching files; paths under `missing/` fail.
+    struct UpperLoader;
+
+    impl AssetLoader for UpperLoader{
+        type Asset = String;
+        type Decoded = String;
+        type Settings = ();
+
+        fn decoder(&self) -> impl Fn(&str, Option<&()>) -> anyhow::Result<String> + Send + Sync + 'static{
+            |path: &str, _: Option<&()>|{
+                if path.starts_with("missing/"){
+                    return Err(anyhow!("{} doesn't exist", path));
+                }
+                Ok(path.to_uppercase())
+            }
+        }
+
+        fn finish(&self, text: String) -> anyhow::Result<String>{
+            Ok(text)
+        }
+    }
+
+    fn manager() -> AssetManager{
+        let mut assets = AssetManager::new();
+        assets.register(UpperLoader);
+        assets
+    }
+
+    /// Finishes loads until the workers are done.
+    fn wait(assets: &mut AssetManager){
+        let start = Instant::now();
+        while assets.pending_count() > 0{
+            assert!(start.elapsed() < Duration::from_secs(5), "the workers never finished");
+            std::thread::sleep(Duration::from_millis(1));
+            assets.update();
+        }
+    }
+
+    #[test]
+    fn loads_go_from_pending_to_ready_or_failed(){
+        let mut assets = manager();
+        let text = assets.load::<String>("a.txt").unwrap();
+        let missing = assets.load::<String>("missing/b.txt").unwrap();
+        // Finishing happens in `update`, so nothing is ready before it.
+        assert_eq!(text.state(), LoadState::Pending);
+        assert_eq!(missing.state(), LoadState::Pending);
+        assert_eq!(assets.pending_count(), 2);
+        assert_eq!(assets.progress(), 0.0);
+
+        wait(&mut assets);
+        assert_eq!(text.state(), LoadState::Ready);
+        assert_eq!(*text.get().unwrap(), "A.TXT");
+        assert!(matches!(missing.state(), LoadState::Failed(e) if e.contains("doesn't exist")));
+        assert!(missing.get().is_none());
+        assert_eq!(assets.progress(), 1.0);
+
+        // The counts start over once the manager was idle.
+        let _other = assets.load::<String>("c.txt").unwrap();
+        assert_eq!(assets.pending_count(), 1);
+        assert_eq!(assets.progress(), 0.0);
+        wait(&mut assets);
+    }
+
+    #[test]
+    fn the_same_path_shares_one_asset_until_its_handles_are_gone(){
+        let mut assets = manager();
+        let first = assets.load::<String>("a.txt").unwrap();
+        let second = assets.load::<String>("a.txt").unwrap();
+        assert!(first.ptr_eq(&second));
+        assert!(assets.get::<String>("a.txt").unwrap().ptr_eq(&first));
+        assert_eq!(assets.pending_count(), 1);
+        assert_eq!(assets.loaded_count(), 1);
+        wait(&mut assets);
+
+        drop(first);
+        assert_eq!(assets.loaded_count(), 1);
+        drop(second);
+        assert_eq!(assets.loaded_count(), 0);
+        assert!(assets.get::<String>("a.txt").is_none());
+        assets.collect_garbage();
+        assert!(assets.watched_paths().is_empty());
+    }
+
+    #[test]
+    fn load_blocking_and_a_worker_fill_the_same_slot(){
+        let mut assets = manager();
+        let pending = assets.load::<String>("a.txt").unwrap();
+        let blocking = assets.load_blocking::<String>("a.txt").unwrap();
+        assert!(blocking.ptr_eq(&pending));
+        assert_eq!(*pending.get().unwrap(), "A.TXT");
+
+        // The worker's result lands in the slot the blocking load already filled.
+        wait(&mut assets);
+        assert!(pending.is_ready());
+        assert_eq!(*blocking.get().unwrap(), "A.TXT");
+        assert_eq!(assets.loaded_count(), 1);
+        assert!(assets.load_blocking::<String>("missing/b.txt").is_err());
+    }
+
+    #[test]
+    fn fails_without_a_loader(){
+        let mut assets = manager();
+        assert!(assets.load::<u32>("a.txt").is_err());
+        assert!(assets.load_blocking::<u32>("a.txt").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::{sync::{mpsc, Arc, Mutex}, thread::{self, JoinHandle}};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads running jobs in the order they were queued.
+pub struct Workers{
+    sender: Option<mpsc::Sender<Job>>,
+    threads: Vec<JoinHandle<()>>
+}
+
+impl Workers{
+    pub fn new(count: usize) -> Self{
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..count.max(1)).map(|index|{
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("asset worker {}", index))
+                .spawn(move ||{
+                    loop{
+                        let job = receiver.lock().unwrap().recv();
+                        match job{
+                            Ok(job) => job(),
+                            Err(_) => break
+                        }
+                    }
+                })
+                .expect("Could not start asset worker")
+        }).collect();
+        Self { sender: Some(sender), threads }
+    }
+
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static){
+        if let Some(sender) = &self.sender{
+            let _ = sender.send(Box::new(job));
+        }
+    }
+}
+
+impl Default for Workers{
+    fn default() -> Self{
+        let count = thread::available_parallelism().map(|count| count.get()).unwrap_or(2).min(4);
+        Self::new(count)
+    }
+}
+
+impl Drop for Workers{
+    fn drop(&mut self){
+        // Closing the channel ends the worker loops once the queued jobs are done.
+        self.sender.take();
+        for thread in self.threads.drain(..){
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use std::{io::Cursor, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+
+use anyhow::{anyhow, Context};
+use symphonia::core::{
+    audio::SampleBuffer, codecs::{Decoder, DecoderOptions}, errors::Error as DecodeError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo}, io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions, probe::Hint, units::Time
+};
+
+use super::{mixer::Source, ring::RingBuffer};
+use crate::engine::app::vfs::{self, VfsFile};
+
+/// A fully decoded sound, stereo and already at the mixer's sample rate.
+pub struct SoundData{
+    samples: Vec<f32>
+}
+
+impl SoundData{
+    /// Decodes a WAV, OGG or FLAC file from memory. `extension` helps picking the format.
+    pub fn decode(bytes: Vec<u8>, extension: Option<&str>, sample_rate: u32) -> anyhow::Result<Self>{
+        let mut stream = Stream::open(Box::new(Cursor::new(bytes)), extension)?;
+        let mut resampler = Resampler::new(stream.sample_rate, sample_rate);
+        let mut samples = Vec::new();
+        while let Some(chunk) = stream.next_chunk()?{
+            resampler.process(&chunk, &mut samples);
+        }
+        Ok(Self { samples })
+    }
+
+    #[cfg(test)]
+    pub fn from_samples(samples: Vec<f32>) -> Self{
+        Self { samples }
+    }
+
+    pub fn samples(&self) -> &[f32]{
+        &self.samples
+    }
+}
+
+/// Plays a decoded sound from memory.
+pub struct BufferSource{
+    data: Arc<SoundData>,
+    position: usize,
+    looping: bool
+}
+
+impl BufferSource{
+    pub fn new(data: Arc<SoundData>, looping: bool) -> Self{
+        Self { data, position: 0, looping }
+    }
+}
+
+impl Source for BufferSource{
+    fn read(&mut self, out: &mut [f32]) -> usize{
+        let samples = self.data.samples();
+        let mut written = 0;
+        while written < out.len(){
+            if self.position == samples.len(){
+                if !self.looping || samples.is_empty(){
+                    break;
+                }
+                self.position = 0;
+            }
+            let count = (out.len() - written).min(samples.len() - self.position);
+            out[written..written + count].copy_from_slice(&samples[self.position..self.position + count]);
+            written += count;
+            self.position += count;
+        }
+        written / 2
+    }
+}
+
+/// Plays a file that a background thread decodes while it plays, used for music.
+/// The thread stays a short buffer ahead, so the audio callback never decodes.
+pub struct StreamSource{
+    ring: Arc<RingBuffer>,
+    /// Set by the decoder thread once the rest of the file is queued.
+    finished: Arc<AtomicBool>,
+    /// Tells the decoder thread to quit when the source is dropped.
+    stopped: Arc<AtomicBool>
+}
+
+impl StreamSource{
+    /// Opens `path` through the VFS.
+    pub fn open(path: &str, sample_rate: u32, looping: bool) -> anyhow::Result<Self>{
+        let file = vfs::open(path).with_context(|| format!("Could not open {}", path))?;
+        let extension = path.rsplit_once('.').map(|(_, extension)| extension);
+        Self::new(Box::new(file), extension, sample_rate, looping)
+    }
+
+    /// Decodes the first half second right away and the rest on a new thread.
+    pub fn new(media: Box<dyn MediaSource>, extension: Option<&str>, sample_rate: u32, looping: bool) -> anyhow::Result<Self>{
+        let stream = Stream::open(media, extension)?;
+        let resampler = Resampler::new(stream.sample_rate, sample_rate);
+        let mut decoder = StreamDecoder { stream, resampler, looping, buffer: Vec::new(), position: 0 };
+        let source = Self {
+            ring: Arc::new(RingBuffer::new(sample_rate as usize)),
+            finished: Arc::default(),
+            stopped: Arc::default()
+        };
+        if !decoder.fill(&source.ring){
+            source.finished.store(true, Ordering::Release);
+            return Ok(source);
+        }
+        let (ring, finished, stopped) = (source.ring.clone(), source.finished.clone(), source.stopped.clone());
+        std::thread::Builder::new()
+            .name("music decoder".to_string())
+            .spawn(move ||{
+                while !stopped.load(Ordering::Acquire){
+                    if !decoder.fill(&ring){
+                        finished.store(true, Ordering::Release);
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .context("Could not start the music decoder")?;
+        Ok(source)
+    }
+}
+
+impl Source for StreamSource{
+    fn read(&mut self, out: &mut [f32]) -> usize{
+        // Read before popping: once it's set, everything the decoder will produce is queued.
+        let finished = self.finished.load(Ordering::Acquire);
+        let count = self.ring.pop(out);
+        if count < out.len() && !finished{
+            // The decoder fell behind; play silence rather than ending the music.
+            out[count..].fill(0.0);
+            return out.len() / 2;
+        }
+        count / 2
+    }
+}
+
+impl Drop for StreamSource{
+    fn drop(&mut self){
+        self.stopped.store(true, Ordering::Release);
+    }
+}
+
+/// The decoding half of a `StreamSource`, owned by its thread.
+struct StreamDecoder{
+    stream: Stream,
+    resampler: Resampler,
+    looping: bool,
+    buffer: Vec<f32>,
+    position: usize
+}
+
+impl StreamDecoder{
+    /// Queues decoded samples until `ring` is full. Returns `false` at the end of the stream.
+    fn fill(&mut self, ring: &RingBuffer) -> bool{
+        loop{
+            if self.position == self.buffer.len() && !self.refill(){
+                return false;
+            }
+            let count = ring.push(&self.buffer[self.position..]);
+            self.position += count;
+            if self.position < self.buffer.len(){
+                return true;
+            }
+        }
+    }
+
+    /// Decodes the next packet into `buffer`. Returns `false` at the end of the stream.
+    fn refill(&mut self) -> bool{
+        self.buffer.clear();
+        self.position = 0;
+        let mut restarted = false;
+        while self.buffer.is_empty(){
+            match self.stream.next_chunk(){
+                Ok(Some(chunk)) => self.resampler.process(&chunk, &mut self.buffer),
+                Ok(None) if self.looping && !restarted => {
+                    restarted = true;
+                    if let Err(e) = self.stream.rewind(){
+                        log::error!("Could not loop music: {}", e);
+                        return false;
+                    }
+                },
+                Ok(None) => return false,
+                Err(e) => {
+                    log::error!("Could not decode music: {}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl MediaSource for VfsFile{
+    fn is_seekable(&self) -> bool{
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64>{
+        self.len()
+    }
+}
+
+/// A symphonia format reader and decoder for the default track.
+struct Stream{
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_rate: u32
+}
+
+impl Stream{
+    fn open(media: Box<dyn MediaSource>, extension: Option<&str>) -> anyhow::Result<Self>{
+        let media = MediaSourceStream::new(media, Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = extension{
+            hint.with_extension(extension);
+        }
+        let probed = symphonia::default::get_probe()
+            .format(&hint, media, &FormatOptions::default(), &MetadataOptions::default())
+            .context("Unsupported audio format")?;
+        let format = probed.format;
+        let track = format.default_track().ok_or_else(|| anyhow!("No audio track"))?;
+        let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+        let track_id = track.id;
+        let sample_rate = track.codec_params.sample_rate.ok_or_else(|| anyhow!("Unknown sample rate"))?;
+        Ok(Self { format, decoder, track_id, sample_rate })
+    }
+
+    /// Decodes the next packet as interleaved stereo, `None` at the end of the stream.
+    fn next_chunk(&mut self) -> anyhow::Result<Option<Vec<f32>>>{
+        loop{
+            let packet = match self.format.next_packet(){
+                Ok(packet) => packet,
+                Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into())
+            };
+            if packet.track_id() != self.track_id{
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet){
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped, the next one may decode fine.
+                Err(DecodeError::DecodeError(e)) => {
+                    log::warn!("Skipping audio packet: {}", e);
+                    continue;
+                },
+                Err(e) => return Err(e.into())
+            };
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+            return Ok(Some(to_stereo(buffer.samples(), channels)));
+        }
+    }
+
+    fn rewind(&mut self) -> anyhow::Result<()>{
+        self.format.seek(SeekMode::Accurate, SeekTo::Time { time: Time::default(), track_id: Some(self.track_id) })?;
+        self.decoder.reset();
+        Ok(())
+    }
+}
+
+/// Mono is copied to both channels, channels after the first two are dropped.
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<f32>{
+    match channels{
+        0 => Vec::new(),
+        1 => samples.iter().flat_map(|&sample| [sample, sample]).collect(),
+        2 => samples.to_vec(),
+        _ => samples.chunks_exact(channels).flat_map(|frame| [frame[0], frame[1]]).collect()
+    }
+}
+
+/// Linear interpolation between sample rates, carrying its position across chunks.
+struct Resampler{
+    step: f64,
+    /// Position in the current chunk, where 0 is the last frame of the previous one.
+    position: f64,
+    last: [f32; 2]
+}
+
+impl Resampler{
+    fn new(from: u32, to: u32) -> Self{
+        Self { step: from as f64 / to as f64, position: 1.0, last: [0.0; 2] }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>){
+        let frames = input.len() / 2;
+        if frames == 0{
+            return;
+        }
+        if self.step == 1.0{
+            output.extend_from_slice(&input[..frames * 2]);
+            return;
+        }
+        let frame = |index: usize| -> [f32; 2]{
+            match index{
+                0 => self.last,
+                index => {
+                    let index = (index.min(frames) - 1) * 2;
+                    [input[index], input[index + 1]]
+                }
+            }
+        };
+        while self.position < frames as f64{
+            let index = self.position as usize;
+            let t = (self.position - index as f64) as f32;
+            let (a, b) = (frame(index), frame(index + 1));
+            output.push(a[0] + (b[0] - a[0]) * t);
+            output.push(a[1] + (b[1] - a[1]) * t);
+            self.position += self.step;
+        }
+        self.position -= frames as f64;
+        self.last = frame(frames);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// A 16-bit stereo WAV file holding `frames` frames of half volume.
+    fn wav(frames: u32, sample_rate: u32) -> Vec<u8>{
+        let data_len = frames * 4;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 4).to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for _ in 0..frames * 2{
+            bytes.extend_from_slice(&16384i16.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn decodes_whole_sounds(){
+        let sound = SoundData::decode(wav(100, 8000), Some("wav"), 8000).unwrap();
+        assert_eq!(sound.samples().len(), 200);
+        assert!(sound.samples().iter().all(|&sample| sample == 0.5));
+    }
+
+    #[test]
+    fn streams_past_the_first_buffer(){
+        // Three times what's decoded before the thread takes over.
+        let frames = 12000;
+        let mut source = StreamSource::new(Box::new(Cursor::new(wav(frames, 8000))), Some("wav"), 8000, false).unwrap();
+        let (mut played, mut out) = (0, [0.0; 256]);
+        let started = std::time::Instant::now();
+        loop{
+            let count = source.read(&mut out);
+            played += out[..count * 2].chunks(2).filter(|frame| frame[0] == 0.5).count();
+            if count < out.len() / 2{
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "The stream never ended");
+        }
+        assert_eq!(played, frames as usize);
+    }
+}
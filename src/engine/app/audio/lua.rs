@@ -0,0 +1,79 @@
+use mlua::prelude::*;
+
+use super::{mixer::VoiceId, AudioManager, SFX};
+
+/// Builds the `audio` table scripts use:
+/// `play(name, volume?, pan?, bus?)`, `loop(name, volume?, pan?, bus?)`, `stop(id)`,
+/// `set_volume(id, v)`, `set_pan(id, p)`, `play_music(path, volume?, loop?)`,
+/// `stop_music()`, `set_bus_volume(bus, v)`, `bus_volume(bus)` and `unload(name)`.
+pub fn create_audio_table(lua: &Lua, audio: &AudioManager) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+
+    for (name, looping) in [("play", false), ("loop", true)]{
+        let audio = audio.clone();
+        table.set(name, lua.create_function(move |_, (name, volume, pan, bus): (String, Option<f32>, Option<f32>, Option<String>)|{
+            let id = audio.play_on(&name, bus.as_deref().unwrap_or(SFX), volume.unwrap_or(1.0), pan.unwrap_or(0.0), looping)
+                .map_err(LuaError::external)?;
+            Ok(id.0)
+        })?)?;
+    }
+
+    let audio_clone = audio.clone();
+    table.set("stop", lua.create_function(move |_, id: u32|{
+        audio_clone.stop(VoiceId(id));
+        Ok(())
+    })?)?;
+
+    let audio_clone = audio.clone();
+    table.set("set_volume", lua.create_function(move |_, (id, volume): (u32, f32)|{
+        audio_clone.set_volume(VoiceId(id), volume);
+        Ok(())
+    })?)?;
+
+    let audio_clone = audio.clone();
+    table.set("set_pan", lua.create_function(move |_, (id, pan): (u32, f32)|{
+        audio_clone.set_pan(VoiceId(id), pan);
+        Ok(())
+    })?)?;
+
+    let audio_clone = audio.clone();
+    table.set("play_music", lua.create_function(move |_, (path, volume, looping): (String, Option<f32>, Option<bool>)|{
+        let id = audio_clone.play_music(&path, volume.unwrap_or(1.0), looping.unwrap_or(true))
+            .map_err(LuaError::external)?;
+        Ok(id.0)
+    })?)?;
+
+    let audio_clone = audio.clone();
+    table.set("stop_music", lua.create_function(move |_, ()|{
+        audio_clone.stop_music();
+        Ok(())
+    })?)?;
+
+    let audio_clone = audio.clone();
+    table.set("set_bus_volume", lua.create_function(move |_, (bus, volume): (String, f32)|{
+        audio_clone.set_bus_volume(&bus, volume);
+        Ok(())
+    })?)?;
+
+    let audio_clone = audio.clone();
+    table.set("bus_volume", lua.create_function(move |_, bus: String|{
+        Ok(audio_clone.bus_volume(&bus))
+    })?)?;
+
+    let audio_clone = audio.clone();
+    table.set("unload", lua.create_function(move |_, name: String|{
+        audio_clone.remove_sound(&name);
+        Ok(())
+    })?)?;
+
+    Ok(table)
+}
+
+/// Sets the `audio` global of a script's Lua state unless it's already there.
+pub fn register(lua: &Lua, audio: &AudioManager) -> LuaResult<()>{
+    let globals = lua.globals();
+    if globals.contains_key("audio")?{
+        return Ok(());
+    }
+    globals.set("audio", create_audio_table(lua, audio)?)
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+/// Something the mixer can pull interleaved stereo frames from.
+pub trait Source: Send{
+    /// Fills `out` with stereo frames and returns how many were written.
+    /// Writing fewer frames than fit means the source is finished.
+    fn read(&mut self, out: &mut [f32]) -> usize;
+}
+
+/// Identifies a playing sound, returned by `AudioManager::play`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VoiceId(pub u32);
+
+struct Voice{
+    id: VoiceId,
+    source: Box<dyn Source>,
+    bus: String,
+    volume: f32,
+    pan: f32,
+    /// Paused voices are skipped by `mix` and resume where they were.
+    paused: bool
+}
+
+pub const MASTER: &str = "master";
+pub const SFX: &str = "sfx";
+pub const MUSIC: &str = "music";
+
+/// Sums the playing voices into one stereo buffer.
+/// Every voice goes through its bus and then through `master`:
+/// `sample * voice volume * bus volume * master volume`, panned with `pan_gains`.
+pub struct Mixer{
+    voices: Vec<Voice>,
+    buses: HashMap<String, f32>,
+    next_id: u32,
+    scratch: Vec<f32>
+}
+
+impl Mixer{
+    pub fn new() -> Self{
+        let buses = [MASTER, SFX, MUSIC].into_iter().map(|bus| (bus.to_string(), 1.0)).collect();
+        Self { voices: Vec::new(), buses, next_id: 0, scratch: Vec::new() }
+    }
+
+    pub fn play(&mut self, source: Box<dyn Source>, bus: &str, volume: f32, pan: f32) -> VoiceId{
+        self.next_id += 1;
+        let id = VoiceId(self.next_id);
+        self.buses.entry(bus.to_string()).or_insert(1.0);
+        self.voices.push(Voice { id, source, bus: bus.to_string(), volume, pan: pan.clamp(-1.0, 1.0), paused: false });
+        id
+    }
+
+    pub fn stop(&mut self, id: VoiceId){
+        self.voices.retain(|voice| voice.id != id);
+    }
+
+    pub fn is_playing(&self, id: VoiceId) -> bool{
+        self.voices.iter().any(|voice| voice.id == id)
+    }
+
+    pub fn set_volume(&mut self, id: VoiceId, volume: f32){
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id){
+            voice.volume = volume;
+        }
+    }
+
+    pub fn set_pan(&mut self, id: VoiceId, pan: f32){
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id){
+            voice.pan = pan.clamp(-1.0, 1.0);
+        }
+    }
+
+    pub fn set_paused(&mut self, id: VoiceId, paused: bool){
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id){
+            voice.paused = paused;
+        }
+    }
+
+    /// Sets the volume of a bus, creating it if needed.
+    pub fn set_bus_volume(&mut self, bus: &str, volume: f32){
+        self.buses.insert(bus.to_string(), volume);
+    }
+
+    pub fn bus_volume(&self, bus: &str) -> Option<f32>{
+        self.buses.get(bus).copied()
+    }
+
+    /// Mixes the next `out.len() / 2` stereo frames into `out`, dropping finished voices.
+    pub fn mix(&mut self, out: &mut [f32]){
+        out.fill(0.0);
+        self.scratch.resize(out.len(), 0.0);
+        let master = self.buses.get(MASTER).copied().unwrap_or(1.0);
+
+        let frames = out.len() / 2;
+        let scratch = &mut self.scratch;
+        let buses = &self.buses;
+        self.voices.retain_mut(|voice|{
+            if voice.paused{
+                return true;
+            }
+            let written = voice.source.read(&mut scratch[..frames * 2]);
+            let gain = voice.volume * buses.get(&voice.bus).copied().unwrap_or(1.0) * master;
+            let (left, right) = pan_gains(voice.pan);
+            for (out, frame) in out.chunks_exact_mut(2).zip(scratch.chunks_exact(2)).take(written){
+                out[0] += frame[0] * gain * left;
+                out[1] += frame[1] * gain * right;
+            }
+            written == frames
+        });
+
+        for sample in out.iter_mut(){
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+    }
+}
+
+impl Default for Mixer{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+/// Balance panning: the centre leaves both channels untouched,
+/// moving to one side fades the other channel out.
+pub fn pan_gains(pan: f32) -> (f32, f32){
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// Plays a constant stereo frame `count` times.
+    struct Constant{
+        frame: [f32; 2],
+        count: usize
+    }
+
+    impl Source for Constant{
+        fn read(&mut self, out: &mut [f32]) -> usize{
+            let frames = (out.len() / 2).min(self.count);
+            for frame in out.chunks_exact_mut(2).take(frames){
+                frame.copy_from_slice(&self.frame);
+            }
+            self.count -= frames;
+            frames
+        }
+    }
+
+    fn constant(value: f32, count: usize) -> Box<dyn Source>{
+        Box::new(Constant { frame: [value, value], count })
+    }
+
+    #[test]
+    fn sums_voices_through_buses(){
+        let mut mixer = Mixer::new();
+        mixer.play(constant(0.5, 4), SFX, 0.5, 0.0);
+        mixer.play(constant(0.25, 4), MUSIC, 1.0, 0.0);
+        mixer.set_bus_volume(MUSIC, 0.5);
+        mixer.set_bus_volume(MASTER, 0.5);
+
+        let mut out = [0.0; 4];
+        mixer.mix(&mut out);
+        // (0.5 * 0.5 + 0.25 * 0.5) * 0.5
+        assert_eq!(out, [0.1875; 4]);
+    }
+
+    #[test]
+    fn pans_between_channels(){
+        let mut mixer = Mixer::new();
+        let left = mixer.play(constant(0.5, 8), SFX, 1.0, -1.0);
+        let mut out = [0.0; 2];
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.5, 0.0]);
+
+        mixer.set_pan(left, 0.5);
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.25, 0.5]);
+    }
+
+    #[test]
+    fn drops_finished_voices_and_clips(){
+        let mut mixer = Mixer::new();
+        let id = mixer.play(constant(0.75, 3), SFX, 1.0, 0.0);
+        mixer.play(constant(0.75, 2), SFX, 1.0, 0.0);
+
+        let mut out = [0.0; 8];
+        mixer.mix(&mut out);
+        assert_eq!(out, [1.0, 1.0, 1.0, 1.0, 0.75, 0.75, 0.0, 0.0]);
+        assert!(!mixer.is_playing(id));
+        assert!(mixer.voices.is_empty());
+    }
+
+    #[test]
+    fn paused_voices_keep_their_place(){
+        let mut mixer = Mixer::new();
+        let id = mixer.play(constant(0.5, 2), SFX, 1.0, 0.0);
+        mixer.set_paused(id, true);
+        let mut out = [0.0; 8];
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.0; 8]);
+        assert!(mixer.is_playing(id));
+
+        mixer.set_paused(id, false);
+        mixer.mix(&mut out);
+        assert_eq!(out, [0.5, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0]);
+        assert!(!mixer.is_playing(id));
+    }
+}
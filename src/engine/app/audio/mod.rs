@@ -0,0 +1,122 @@
+pub mod mixer;
+pub mod decoder;
+pub mod output;
+pub mod ring;
+pub mod lua;
+pub mod spatial;
+
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use anyhow::Context;
+
+use crate::engine::app::assets::Handle;
+use decoder::{BufferSource, SoundData, StreamSource};
+use mixer::{Mixer, VoiceId};
+pub use mixer::{MASTER, MUSIC, SFX};
+pub use output::AudioOutput;
+
+/// Loads sounds and plays them through the mixer.
+/// Cloning is cheap and gives another handle to the same mixer, which is how
+/// scripts get access to audio.
+#[derive(Clone)]
+pub struct AudioManager{
+    mixer: Arc<Mutex<Mixer>>,
+    sounds: Arc<Mutex<HashMap<String, Handle<SoundData>>>>,
+    music: Arc<Mutex<Option<VoiceId>>>,
+    sample_rate: u32
+}
+
+impl AudioManager{
+    /// A manager without an output; samples are only produced by `mix`.
+    pub fn new(sample_rate: u32) -> Self{
+        Self {
+            mixer: Arc::new(Mutex::new(Mixer::new())),
+            sounds: Arc::default(),
+            music: Arc::default(),
+            sample_rate
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32{
+        self.sample_rate
+    }
+
+    /// Makes a sound playable under `name` once it's loaded. The sound stays loaded while it's registered.
+    pub fn add_sound(&self, name: &str, sound: Handle<SoundData>){
+        self.sounds.lock().unwrap().insert(name.to_string(), sound);
+    }
+
+    /// Forgets a sound; it's unloaded once nothing else holds its handle.
+    pub fn remove_sound(&self, name: &str){
+        self.sounds.lock().unwrap().remove(name);
+    }
+
+    /// `true` while a registered sound is still loading; `false` once it's ready or failed.
+    pub fn is_loading(&self, name: &str) -> bool{
+        self.sounds.lock().unwrap().get(name).is_some_and(|sound| !sound.is_ready() && !sound.is_failed())
+    }
+
+    pub fn get_sound(&self, name: &str) -> Option<Arc<SoundData>>{
+        self.sounds.lock().unwrap().get(name).and_then(|sound| sound.get())
+    }
+
+    pub fn play_on(&self, name: &str, bus: &str, volume: f32, pan: f32, looping: bool) -> anyhow::Result<VoiceId>{
+        let sound = self.get_sound(name).with_context(|| format!("Sound {} is not loaded or still loading", name))?;
+        Ok(self.mixer.lock().unwrap().play(Box::new(BufferSource::new(sound, looping)), bus, volume, pan))
+    }
+
+    /// Streams a file on the `music` bus, replacing the music that was playing.
+    pub fn play_music(&self, path: &str, volume: f32, looping: bool) -> anyhow::Result<VoiceId>{
+        let source = StreamSource::open(path, self.sample_rate, looping)?;
+        let mut music = self.music.lock().unwrap();
+        let mut mixer = self.mixer.lock().unwrap();
+        if let Some(id) = music.take(){
+            mixer.stop(id);
+        }
+        let id = mixer.play(Box::new(source), MUSIC, volume, 0.0);
+        *music = Some(id);
+        Ok(id)
+    }
+
+    pub fn stop_music(&self){
+        if let Some(id) = self.music.lock().unwrap().take(){
+            self.stop(id);
+        }
+    }
+
+    pub fn stop(&self, id: VoiceId){
+        self.mixer.lock().unwrap().stop(id);
+    }
+
+    pub fn is_playing(&self, id: VoiceId) -> bool{
+        self.mixer.lock().unwrap().is_playing(id)
+    }
+
+    pub fn set_volume(&self, id: VoiceId, volume: f32){
+        self.mixer.lock().unwrap().set_volume(id, volume);
+    }
+
+    /// -1 is fully left, 1 fully right.
+    pub fn set_pan(&self, id: VoiceId, pan: f32){
+        self.mixer.lock().unwrap().set_pan(id, pan);
+    }
+
+    /// A paused voice keeps its place until it's resumed.
+    pub fn set_paused(&self, id: VoiceId, paused: bool){
+        self.mixer.lock().unwrap().set_paused(id, paused);
+    }
+
+    pub fn set_bus_volume(&self, bus: &str, volume: f32){
+        self.mixer.lock().unwrap().set_bus_volume(bus, volume);
+    }
+
+    pub fn bus_volume(&self, bus: &str) -> Option<f32>{
+        self.mixer.lock().unwrap().bus_volume(bus)
+    }
+
+    /// Mixes the next `out.len() / 2` stereo frames. Called by the output;
+    /// without one it can be used to render audio offline.
+    pub fn mix(&self, out: &mut [f32]){
+        self.mixer.lock().unwrap().mix(out);
+    }
+}
@@ -0,0 +1,147 @@
+use super::AudioManager;
+
+/// Sample rate used when there is no device to ask.
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// Most frames the null output mixes at once, so a long frame doesn't need a huge buffer.
+const NULL_BLOCK_FRAMES: usize = 1024;
+
+/// Keeps the audio device open. Dropping it stops the sound.
+pub enum AudioOutput{
+    /// No device: `pump` mixes in real time and drops the samples, so sounds still end.
+    Null(NullOutput),
+    #[cfg(feature = "audio-device")]
+    Device{ _stream: cpal::Stream }
+}
+
+impl AudioOutput{
+    /// Opens the default output device when the `audio-device` feature is enabled,
+    /// falling back to the null output if that fails.
+    pub fn open_default() -> (AudioManager, AudioOutput){
+        #[cfg(feature = "audio-device")]
+        match device::open(){
+            Ok(opened) => return opened,
+            Err(e) => log::warn!("No audio device, audio is muted: {}", e)
+        }
+        Self::null()
+    }
+
+    pub fn null() -> (AudioManager, AudioOutput){
+        (AudioManager::new(DEFAULT_SAMPLE_RATE), AudioOutput::Null(NullOutput::default()))
+    }
+
+    pub fn is_null(&self) -> bool{
+        matches!(self, AudioOutput::Null(_))
+    }
+
+    /// Plays `dt` seconds on the null output; a device pulls its samples by itself.
+    pub fn pump(&mut self, audio: &AudioManager, dt: f32){
+        match self{
+            AudioOutput::Null(null) => null.pump(audio, dt),
+            #[cfg(feature = "audio-device")]
+            AudioOutput::Device{ .. } => {}
+        }
+    }
+}
+
+/// Pulls samples from the mixer at the rate a device would, and drops them.
+#[derive(Default)]
+pub struct NullOutput{
+    /// The part of a frame left over from the last `pump`.
+    remainder: f64,
+    buffer: Vec<f32>
+}
+
+impl NullOutput{
+    fn pump(&mut self, audio: &AudioManager, dt: f32){
+        let frames = self.remainder + dt.max(0.0) as f64 * audio.sample_rate() as f64;
+        self.remainder = frames.fract();
+        let mut frames = frames as usize;
+        while frames > 0{
+            let block = frames.min(NULL_BLOCK_FRAMES);
+            self.buffer.resize(block * 2, 0.0);
+            audio.mix(&mut self.buffer);
+            frames -= block;
+        }
+    }
+}
+
+#[cfg(feature = "audio-device")]
+mod device{
+    use anyhow::anyhow;
+    use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, FromSample, SampleFormat, SizedSample, StreamConfig};
+
+    use super::{AudioManager, AudioOutput};
+
+    pub fn open() -> anyhow::Result<(AudioManager, AudioOutput)>{
+        let device = cpal::default_host().default_output_device().ok_or_else(|| anyhow!("No output device"))?;
+        let supported = device.default_output_config()?;
+        let format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+        let audio = AudioManager::new(config.sample_rate.0);
+
+        let stream = match format{
+            SampleFormat::F32 => build::<f32>(&device, &config, audio.clone())?,
+            SampleFormat::I16 => build::<i16>(&device, &config, audio.clone())?,
+            SampleFormat::U16 => build::<u16>(&device, &config, audio.clone())?,
+            format => return Err(anyhow!("Unsupported sample format {}", format))
+        };
+        stream.play()?;
+        Ok((audio, AudioOutput::Device { _stream: stream }))
+    }
+
+    fn build<T>(device: &cpal::Device, config: &StreamConfig, audio: AudioManager) -> anyhow::Result<cpal::Stream>
+        where T: SizedSample + FromSample<f32>
+    {
+        let channels = config.channels as usize;
+        let mut stereo = Vec::new();
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let frames = data.len() / channels;
+                stereo.resize(frames * 2, 0.0);
+                audio.mix(&mut stereo);
+                for (out, frame) in data.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)){
+                    match out{
+                        [mono] => *mono = T::from_sample((frame[0] + frame[1]) * 0.5),
+                        [left, right, rest @ ..] => {
+                            *left = T::from_sample(frame[0]);
+                            *right = T::from_sample(frame[1]);
+                            for sample in rest{
+                                *sample = T::from_sample(0.0);
+                            }
+                        },
+                        [] => {}
+                    }
+                }
+            },
+            |e| log::error!("Audio output error: {}", e),
+            None
+        )?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::{assets::Handle, audio::{decoder::SoundData, SFX}};
+
+    #[test]
+    fn the_null_output_plays_sounds_to_the_end(){
+        let (audio, mut output) = AudioOutput::null();
+        // A tenth of a second.
+        let frames = DEFAULT_SAMPLE_RATE as usize / 10;
+        audio.add_sound("beep", Handle::from_asset("beep", SoundData::from_samples(vec![0.5; frames * 2])));
+        let voice = audio.play_on("beep", SFX, 1.0, 0.0, false).unwrap();
+
+        for _ in 0..5{
+            output.pump(&audio, 1.0 / 60.0);
+        }
+        assert!(audio.is_playing(voice));
+        for _ in 0..2{
+            output.pump(&audio, 1.0 / 60.0);
+        }
+        assert!(!audio.is_playing(voice));
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// A fixed-size queue of samples for one writer thread and one reader thread.
+/// Neither side ever blocks, so the audio callback can read from it safely.
+pub struct RingBuffer{
+    samples: Box<[AtomicU32]>,
+    /// Total samples read and written; the difference is what's queued.
+    read: AtomicUsize,
+    written: AtomicUsize
+}
+
+impl RingBuffer{
+    pub fn new(capacity: usize) -> Self{
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            read: AtomicUsize::new(0),
+            written: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn capacity(&self) -> usize{
+        self.samples.len()
+    }
+
+    /// Queues as many of `samples` as fit. Returns how many were queued.
+    pub fn push(&self, samples: &[f32]) -> usize{
+        let written = self.written.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        let count = samples.len().min(self.capacity() - written.wrapping_sub(read));
+        for (i, sample) in samples[..count].iter().enumerate(){
+            self.samples[written.wrapping_add(i) % self.capacity()].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(written.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Takes queued samples into `out`. Returns how many were taken.
+    pub fn pop(&self, out: &mut [f32]) -> usize{
+        let read = self.read.load(Ordering::Relaxed);
+        let written = self.written.load(Ordering::Acquire);
+        let count = out.len().min(written.wrapping_sub(read));
+        for (i, sample) in out[..count].iter_mut().enumerate(){
+            *sample = f32::from_bits(self.samples[read.wrapping_add(i) % self.capacity()].load(Ordering::Relaxed));
+        }
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn pushes_only_what_fits(){
+        let ring = RingBuffer::new(4);
+        assert_eq!(ring.push(&[1.0, 2.0, 3.0, 4.0, 5.0]), 4);
+        assert_eq!(ring.push(&[6.0]), 0);
+        let mut out = [0.0; 5];
+        assert_eq!(ring.pop(&mut out), 4);
+        assert_eq!(out[..4], [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn pops_in_order_across_the_wrap(){
+        let ring = RingBuffer::new(4);
+        let mut out = [0.0; 3];
+        ring.push(&[1.0, 2.0, 3.0]);
+        assert_eq!(ring.pop(&mut out), 3);
+        ring.push(&[4.0, 5.0, 6.0]);
+        assert_eq!(ring.pop(&mut out), 3);
+        assert_eq!(out, [4.0, 5.0, 6.0]);
+        assert_eq!(ring.pop(&mut out), 0);
+    }
+
+    #[test]
+    fn carries_samples_between_threads(){
+        let ring = std::sync::Arc::new(RingBuffer::new(16));
+        let writer = {
+            let ring = ring.clone();
+            std::thread::spawn(move ||{
+                let samples: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+                let mut sent = 0;
+                while sent < samples.len(){
+                    sent += ring.push(&samples[sent..]);
+                }
+            })
+        };
+        let mut received = Vec::new();
+        let mut out = [0.0; 7];
+        while received.len() < 1000{
+            let count = ring.pop(&mut out);
+            received.extend_from_slice(&out[..count]);
+        }
+        writer.join().unwrap();
+        assert!(received.iter().enumerate().all(|(i, &sample)| sample == i as f32));
+    }
+}
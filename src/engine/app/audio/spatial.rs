@@ -0,0 +1,138 @@
+/// The smallest `min_distance` of the inverse and exponential curves, which divide by it.
+const SMALLEST_MIN_DISTANCE: f32 = 0.001;
+
+/// How the volume of a positional sound falls off with distance.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Attenuation{
+    /// Same volume everywhere.
+    None,
+    /// Full volume up to `min_distance`, silent from `max_distance`, a straight line in between.
+    Linear { min_distance: f32, max_distance: f32 },
+    /// `min_distance / (min_distance + rolloff * (distance - min_distance))`, like OpenAL's default.
+    Inverse { min_distance: f32, rolloff: f32 },
+    /// `(distance / min_distance) ^ -rolloff`.
+    Exponential { min_distance: f32, rolloff: f32 },
+    /// Points `(distance, gain)` sorted by distance, interpolated linearly and held at both ends.
+    Curve(Vec<(f32, f32)>)
+}
+
+impl Attenuation{
+    pub fn gain(&self, distance: f32) -> f32{
+        let distance = distance.max(0.0);
+        match self{
+            Attenuation::None => 1.0,
+            Attenuation::Linear { min_distance, max_distance } => {
+                if distance <= *min_distance{
+                    1.0
+                } else if distance >= *max_distance{
+                    0.0
+                } else{
+                    1.0 - (distance - min_distance) / (max_distance - min_distance)
+                }
+            },
+            Attenuation::Inverse { min_distance, rolloff } => {
+                let min_distance = min_distance.max(SMALLEST_MIN_DISTANCE);
+                let distance = distance.max(min_distance);
+                min_distance / (min_distance + rolloff * (distance - min_distance))
+            },
+            Attenuation::Exponential { min_distance, rolloff } => {
+                let min_distance = min_distance.max(SMALLEST_MIN_DISTANCE);
+                (distance.max(min_distance) / min_distance).powf(-rolloff)
+            },
+            Attenuation::Curve(points) => curve_gain(points, distance)
+        }
+    }
+}
+
+impl Default for Attenuation{
+    fn default() -> Self{
+        Attenuation::Linear { min_distance: 1.0, max_distance: 10.0 }
+    }
+}
+
+fn curve_gain(points: &[(f32, f32)], distance: f32) -> f32{
+    let Some(&(first_distance, first_gain)) = points.first() else{
+        return 1.0;
+    };
+    if distance <= first_distance{
+        return first_gain;
+    }
+    for pair in points.windows(2){
+        let ((d0, g0), (d1, g1)) = (pair[0], pair[1]);
+        if distance <= d1{
+            let t = if d1 > d0 { (distance - d0) / (d1 - d0) } else { 1.0 };
+            return g0 + (g1 - g0) * t;
+        }
+    }
+    points[points.len() - 1].1
+}
+
+/// Volume and pan of a sound at `source` heard from `listener`.
+/// The pan follows the horizontal offset and reaches a side at `pan_range`.
+pub fn spatialize(attenuation: &Attenuation, listener: (f32, f32), source: (f32, f32), pan_range: f32) -> (f32, f32){
+    let (dx, dy) = (source.0 - listener.0, source.1 - listener.1);
+    let gain = attenuation.gain((dx * dx + dy * dy).sqrt());
+    let pan = if pan_range > 0.0 { (dx / pan_range).clamp(-1.0, 1.0) } else { 0.0 };
+    (gain, pan)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool{
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn linear_falls_off_between_distances(){
+        let attenuation = Attenuation::Linear { min_distance: 2.0, max_distance: 6.0 };
+        assert_eq!(attenuation.gain(1.0), 1.0);
+        assert!(close(attenuation.gain(4.0), 0.5));
+        assert_eq!(attenuation.gain(8.0), 0.0);
+        assert_eq!(Attenuation::None.gain(1000.0), 1.0);
+    }
+
+    #[test]
+    fn inverse_and_exponential_hold_inside_min_distance(){
+        let inverse = Attenuation::Inverse { min_distance: 2.0, rolloff: 1.0 };
+        assert_eq!(inverse.gain(1.0), 1.0);
+        assert!(close(inverse.gain(4.0), 0.5));
+        let exponential = Attenuation::Exponential { min_distance: 2.0, rolloff: 2.0 };
+        assert_eq!(exponential.gain(0.5), 1.0);
+        assert!(close(exponential.gain(4.0), 0.25));
+    }
+
+    #[test]
+    fn a_zero_min_distance_gives_finite_gains(){
+        for attenuation in [Attenuation::Inverse { min_distance: 0.0, rolloff: 1.0 }, Attenuation::Exponential { min_distance: 0.0, rolloff: 1.0 }]{
+            for distance in [0.0, 0.5, 10.0]{
+                let gain = attenuation.gain(distance);
+                assert!(gain.is_finite() && (0.0..=1.0).contains(&gain), "{:?} at {}: {}", attenuation, distance, gain);
+            }
+            assert_eq!(attenuation.gain(0.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn curves_interpolate_and_hold_their_ends(){
+        let curve = Attenuation::Curve(vec![(1.0, 1.0), (3.0, 0.5), (5.0, 0.0)]);
+        assert_eq!(curve.gain(0.0), 1.0);
+        assert!(close(curve.gain(2.0), 0.75));
+        assert!(close(curve.gain(4.0), 0.25));
+        assert_eq!(curve.gain(9.0), 0.0);
+        assert_eq!(Attenuation::Curve(Vec::new()).gain(3.0), 1.0);
+    }
+
+    #[test]
+    fn pans_with_the_horizontal_offset(){
+        let attenuation = Attenuation::Linear { min_distance: 1.0, max_distance: 10.0 };
+        let (gain, pan) = spatialize(&attenuation, (1.0, 1.0), (3.0, 1.0), 4.0);
+        assert!(close(gain, 1.0 - 1.0 / 9.0));
+        assert!(close(pan, 0.5));
+        assert_eq!(spatialize(&attenuation, (0.0, 0.0), (-20.0, 0.0), 4.0).1, -1.0);
+        // Straight above the listener, or without a pan range, stays centred.
+        assert_eq!(spatialize(&attenuation, (0.0, 0.0), (0.0, 3.0), 4.0).1, 0.0);
+        assert_eq!(spatialize(&attenuation, (0.0, 0.0), (3.0, 0.0), 0.0).1, 0.0);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::VecDeque, sync::{Mutex, OnceLock}};
+
+use egui::{Color32, RichText};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+const CAPACITY: usize = 1000;
+
+pub struct ConsoleEntry{
+    pub level: Level,
+    /// Log target, or `print` plus the script file for Lua output.
+    pub source: String,
+    pub message: String
+}
+
+/// Collects log records, Lua `print` output and script errors for the in-game console.
+#[derive(Default)]
+pub struct Console{
+    entries: VecDeque<ConsoleEntry>
+}
+
+impl Console{
+    pub fn push(&mut self, level: Level, source: impl Into<String>, message: impl Into<String>){
+        if self.entries.len() == CAPACITY{
+            self.entries.pop_front();
+        }
+        self.entries.push_back(ConsoleEntry { level, source: source.into(), message: message.into() });
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &ConsoleEntry>{
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self){
+        self.entries.clear();
+    }
+}
+
+pub fn console() -> &'static Mutex<Console>{
+    static CONSOLE: OnceLock<Mutex<Console>> = OnceLock::new();
+    CONSOLE.get_or_init(|| Mutex::new(Console::default()))
+}
+
+/// Lua `print` output of the script `file`.
+pub fn print(file: &str, message: impl Into<String>){
+    console().lock().unwrap().push(Level::Info, format!("print: {}", file), message);
+}
+
+/// Forwards records to `env_logger` and copies them into the console.
+/// Besides what `RUST_LOG` enables, the console gets the engine's own info
+/// messages and warnings from every crate.
+struct ConsoleLogger{
+    inner: env_logger::Logger
+}
+
+impl Log for ConsoleLogger{
+    fn enabled(&self, metadata: &Metadata) -> bool{
+        self.inner.enabled(metadata) || captured(metadata)
+    }
+
+    fn log(&self, record: &Record){
+        let forwarded = self.inner.enabled(record.metadata());
+        if forwarded{
+            self.inner.log(record);
+        }
+        if forwarded || captured(record.metadata()){
+            console().lock().unwrap().push(record.level(), record.target(), record.args().to_string());
+        }
+    }
+
+    fn flush(&self){
+        self.inner.flush();
+    }
+}
+
+fn captured(metadata: &Metadata) -> bool{
+    metadata.level() <= Level::Warn
+        || (metadata.level() <= Level::Info && metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+}
+
+/// Replacement for `env_logger::init` that also feeds the console.
+pub fn init_logger(){
+    let inner = env_logger::Builder::from_default_env().build();
+    let max_level = inner.filter().max(LevelFilter::Info);
+    log::set_boxed_logger(Box::new(ConsoleLogger { inner })).expect("Logger already initialized");
+    log::set_max_level(max_level);
+}
+
+/// Console window state: which levels are shown and the text filter.
+pub struct ConsoleView{
+    filter: String,
+    levels: [bool; 5]
+}
+
+impl ConsoleView{
+    pub fn new() -> Self{
+        Self { filter: String::new(), levels: [true, true, true, false, false] }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, open: &mut bool){
+        egui::Window::new("Console")
+            .open(open)
+            .resizable(true)
+            .default_height(200.0)
+            .show(ctx, |ui|{
+                ui.horizontal(|ui|{
+                    for (level, shown) in Level::iter().zip(self.levels.iter_mut()){
+                        ui.checkbox(shown, level.as_str());
+                    }
+                    ui.add(egui::TextEdit::singleline(&mut self.filter).hint_text("filter"));
+                    if ui.button("Clear").clicked(){
+                        console().lock().unwrap().clear();
+                    }
+                });
+                ui.separator();
+
+                // Copied out so nothing logged while drawing can deadlock on the console.
+                let lines: Vec<(Color32, String)> = console().lock().unwrap().entries()
+                    .filter(|entry| self.levels[entry.level as usize - 1])
+                    .filter(|entry| self.filter.is_empty() || entry.message.contains(&self.filter) || entry.source.contains(&self.filter))
+                    .map(|entry|{
+                        let color = match entry.level{
+                            Level::Error => Color32::RED,
+                            Level::Warn => Color32::YELLOW,
+                            Level::Info => Color32::LIGHT_GRAY,
+                            Level::Debug | Level::Trace => Color32::GRAY
+                        };
+                        (color, format!("[{}] {}", entry.source, entry.message))
+                    })
+                    .collect();
+
+                egui::ScrollArea::vertical().stick_to_bottom(true).auto_shrink(false).show(ui, |ui|{
+                    for (color, line) in lines{
+                        ui.label(RichText::new(line).color(color).monospace());
+                    }
+                });
+            });
+    }
+}
+
+impl Default for ConsoleView{
+    fn default() -> Self{
+        Self::new()
+    }
+}
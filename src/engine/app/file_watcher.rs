@@ -0,0 +1,77 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+
+/// Polls the modification time of registered files.
+///
+/// Polling keeps the watcher portable and dependency free; with a handful
+/// of assets checking a few times per second is cheap.
+pub struct FileWatcher{
+    files: HashMap<PathBuf, Option<SystemTime>>,
+    interval: Duration,
+    last_poll: Instant,
+    pub enabled: bool
+}
+
+impl FileWatcher{
+    pub fn new(interval: Duration) -> Self{
+        Self { files: HashMap::new(), interval, last_poll: Instant::now(), enabled: true }
+    }
+
+    /// Starts watching `path`. Watching an already watched file is a no-op.
+    pub fn watch(&mut self, path: &Path){
+        if !self.files.contains_key(path){
+            self.files.insert(path.to_path_buf(), modified(path));
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &Path){
+        self.files.remove(path);
+    }
+
+    /// Returns the files that changed since the last poll.
+    /// Does nothing until the poll interval has passed.
+    pub fn poll(&mut self) -> Vec<PathBuf>{
+        if !self.enabled || self.last_poll.elapsed() < self.interval{
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = Vec::new();
+        for (path, last_modified) in &mut self.files{
+            let current = modified(path);
+            if current.is_some() && current != *last_modified{
+                *last_modified = current;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+impl Default for FileWatcher{
+    fn default() -> Self{
+        Self::new(Duration::from_millis(500))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime>{
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn notices_files_created_after_watching(){
+        let file = std::env::temp_dir().join(format!("eng-rs-watch-{}.meta", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let mut watcher = FileWatcher::new(Duration::ZERO);
+        watcher.watch(&file);
+        assert!(watcher.poll().is_empty());
+
+        fs::write(&file, "filter = linear").unwrap();
+        assert_eq!(watcher.poll(), std::slice::from_ref(&file));
+        assert!(watcher.poll().is_empty());
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,20 @@
+/// Marks the entity whose `Transform` positional sounds are heard from.
+/// Without one the camera position is used.
+pub struct AudioListener{
+    /// Horizontal distance at which a sound is panned fully to one side.
+    pub pan_range: f32
+}
+
+impl AudioListener{
+    pub fn new() -> Self{
+        Self { pan_range: DEFAULT_PAN_RANGE }
+    }
+}
+
+impl Default for AudioListener{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+const DEFAULT_PAN_RANGE: f32 = 5.0;
@@ -0,0 +1,262 @@
+use hecs::{Entity, World};
+use mlua::{prelude::*, Scope};
+
+use crate::engine::app::audio::{mixer::VoiceId, spatial::{self, Attenuation}, AudioManager, SFX};
+use super::TransformComponent;
+
+/// A sound played at the entity's `Transform`.
+/// Volume and pan are recomputed every frame from the distance to the `AudioListener`.
+pub struct AudioSource{
+    /// Name of a sound loaded into the `AudioManager`.
+    pub sound: String,
+    pub bus: String,
+    pub volume: f32,
+    pub looping: bool,
+    pub attenuation: Attenuation,
+    /// Set to start playing, cleared when a one-shot sound ends.
+    pub playing: bool,
+    pub(crate) voice: Option<VoiceId>,
+    pub(crate) restart: bool
+}
+
+impl AudioSource{
+    /// A looping sound that starts playing right away, e.g. ambience.
+    pub fn new(sound: &str, attenuation: Attenuation) -> Self{
+        Self {
+            sound: sound.to_string(),
+            bus: SFX.to_string(),
+            volume: 1.0,
+            looping: true,
+            attenuation,
+            playing: true,
+            voice: None,
+            restart: false
+        }
+    }
+
+    /// A sound that plays once when `play` is called.
+    pub fn one_shot(sound: &str, attenuation: Attenuation) -> Self{
+        Self { looping: false, playing: false, ..Self::new(sound, attenuation) }
+    }
+
+    /// Restarts the sound from the beginning.
+    pub fn play(&mut self){
+        self.playing = true;
+        self.restart = true;
+    }
+
+    pub fn stop(&mut self){
+        self.playing = false;
+    }
+}
+
+/// Keeps the mixer's voices in step with the `AudioSource`s of a world.
+#[derive(Default)]
+pub struct AudioSourceVoices{
+    /// Voices started for sources, with their entity.
+    voices: Vec<(Entity, VoiceId)>,
+    paused: bool
+}
+
+impl AudioSourceVoices{
+    /// Starts, stops and moves the voices relative to the listener at `listener`.
+    /// Voices of entities that were despawned or lost their `AudioSource` are stopped,
+    /// and while `paused` every voice holds its place.
+    pub fn update(&mut self, world: &World, audio: &AudioManager, listener: (f32, f32), pan_range: f32, paused: bool){
+        self.voices.retain(|&(entity, voice)|{
+            let owned = world.get::<&AudioSource>(entity).is_ok_and(|source| source.voice == Some(voice));
+            if !owned{
+                audio.stop(voice);
+            }
+            owned
+        });
+        if paused != self.paused{
+            self.paused = paused;
+            for &(_, voice) in &self.voices{
+                audio.set_paused(voice, paused);
+            }
+        }
+        if paused{
+            return;
+        }
+
+        for (entity, (source, transform)) in &mut world.query::<(&mut AudioSource, &TransformComponent)>(){
+            if source.restart{
+                source.restart = false;
+                if let Some(voice) = source.voice.take(){
+                    audio.stop(voice);
+                }
+            }
+            if let Some(voice) = source.voice{
+                if !source.playing{
+                    audio.stop(voice);
+                    source.voice = None;
+                    continue;
+                }
+                if !audio.is_playing(voice){
+                    source.voice = None;
+                    source.playing = false;
+                    continue;
+                }
+            }
+            if !source.playing{
+                continue;
+            }
+
+            let position = transform.lock().unwrap().position;
+            let (gain, pan) = spatial::spatialize(&source.attenuation, listener, (position.x, position.y), pan_range);
+            match source.voice{
+                Some(voice) => {
+                    audio.set_volume(voice, source.volume * gain);
+                    audio.set_pan(voice, pan);
+                },
+                None => match audio.play_on(&source.sound, &source.bus, source.volume * gain, pan, source.looping){
+                    Ok(voice) => {
+                        source.voice = Some(voice);
+                        self.voices.push((entity, voice));
+                    },
+                    // Tried again next frame until the sound is loaded.
+                    Err(_) if audio.is_loading(&source.sound) => {},
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                        source.playing = false;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Builds the `audioSource` table for the script's own source: `play()`, `stop()`, `isPlaying()`,
+/// `setSound(name)`, `setVolume(v)`, `setLooping(loop)` and `setAttenuation(kind, ...)` where kind is
+/// `"none"`, `"linear"` (min, max), `"inverse"` (min, rolloff), `"exponential"` (min, rolloff)
+/// or `"curve"` with a list of `{distance, gain}` points.
+pub fn create_lua_table<'scope, 'env>(lua: &Lua, scope: &'scope Scope<'scope, 'env>, world: &'env World, entity: Entity) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+
+    table.set("play", scope.create_function(move |_, ()| with_source(world, entity, AudioSource::play))?)?;
+    table.set("stop", scope.create_function(move |_, ()| with_source(world, entity, AudioSource::stop))?)?;
+    table.set("isPlaying", scope.create_function(move |_, ()| with_source(world, entity, |source| source.playing))?)?;
+    table.set("setSound", scope.create_function(move |_, sound: String|{
+        with_source(world, entity, |source|{
+            source.sound = sound;
+            source.restart = true;
+        })
+    })?)?;
+    table.set("setVolume", scope.create_function(move |_, volume: f32| with_source(world, entity, |source| source.volume = volume.max(0.0)))?)?;
+    table.set("setLooping", scope.create_function(move |_, looping: bool| with_source(world, entity, |source| source.looping = looping))?)?;
+    table.set("setAttenuation", scope.create_function(move |lua, (kind, a, b): (String, LuaValue, Option<f32>)|{
+        let attenuation = match kind.as_str(){
+            "none" => Attenuation::None,
+            "linear" => Attenuation::Linear { min_distance: f32::from_lua(a, lua)?, max_distance: b.unwrap_or(10.0) },
+            "inverse" => Attenuation::Inverse { min_distance: f32::from_lua(a, lua)?, rolloff: b.unwrap_or(1.0) },
+            "exponential" => Attenuation::Exponential { min_distance: f32::from_lua(a, lua)?, rolloff: b.unwrap_or(1.0) },
+            "curve" => {
+                let mut points = Vec::new();
+                for point in Vec::<Vec<f32>>::from_lua(a, lua)?{
+                    let [distance, gain] = point[..] else{
+                        return Err(LuaError::external("curve points are {distance, gain}"));
+                    };
+                    points.push((distance, gain));
+                }
+                points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Attenuation::Curve(points)
+            },
+            other => return Err(LuaError::external(format!("unknown attenuation {}", other)))
+        };
+        with_source(world, entity, |source| source.attenuation = attenuation)
+    })?)?;
+
+    Ok(table)
+}
+
+/// Names of the `audioSource` table's functions, for completion.
+pub const AUDIO_SOURCE_API: &[&str] = &["play", "stop", "isPlaying", "setSound", "setVolume", "setLooping", "setAttenuation"];
+
+fn with_source<R>(world: &World, entity: Entity, f: impl FnOnce(&mut AudioSource) -> R) -> LuaResult<R>{
+    let mut source = world.get::<&mut AudioSource>(entity).map_err(|_| LuaError::external("this object has no audio source"))?;
+    Ok(f(&mut source))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::{assets::Handle, audio::decoder::SoundData, game::components::Transform};
+
+    fn audio() -> AudioManager{
+        let audio = AudioManager::new(48000);
+        audio.add_sound("hum", Handle::from_asset("hum", SoundData::from_samples(vec![0.5; 4800])));
+        audio
+    }
+
+    fn voice(world: &World, entity: Entity) -> Option<VoiceId>{
+        world.get::<&AudioSource>(entity).unwrap().voice
+    }
+
+    #[test]
+    fn starts_and_stops_with_the_component(){
+        let (audio, mut world, mut voices) = (audio(), World::new(), AudioSourceVoices::default());
+        let entity = world.spawn((AudioSource::one_shot("hum", Attenuation::None), Transform::new(0.0, 0.0, 0.0)));
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        assert_eq!(voice(&world, entity), None);
+
+        world.get::<&mut AudioSource>(entity).unwrap().play();
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        let id = voice(&world, entity).unwrap();
+        assert!(audio.is_playing(id));
+
+        world.get::<&mut AudioSource>(entity).unwrap().stop();
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        assert!(!audio.is_playing(id));
+    }
+
+    #[test]
+    fn waits_for_sounds_that_are_loading(){
+        let (audio, mut world, mut voices) = (audio(), World::new(), AudioSourceVoices::default());
+        audio.add_sound("late", Handle::pending("late.ogg"));
+        let entity = world.spawn((AudioSource::new("late", Attenuation::None), Transform::new(0.0, 0.0, 0.0)));
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        assert_eq!(voice(&world, entity), None);
+        assert!(world.get::<&AudioSource>(entity).unwrap().playing);
+
+        audio.add_sound("late", Handle::from_asset("late.ogg", SoundData::from_samples(vec![0.5; 480])));
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        assert!(voice(&world, entity).is_some());
+
+        // A sound that isn't registered will never load.
+        let missing = world.spawn((AudioSource::new("missing", Attenuation::None), Transform::new(0.0, 0.0, 0.0)));
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        assert!(!world.get::<&AudioSource>(missing).unwrap().playing);
+    }
+
+    #[test]
+    fn stops_voices_of_despawned_entities(){
+        let (audio, mut world, mut voices) = (audio(), World::new(), AudioSourceVoices::default());
+        let entity = world.spawn((AudioSource::new("hum", Attenuation::None), Transform::new(0.0, 0.0, 0.0)));
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        let id = voice(&world, entity).unwrap();
+
+        world.despawn(entity).unwrap();
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        assert!(!audio.is_playing(id));
+    }
+
+    #[test]
+    fn pauses_voices_in_place(){
+        let (audio, mut world, mut voices) = (audio(), World::new(), AudioSourceVoices::default());
+        let entity = world.spawn((AudioSource::new("hum", Attenuation::None), Transform::new(0.0, 0.0, 0.0)));
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        let id = voice(&world, entity).unwrap();
+
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, true);
+        let mut out = [1.0; 8];
+        audio.mix(&mut out);
+        assert_eq!(out, [0.0; 8]);
+        assert!(audio.is_playing(id));
+
+        voices.update(&world, &audio, (0.0, 0.0), 10.0, false);
+        audio.mix(&mut out);
+        assert_eq!(out, [0.5; 8]);
+        assert_eq!(voice(&world, entity), Some(id));
+    }
+}
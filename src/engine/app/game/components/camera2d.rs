@@ -0,0 +1,290 @@
+use hecs::{Entity, World};
+use mlua::{prelude::*, Scope};
+
+use super::TransformComponent;
+
+/// Every layer, the default `layer_mask`.
+pub const ALL_LAYERS: u32 = u32::MAX;
+
+/// A view into the world from the entity's `Transform`.
+/// All cameras are drawn, lowest `priority` first, each into its viewport;
+/// without any, the editor camera is used.
+pub struct Camera2D{
+    /// 1 shows 2 world units vertically, 2 shows 1.
+    pub zoom: f32,
+    /// Radians, counterclockwise.
+    pub rotation: f32,
+    pub viewport: Viewport,
+    pub priority: i32,
+    /// Bit `n` set shows sprites on layer `n`.
+    pub layer_mask: u32,
+    pub follow: Option<Follow>,
+    /// The view is kept inside these, or centered on them if it's bigger.
+    pub bounds: Option<Bounds>,
+    shake: Shake
+}
+
+/// Part of the window a camera draws to, in `[0, 1]` from the top left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport{
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32
+}
+
+impl Viewport{
+    pub const FULL: Viewport = Viewport { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+}
+
+impl Default for Viewport{
+    fn default() -> Self{
+        Self::FULL
+    }
+}
+
+/// Moves the camera after `target` once it leaves the deadzone.
+#[derive(Clone, Copy, Debug)]
+pub struct Follow{
+    pub target: Entity,
+    /// Half size of the box around the camera center the target can move in freely.
+    pub deadzone: (f32, f32),
+    /// How fast the camera catches up, per second; 0 snaps to the target.
+    pub smoothing: f32,
+    /// Added to the target position, e.g. to look ahead of a player.
+    pub offset: (f32, f32)
+}
+
+impl Follow{
+    pub fn new(target: Entity) -> Self{
+        Self { target, deadzone: (0.0, 0.0), smoothing: 0.0, offset: (0.0, 0.0) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds{
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32
+}
+
+#[derive(Default)]
+struct Shake{
+    intensity: f32,
+    duration: f32,
+    remaining: f32,
+    time: f32
+}
+
+impl Camera2D{
+    pub fn new() -> Self{
+        Self { zoom: 1.0, rotation: 0.0, viewport: Viewport::FULL, priority: 0, layer_mask: ALL_LAYERS, follow: None, bounds: None, shake: Shake::default() }
+    }
+
+    /// Shakes the view by up to `intensity` world units, fading out over `duration` seconds.
+    /// A stronger shake replaces a weaker one that is still running.
+    pub fn shake(&mut self, intensity: f32, duration: f32){
+        let current = if self.shake.duration > 0.0 { self.shake.intensity * self.shake.remaining / self.shake.duration } else { 0.0 };
+        if intensity >= current && duration > 0.0{
+            self.shake = Shake { intensity, duration, remaining: duration, time: self.shake.time };
+        }
+    }
+
+    /// Half the width and height of the view in world units, for a viewport of the given aspect ratio.
+    pub fn half_extents(&self, aspect: f32) -> (f32, f32){
+        let half_height = 1.0 / self.zoom.max(f32::EPSILON);
+        (half_height * aspect, half_height)
+    }
+
+    /// The next camera position: `position` moved toward `target` (if following) and clamped to the bounds.
+    /// Bounds are applied to the unrotated view.
+    pub fn step(&self, position: (f32, f32), target: Option<(f32, f32)>, aspect: f32, dt: f32) -> (f32, f32){
+        let mut position = position;
+        if let (Some(follow), Some(target)) = (&self.follow, target){
+            let target = (target.0 + follow.offset.0, target.1 + follow.offset.1);
+            let desired = (
+                deadzone_axis(position.0, target.0, follow.deadzone.0),
+                deadzone_axis(position.1, target.1, follow.deadzone.1)
+            );
+            let t = if follow.smoothing > 0.0 { 1.0 - (-follow.smoothing * dt).exp() } else { 1.0 };
+            position = (position.0 + (desired.0 - position.0) * t, position.1 + (desired.1 - position.1) * t);
+        }
+        match &self.bounds{
+            Some(bounds) => {
+                let (half_width, half_height) = self.half_extents(aspect);
+                (clamp_axis(position.0, half_width, bounds.min_x, bounds.max_x), clamp_axis(position.1, half_height, bounds.min_y, bounds.max_y))
+            },
+            None => position
+        }
+    }
+
+    /// Advances the shake and returns the offset to draw the view at this frame.
+    pub fn update_shake(&mut self, dt: f32) -> (f32, f32){
+        if self.shake.remaining <= 0.0{
+            return (0.0, 0.0);
+        }
+        self.shake.remaining = (self.shake.remaining - dt).max(0.0);
+        self.shake.time += dt;
+        let amplitude = self.shake.intensity * self.shake.remaining / self.shake.duration;
+        let t = self.shake.time;
+        // Sines with unrelated frequencies look random enough and don't need a generator.
+        let x = (t * 47.0).sin() * 0.6 + (t * 83.0 + 1.3).sin() * 0.4;
+        let y = (t * 53.0 + 0.7).sin() * 0.6 + (t * 71.0 + 2.1).sin() * 0.4;
+        (x * amplitude, y * amplitude)
+    }
+
+    pub fn is_shaking(&self) -> bool{
+        self.shake.remaining > 0.0
+    }
+}
+
+impl Default for Camera2D{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+/// The camera drawn on top, which the listener and the REPL use:
+/// the highest priority, the last one on ties.
+pub fn main_camera(world: &World) -> Option<Entity>{
+    let mut active: Option<(Entity, i32)> = None;
+    for (entity, camera) in &mut world.query::<&Camera2D>(){
+        if active.is_none_or(|(_, priority)| camera.priority >= priority){
+            active = Some((entity, camera.priority));
+        }
+    }
+    active.map(|(entity, _)| entity)
+}
+
+/// Builds the `camera` table scripts use to control the camera on their object, or else the main camera:
+/// `getPosition()`, `setPosition(x, y)`, `getZoom()`, `setZoom(z)`, `getRotation()`, `setRotation(r)`,
+/// `follow(deadzoneX?, deadzoneY?, smoothing?)` to follow the script's object, `unfollow()`,
+/// `setBounds(minX, minY, maxX, maxY)`, `clearBounds()`, `shake(intensity, duration)` and `isShaking()`.
+pub fn create_lua_table<'scope, 'env>(lua: &Lua, scope: &'scope Scope<'scope, 'env>, world: &'env World, entity: Entity) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+
+    table.set("getPosition", scope.create_function(move |_, ()|{
+        let transform = camera_transform(world, entity)?;
+        let transform = transform.lock().unwrap();
+        Ok((transform.position.x, transform.position.y))
+    })?)?;
+    table.set("setPosition", scope.create_function(move |_, (x, y): (f32, f32)|{
+        let transform = camera_transform(world, entity)?;
+        let mut transform = transform.lock().unwrap();
+        transform.position.x = x;
+        transform.position.y = y;
+        Ok(())
+    })?)?;
+    table.set("getZoom", scope.create_function(move |_, ()| with_camera(world, entity, |camera| camera.zoom))?)?;
+    table.set("setZoom", scope.create_function(move |_, zoom: f32|{
+        if zoom <= 0.0{
+            return Err(LuaError::external(format!("zoom must be positive, got {}", zoom)));
+        }
+        with_camera(world, entity, |camera| camera.zoom = zoom)
+    })?)?;
+    table.set("getRotation", scope.create_function(move |_, ()| with_camera(world, entity, |camera| camera.rotation))?)?;
+    table.set("setRotation", scope.create_function(move |_, rotation: f32| with_camera(world, entity, |camera| camera.rotation = rotation))?)?;
+    table.set("follow", scope.create_function(move |_, (deadzone_x, deadzone_y, smoothing): (Option<f32>, Option<f32>, Option<f32>)|{
+        with_camera(world, entity, |camera|{
+            camera.follow = Some(Follow {
+                deadzone: (deadzone_x.unwrap_or(0.0), deadzone_y.or(deadzone_x).unwrap_or(0.0)),
+                smoothing: smoothing.unwrap_or(0.0),
+                ..Follow::new(entity)
+            });
+        })
+    })?)?;
+    table.set("unfollow", scope.create_function(move |_, ()| with_camera(world, entity, |camera| camera.follow = None))?)?;
+    table.set("setBounds", scope.create_function(move |_, (min_x, min_y, max_x, max_y): (f32, f32, f32, f32)|{
+        with_camera(world, entity, |camera| camera.bounds = Some(Bounds { min_x, min_y, max_x, max_y }))
+    })?)?;
+    table.set("clearBounds", scope.create_function(move |_, ()| with_camera(world, entity, |camera| camera.bounds = None))?)?;
+    table.set("shake", scope.create_function(move |_, (intensity, duration): (f32, f32)|{
+        with_camera(world, entity, |camera| camera.shake(intensity, duration))
+    })?)?;
+    table.set("isShaking", scope.create_function(move |_, ()| with_camera(world, entity, |camera| camera.is_shaking()))?)?;
+
+    Ok(table)
+}
+
+/// Functions of the `camera` table, for editor autocompletion.
+pub const CAMERA_API: &[&str] = &["getPosition", "setPosition", "getZoom", "setZoom", "getRotation", "setRotation", "follow", "unfollow", "setBounds", "clearBounds", "shake", "isShaking"];
+
+/// The script's own camera if its object has one, the main camera otherwise.
+fn script_camera(world: &World, entity: Entity) -> LuaResult<Entity>{
+    if world.satisfies::<&Camera2D>(entity).unwrap_or(false){
+        return Ok(entity);
+    }
+    main_camera(world).ok_or_else(|| LuaError::external("there is no camera"))
+}
+
+fn with_camera<R>(world: &World, entity: Entity, f: impl FnOnce(&mut Camera2D) -> R) -> LuaResult<R>{
+    let entity = script_camera(world, entity)?;
+    let mut camera = world.get::<&mut Camera2D>(entity).map_err(LuaError::external)?;
+    Ok(f(&mut camera))
+}
+
+fn camera_transform(world: &World, entity: Entity) -> LuaResult<TransformComponent>{
+    let entity = script_camera(world, entity)?;
+    let transform = world.get::<&TransformComponent>(entity).map_err(|_| LuaError::external("the camera has no transform"))?;
+    Ok((*transform).clone())
+}
+
+fn deadzone_axis(position: f32, target: f32, deadzone: f32) -> f32{
+    let delta = target - position;
+    if delta > deadzone{
+        target - deadzone
+    } else if delta < -deadzone{
+        target + deadzone
+    } else{
+        position
+    }
+}
+
+fn clamp_axis(position: f32, half_extent: f32, min: f32, max: f32) -> f32{
+    if max - min <= half_extent * 2.0{
+        (min + max) * 0.5
+    } else{
+        position.clamp(min + half_extent, max - half_extent)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn follows_only_outside_the_deadzone(){
+        let mut world = World::new();
+        let target = world.spawn(());
+        let mut camera = Camera2D::new();
+        camera.follow = Some(Follow { deadzone: (1.0, 1.0), ..Follow::new(target) });
+
+        assert_eq!(camera.step((0.0, 0.0), Some((0.5, -0.5)), 1.0, 0.1), (0.0, 0.0));
+        assert_eq!(camera.step((0.0, 0.0), Some((3.0, -2.0)), 1.0, 0.1), (2.0, -1.0));
+    }
+
+    #[test]
+    fn clamps_to_bounds_or_centers(){
+        let mut camera = Camera2D::new();
+        camera.bounds = Some(Bounds { min_x: -10.0, min_y: -10.0, max_x: 10.0, max_y: 10.0 });
+        // Half extents at aspect 2: (2, 1).
+        assert_eq!(camera.step((20.0, -20.0), None, 2.0, 0.1), (8.0, -9.0));
+
+        camera.zoom = 0.05;
+        assert_eq!(camera.step((5.0, 5.0), None, 1.0, 0.1), (0.0, 0.0));
+    }
+
+    #[test]
+    fn shake_fades_out(){
+        let mut camera = Camera2D::new();
+        camera.shake(1.0, 0.5);
+        assert!(camera.is_shaking());
+        for _ in 0..10{
+            let (x, y) = camera.update_shake(0.1);
+            assert!(x.abs() <= 1.0 && y.abs() <= 1.0);
+        }
+        assert!(!camera.is_shaking());
+        assert_eq!(camera.update_shake(0.1), (0.0, 0.0));
+    }
+}
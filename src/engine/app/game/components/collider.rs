@@ -0,0 +1,131 @@
+use hecs::{Entity, World};
+use mlua::{prelude::*, Scope};
+
+use super::{Label, Tilemap, TransformComponent};
+
+/// An axis-aligned box centered on the entity's `Transform`, in world units.
+/// Nothing resolves collisions yet; game code and scripts can test boxes against each other.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Collider{
+    pub width: f32,
+    pub height: f32
+}
+
+impl Collider{
+    pub fn new(width: f32, height: f32) -> Self{
+        Self { width, height }
+    }
+
+    /// True if this box at `position` overlaps `other` at `other_position`.
+    pub fn overlaps(&self, position: (f32, f32), other: &Collider, other_position: (f32, f32)) -> bool{
+        (position.0 - other_position.0).abs() * 2.0 < self.width + other.width
+            && (position.1 - other_position.1).abs() * 2.0 < self.height + other.height
+    }
+}
+
+/// The other entities whose colliders overlap the collider of `entity`.
+pub fn overlapping(world: &World, entity: Entity) -> Vec<Entity>{
+    let Some((collider, position)) = placed_collider(world, entity) else{
+        return Vec::new();
+    };
+    world.query::<&Collider>().iter()
+        .filter(|(other, _)| *other != entity)
+        .filter_map(|(other, other_collider)| Some((other, *other_collider, position_of(world, other)?)))
+        .filter(|(_, other_collider, other_position)| collider.overlaps(position, other_collider, *other_position))
+        .map(|(other, _, _)| other)
+        .collect()
+}
+
+/// True if the collider of `entity` touches a solid tile of any tilemap.
+pub fn touches_solid(world: &World, entity: Entity) -> bool{
+    let Some((collider, (x, y))) = placed_collider(world, entity) else{
+        return false;
+    };
+    let (half_width, half_height) = (collider.width * 0.5, collider.height * 0.5);
+    world.query::<&Tilemap>().iter().any(|(tilemap_entity, tilemap)|{
+        let origin = position_of(world, tilemap_entity).unwrap_or((0.0, 0.0));
+        tilemap.overlaps_solid(x - half_width - origin.0, y - half_height - origin.1, x + half_width - origin.0, y + half_height - origin.1)
+    })
+}
+
+fn placed_collider(world: &World, entity: Entity) -> Option<(Collider, (f32, f32))>{
+    let collider = *world.get::<&Collider>(entity).ok()?;
+    Some((collider, position_of(world, entity)?))
+}
+
+fn position_of(world: &World, entity: Entity) -> Option<(f32, f32)>{
+    let transform = world.get::<&TransformComponent>(entity).ok()?;
+    let transform = transform.lock().unwrap();
+    Some((transform.position.x, transform.position.y))
+}
+
+/// Builds the `collider` table for the script's own collider: `getSize()`, `setSize(width, height)`,
+/// `overlapping()`, the labels of the objects whose colliders overlap it, and `touchesSolid()`.
+pub fn create_lua_table<'scope, 'env>(lua: &Lua, scope: &'scope Scope<'scope, 'env>, world: &'env World, entity: Entity) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+
+    table.set("getSize", scope.create_function(move |_, ()| with_collider(world, entity, |collider| (collider.width, collider.height)))?)?;
+    table.set("setSize", scope.create_function(move |_, (width, height): (f32, f32)|{
+        with_collider(world, entity, |collider| *collider = Collider::new(width.max(0.0), height.max(0.0)))
+    })?)?;
+    table.set("overlapping", scope.create_function(move |_, ()|{
+        with_collider(world, entity, |_| ())?;
+        Ok(overlapping(world, entity).into_iter()
+            .filter_map(|other| world.get::<&Label>(other).ok().map(|label| label.label.clone()))
+            .collect::<Vec<_>>())
+    })?)?;
+    table.set("touchesSolid", scope.create_function(move |_, ()|{
+        with_collider(world, entity, |_| ())?;
+        Ok(touches_solid(world, entity))
+    })?)?;
+
+    Ok(table)
+}
+
+/// Names of the `collider` table's functions, for completion.
+pub const COLLIDER_API: &[&str] = &["getSize", "setSize", "overlapping", "touchesSolid"];
+
+fn with_collider<R>(world: &World, entity: Entity, f: impl FnOnce(&mut Collider) -> R) -> LuaResult<R>{
+    let mut collider = world.get::<&mut Collider>(entity).map_err(|_| LuaError::external("this object has no collider"))?;
+    Ok(f(&mut collider))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::{assets::Handle, game::components::{Tile, Tileset, Transform}};
+
+    #[test]
+    fn overlaps_only_when_the_boxes_intersect(){
+        let a = Collider::new(2.0, 1.0);
+        let b = Collider::new(1.0, 1.0);
+        assert!(a.overlaps((0.0, 0.0), &b, (1.4, 0.9)));
+        assert!(!a.overlaps((0.0, 0.0), &b, (1.5, 0.0)));
+        assert!(!a.overlaps((0.0, 0.0), &b, (0.0, -1.0)));
+        assert!(b.overlaps((1.4, 0.9), &a, (0.0, 0.0)));
+    }
+
+    #[test]
+    fn finds_overlapping_objects_and_solid_tiles(){
+        let mut world = World::new();
+        let player = world.spawn((Collider::new(1.0, 1.0), Transform::new(0.0, 0.0, 0.0), Label::from_str("player")));
+        world.spawn((Collider::new(1.0, 1.0), Transform::new(0.5, 0.5, 0.0), Label::from_str("coin")));
+        world.spawn((Collider::new(1.0, 1.0), Transform::new(3.0, 0.0, 0.0), Label::from_str("door")));
+        let mut ground = Tilemap::new(Tileset::new(Handle::pending("tiles"), 16, 16, 4), 4, 1, 1.0);
+        ground.set(0, 3, 0, Some(Tile::solid(0)));
+        world.spawn((ground, Transform::new(-2.0, -0.5, 0.0)));
+
+        let lua = Lua::new();
+        let (names, solid): (Vec<String>, bool) = lua.scope(|scope|{
+            lua.globals().set("collider", create_lua_table(&lua, scope, &world, player)?)?;
+            lua.load("return collider.overlapping(), collider.touchesSolid()").eval()
+        }).unwrap();
+        assert_eq!(names, vec!["coin".to_string()]);
+        assert!(!solid);
+
+        // The solid tile spans x 1 to 2 and y -0.5 to -1.5.
+        world.get::<&mut Collider>(player).unwrap().height = 1.2;
+        world.get::<&mut Collider>(player).unwrap().width = 2.2;
+        assert!(touches_solid(&world, player));
+    }
+}
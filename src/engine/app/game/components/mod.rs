@@ -2,6 +2,12 @@ mod sprite;
 mod label;
 mod transform;
 mod script;
+mod audio_source;
+mod audio_listener;
+mod camera2d;
+mod tilemap;
+mod collider;
+mod particles;
 
 pub use sprite::Sprite;
 pub use label::Label;
@@ -9,3 +15,10 @@ pub use transform::Transform;
 pub use transform::TransformComponent;
 pub use script::Script;
 pub use script::ScriptState;
+pub use script::ENTITY_TABLES;
+pub use audio_source::{AudioSource, AudioSourceVoices};
+pub use audio_listener::AudioListener;
+pub use camera2d::{Bounds, Camera2D, Follow, Viewport, ALL_LAYERS};
+pub use tilemap::{Tile, TileFlags, TileQuad, Tilemap, Tileset};
+pub use collider::Collider;
+pub use particles::{Burst, Curve, ParticleBlend, ParticleEmitter};
@@ -0,0 +1,397 @@
+use std::f32::consts::PI;
+
+use hecs::{Entity, World};
+use mlua::{prelude::*, Scope};
+
+use crate::engine::app::{assets::Handle, renderer::texture::Texture};
+
+/// How particles are blended over what's behind them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ParticleBlend{
+    #[default]
+    Alpha,
+    /// Adds the color, for fire, sparks and glows.
+    Additive
+}
+
+/// Values that can be blended along a `Curve`.
+pub trait Lerp: Copy{
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32{
+    fn lerp(self, other: f32, t: f32) -> f32{
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for [f32; 4]{
+    fn lerp(self, other: [f32; 4], t: f32) -> [f32; 4]{
+        std::array::from_fn(|i| self[i].lerp(other[i], t))
+    }
+}
+
+/// A value over a particle's life, from 0 at birth to 1 at death, linear between the keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T>{
+    /// Sorted by time.
+    pub keys: Vec<(f32, T)>
+}
+
+impl<T: Lerp> Curve<T>{
+    pub fn constant(value: T) -> Self{
+        Self { keys: vec![(0.0, value)] }
+    }
+
+    pub fn linear(start: T, end: T) -> Self{
+        Self { keys: vec![(0.0, start), (1.0, end)] }
+    }
+
+    pub fn sample(&self, t: f32) -> Option<T>{
+        let first = self.keys.first()?;
+        if t <= first.0{
+            return Some(first.1);
+        }
+        for pair in self.keys.windows(2){
+            let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+            if t <= t1{
+                let span = t1 - t0;
+                return Some(if span > 0.0 { v0.lerp(v1, (t - t0) / span) } else { v1 });
+            }
+        }
+        self.keys.last().map(|key| key.1)
+    }
+}
+
+/// Emits `count` particles at `time` seconds after the emitter started, then every `interval` if set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Burst{
+    pub time: f32,
+    pub count: u32,
+    pub interval: Option<f32>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle{
+    pub position: (f32, f32),
+    previous_position: (f32, f32),
+    pub velocity: (f32, f32),
+    pub age: f32,
+    pub lifetime: f32
+}
+
+impl Particle{
+    /// Position blended between the last two simulation steps, like `Transform::interpolated_position`.
+    pub fn interpolated_position(&self, alpha: f32) -> (f32, f32){
+        (self.previous_position.0.lerp(self.position.0, alpha), self.previous_position.1.lerp(self.position.1, alpha))
+    }
+
+    /// How far through its life the particle is, 0 to 1.
+    pub fn life(&self) -> f32{
+        (self.age / self.lifetime).min(1.0)
+    }
+}
+
+/// Spawns particles at the entity's `Transform` and simulates them in world space every fixed step.
+/// Given the same seed and steps, an emitter always produces the same particles.
+#[derive(Clone)]
+pub struct ParticleEmitter{
+    pub texture: Handle<Texture>,
+    /// The part of the texture each particle shows, left, top, right, bottom from 0 to 1, e.g. from `Tileset::uv`.
+    pub region: [f32; 4],
+    pub blend: ParticleBlend,
+    /// Camera layer, like `Sprite::layer`.
+    pub layer: u32,
+    pub emitting: bool,
+    /// Particles per second while emitting.
+    pub rate: f32,
+    pub bursts: Vec<Burst>,
+    /// Seconds, picked between the two for each particle.
+    pub lifetime: (f32, f32),
+    /// World units per second.
+    pub speed: (f32, f32),
+    /// Radians, 0 is right, counterclockwise.
+    pub direction: f32,
+    /// Half the angle of the cone particles start moving in.
+    pub spread: f32,
+    /// World units per second squared.
+    pub gravity: (f32, f32),
+    /// Straight alpha RGBA.
+    pub color: Curve<[f32; 4]>,
+    /// World units.
+    pub size: Curve<f32>,
+    /// Radians.
+    pub rotation: Curve<f32>,
+    pub max_particles: usize,
+    seed: u64,
+    rng: u64,
+    time: f32,
+    to_emit: f32,
+    particles: Vec<Particle>
+}
+
+impl ParticleEmitter{
+    pub fn new(texture: Handle<Texture>) -> Self{
+        let mut emitter = Self {
+            texture,
+            region: [0.0, 0.0, 1.0, 1.0],
+            blend: ParticleBlend::Alpha,
+            layer: 0,
+            emitting: true,
+            rate: 10.0,
+            bursts: Vec::new(),
+            lifetime: (1.0, 1.0),
+            speed: (1.0, 1.0),
+            direction: PI / 2.0,
+            spread: PI / 8.0,
+            gravity: (0.0, 0.0),
+            color: Curve::linear([1.0; 4], [1.0, 1.0, 1.0, 0.0]),
+            size: Curve::constant(0.2),
+            rotation: Curve::constant(0.0),
+            max_particles: 1000,
+            seed: 0,
+            rng: 0,
+            time: 0.0,
+            to_emit: 0.0,
+            particles: Vec::new()
+        };
+        emitter.restart(1);
+        emitter
+    }
+
+    /// Clears the particles and starts over with `seed`.
+    pub fn restart(&mut self, seed: u64){
+        self.seed = seed;
+        self.rng = seed;
+        self.time = 0.0;
+        self.to_emit = 0.0;
+        self.particles.clear();
+    }
+
+    pub fn seed(&self) -> u64{
+        self.seed
+    }
+
+    pub fn particles(&self) -> &[Particle]{
+        &self.particles
+    }
+
+    /// Emits `count` particles at once, even when not emitting.
+    pub fn burst(&mut self, origin: (f32, f32), count: u32){
+        for _ in 0..count{
+            self.spawn(origin);
+        }
+    }
+
+    /// Ages, moves and emits particles; `origin` is where new ones start.
+    pub fn step(&mut self, origin: (f32, f32), dt: f32){
+        for particle in &mut self.particles{
+            particle.previous_position = particle.position;
+            particle.age += dt;
+            particle.velocity.0 += self.gravity.0 * dt;
+            particle.velocity.1 += self.gravity.1 * dt;
+            particle.position.0 += particle.velocity.0 * dt;
+            particle.position.1 += particle.velocity.1 * dt;
+        }
+        self.particles.retain(|particle| particle.age < particle.lifetime);
+
+        if !self.emitting{
+            return;
+        }
+        let (start, end) = (self.time, self.time + dt);
+        self.time = end;
+        let mut count = 0;
+        for burst in &self.bursts{
+            count += burst.count * burst_repeats(burst, start, end);
+        }
+        self.to_emit += self.rate.max(0.0) * dt;
+        count += self.to_emit as u32;
+        self.to_emit = self.to_emit.fract();
+        self.burst(origin, count);
+    }
+
+    fn spawn(&mut self, origin: (f32, f32)){
+        if self.particles.len() >= self.max_particles{
+            return;
+        }
+        let lifetime = self.random_between(self.lifetime).max(1e-3);
+        let speed = self.random_between(self.speed);
+        let angle = self.direction + self.random_between((-self.spread, self.spread));
+        self.particles.push(Particle {
+            position: origin,
+            previous_position: origin,
+            velocity: (angle.cos() * speed, angle.sin() * speed),
+            age: 0.0,
+            lifetime
+        });
+    }
+
+    /// SplitMix64, so the sequence only depends on the seed.
+    fn random(&mut self) -> f32{
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn random_between(&mut self, (min, max): (f32, f32)) -> f32{
+        min + (max - min) * self.random()
+    }
+}
+
+/// How many times a burst fires between `start` (exclusive, except at 0) and `end` (inclusive).
+fn burst_repeats(burst: &Burst, start: f32, end: f32) -> u32{
+    let fires_by = |time: f32| -> i64{
+        if time < burst.time{
+            return 0;
+        }
+        match burst.interval{
+            Some(interval) if interval > 0.0 => ((time - burst.time) / interval).floor() as i64 + 1,
+            _ => 1
+        }
+    };
+    let before = if start > 0.0 || burst.time > 0.0 { fires_by(start) } else { 0 };
+    (fires_by(end) - before).max(0) as u32
+}
+
+/// Builds the `particles` table for the script's own emitter: `start()`, `stop()`, `burst(count)`,
+/// `restart(seed?)`, `count()`, `setRate(rate)`, `setLifetime(min, max?)`, `setSpeed(min, max?)`,
+/// `setDirection(angle, spread?)`, `setGravity(x, y)`, `setColor(r, g, b, a, r2?, g2?, b2?, a2?)`,
+/// `setSize(start, end?)`, `setRotation(start, end?)`, `addBurst(time, count, interval?)`,
+/// `setRegion(left, top, right, bottom)` and `setBlend("alpha" | "additive")`.
+pub fn create_lua_table<'scope, 'env>(lua: &Lua, scope: &'scope Scope<'scope, 'env>, world: &'env World, entity: Entity) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+    let origin = move || -> (f32, f32){
+        world.get::<&super::TransformComponent>(entity).map_or((0.0, 0.0), |transform|{
+            let transform = transform.lock().unwrap();
+            (transform.position.x, transform.position.y)
+        })
+    };
+
+    table.set("start", scope.create_function(move |_, ()| with_emitter(world, entity, |emitter| emitter.emitting = true))?)?;
+    table.set("stop", scope.create_function(move |_, ()| with_emitter(world, entity, |emitter| emitter.emitting = false))?)?;
+    table.set("burst", scope.create_function(move |_, count: u32|{
+        let origin = origin();
+        with_emitter(world, entity, |emitter| emitter.burst(origin, count))
+    })?)?;
+    table.set("restart", scope.create_function(move |_, seed: Option<u64>|{
+        with_emitter(world, entity, |emitter| emitter.restart(seed.unwrap_or(emitter.seed)))
+    })?)?;
+    table.set("count", scope.create_function(move |_, ()| with_emitter(world, entity, |emitter| emitter.particles.len()))?)?;
+    table.set("setRate", scope.create_function(move |_, rate: f32| with_emitter(world, entity, |emitter| emitter.rate = rate.max(0.0)))?)?;
+    table.set("setLifetime", scope.create_function(move |_, (min, max): (f32, Option<f32>)|{
+        with_emitter(world, entity, |emitter| emitter.lifetime = (min, max.unwrap_or(min)))
+    })?)?;
+    table.set("setSpeed", scope.create_function(move |_, (min, max): (f32, Option<f32>)|{
+        with_emitter(world, entity, |emitter| emitter.speed = (min, max.unwrap_or(min)))
+    })?)?;
+    table.set("setDirection", scope.create_function(move |_, (angle, spread): (f32, Option<f32>)|{
+        with_emitter(world, entity, |emitter|{
+            emitter.direction = angle;
+            if let Some(spread) = spread{
+                emitter.spread = spread;
+            }
+        })
+    })?)?;
+    table.set("setGravity", scope.create_function(move |_, (x, y): (f32, f32)| with_emitter(world, entity, |emitter| emitter.gravity = (x, y)))?)?;
+    table.set("setColor", scope.create_function(move |_, (r, g, b, a, end): (f32, f32, f32, f32, LuaMultiValue)|{
+        let start = [r, g, b, a];
+        let end: Vec<f32> = end.into_iter().filter_map(|value| value.as_f32()).collect();
+        let curve = match end[..]{
+            [r, g, b, a] => Curve::linear(start, [r, g, b, a]),
+            [] => Curve::constant(start),
+            _ => return Err(LuaError::external("setColor takes 4 or 8 numbers"))
+        };
+        with_emitter(world, entity, |emitter| emitter.color = curve)
+    })?)?;
+    table.set("setSize", scope.create_function(move |_, (start, end): (f32, Option<f32>)|{
+        with_emitter(world, entity, |emitter| emitter.size = Curve::linear(start, end.unwrap_or(start)))
+    })?)?;
+    table.set("setRotation", scope.create_function(move |_, (start, end): (f32, Option<f32>)|{
+        with_emitter(world, entity, |emitter| emitter.rotation = Curve::linear(start, end.unwrap_or(start)))
+    })?)?;
+    table.set("addBurst", scope.create_function(move |_, (time, count, interval): (f32, u32, Option<f32>)|{
+        with_emitter(world, entity, |emitter| emitter.bursts.push(Burst { time, count, interval }))
+    })?)?;
+    table.set("setRegion", scope.create_function(move |_, (left, top, right, bottom): (f32, f32, f32, f32)|{
+        with_emitter(world, entity, |emitter| emitter.region = [left, top, right, bottom])
+    })?)?;
+    table.set("setBlend", scope.create_function(move |_, blend: String|{
+        let blend = match blend.as_str(){
+            "alpha" => ParticleBlend::Alpha,
+            "additive" => ParticleBlend::Additive,
+            other => return Err(LuaError::external(format!("unknown blend mode '{}', use 'alpha' or 'additive'", other)))
+        };
+        with_emitter(world, entity, |emitter| emitter.blend = blend)
+    })?)?;
+
+    Ok(table)
+}
+
+/// Names of the `particles` table's functions, for completion.
+pub const PARTICLES_API: &[&str] = &["start", "stop", "burst", "restart", "count", "setRate", "setLifetime", "setSpeed", "setDirection", "setGravity", "setColor", "setSize", "setRotation", "addBurst", "setRegion", "setBlend"];
+
+fn with_emitter<R>(world: &World, entity: Entity, f: impl FnOnce(&mut ParticleEmitter) -> R) -> LuaResult<R>{
+    let mut emitter = world.get::<&mut ParticleEmitter>(entity).map_err(|_| LuaError::external("this object has no particle emitter"))?;
+    Ok(f(&mut emitter))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn emitter(seed: u64) -> ParticleEmitter{
+        let mut emitter = ParticleEmitter::new(Handle::pending("spark.png"));
+        emitter.rate = 30.0;
+        emitter.lifetime = (0.5, 1.5);
+        emitter.speed = (1.0, 3.0);
+        emitter.gravity = (0.0, -9.8);
+        emitter.bursts.push(Burst { time: 0.0, count: 5, interval: Some(0.5) });
+        emitter.restart(seed);
+        emitter
+    }
+
+    fn run(emitter: &mut ParticleEmitter, steps: usize) -> Vec<Particle>{
+        for _ in 0..steps{
+            emitter.step((1.0, 2.0), 1.0 / 60.0);
+        }
+        emitter.particles().to_vec()
+    }
+
+    #[test]
+    fn same_seed_same_particles(){
+        let first = run(&mut emitter(42), 90);
+        assert!(!first.is_empty());
+        assert_eq!(first, run(&mut emitter(42), 90));
+        assert_ne!(first, run(&mut emitter(7), 90));
+
+        // Restarting replays the same sequence.
+        let mut replayed = emitter(42);
+        run(&mut replayed, 30);
+        replayed.restart(42);
+        assert_eq!(first, run(&mut replayed, 90));
+    }
+
+    #[test]
+    fn emits_at_the_rate_plus_bursts(){
+        let mut emitter = emitter(1);
+        emitter.lifetime = (10.0, 10.0);
+        // One second in exact steps: 30 from the rate and bursts at 0, 0.5 and 1.0.
+        for _ in 0..4{
+            emitter.step((0.0, 0.0), 0.25);
+        }
+        assert_eq!(emitter.particles().len(), 45);
+    }
+
+    #[test]
+    fn samples_curves(){
+        let curve = Curve { keys: vec![(0.0, 1.0), (0.5, 3.0), (1.0, 0.0)] };
+        assert_eq!(curve.sample(-1.0), Some(1.0));
+        assert_eq!(curve.sample(0.25), Some(2.0));
+        assert_eq!(curve.sample(0.75), Some(1.5));
+        assert_eq!(curve.sample(2.0), Some(0.0));
+        assert_eq!(Curve::<f32> { keys: Vec::new() }.sample(0.5), None);
+    }
+}
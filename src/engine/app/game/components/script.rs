@@ -1,66 +1,154 @@
-use std::{env, fs, path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc};
 
 use mlua::prelude::*;
 use hecs::{Entity, World};
 
-use crate::engine::app::game::components::{script, transform::TransformComponent};
+use crate::engine::app::game::components::{audio_source, camera2d, collider, particles, sprite, tilemap, transform::TransformComponent};
+use crate::engine::app::assets::{loaders::ScriptSource, Handle, LoadState};
+use crate::engine::app::console;
+use crate::engine::app::game::coroutines::{self, SharedCoroutines};
+use crate::engine::app::game::script_error::ScriptError;
+use crate::engine::app::game::timers::{self, SharedTimers};
+use crate::engine::app::audio::{self, AudioManager};
+use crate::engine::app::renderer::screen::{self, SharedScreenSpace};
+use crate::engine::app::vfs;
+use crate::engine::app::time::Time;
 
 use log::{error};
 
+/// Functions of the `gameObject` table, for editor autocompletion.
+pub const GAME_OBJECT_API: &[&str] = &["getPosition", "setPosition"];
+
+/// The tables `run` binds for the script's entity with the names of their functions,
+/// for autocompletion in the editor and the REPL.
+pub const ENTITY_TABLES: &[(&str, &[&str])] = &[
+    ("gameObject", GAME_OBJECT_API),
+    ("camera", camera2d::CAMERA_API),
+    ("sprite", sprite::SPRITE_API),
+    ("tilemap", tilemap::TILEMAP_API),
+    ("collider", collider::COLLIDER_API),
+    ("particles", particles::PARTICLES_API),
+    ("audioSource", audio_source::AUDIO_SOURCE_API)
+];
+
 pub enum ScriptState{
     Ok,
-    Err(String)
+    Err(ScriptError)
+}
+
+/// What the script was doing when it failed. Each phase keeps its own error,
+/// which only goes away once that phase succeeds again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase{
+    Load,
+    Timers,
+    Coroutines,
+    Hook(&'static str)
 }
 
 pub struct Script{
-    path: PathBuf,
+    /// VFS path of the source, also used as the Lua chunk name in errors.
+    name: String,
+    source: Handle<ScriptSource>,
+    /// The source last run, to notice when the asset is reloaded.
+    loaded: Option<Arc<ScriptSource>>,
     script: String,
+    /// The error to show: the one of the last load, or else the first of the others.
     pub state: ScriptState,
+    errors: Vec<(Phase, ScriptError)>,
     pub lua: Arc<Lua>,
+    timers: SharedTimers,
+    coroutines: SharedCoroutines
 }
 
 impl Script{
-    pub fn new(path: String) -> Self{
-         let exe_path = env::current_exe().unwrap();
-    
-        // Get the directory containing the executable
-        let exe_dir = exe_path.parent()
-            .ok_or_else(|| std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Executable has no parent directory"
-            )).unwrap();
-        
-        // Construct path to your file relative to the executable
-        let file_path = exe_dir.join(path);
-        let script = fs::read_to_string(&file_path).unwrap();
+    /// Runs the script once its source is loaded. A missing file or a broken script doesn't panic,
+    /// the error is kept in `state` and the script runs again when the asset is reloaded.
+    pub fn new(source: Handle<ScriptSource>) -> Self{
+        let path = source.path().to_string();
         let lua = Lua::new();
-        match lua.load(script.clone()).exec(){
-            Ok(()) => {},
-            Err(e) => {
-                error!("{:?}", e);
+        let timers = SharedTimers::default();
+        let time_table = timers::create_time_table(&lua, &timers).unwrap();
+        lua.globals().set("time", time_table).unwrap();
+        let coroutines = SharedCoroutines::default();
+        coroutines::register(&lua, &coroutines).unwrap();
+
+        let print_name = path.clone();
+        let print_func = lua.create_function(move |_, args: LuaMultiValue|{
+            let parts: Vec<String> = args.iter().map(|value| value.to_string().unwrap_or_default()).collect();
+            console::print(&print_name, parts.join("\t"));
+            Ok(())
+        }).unwrap();
+        lua.globals().set("print", print_func).unwrap();
+
+        let mut script = Self {name: path, source, loaded: None, script: String::new(), state: ScriptState::Ok, errors: Vec::new(), lua: Arc::new(lua), timers, coroutines };
+        script.sync_source();
+        script
+    }
+
+    /// The file on disk the script is loaded from, if it isn't in an archive.
+    pub fn path(&self) -> Option<PathBuf>{
+        vfs::real_path(&self.name)
+    }
+
+    /// Runs the source again if the asset finished loading or was reloaded since the last call.
+    /// A failed load is stored in `state` instead of panicking.
+    pub fn sync_source(&mut self){
+        match self.source.state(){
+            LoadState::Pending => {},
+            LoadState::Failed(e) => self.set_error(Phase::Load, ScriptError::new(&self.name, e)),
+            LoadState::Ready => {
+                let Some(source) = self.source.get() else{
+                    return;
+                };
+                if self.loaded.as_ref().is_some_and(|loaded| Arc::ptr_eq(loaded, &source)){
+                    return;
+                }
+                self.load(source.source.clone());
+                self.loaded = Some(source);
             }
         }
-        Self {path: file_path, script: script, state: ScriptState::Ok, lua: Arc::new(lua) }
     }
 
-    pub fn reload(&mut self){
-        let script = fs::read_to_string(&self.path).unwrap();
-        self.set_script(script);
+    pub fn set_script(&mut self, script: String){
+        self.load(script);
+        if let Err(e) = vfs::write(&self.name, self.script.as_bytes()){
+            error!("Could not save {}: {}", self.name, e);
+        }
     }
 
-    pub fn set_script(&mut self, script: String){
+    /// Executes new source in the existing Lua state.
+    /// The global `state` table survives the reload: the old table is kept
+    /// and only gains keys that the new source introduces.
+    fn load(&mut self, script: String){
         self.script = script.clone();
-        match self.lua.load(script.clone()).exec(){
+        self.timers.lock().unwrap().clear();
+        self.coroutines.lock().unwrap().clear();
+
+        let globals = self.lua.globals();
+        let saved_state = globals.get::<Option<LuaTable>>("state").ok().flatten();
+
+        match self.lua.load(script).set_name(format!("@{}", self.name)).exec(){
             Ok(()) => {
-                self.state = ScriptState::Ok;
+                // The hooks are new, so are their errors.
+                self.errors.clear();
+                self.update_state();
             },
             Err(e) => {
-                error!("{:?}", e);
-                self.state = ScriptState::Err(e.to_string());
+                self.set_error(Phase::Load, ScriptError::from_lua(&self.name, &self.script, &e));
             }
         }
 
-        fs::write(&self.path, self.script.clone()).unwrap();
+        if let Some(saved_state) = saved_state{
+            if let Ok(Some(new_state)) = globals.get::<Option<LuaTable>>("state"){
+                for (key, value) in new_state.pairs::<LuaValue, LuaValue>().flatten(){
+                    if !saved_state.contains_key(key.clone()).unwrap_or(true){
+                        let _ = saved_state.set(key, value);
+                    }
+                }
+            }
+            let _ = globals.set("state", saved_state);
+        }
     }
 
     pub fn get_script(&self) -> String{
@@ -68,46 +156,218 @@ impl Script{
     }
 
     pub fn update(&mut self, dt:f32, world: &World, entity: &Entity){
-        let result = self.lua.scope(|scope|{
+        self.call_hook("update", true, dt, world, entity);
+    }
+
+    /// Runs the optional `fixed_update(dt)` function at the fixed simulation rate.
+    pub fn fixed_update(&mut self, dt:f32, world: &World, entity: &Entity){
+        self.call_hook("fixed_update", false, dt, world, entity);
+    }
+
+    /// Gives the script the `audio` table, once.
+    pub fn bind_audio(&self, audio: &AudioManager){
+        if let Err(e) = audio::lua::register(&self.lua, audio){
+            log::error!("{}: could not register audio: {}", self.name, e);
+        }
+    }
+
+    /// Gives the script the `screen` table, once.
+    pub fn bind_screen(&self, screen: &SharedScreenSpace){
+        if let Err(e) = screen::register(&self.lua, screen){
+            log::error!("{}: could not register screen: {}", self.name, e);
+        }
+    }
+
+    /// Copies the frame timing into the script's `time` table.
+    pub fn sync_time(&self, time: &Time){
+        if let Ok(table) = self.lua.globals().get::<LuaTable>("time"){
+            let _ = table.set("delta", time.delta());
+            let _ = table.set("unscaledDelta", time.unscaled_delta());
+            let _ = table.set("fixedDelta", time.fixed_delta());
+            let _ = table.set("elapsed", time.elapsed());
+            let _ = table.set("unscaledElapsed", time.unscaled_elapsed());
+            let _ = table.set("frame", time.frame_count());
+            let _ = table.set("scale", time.time_scale());
+        }
+    }
+
+    /// Fires `time.after`/`time.every` callbacks that became due during `dt`.
+    pub fn tick_timers(&mut self, dt: f32, world: &World, entity: &Entity){
+        let due = {
+            let mut timers = self.timers.lock().unwrap();
+            if timers.is_empty(){
+                return;
+            }
+            timers.tick(dt)
+        };
+        if due.is_empty(){
+            return;
+        }
+
+        self.with_game_object(Phase::Timers, world, entity, |_|{
+            for callback in due{
+                callback.call::<()>(())?;
+            }
+            Ok(())
+        });
+    }
+
+    /// Resumes coroutines started with `start_coroutine` whose wait is over.
+    pub fn resume_coroutines(&mut self, dt: f32, world: &World, entity: &Entity){
+        if self.coroutines.lock().unwrap().is_empty(){
+            return;
+        }
+
+        let coroutines = self.coroutines.clone();
+        self.with_game_object(Phase::Coroutines, world, entity, |_| coroutines::resume_all(&coroutines, dt));
+    }
+
+    fn call_hook(&mut self, name: &'static str, required: bool, dt:f32, world: &World, entity: &Entity){
+        // Nothing to call until the source has run once, and nothing of a source that didn't.
+        if self.loaded.is_none() || self.load_failed(){
+            return;
+        }
+        if !required && !matches!(self.lua.globals().get::<mlua::Value>(name), Ok(mlua::Value::Function(_))){
+            return;
+        }
+
+        self.with_game_object(Phase::Hook(name), world, entity, |lua|{
+            let func = lua.globals().get::<mlua::Function>(name)?;
+            func.call::<()>(dt)
+        });
+    }
+
+    /// Runs `f` with the `gameObject` table bound to `entity` and records the outcome of `phase`.
+    fn with_game_object<F>(&mut self, phase: Phase, world: &World, entity: &Entity, f: F)
+        where F: FnOnce(&Lua) -> LuaResult<()>
+    {
+        match self.run(world, entity, f){
+            Ok(()) => self.clear_error(phase),
+            Err(e) => self.set_error(phase, ScriptError::from_lua(&self.name, &self.script, &e))
+        }
+    }
+
+    /// Runs `f` inside the script's Lua state with `gameObject` bound to `entity`.
+    /// Unlike the update hooks this leaves `state` alone, which suits tools like the REPL.
+    pub fn run<F, R>(&self, world: &World, entity: &Entity, f: F) -> LuaResult<R>
+        where F: FnOnce(&Lua) -> LuaResult<R>
+    {
+        self.lua.scope(|scope|{
             let game_object_table = self.lua.create_table().unwrap();
-            match world.get::<&TransformComponent>(*entity){
-                Ok(transform_arc) => {
-                    let transform_arc_clone = transform_arc.clone();
-                    let get_position_func = scope.create_function( move |_,()|{
-                        let transform = transform_arc_clone.lock().unwrap();
-                        Ok((transform.position.x, transform.position.y))
-                    }).unwrap();
-                    game_object_table.set("getPosition", get_position_func).unwrap();
-
-                    let transform_arc_clone = transform_arc.clone();
-
-                    let set_position_func = scope.create_function( move |_,(x,y):(f32, f32)|{
-                        let mut transform = transform_arc_clone.lock().unwrap();
-                        transform.position.x = x;
-                        transform.position.y = y;
-                        Ok(())
-                    }).unwrap();
-                    game_object_table.set("setPosition", set_position_func).unwrap();
-                },
-                _ => {}
+            if let Ok(transform_arc) = world.get::<&TransformComponent>(*entity){
+                let transform_arc_clone = transform_arc.clone();
+                let get_position_func = scope.create_function( move |_,()|{
+                    let transform = transform_arc_clone.lock().unwrap();
+                    Ok((transform.position.x, transform.position.y))
+                }).unwrap();
+                game_object_table.set("getPosition", get_position_func).unwrap();
+
+                let transform_arc_clone = transform_arc.clone();
+
+                let set_position_func = scope.create_function( move |_,(x,y):(f32, f32)|{
+                    let mut transform = transform_arc_clone.lock().unwrap();
+                    transform.position.x = x;
+                    transform.position.y = y;
+                    Ok(())
+                }).unwrap();
+                game_object_table.set("setPosition", set_position_func).unwrap();
             }
 
             self.lua.globals().set("gameObject", game_object_table).unwrap();
+            self.lua.globals().set("camera", camera2d::create_lua_table(&self.lua, scope, world, *entity)?)?;
+            self.lua.globals().set("sprite", sprite::create_lua_table(&self.lua, scope, world, *entity)?)?;
+            self.lua.globals().set("tilemap", tilemap::create_lua_table(&self.lua, scope, world, *entity)?)?;
+            self.lua.globals().set("collider", collider::create_lua_table(&self.lua, scope, world, *entity)?)?;
+            self.lua.globals().set("particles", particles::create_lua_table(&self.lua, scope, world, *entity)?)?;
+            self.lua.globals().set("audioSource", audio_source::create_lua_table(&self.lua, scope, world, *entity)?)?;
 
-            let update_func = match self.lua.globals().get::<mlua::Function>("update"){
-                Ok(func)=>func,
-                Err(e) => {
-                    self.state = ScriptState::Err(e.to_string());
-                    return Err(e);
-                }
-            };
+            f(&self.lua)
+        })
+    }
 
-            update_func.call::<()>(dt)
-        });
+    /// Stores the error of `phase` and logs it once, not every frame it repeats.
+    fn set_error(&mut self, phase: Phase, e: ScriptError){
+        if self.errors.iter().any(|(other, current)| *other == phase && *current == e){
+            return;
+        }
+        error!("{}", e);
+        self.errors.retain(|(other, _)| *other != phase);
+        self.errors.push((phase, e));
+        self.update_state();
+    }
 
-        match result{
-            Ok(()) => self.state = ScriptState::Ok,
-            Err(e) => self.state = ScriptState::Err(e.to_string())
+    fn clear_error(&mut self, phase: Phase){
+        if self.errors.iter().any(|(other, _)| *other == phase){
+            self.errors.retain(|(other, _)| *other != phase);
+            self.update_state();
         }
     }
-}
\ No newline at end of file
+
+    fn load_failed(&self) -> bool{
+        self.errors.iter().any(|(phase, _)| *phase == Phase::Load)
+    }
+
+    fn update_state(&mut self){
+        let shown = self.errors.iter().find(|(phase, _)| *phase == Phase::Load).or(self.errors.first());
+        self.state = match shown{
+            Some((_, e)) => ScriptState::Err(e.clone()),
+            None => ScriptState::Ok
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::assets::Handle;
+
+    fn script(source: &str) -> Script{
+        Script::new(Handle::from_asset("scripts/test.lua", ScriptSource { source: source.to_string() }))
+    }
+
+    fn error_message(script: &Script) -> Option<String>{
+        match &script.state{
+            ScriptState::Ok => None,
+            ScriptState::Err(e) => Some(e.message.clone())
+        }
+    }
+
+    #[test]
+    fn reload_errors_stay_until_a_load_succeeds(){
+        let mut world = World::new();
+        let entity = world.spawn(());
+        let mut script = script("state = { calls = 0 }\nfunction update(dt) state.calls = state.calls + 1 end");
+        script.update(0.1, &world, &entity);
+        assert!(error_message(&script).is_none());
+
+        script.load("function update(dt".to_string());
+        let reload_error = error_message(&script).unwrap();
+        script.update(0.1, &world, &entity);
+        script.fixed_update(0.1, &world, &entity);
+        assert_eq!(error_message(&script), Some(reload_error));
+        // The hooks of the broken source don't run.
+        assert_eq!(script.lua.load("return state.calls").eval::<i32>().unwrap(), 1);
+
+        script.load("function update(dt) state.calls = state.calls + 10 end".to_string());
+        script.update(0.1, &world, &entity);
+        assert!(error_message(&script).is_none());
+        assert_eq!(script.lua.load("return state.calls").eval::<i32>().unwrap(), 11);
+    }
+
+    #[test]
+    fn a_hook_that_succeeds_keeps_the_error_of_another(){
+        let mut world = World::new();
+        let entity = world.spawn(());
+        let mut script = script("broken = true\nfunction update(dt) if broken then error('update failed') end end\nfunction fixed_update(dt) end");
+        for _ in 0..3{
+            script.update(0.1, &world, &entity);
+            script.fixed_update(0.1, &world, &entity);
+            assert!(error_message(&script).is_some_and(|message| message.contains("update failed")));
+        }
+        assert_eq!(script.errors.len(), 1);
+
+        script.lua.load("broken = false").exec().unwrap();
+        script.update(0.1, &world, &entity);
+        assert!(error_message(&script).is_none());
+    }
+}
@@ -1,13 +1,90 @@
-use std::sync::Arc;
+use hecs::{Entity, World};
+use mlua::{prelude::*, Scope};
 
-use crate::engine::app::renderer::texture::Texture;
+use crate::engine::app::{assets::Handle, renderer::{material::Material, texture::Texture}};
 
 pub struct Sprite{
-    pub texture: Arc<Texture>
+    pub texture: Handle<Texture>,
+    /// 0 to 31, cameras only draw the layers in their `layer_mask`.
+    pub layer: u32,
+    /// Drawn with this instead of the plain sprite shader once it's loaded.
+    pub material: Option<Handle<Material>>,
+    /// Material params this sprite sets differently from the `.material` file.
+    pub params: Vec<(String, [f32; 4])>
 }
 
 impl Sprite{
-    pub fn new(texture: Arc<Texture>) -> Self{
-        Self{texture}
+    pub fn new(texture: Handle<Texture>) -> Self{
+        Self{texture, layer: 0, material: None, params: Vec::new()}
     }
-}
\ No newline at end of file
+
+    pub fn with_material(texture: Handle<Texture>, material: Handle<Material>) -> Self{
+        Self{material: Some(material), ..Self::new(texture)}
+    }
+
+    pub fn is_on(&self, layer_mask: u32) -> bool{
+        layer_mask & (1 << self.layer.min(31)) != 0
+    }
+
+    /// Overrides a material param for this sprite only.
+    pub fn set_param(&mut self, name: &str, value: [f32; 4]){
+        match self.params.iter_mut().find(|(other, _)| other == name){
+            Some((_, current)) => *current = value,
+            None => self.params.push((name.to_string(), value))
+        }
+    }
+
+    /// The param as this sprite draws it: its override, else the material's.
+    pub fn param(&self, name: &str) -> Option<[f32; 4]>{
+        self.params.iter().find(|(other, _)| other == name).map(|(_, value)| *value).or_else(||{
+            let material = self.material.as_ref()?.get()?;
+            material.params().iter().find(|(other, _)| other == name).map(|(_, value)| *value)
+        })
+    }
+}
+
+/// Builds the `sprite` table for the script's own sprite: `getLayer()`, `setLayer(layer)`,
+/// `getParam(name)` and `setParam(name, x, y?, z?, w?)` for its material.
+pub fn create_lua_table<'scope, 'env>(lua: &Lua, scope: &'scope Scope<'scope, 'env>, world: &'env World, entity: Entity) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+
+    table.set("getLayer", scope.create_function(move |_, ()| with_sprite(world, entity, |sprite| sprite.layer))?)?;
+    table.set("setLayer", scope.create_function(move |_, layer: u32| with_sprite(world, entity, |sprite| sprite.layer = layer.min(31)))?)?;
+    table.set("getParam", scope.create_function(move |_, name: String|{
+        let value = with_sprite(world, entity, |sprite| sprite.param(&name))?;
+        Ok(LuaVariadic::from_iter(value.into_iter().flatten()))
+    })?)?;
+    table.set("setParam", scope.create_function(move |_, (name, x, y, z, w): (String, f32, Option<f32>, Option<f32>, Option<f32>)|{
+        with_sprite(world, entity, |sprite| sprite.set_param(&name, [x, y.unwrap_or(0.0), z.unwrap_or(0.0), w.unwrap_or(0.0)]))
+    })?)?;
+
+    Ok(table)
+}
+
+/// Names of the `sprite` table's functions, for completion.
+pub const SPRITE_API: &[&str] = &["getLayer", "setLayer", "getParam", "setParam"];
+
+fn with_sprite<R>(world: &World, entity: Entity, f: impl FnOnce(&mut Sprite) -> R) -> LuaResult<R>{
+    let mut sprite = world.get::<&mut Sprite>(entity).map_err(|_| LuaError::external("this object has no sprite"))?;
+    Ok(f(&mut sprite))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::game::components::ALL_LAYERS;
+
+    #[test]
+    fn is_on_the_layers_of_the_mask(){
+        let mut sprite = Sprite::new(Handle::pending("a.png"));
+        assert!(sprite.is_on(ALL_LAYERS));
+        assert!(sprite.is_on(0b1));
+        assert!(!sprite.is_on(0b10));
+        sprite.layer = 5;
+        assert!(sprite.is_on(1 << 5));
+        assert!(!sprite.is_on(!(1 << 5)));
+        assert!(!sprite.is_on(0));
+        sprite.layer = 40;
+        assert!(sprite.is_on(1 << 31));
+    }
+}
@@ -0,0 +1,502 @@
+use std::{ops::BitOr, sync::atomic::{AtomicU64, Ordering}};
+
+use hecs::{Entity, World};
+use mlua::{prelude::*, Scope};
+
+use crate::engine::app::{assets::Handle, renderer::texture::Texture};
+use super::TransformComponent;
+
+/// Tiles per chunk side. Each chunk of each layer is one mesh, rebuilt when one of its tiles changes.
+pub const CHUNK_SIZE: u32 = 16;
+
+/// Per-tile flags, combined with `|`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TileFlags(pub u8);
+
+impl TileFlags{
+    pub const NONE: TileFlags = TileFlags(0);
+    /// Part of the tilemap's colliders.
+    pub const SOLID: TileFlags = TileFlags(1);
+    pub const FLIP_X: TileFlags = TileFlags(2);
+    pub const FLIP_Y: TileFlags = TileFlags(4);
+
+    pub fn contains(self, other: TileFlags) -> bool{
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for TileFlags{
+    type Output = TileFlags;
+
+    fn bitor(self, other: TileFlags) -> TileFlags{
+        TileFlags(self.0 | other.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile{
+    /// Position in the tileset, left to right, then top to bottom.
+    pub index: u32,
+    pub flags: TileFlags
+}
+
+impl Tile{
+    pub fn new(index: u32) -> Self{
+        Self { index, flags: TileFlags::NONE }
+    }
+
+    pub fn solid(index: u32) -> Self{
+        Self { index, flags: TileFlags::SOLID }
+    }
+}
+
+/// A texture atlas cut into a grid of equally sized tiles.
+pub struct Tileset{
+    pub texture: Handle<Texture>,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub columns: u32,
+    /// Pixels around the whole grid.
+    pub margin: u32,
+    /// Pixels between neighbouring tiles.
+    pub spacing: u32,
+    /// Tiles in the atlas when its last row isn't full, `None` to use every cell of the texture.
+    pub tile_count: Option<u32>
+}
+
+impl Tileset{
+    pub fn new(texture: Handle<Texture>, tile_width: u32, tile_height: u32, columns: u32) -> Self{
+        Self { texture, tile_width, tile_height, columns: columns.max(1), margin: 0, spacing: 0, tile_count: None }
+    }
+
+    /// Number of tiles in a texture of `texture_size`.
+    pub fn tiles_in(&self, texture_size: (u32, u32)) -> u32{
+        let stride = self.tile_height + self.spacing;
+        let rows = (texture_size.1.saturating_sub(self.margin * 2) + self.spacing) / stride.max(1);
+        let cells = rows * self.columns;
+        self.tile_count.map_or(cells, |count| count.min(cells))
+    }
+
+    /// Texture coordinates of a tile: left, top, right, bottom.
+    pub fn uv(&self, index: u32, texture_size: (u32, u32)) -> [f32; 4]{
+        let column = index % self.columns;
+        let row = index / self.columns;
+        let x = self.margin + column * (self.tile_width + self.spacing);
+        let y = self.margin + row * (self.tile_height + self.spacing);
+        let (width, height) = (texture_size.0.max(1) as f32, texture_size.1.max(1) as f32);
+        [x as f32 / width, y as f32 / height, (x + self.tile_width) as f32 / width, (y + self.tile_height) as f32 / height]
+    }
+}
+
+pub struct TileLayer{
+    pub name: String,
+    pub visible: bool,
+    tiles: Vec<Option<Tile>>,
+    /// Bumped whenever a tile of the chunk changes.
+    revisions: Vec<u64>
+}
+
+/// One tile of a chunk mesh, in the tilemap's local space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileQuad{
+    /// Left, bottom, right, top.
+    pub rect: [f32; 4],
+    /// Left, top, right, bottom, already flipped.
+    pub uv: [f32; 4]
+}
+
+/// An axis-aligned box in the tilemap's local space, a run of solid tiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileCollider{
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32
+}
+
+/// A grid of tiles from one tileset, drawn at the entity's `Transform`.
+/// Tile (0, 0) is the top left one and its top left corner is the origin; rows go down.
+pub struct Tilemap{
+    pub tileset: Tileset,
+    /// World units per tile side.
+    pub tile_size: f32,
+    /// Camera layer, like `Sprite::layer`.
+    pub layer: u32,
+    width: u32,
+    height: u32,
+    layers: Vec<TileLayer>,
+    id: u64
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+impl Tilemap{
+    /// An empty tilemap with one layer called "main".
+    pub fn new(tileset: Tileset, width: u32, height: u32, tile_size: f32) -> Self{
+        let mut tilemap = Self { tileset, tile_size, layer: 0, width, height, layers: Vec::new(), id: NEXT_ID.fetch_add(1, Ordering::Relaxed) };
+        tilemap.add_layer("main");
+        tilemap
+    }
+
+    /// Adds an empty layer drawn over the existing ones and returns its index.
+    pub fn add_layer(&mut self, name: &str) -> usize{
+        let (columns, rows) = self.chunk_counts();
+        self.layers.push(TileLayer {
+            name: name.to_string(),
+            visible: true,
+            tiles: vec![None; (self.width * self.height) as usize],
+            revisions: vec![1; (columns * rows) as usize]
+        });
+        self.layers.len() - 1
+    }
+
+    pub fn layer_index(&self, name: &str) -> Option<usize>{
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    pub fn layers(&self) -> &[TileLayer]{
+        &self.layers
+    }
+
+    pub fn set_layer_visible(&mut self, layer: usize, visible: bool){
+        if let Some(layer) = self.layers.get_mut(layer){
+            layer.visible = visible;
+        }
+    }
+
+    pub fn width(&self) -> u32{
+        self.width
+    }
+
+    pub fn height(&self) -> u32{
+        self.height
+    }
+
+    /// Identifies the tilemap across frames, for caching its meshes.
+    pub fn id(&self) -> u64{
+        self.id
+    }
+
+    pub fn get(&self, layer: usize, x: u32, y: u32) -> Option<Tile>{
+        let index = self.index(x, y)?;
+        self.layers.get(layer)?.tiles[index]
+    }
+
+    /// Changes a tile, `None` erases it. Returns false if the layer or position doesn't exist.
+    pub fn set(&mut self, layer: usize, x: u32, y: u32, tile: Option<Tile>) -> bool{
+        let Some(index) = self.index(x, y) else{
+            return false;
+        };
+        let chunk = self.chunk_index(x / CHUNK_SIZE, y / CHUNK_SIZE);
+        let Some(layer) = self.layers.get_mut(layer) else{
+            return false;
+        };
+        if layer.tiles[index] != tile{
+            layer.tiles[index] = tile;
+            layer.revisions[chunk] += 1;
+        }
+        true
+    }
+
+    /// Sets every tile of a layer.
+    pub fn fill(&mut self, layer: usize, tile: Option<Tile>){
+        for y in 0..self.height{
+            for x in 0..self.width{
+                self.set(layer, x, y, tile);
+            }
+        }
+    }
+
+    /// True if a tile on any layer is solid.
+    pub fn is_solid(&self, x: u32, y: u32) -> bool{
+        (0..self.layers.len()).any(|layer| self.get(layer, x, y).is_some_and(|tile| tile.flags.contains(TileFlags::SOLID)))
+    }
+
+    /// The tile containing a point in local space.
+    pub fn tile_at(&self, x: f32, y: f32) -> Option<(u32, u32)>{
+        let column = (x / self.tile_size).floor();
+        let row = (-y / self.tile_size).floor();
+        if column < 0.0 || row < 0.0 || column >= self.width as f32 || row >= self.height as f32{
+            return None;
+        }
+        Some((column as u32, row as u32))
+    }
+
+    /// True if the point in local space is inside a solid tile.
+    pub fn is_solid_at(&self, x: f32, y: f32) -> bool{
+        self.tile_at(x, y).is_some_and(|(x, y)| self.is_solid(x, y))
+    }
+
+    /// True if the box in local space touches a solid tile.
+    pub fn overlaps_solid(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> bool{
+        let first_column = (min_x / self.tile_size).floor().max(0.0) as u32;
+        let last_column = ((max_x / self.tile_size).ceil().max(0.0) as u32).min(self.width);
+        let first_row = (-max_y / self.tile_size).floor().max(0.0) as u32;
+        let last_row = ((-min_y / self.tile_size).ceil().max(0.0) as u32).min(self.height);
+        (first_row..last_row).any(|y| (first_column..last_column).any(|x| self.is_solid(x, y)))
+    }
+
+    /// The solid tiles as boxes: runs of a row merged, then equal runs of neighbouring rows merged.
+    pub fn colliders(&self) -> Vec<TileCollider>{
+        // (first column, end column, first row, end row)
+        let mut open: Vec<(u32, u32, u32, u32)> = Vec::new();
+        let mut closed = Vec::new();
+        for y in 0..self.height{
+            let mut runs = Vec::new();
+            let mut x = 0;
+            while x < self.width{
+                if !self.is_solid(x, y){
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < self.width && self.is_solid(x, y){
+                    x += 1;
+                }
+                runs.push((start, x));
+            }
+            let mut next = Vec::new();
+            for (start, end) in runs{
+                match open.iter().position(|&(open_start, open_end, _, _)| open_start == start && open_end == end){
+                    Some(index) => {
+                        let (start, end, first_row, _) = open.swap_remove(index);
+                        next.push((start, end, first_row, y + 1));
+                    },
+                    None => next.push((start, end, y, y + 1))
+                }
+            }
+            closed.append(&mut open);
+            open = next;
+        }
+        closed.append(&mut open);
+
+        closed.into_iter().map(|(start, end, first_row, end_row)| TileCollider {
+            min_x: start as f32 * self.tile_size,
+            max_x: end as f32 * self.tile_size,
+            min_y: -(end_row as f32) * self.tile_size,
+            max_y: -(first_row as f32) * self.tile_size
+        }).collect()
+    }
+
+    /// Number of chunks across and down.
+    pub fn chunk_counts(&self) -> (u32, u32){
+        (self.width.div_ceil(CHUNK_SIZE), self.height.div_ceil(CHUNK_SIZE))
+    }
+
+    /// Changes whenever a tile of the chunk changes.
+    pub fn chunk_revision(&self, layer: usize, chunk_x: u32, chunk_y: u32) -> u64{
+        self.layers[layer].revisions[self.chunk_index(chunk_x, chunk_y)]
+    }
+
+    /// The tiles of one chunk, for building its mesh.
+    pub fn chunk_quads(&self, layer: usize, chunk_x: u32, chunk_y: u32, texture_size: (u32, u32)) -> Vec<TileQuad>{
+        let mut quads = Vec::new();
+        let (first_x, first_y) = (chunk_x * CHUNK_SIZE, chunk_y * CHUNK_SIZE);
+        for y in first_y..(first_y + CHUNK_SIZE).min(self.height){
+            for x in first_x..(first_x + CHUNK_SIZE).min(self.width){
+                let Some(tile) = self.get(layer, x, y) else{
+                    continue;
+                };
+                let [mut left, mut top, mut right, mut bottom] = self.tileset.uv(tile.index, texture_size);
+                if tile.flags.contains(TileFlags::FLIP_X){
+                    std::mem::swap(&mut left, &mut right);
+                }
+                if tile.flags.contains(TileFlags::FLIP_Y){
+                    std::mem::swap(&mut top, &mut bottom);
+                }
+                let size = self.tile_size;
+                quads.push(TileQuad {
+                    rect: [x as f32 * size, -((y + 1) as f32) * size, (x + 1) as f32 * size, -(y as f32) * size],
+                    uv: [left, top, right, bottom]
+                });
+            }
+        }
+        quads
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize>{
+        (x < self.width && y < self.height).then(|| (y * self.width + x) as usize)
+    }
+
+    fn chunk_index(&self, chunk_x: u32, chunk_y: u32) -> usize{
+        (chunk_y * self.chunk_counts().0 + chunk_x) as usize
+    }
+}
+
+/// Builds the `tilemap` table scripts use, for the script's own tilemap or else the first one:
+/// `get(x, y, layer?)` returns the index and flags or nothing, `set(x, y, index, flags?, layer?)`,
+/// `clear(x, y, layer?)`, `size()`, `worldToTile(x, y)`, `isSolidAt(x, y)`,
+/// `overlapsSolid(minX, minY, maxX, maxY)` and `colliders()`, a list of the merged solid boxes as
+/// `{minX, minY, maxX, maxY}` tables, plus the `SOLID`, `FLIP_X` and `FLIP_Y` flags.
+/// Layers are given by name and default to the first one; world positions ignore rotation and scale.
+pub fn create_lua_table<'scope, 'env>(lua: &Lua, scope: &'scope Scope<'scope, 'env>, world: &'env World, entity: Entity) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+    table.set("SOLID", TileFlags::SOLID.0)?;
+    table.set("FLIP_X", TileFlags::FLIP_X.0)?;
+    table.set("FLIP_Y", TileFlags::FLIP_Y.0)?;
+
+    table.set("get", scope.create_function(move |_, (x, y, layer): (u32, u32, Option<String>)|{
+        with_tilemap(world, entity, |tilemap, _|{
+            let layer = lua_layer(tilemap, layer)?;
+            Ok(tilemap.get(layer, x, y).map(|tile| (tile.index, tile.flags.0)).unzip())
+        })
+    })?)?;
+    table.set("set", scope.create_function(move |_, (x, y, index, flags, layer): (u32, u32, u32, Option<u8>, Option<String>)|{
+        with_tilemap(world, entity, |tilemap, _|{
+            let layer = lua_layer(tilemap, layer)?;
+            Ok(tilemap.set(layer, x, y, Some(Tile { index, flags: TileFlags(flags.unwrap_or(0)) })))
+        })
+    })?)?;
+    table.set("clear", scope.create_function(move |_, (x, y, layer): (u32, u32, Option<String>)|{
+        with_tilemap(world, entity, |tilemap, _|{
+            let layer = lua_layer(tilemap, layer)?;
+            Ok(tilemap.set(layer, x, y, None))
+        })
+    })?)?;
+    table.set("size", scope.create_function(move |_, ()|{
+        with_tilemap(world, entity, |tilemap, _| Ok((tilemap.width(), tilemap.height())))
+    })?)?;
+    table.set("worldToTile", scope.create_function(move |_, (x, y): (f32, f32)|{
+        with_tilemap(world, entity, |tilemap, origin| Ok(tilemap.tile_at(x - origin.0, y - origin.1).unzip()))
+    })?)?;
+    table.set("isSolidAt", scope.create_function(move |_, (x, y): (f32, f32)|{
+        with_tilemap(world, entity, |tilemap, origin| Ok(tilemap.is_solid_at(x - origin.0, y - origin.1)))
+    })?)?;
+    table.set("overlapsSolid", scope.create_function(move |_, (min_x, min_y, max_x, max_y): (f32, f32, f32, f32)|{
+        with_tilemap(world, entity, |tilemap, origin|{
+            Ok(tilemap.overlaps_solid(min_x - origin.0, min_y - origin.1, max_x - origin.0, max_y - origin.1))
+        })
+    })?)?;
+    table.set("colliders", scope.create_function(move |lua, ()|{
+        with_tilemap(world, entity, |tilemap, origin|{
+            let colliders = lua.create_table()?;
+            for collider in tilemap.colliders(){
+                let bounds = lua.create_table()?;
+                bounds.set("minX", collider.min_x + origin.0)?;
+                bounds.set("minY", collider.min_y + origin.1)?;
+                bounds.set("maxX", collider.max_x + origin.0)?;
+                bounds.set("maxY", collider.max_y + origin.1)?;
+                colliders.push(bounds)?;
+            }
+            Ok(colliders)
+        })
+    })?)?;
+
+    Ok(table)
+}
+
+/// Names of the `tilemap` table's functions, for completion.
+pub const TILEMAP_API: &[&str] = &["get", "set", "clear", "size", "worldToTile", "isSolidAt", "overlapsSolid", "colliders", "SOLID", "FLIP_X", "FLIP_Y"];
+
+/// Runs `f` on the script's own tilemap if its object has one, the first tilemap otherwise,
+/// along with the position of its origin.
+fn with_tilemap<R>(world: &World, entity: Entity, f: impl FnOnce(&mut Tilemap, (f32, f32)) -> LuaResult<R>) -> LuaResult<R>{
+    let entity = if world.satisfies::<&Tilemap>(entity).unwrap_or(false){
+        entity
+    } else{
+        world.query::<&Tilemap>().iter().next().map(|(entity, _)| entity).ok_or_else(|| LuaError::external("there is no tilemap"))?
+    };
+    let origin = world.get::<&TransformComponent>(entity).map_or((0.0, 0.0), |transform|{
+        let transform = transform.lock().unwrap();
+        (transform.position.x, transform.position.y)
+    });
+    let mut tilemap = world.get::<&mut Tilemap>(entity).map_err(LuaError::external)?;
+    f(&mut tilemap, origin)
+}
+
+fn lua_layer(tilemap: &Tilemap, name: Option<String>) -> LuaResult<usize>{
+    match name{
+        Some(name) => tilemap.layer_index(&name).ok_or_else(|| LuaError::external(format!("no tilemap layer called '{}'", name))),
+        None => Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::game::components::Transform;
+
+    fn tilemap(width: u32, height: u32) -> Tilemap{
+        Tilemap::new(Tileset::new(Handle::pending("tiles"), 16, 16, 4), width, height, 1.0)
+    }
+
+    #[test]
+    fn only_the_edited_chunk_changes(){
+        let mut tilemap = tilemap(40, 20);
+        assert_eq!(tilemap.chunk_counts(), (3, 2));
+        let before = (tilemap.chunk_revision(0, 0, 0), tilemap.chunk_revision(0, 2, 1));
+        tilemap.set(0, 35, 17, Some(Tile::new(3)));
+        assert_eq!(tilemap.chunk_revision(0, 0, 0), before.0);
+        assert_ne!(tilemap.chunk_revision(0, 2, 1), before.1);
+
+        // Setting the same tile again isn't a change.
+        let after = tilemap.chunk_revision(0, 2, 1);
+        tilemap.set(0, 35, 17, Some(Tile::new(3)));
+        assert_eq!(tilemap.chunk_revision(0, 2, 1), after);
+        assert!(!tilemap.set(0, 40, 0, Some(Tile::new(3))));
+    }
+
+    #[test]
+    fn merges_solid_tiles_into_boxes(){
+        let mut tilemap = tilemap(6, 4);
+        // A 3x2 block and a single tile on another layer.
+        for (x, y) in [(1, 1), (2, 1), (3, 1), (1, 2), (2, 2), (3, 2)]{
+            tilemap.set(0, x, y, Some(Tile::solid(0)));
+        }
+        let top = tilemap.add_layer("top");
+        tilemap.set(top, 5, 0, Some(Tile::solid(0)));
+        tilemap.set(top, 0, 3, Some(Tile::new(0)));
+
+        let mut colliders = tilemap.colliders();
+        colliders.sort_by(|a, b| a.min_x.total_cmp(&b.min_x));
+        assert_eq!(colliders, vec![
+            TileCollider { min_x: 1.0, min_y: -3.0, max_x: 4.0, max_y: -1.0 },
+            TileCollider { min_x: 5.0, min_y: -1.0, max_x: 6.0, max_y: 0.0 }
+        ]);
+        assert!(tilemap.is_solid_at(2.5, -1.5));
+        assert!(!tilemap.is_solid_at(0.5, -3.5));
+        assert!(tilemap.overlaps_solid(3.5, -1.5, 4.5, -0.5));
+        assert!(!tilemap.overlaps_solid(4.1, -3.0, 4.9, -0.1));
+    }
+
+    #[test]
+    fn scripts_get_colliders_in_world_space(){
+        let mut tilemap = tilemap(3, 2);
+        tilemap.fill(0, Some(Tile::solid(0)));
+        tilemap.set(0, 2, 1, None);
+        let mut world = World::new();
+        let entity = world.spawn((tilemap, Transform::new(10.0, 5.0, 0.0)));
+
+        let lua = Lua::new();
+        let boxes: Vec<(f32, f32, f32, f32)> = lua.scope(|scope|{
+            lua.globals().set("tilemap", create_lua_table(&lua, scope, &world, entity)?)?;
+            lua.load("local boxes = {} for _, b in ipairs(tilemap.colliders()) do boxes[#boxes + 1] = {b.minX, b.minY, b.maxX, b.maxY} end return boxes")
+                .eval::<Vec<LuaTable>>()?
+                .into_iter()
+                .map(|b| Ok((b.get(1)?, b.get(2)?, b.get(3)?, b.get(4)?)))
+                .collect()
+        }).unwrap();
+        assert_eq!(boxes.len(), 2);
+        assert!(boxes.contains(&(10.0, 4.0, 13.0, 5.0)));
+        assert!(boxes.contains(&(10.0, 3.0, 12.0, 4.0)));
+    }
+
+    #[test]
+    fn counts_the_tiles_of_the_atlas(){
+        let mut tileset = Tileset { margin: 1, spacing: 2, ..Tileset::new(Handle::pending("tiles"), 16, 16, 4) };
+        // 3 rows: 1 + 3 * 16 + 2 * 2 + 1 = 54 pixels.
+        assert_eq!(tileset.tiles_in((72, 54)), 12);
+        assert_eq!(tileset.tiles_in((72, 53)), 8);
+        tileset.tile_count = Some(10);
+        assert_eq!(tileset.tiles_in((72, 54)), 10);
+        assert_eq!(tileset.tiles_in((72, 20)), 4);
+    }
+
+    #[test]
+    fn flips_texture_coordinates(){
+        let mut tilemap = tilemap(2, 1);
+        tilemap.set(0, 1, 0, Some(Tile { index: 5, flags: TileFlags::FLIP_X }));
+        let quads = tilemap.chunk_quads(0, 0, 0, (64, 64));
+        assert_eq!(quads, vec![TileQuad { rect: [1.0, -1.0, 2.0, 0.0], uv: [0.5, 0.25, 0.25, 0.5] }]);
+    }
+}
@@ -1,40 +1,133 @@
-use std::sync::{Arc, Mutex};
+use std::{f32::consts::{PI, TAU}, sync::{Arc, Mutex}};
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Position{
     pub x: f32,
     pub y: f32
 }
 
 impl Position{
-    fn to_mat(&self) -> cgmath::Matrix4<f32>{
+    fn to_mat(self) -> cgmath::Matrix4<f32>{
         let position = cgmath::vec3(self.x, self.y, 0.0);
         cgmath::Matrix4::from_translation(position)
     }
+
+    fn lerp(&self, other: &Position, alpha: f32) -> Position{
+        Position { x: self.x + (other.x - self.x) * alpha, y: self.y + (other.y - self.y) * alpha }
+    }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Rotation{
     pub angle: f32
 }
 
 impl Rotation{
-    fn to_mat(&self) -> cgmath::Matrix4<f32>{
+    fn to_mat(self) -> cgmath::Matrix4<f32>{
         cgmath::Matrix4::from_angle_z(cgmath::Rad(self.angle))
     }
+
+    /// Turns the short way, so going from just under π to just over -π doesn't spin around.
+    fn lerp(&self, other: &Rotation, alpha: f32) -> Rotation{
+        let mut delta = (other.angle - self.angle).rem_euclid(TAU);
+        if delta > PI{
+            delta -= TAU;
+        }
+        Rotation { angle: self.angle + delta * alpha }
+    }
 }
 
 pub struct Transform{
     pub position: Position,
-    pub rotation: Rotation
+    pub rotation: Rotation,
+    previous_position: Position,
+    previous_rotation: Rotation,
+    /// Where the last fixed step left the transform. Anything else moved it since, e.g. `update`.
+    stepped_position: Position,
+    stepped_rotation: Rotation
 }
 
 pub type TransformComponent = Arc<Mutex<Transform>>;
 
 impl Transform{
     pub fn new(x: f32, y: f32, angle: f32) -> TransformComponent{
-        Arc::new(Mutex::new(Self{ position: Position { x: x, y: y }, rotation: Rotation { angle:  angle}}))
+        let position = Position { x, y };
+        let rotation = Rotation { angle };
+        Arc::new(Mutex::new(Self{ position, rotation, previous_position: position, previous_rotation: rotation, stepped_position: position, stepped_rotation: rotation }))
     }
 
     pub fn to_mat(&self) -> cgmath::Matrix4<f32>{
         self.position.to_mat() * self.rotation.to_mat()
     }
-}
\ No newline at end of file
+
+    /// Remembers the current state as the start point for render interpolation.
+    /// Called once before every fixed simulation step.
+    pub fn snapshot(&mut self){
+        self.previous_position = self.position;
+        self.previous_rotation = self.rotation;
+    }
+
+    /// Remembers the current state as what the fixed steps of this frame produced.
+    /// Called once after them.
+    pub fn finish_steps(&mut self){
+        self.stepped_position = self.position;
+        self.stepped_rotation = self.rotation;
+    }
+
+    /// Position and rotation blended between the last two simulation steps, `alpha` in `[0, 1]`.
+    /// A transform moved outside the fixed step isn't blended, it would lag a frame behind.
+    fn interpolated(&self, alpha: f32) -> (Position, Rotation){
+        if self.position != self.stepped_position || self.rotation != self.stepped_rotation{
+            return (self.position, self.rotation);
+        }
+        (self.previous_position.lerp(&self.position, alpha), self.previous_rotation.lerp(&self.rotation, alpha))
+    }
+
+    /// Position blended between the last two simulation steps, `alpha` in `[0, 1]`.
+    pub fn interpolated_position(&self, alpha: f32) -> (f32, f32){
+        let (position, _) = self.interpolated(alpha);
+        (position.x, position.y)
+    }
+
+    /// Model matrix blended between the last two simulation steps, `alpha` in `[0, 1]`.
+    pub fn interpolated_mat(&self, alpha: f32) -> cgmath::Matrix4<f32>{
+        let (position, rotation) = self.interpolated(alpha);
+        position.to_mat() * rotation.to_mat()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn interpolates_fixed_step_movement(){
+        let transform = Transform::new(0.0, 0.0, 0.0);
+        let mut transform = transform.lock().unwrap();
+        transform.snapshot();
+        transform.position.x = 1.0;
+        transform.finish_steps();
+        assert_eq!(transform.interpolated_position(0.25), (0.25, 0.0));
+    }
+
+    #[test]
+    fn does_not_interpolate_movement_outside_the_fixed_step(){
+        let transform = Transform::new(0.0, 0.0, 0.0);
+        let mut transform = transform.lock().unwrap();
+        transform.snapshot();
+        transform.finish_steps();
+        // Moved in `update`, after the steps.
+        transform.position.x = 1.0;
+        assert_eq!(transform.interpolated_position(0.25), (1.0, 0.0));
+    }
+
+    #[test]
+    fn rotation_takes_the_short_way(){
+        let from = Rotation { angle: PI - 0.1 };
+        let to = Rotation { angle: -PI + 0.1 };
+        let halfway = from.lerp(&to, 0.5).angle;
+        assert!((halfway - PI).abs() < 1e-5, "{}", halfway);
+        assert!((to.lerp(&from, 0.5).angle + PI).abs() < 1e-5);
+        assert!((Rotation { angle: 0.0 }.lerp(&Rotation { angle: 1.0 }, 0.5).angle - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,280 @@
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+
+/// Lua side of the waiting functions. Rust callbacks can't yield, so the
+/// helpers yield a description of what to wait for and the scheduler below
+/// decides when to resume.
+const PRELUDE: &str = r#"
+function wait(seconds) return coroutine.yield("seconds", seconds) end
+function wait_frames(frames) return coroutine.yield("frames", frames) end
+function wait_until(condition) return coroutine.yield("until", condition) end
+"#;
+
+enum Wait{
+    NextFrame,
+    Seconds(f32),
+    Frames(u32),
+    Until(LuaFunction)
+}
+
+struct Coroutine{
+    id: u32,
+    thread: LuaThread,
+    wait: Wait
+}
+
+/// Coroutines started by a script with `start_coroutine(fn)`.
+/// They are resumed once per `GameManager::update` until they finish.
+#[derive(Default)]
+pub struct Coroutines{
+    next_id: u32,
+    running: Vec<Coroutine>,
+    /// Ids taken out of `running` by `resume_all` for the current pass.
+    resuming: Vec<u32>,
+    /// Coroutines of `resuming` stopped during the pass, dropped when it ends.
+    stopped: Vec<u32>
+}
+
+pub type SharedCoroutines = Arc<Mutex<Coroutines>>;
+
+impl Coroutines{
+    pub fn start(&mut self, thread: LuaThread) -> u32{
+        self.next_id += 1;
+        self.running.push(Coroutine { id: self.next_id, thread, wait: Wait::NextFrame });
+        self.next_id
+    }
+
+    pub fn stop(&mut self, id: u32){
+        self.running.retain(|coroutine| coroutine.id != id);
+        if self.resuming.contains(&id){
+            self.stopped.push(id);
+        }
+    }
+
+    pub fn clear(&mut self){
+        self.running.clear();
+        self.stopped.extend(self.resuming.iter().copied());
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.running.is_empty()
+    }
+}
+
+/// Registers `start_coroutine`, `stop_coroutine` and the `wait*` helpers in `lua`.
+pub fn register(lua: &Lua, coroutines: &SharedCoroutines) -> LuaResult<()>{
+    let globals = lua.globals();
+
+    let coroutines_clone = coroutines.clone();
+    globals.set("start_coroutine", lua.create_function(move |lua, func: LuaFunction|{
+        let thread = lua.create_thread(func)?;
+        Ok(coroutines_clone.lock().unwrap().start(thread))
+    })?)?;
+
+    let coroutines_clone = coroutines.clone();
+    globals.set("stop_coroutine", lua.create_function(move |_, id: u32|{
+        coroutines_clone.lock().unwrap().stop(id);
+        Ok(())
+    })?)?;
+
+    lua.load(PRELUDE).set_name("=coroutines").exec()
+}
+
+/// Resumes every coroutine whose wait condition is met.
+/// Finished and failed coroutines are dropped; the first failure is returned
+/// with the coroutine's stack trace, which mlua adds to errors of threads.
+pub fn resume_all(coroutines: &SharedCoroutines, dt: f32) -> LuaResult<()>{
+    // Taken out of the lock so coroutines can start or stop others while running.
+    let mut running = {
+        let mut guard = coroutines.lock().unwrap();
+        let running = std::mem::take(&mut guard.running);
+        guard.resuming = running.iter().map(|coroutine| coroutine.id).collect();
+        running
+    };
+    let mut first_error = None;
+
+    running.retain_mut(|coroutine|{
+        let ready = match &mut coroutine.wait{
+            Wait::NextFrame => true,
+            Wait::Seconds(seconds) => {
+                *seconds -= dt;
+                *seconds <= 0.0
+            },
+            Wait::Frames(frames) => {
+                *frames = frames.saturating_sub(1);
+                *frames == 0
+            },
+            Wait::Until(condition) => match condition.call::<bool>(()){
+                Ok(ready) => ready,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                    return false;
+                }
+            }
+        };
+        if !ready{
+            return true;
+        }
+        if coroutines.lock().unwrap().stopped.contains(&coroutine.id){
+            return false;
+        }
+
+        match coroutine.thread.resume::<LuaMultiValue>(()){
+            Ok(values) => {
+                if coroutine.thread.status() != LuaThreadStatus::Resumable{
+                    return false;
+                }
+                coroutine.wait = parse_wait(values);
+                true
+            },
+            Err(e) => {
+                first_error.get_or_insert(e);
+                false
+            }
+        }
+    });
+
+    let mut guard = coroutines.lock().unwrap();
+    running.append(&mut guard.running);
+    let stopped = std::mem::take(&mut guard.stopped);
+    guard.resuming.clear();
+    running.retain(|coroutine| !stopped.contains(&coroutine.id));
+    guard.running = running;
+
+    match first_error{
+        Some(e) => Err(e),
+        None => Ok(())
+    }
+}
+
+fn parse_wait(values: LuaMultiValue) -> Wait{
+    let mut values = values.into_iter();
+    let kind = values.next();
+    let arg = values.next();
+    match (kind, arg){
+        (Some(LuaValue::String(kind)), Some(arg)) => match (kind.to_str().as_deref(), arg){
+            (Ok("seconds"), LuaValue::Number(seconds)) => Wait::Seconds(seconds as f32),
+            (Ok("seconds"), LuaValue::Integer(seconds)) => Wait::Seconds(seconds as f32),
+            (Ok("frames"), LuaValue::Integer(frames)) => Wait::Frames(frames.max(1) as u32),
+            (Ok("frames"), LuaValue::Number(frames)) => Wait::Frames(frames.max(1.0) as u32),
+            (Ok("until"), LuaValue::Function(condition)) => Wait::Until(condition),
+            _ => Wait::NextFrame
+        },
+        _ => Wait::NextFrame
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn setup(source: &str) -> (Lua, SharedCoroutines){
+        let lua = Lua::new();
+        let coroutines = SharedCoroutines::default();
+        register(&lua, &coroutines).unwrap();
+        lua.load(source).set_name("@test.lua").exec().unwrap();
+        (lua, coroutines)
+    }
+
+    fn log(lua: &Lua) -> String{
+        lua.globals().get("log").unwrap()
+    }
+
+    #[test]
+    fn waits_for_seconds_frames_and_conditions(){
+        let (lua, coroutines) = setup(r#"
+            log = ""
+            ready = false
+            start_coroutine(function()
+                log = log .. "a"
+                wait(0.5)
+                log = log .. "b"
+                wait_frames(2)
+                log = log .. "c"
+                wait_until(function() return ready end)
+                log = log .. "d"
+            end)
+        "#);
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "a");
+        resume_all(&coroutines, 0.3).unwrap();
+        assert_eq!(log(&lua), "a");
+        resume_all(&coroutines, 0.3).unwrap();
+        assert_eq!(log(&lua), "ab");
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "ab");
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "abc");
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "abc");
+        lua.globals().set("ready", true).unwrap();
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "abcd");
+        assert!(coroutines.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stopped_coroutines_stay_stopped_and_new_ones_run(){
+        let (lua, coroutines) = setup(r#"
+            log = ""
+            local function counter(name)
+                return function()
+                    while true do
+                        log = log .. name
+                        wait_frames(1)
+                    end
+                end
+            end
+            first = start_coroutine(counter("a"))
+            start_coroutine(function() stop_coroutine(first) end)
+            function restart() first = start_coroutine(counter("b")) end
+        "#);
+        resume_all(&coroutines, 0.1).unwrap();
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "a");
+
+        lua.globals().get::<LuaFunction>("restart").unwrap().call::<()>(()).unwrap();
+        resume_all(&coroutines, 0.1).unwrap();
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "abb");
+    }
+
+    #[test]
+    fn a_coroutine_can_stop_itself(){
+        let (lua, coroutines) = setup(r#"
+            log = ""
+            local id
+            id = start_coroutine(function()
+                log = log .. "a"
+                stop_coroutine(id)
+                wait_frames(1)
+                log = log .. "b"
+            end)
+        "#);
+        resume_all(&coroutines, 0.1).unwrap();
+        resume_all(&coroutines, 0.1).unwrap();
+        assert_eq!(log(&lua), "a");
+        assert!(coroutines.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors_carry_the_coroutine_stack(){
+        let (_lua, coroutines) = setup(r#"
+            local function explode()
+                error("boom")
+            end
+            start_coroutine(function()
+                wait(0)
+                explode()
+            end)
+        "#);
+        resume_all(&coroutines, 0.1).unwrap();
+        let error = resume_all(&coroutines, 0.1).unwrap_err().to_string();
+        // mlua adds the coroutine's stack, there's no debug library for scripts to do it.
+        assert!(error.contains("test.lua:3: boom\nstack traceback:"), "{}", error);
+        assert!(error.contains("test.lua:3: in "), "{}", error);
+        assert!(error.contains("test.lua:7: in "), "{}", error);
+        assert!(coroutines.lock().unwrap().is_empty());
+    }
+}
@@ -1,9 +1,17 @@
 use crate::engine::app::GameManager;
 use crate::engine::app::renderer::egui_tools::EguiRenderer;
 pub mod components;
+pub mod coroutines;
+pub mod script_error;
+pub mod timers;
 pub trait GameHandler
 {
     fn on_start(&mut self, gm: &mut GameManager);
+    /// Called at the fixed simulation rate, possibly several times per frame.
+    fn fixed_update(&mut self, _gm: &mut GameManager, _dt: f32) {}
+    /// Called once per frame with the real frame time, also while paused so editor and UI logic keep working.
+    /// Gameplay should use `gm.time.delta()`, which is scaled and stops while paused.
     fn update(&mut self, gm: &mut GameManager, dt: f32);
-    fn on_ui(&mut self, gm: &mut GameManager, egui_renderer: &mut EguiRenderer);
-}
\ No newline at end of file
+    /// Called inside the frame's egui pass, for the game's own windows.
+    fn on_ui(&mut self, _gm: &mut GameManager, _egui_renderer: &mut EguiRenderer) {}
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+use mlua::Error as LuaError;
+
+/// A script failure with its location in the source file.
+#[derive(Clone, PartialEq)]
+pub struct ScriptError{
+    pub file: String,
+    pub line: Option<usize>,
+    /// Lua only reports lines; the column is guessed from the `near '...'` token of syntax errors.
+    pub column: Option<usize>,
+    pub message: String,
+    pub traceback: Option<String>
+}
+
+impl ScriptError{
+    pub fn new(file: &str, message: impl Into<String>) -> Self{
+        Self { file: file.to_string(), line: None, column: None, message: message.into(), traceback: None }
+    }
+
+    /// Extracts message, location and traceback from an `mlua` error raised by the chunk `file`.
+    /// `source` is used to locate the column of syntax errors.
+    pub fn from_lua(file: &str, source: &str, error: &LuaError) -> Self{
+        let (message, traceback) = match error{
+            LuaError::SyntaxError { message, .. } => (message.clone(), None),
+            LuaError::RuntimeError(message) => split_traceback(message),
+            LuaError::CallbackError { traceback, cause } => {
+                let (message, _) = split_traceback(&cause.to_string());
+                (message, Some(traceback.clone()))
+            },
+            e => (e.to_string(), None)
+        };
+
+        // Errors raised from Rust callbacks carry no location, the traceback still does.
+        let (line, message) = match parse_location(file, &message){
+            Some((line, rest)) => (Some(line), rest),
+            None => {
+                let line = traceback.as_deref().and_then(|traceback|{
+                    traceback.lines().find_map(|line| parse_location(file, line.trim()))
+                });
+                (line.map(|(line, _)| line), message)
+            }
+        };
+        let column = line.and_then(|line| near_column(source, line, &message));
+
+        Self { file: file.to_string(), line, column, message, traceback }
+    }
+}
+
+impl fmt::Display for ScriptError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line{
+            write!(f, ":{}", line)?;
+        }
+        if let Some(column) = self.column{
+            write!(f, ":{}", column)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(traceback) = &self.traceback{
+            write!(f, "\n{}", traceback)?;
+        }
+        Ok(())
+    }
+}
+
+fn split_traceback(message: &str) -> (String, Option<String>){
+    match message.split_once("\nstack traceback:"){
+        Some((message, traceback)) => (message.to_string(), Some(format!("stack traceback:{}", traceback))),
+        None => (message.to_string(), None)
+    }
+}
+
+/// Parses `file:LINE: rest` and returns the line and the rest of the message.
+fn parse_location(file: &str, message: &str) -> Option<(usize, String)>{
+    let rest = message.strip_prefix(file)?.strip_prefix(':')?;
+    let (line, rest) = rest.split_once(':')?;
+    let line = line.parse().ok()?;
+    Some((line, rest.trim_start().to_string()))
+}
+
+fn near_column(source: &str, line: usize, message: &str) -> Option<usize>{
+    let (_, token) = message.split_once("near '")?;
+    let token = token.split('\'').next()?;
+    let source_line = source.lines().nth(line.checked_sub(1)?)?;
+    source_line.find(token).map(|column| column + 1)
+}
+
+#[cfg(test)]
+mod tests{
+    use mlua::Lua;
+
+    use super::*;
+
+    fn run(source: &str) -> ScriptError{
+        let lua = Lua::new();
+        let error = lua.load(source).set_name("@scripts/enemy.lua").exec().unwrap_err();
+        ScriptError::from_lua("scripts/enemy.lua", source, &error)
+    }
+
+    #[test]
+    fn parses_runtime_errors(){
+        let error = run("local x = 1\nlocal y = nil\nreturn x + y");
+        assert_eq!(error.file, "scripts/enemy.lua");
+        assert_eq!(error.line, Some(3));
+        assert_eq!(error.column, None);
+        assert!(error.message.starts_with("attempt to perform arithmetic"), "{}", error.message);
+        assert!(error.traceback.is_some_and(|traceback| traceback.starts_with("stack traceback:")));
+    }
+
+    #[test]
+    fn parses_syntax_errors_with_a_column(){
+        let error = run("local a = 1\nlocal b = a + * 2");
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.column, Some(15));
+        assert!(error.message.contains("near '*'"), "{}", error.message);
+        assert_eq!(error.traceback, None);
+    }
+
+    #[test]
+    fn finds_the_line_of_callback_errors_in_the_traceback(){
+        let lua = Lua::new();
+        let fail = lua.create_function(|_, ()| Err::<(), _>(mlua::Error::external("no such object"))).unwrap();
+        lua.globals().set("fail", fail).unwrap();
+        let source = "\nfail()";
+        let error = lua.load(source).set_name("@scripts/enemy.lua").exec().unwrap_err();
+        let error = ScriptError::from_lua("scripts/enemy.lua", source, &error);
+        assert_eq!(error.line, Some(2));
+        assert!(error.message.contains("no such object"), "{}", error.message);
+    }
+
+    #[test]
+    fn displays_location_and_message(){
+        let mut error = ScriptError::new("a.lua", "boom");
+        assert_eq!(error.to_string(), "a.lua: boom");
+        error.line = Some(4);
+        error.column = Some(2);
+        assert_eq!(error.to_string(), "a.lua:4:2: boom");
+    }
+}
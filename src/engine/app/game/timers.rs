@@ -0,0 +1,146 @@
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+
+struct Timer{
+    id: u32,
+    remaining: f32,
+    interval: Option<f32>,
+    callback: LuaFunction
+}
+
+/// Lua callbacks scheduled with `time.after` and `time.every`.
+///
+/// Each `Script` owns its timers, so they are dropped together with the
+/// entity's script when the entity is despawned.
+#[derive(Default)]
+pub struct Timers{
+    next_id: u32,
+    timers: Vec<Timer>
+}
+
+pub type SharedTimers = Arc<Mutex<Timers>>;
+
+impl Timers{
+    pub fn after(&mut self, seconds: f32, callback: LuaFunction) -> u32{
+        self.push(seconds, None, callback)
+    }
+
+    pub fn every(&mut self, seconds: f32, callback: LuaFunction) -> u32{
+        self.push(seconds, Some(seconds.max(f32::EPSILON)), callback)
+    }
+
+    pub fn cancel(&mut self, id: u32){
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    pub fn clear(&mut self){
+        self.timers.clear();
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.timers.is_empty()
+    }
+
+    /// Advances all timers and returns the callbacks that are due, in firing order.
+    /// Callbacks are returned instead of called so they can schedule new timers.
+    pub fn tick(&mut self, dt: f32) -> Vec<LuaFunction>{
+        let mut due = Vec::new();
+        for timer in &mut self.timers{
+            timer.remaining -= dt;
+            if timer.remaining <= 0.0{
+                due.push(timer.callback.clone());
+                if let Some(interval) = timer.interval{
+                    timer.remaining = (timer.remaining + interval).max(0.0);
+                }
+            }
+        }
+        self.timers.retain(|timer| timer.interval.is_some() || timer.remaining > 0.0);
+        due
+    }
+
+    fn push(&mut self, seconds: f32, interval: Option<f32>, callback: LuaFunction) -> u32{
+        self.next_id += 1;
+        self.timers.push(Timer { id: self.next_id, remaining: seconds, interval, callback });
+        self.next_id
+    }
+}
+
+/// Builds the `time` table with `after`, `every` and `cancel` bound to `timers`.
+pub fn create_time_table(lua: &Lua, timers: &SharedTimers) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+
+    let timers_clone = timers.clone();
+    table.set("after", lua.create_function(move |_, (seconds, callback): (f32, LuaFunction)|{
+        Ok(timers_clone.lock().unwrap().after(seconds, callback))
+    })?)?;
+
+    let timers_clone = timers.clone();
+    table.set("every", lua.create_function(move |_, (seconds, callback): (f32, LuaFunction)|{
+        Ok(timers_clone.lock().unwrap().every(seconds, callback))
+    })?)?;
+
+    let timers_clone = timers.clone();
+    table.set("cancel", lua.create_function(move |_, id: u32|{
+        timers_clone.lock().unwrap().cancel(id);
+        Ok(())
+    })?)?;
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn callback(lua: &Lua, name: &str) -> LuaFunction{
+        lua.load(format!("return function() return '{}' end", name)).eval().unwrap()
+    }
+
+    fn names(due: Vec<LuaFunction>) -> Vec<String>{
+        due.into_iter().map(|callback| callback.call(()).unwrap()).collect()
+    }
+
+    #[test]
+    fn after_fires_once(){
+        let lua = Lua::new();
+        let mut timers = Timers::default();
+        timers.after(0.5, callback(&lua, "a"));
+        assert!(names(timers.tick(0.25)).is_empty());
+        assert_eq!(names(timers.tick(0.25)), ["a"]);
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn every_repeats_and_keeps_the_remainder(){
+        let lua = Lua::new();
+        let mut timers = Timers::default();
+        timers.every(0.5, callback(&lua, "b"));
+        assert_eq!(names(timers.tick(0.6)), ["b"]);
+        // 0.1 carried over, so 0.4 more is enough.
+        assert_eq!(names(timers.tick(0.4)), ["b"]);
+        assert!(names(timers.tick(0.3)).is_empty());
+        assert!(!timers.is_empty());
+    }
+
+    #[test]
+    fn cancel_and_clear_remove_timers(){
+        let lua = Lua::new();
+        let mut timers = Timers::default();
+        let a = timers.after(0.1, callback(&lua, "a"));
+        timers.every(0.1, callback(&lua, "b"));
+        timers.cancel(a);
+        assert_eq!(names(timers.tick(0.1)), ["b"]);
+        timers.clear();
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn lua_table_schedules_and_cancels(){
+        let lua = Lua::new();
+        let timers = SharedTimers::default();
+        lua.globals().set("time", create_time_table(&lua, &timers).unwrap()).unwrap();
+        lua.load("local id = time.after(1, function() end); time.every(1, function() end); time.cancel(id)").exec().unwrap();
+        assert_eq!(timers.lock().unwrap().timers.len(), 1);
+    }
+}
@@ -1,14 +1,24 @@
 pub mod renderer;
+pub mod audio;
 pub mod game;
-pub mod texture_manager;
-
+pub mod assets;
+pub mod timestep;
+pub mod time;
+pub mod vfs;
+pub mod file_watcher;
+pub mod console;
+pub mod repl;
+pub mod tiled;
+pub mod tile_painter;
+
+use anyhow::Context;
 use egui::{Color32, Frame, Key, RichText};
 use egui_code_editor::{CodeEditor, ColorTheme, Syntax};
 use game::{components::Label, GameHandler};
 use hecs::{Entity, World};
 use renderer::State;
-use std::{cell::RefCell, fmt::format, rc::Rc, sync::Arc};
-use crate::engine::app::{game::components::{self, Script, TransformComponent}, renderer::egui_tools::EguiRenderer, texture_manager::TextureManager};
+use std::{cell::RefCell, collections::HashMap, fmt::format, rc::Rc, sync::Arc};
+use crate::engine::app::{audio::{AudioManager, AudioOutput}, console::ConsoleView, repl::Repl, tile_painter::TilePainter, tiled::{ImportedMap, TiledMap}, game::components::{self, Script, TransformComponent}, renderer::egui_tools::EguiRenderer, file_watcher::FileWatcher, assets::{loaders, AssetManager, Handle}, renderer::{post::{PostEffect, PostPass}, resolution::{ScaleMode, VirtualResolution}, screen::SharedScreenSpace, texture::{Texture, TextureSettings}}, time::{System, Time, SYSTEMS}, timestep::FixedTimestep};
 
 use winit::{
     application::ApplicationHandler, dpi::PhysicalSize, event::{ElementState, MouseButton, WindowEvent}, event_loop::ActiveEventLoop, keyboard::{PhysicalKey}, window::{Window, WindowId}
@@ -18,18 +28,56 @@ use std::time::{Instant};
 
 pub struct GameManager{
     state: Rc<RefCell<State>>,
-    pub texture_manager: TextureManager,
-    pub world: World
+    pub assets: AssetManager,
+    pub audio_manager: AudioManager,
+    audio_output: AudioOutput,
+    pub world: World,
+    pub time: Time,
+    pub file_watcher: FileWatcher,
+    /// Errors from the last hot reloads that could not be shown on a component.
+    pub reload_errors: Vec<String>,
+    /// Maps spawned with `import_tiled_map`, reimported when their files change.
+    tiled_maps: Vec<ImportedMap>,
+    /// Fonts from `load_font` that egui doesn't have yet.
+    fonts: Vec<Handle<loaders::Font>>,
+    audio_sources: components::AudioSourceVoices,
+    /// Views the world through the free WASD camera instead of the `Camera2D`s.
+    pub editor_camera: bool,
+    /// Full-screen passes the frame goes through before the overlay is drawn, in order.
+    pub post_passes: Vec<PostPass>,
+    screen: SharedScreenSpace,
+    /// Handed to the renderer with the cameras, as the overlay can change it while the renderer is drawing.
+    virtual_resolution: Option<VirtualResolution>,
+    resolution_changed: bool
 }
 
 impl GameManager{
     fn new(state: Rc<RefCell<State>>) -> Self{
-        let texture_manager = TextureManager::new(state.clone());
+        let (audio_manager, audio_output) = AudioOutput::open_default();
+        let mut assets = AssetManager::new();
+        assets.register(loaders::TextureLoader::new(state.clone()));
+        assets.register(loaders::MaterialLoader::new(state.clone()));
+        assets.register(loaders::SoundLoader::new(audio_manager.sample_rate()));
+        assets.register(loaders::ScriptLoader);
+        assets.register(loaders::FontLoader);
 
         Self {
             state,
-            texture_manager,
-            world: World::new()
+            assets,
+            audio_manager,
+            audio_output,
+            world: World::new(),
+            time: Time::new(),
+            file_watcher: FileWatcher::default(),
+            reload_errors: Vec::new(),
+            tiled_maps: Vec::new(),
+            fonts: Vec::new(),
+            audio_sources: Default::default(),
+            editor_camera: false,
+            post_passes: Vec::new(),
+            screen: SharedScreenSpace::default(),
+            virtual_resolution: None,
+            resolution_changed: false
         }
     }
 
@@ -37,24 +85,498 @@ impl GameManager{
         self.world.spawn((Label::from_str(label),))
     }
 
-    pub fn add_components_to_object(&mut self, entity: hecs::Entity, components: impl hecs::DynamicBundle){
-        self.world.insert(entity, components).expect("Error while adding components to entity");
+    /// Finds an object by the id shown in the editor (`Label::id`).
+    pub fn find_object(&self, id: u32) -> Option<hecs::Entity>{
+        self.world.query::<&Label>().iter().find(|(_, label)| label.id == id).map(|(entity, _)| entity)
+    }
+
+    /// Despawns the object together with its components; script timers are cancelled with it.
+    pub fn remove_object(&mut self, entity: hecs::Entity){
+        if let Ok(source) = self.world.get::<&components::AudioSource>(entity)
+            && let Some(voice) = source.voice{
+            self.audio_manager.stop(voice);
+        }
+        self.world.despawn(entity).expect("Error while removing entity");
     }
 
     pub fn add_component_to_object(&mut self, entity: hecs::Entity, component: impl hecs::Component){
         self.world.insert_one(entity, component).expect("Error while adding component to entity");
     }
 
-    fn update(&mut self, dt: f32){
+    fn fixed_update(&mut self, dt: f32){
+        if self.time.is_running(System::Simulation){
+            for (id, script) in &mut self.world.query::<&mut components::Script>(){
+                script.sync_time(&self.time);
+                script.bind_audio(&self.audio_manager);
+                script.bind_screen(&self.screen);
+                script.fixed_update(dt, &self.world, &id);
+            }
+        }
+        if !self.time.is_running(System::Particles){
+            return;
+        }
+        for (_id, (emitter, transform)) in &mut self.world.query::<(&mut components::ParticleEmitter, &TransformComponent)>(){
+            let position = transform.lock().unwrap().position;
+            emitter.step((position.x, position.y), dt);
+        }
+    }
+
+    fn update(&mut self){
+        if !self.time.is_running(System::Scripts){
+            return;
+        }
+        let dt = self.time.delta();
         for (id, script) in &mut self.world.query::<&mut components::Script>(){
-                script.update(dt, &self.world, &id);
+            script.sync_time(&self.time);
+            script.bind_audio(&self.audio_manager);
+            script.bind_screen(&self.screen);
+            script.tick_timers(dt, &self.world, &id);
+            script.resume_coroutines(dt, &self.world, &id);
+            script.update(dt, &self.world, &id);
+        }
+    }
+
+    /// Reloads scripts, assets and engine shaders whose files changed on disk.
+    /// Script errors end up in the script's `state`, asset errors in `reload_errors`.
+    fn hot_reload(&mut self){
+        self.assets.collect_garbage();
+        for path in self.assets.watched_paths(){
+            self.file_watcher.watch(&path);
+        }
+        for path in self.tiled_maps.iter().flat_map(ImportedMap::files).filter_map(|path| vfs::real_path(path)){
+            self.file_watcher.watch(&path);
+        }
+        for path in self.state.borrow().shader_paths(){
+            self.file_watcher.watch(&path);
+        }
+        self.reload_errors.extend(self.state.borrow_mut().take_shader_errors());
+
+        for path in self.file_watcher.poll(){
+            log::info!("reloading {}", path.display());
+
+            for e in self.assets.reload_file(&path).1{
+                log::error!("{}: {:?}", path.display(), e);
+                self.reload_errors.push(format!("{}: {:#}", path.display(), e));
+            }
+
+            if let Err(e) = self.state.borrow_mut().reload_shader(&path){
+                log::error!("{}: {:?}", path.display(), e);
+                self.reload_errors.push(format!("{}: {:#}", path.display(), e));
+            }
+
+            for index in 0..self.tiled_maps.len(){
+                if !self.tiled_maps[index].files().filter_map(|file| vfs::real_path(file)).any(|file| file == path){
+                    continue;
+                }
+                if let Err(e) = self.reimport_tiled_map(index){
+                    log::error!("{}: {:?}", path.display(), e);
+                    self.reload_errors.push(format!("{}: {:#}", path.display(), e));
+                }
+            }
+        }
+
+        for (_id, script) in &mut self.world.query::<&mut components::Script>(){
+            script.sync_source();
+        }
+    }
+
+    /// Spawns the layers and objects of a Tiled `.tmx` or `.tmj` map, see `tiled`.
+    /// The entities are replaced when the map or its tilesets change on disk.
+    pub fn import_tiled_map(&mut self, path: &str) -> anyhow::Result<Vec<Entity>>{
+        let map = TiledMap::load(path)?;
+        let imported = map.spawn(path, &mut self.world, &mut self.assets)?;
+        let entities = imported.entities.clone();
+        self.tiled_maps.push(imported);
+        Ok(entities)
+    }
+
+    /// Replaces the entities of an imported map, keeping the old ones if the map doesn't load.
+    fn reimport_tiled_map(&mut self, index: usize) -> anyhow::Result<()>{
+        let path = self.tiled_maps[index].path.clone();
+        let map = TiledMap::load(&path)?;
+        let imported = map.spawn(&path, &mut self.world, &mut self.assets)?;
+        let old = std::mem::replace(&mut self.tiled_maps[index], imported);
+        for entity in old.entities{
+            // Scripts may have removed some already.
+            if self.world.contains(entity){
+                self.remove_object(entity);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the tiles of the imported map `entity` came from back into the map file and returns its path.
+    pub fn save_tiled_map(&mut self, entity: Entity) -> anyhow::Result<String>{
+        let imported = self.tiled_maps.iter().find(|imported| imported.entities.contains(&entity))
+            .context("the tilemap was not imported from a Tiled map")?;
+        let path = imported.path.clone();
+        let text = vfs::read_to_string(&path).with_context(|| format!("Could not read {}", path))?;
+        let map = TiledMap::parse(&path, &text)?;
+
+        let mut layers = HashMap::new();
+        let mut tile_layer = 0;
+        while map.tile_layer(tile_layer).is_some(){
+            let tilemaps: Vec<(u32, hecs::Ref<components::Tilemap>)> = imported.tile_layers.iter()
+                .filter(|entry| entry.layer == tile_layer)
+                .filter_map(|entry| self.world.get::<&components::Tilemap>(entry.entity).ok().map(|tilemap| (entry.first_gid, tilemap)))
+                .collect();
+            if !tilemaps.is_empty(){
+                let tilemaps: Vec<(u32, &components::Tilemap)> = tilemaps.iter().map(|(first_gid, tilemap)| (*first_gid, &**tilemap)).collect();
+                layers.insert(tile_layer, map.edited_tile_layer(tile_layer, &tilemaps)?);
+            }
+            tile_layer += 1;
+        }
+        let text = tiled::replace_tile_data(&path, &text, &layers)?;
+        vfs::write(&path, text.as_bytes()).with_context(|| format!("Could not write {}", path))?;
+
+        // Watching again takes the new modification time, so the save isn't reimported.
+        if let Some(file) = vfs::real_path(&path){
+            self.file_watcher.unwatch(&file);
+            self.file_watcher.watch(&file);
+        }
+        Ok(path)
+    }
+
+    /// Starts, stops and moves the voices of `AudioSource`s relative to the listener.
+    /// They're paused with the `Audio` system; music keeps playing. Without a device the mixer is run here.
+    fn update_audio(&mut self){
+        let listener = self.world.query::<(&components::AudioListener, &TransformComponent)>().iter()
+            .next()
+            .map(|(_, (listener, transform))|{
+                let position = transform.lock().unwrap().position;
+                ((position.x, position.y), listener.pan_range)
+            });
+        let (listener, pan_range) = listener.unwrap_or_else(||
+            (self.state.borrow().camera_position(), components::AudioListener::default().pan_range)
+        );
+
+        let paused = !self.time.is_running(System::Audio);
+        self.audio_sources.update(&self.world, &self.audio_manager, listener, pan_range, paused);
+        self.audio_output.pump(&self.audio_manager, self.time.unscaled_delta());
+    }
+
+    /// Moves every `Camera2D` after its follow target and hands their views, and a changed
+    /// virtual resolution, to the renderer.
+    /// `alpha` is the render interpolation, so following a fixed-step object doesn't jitter.
+    fn update_cameras(&mut self, alpha: f32){
+        let dt = self.time.delta();
+        let (width, height) = self.state.borrow().size();
+        let mut views = Vec::new();
+
+        for (_id, (camera, transform_arc)) in &mut self.world.query::<(&mut components::Camera2D, &TransformComponent)>(){
+            let target = camera.follow.and_then(|follow|{
+                let transform = self.world.get::<&TransformComponent>(follow.target).ok()?;
+                let position = transform.lock().unwrap().interpolated_position(alpha);
+                Some(position)
+            });
+            let aspect = (camera.viewport.width * width as f32) / (camera.viewport.height * height as f32).max(1.0);
+            let shake = camera.update_shake(dt);
+
+            let mut transform = transform_arc.lock().unwrap();
+            let position = (transform.position.x, transform.position.y);
+            let (x, y) = camera.step(position, target, aspect, dt);
+            transform.position.x = x;
+            transform.position.y = y;
+
+            let view = renderer::CameraView { position: (x + shake.0, y + shake.1), zoom: camera.zoom, rotation: camera.rotation, viewport: camera.viewport, layer_mask: camera.layer_mask };
+            views.push((camera.priority, view));
+        }
+        // Stable, so cameras with the same priority keep their order and later ones end up on top.
+        views.sort_by_key(|(priority, _)| *priority);
+        let mut state = self.state.borrow_mut();
+        state.set_game_cameras(views.into_iter().map(|(_, view)| view).collect());
+        state.set_editor_camera(self.editor_camera);
+        if self.resolution_changed{
+            state.set_virtual_resolution(self.virtual_resolution);
+            self.resolution_changed = false;
+        }
+    }
+
+    /// Takes the cameras `State::update` just laid out, for the conversions below and the Lua `screen` table.
+    fn sync_screen_space(&mut self){
+        *self.screen.lock().unwrap() = self.state.borrow().screen_space();
+    }
+
+    /// The world point under a window position in logical pixels, through the topmost camera there.
+    /// `None` where no camera is drawn.
+    pub fn screen_to_world(&self, x: f32, y: f32) -> Option<(f32, f32)>{
+        self.screen.lock().unwrap().screen_to_world((x, y))
+    }
+
+    /// Where a world point is drawn by the main camera, in logical pixels.
+    pub fn world_to_screen(&self, x: f32, y: f32) -> Option<(f32, f32)>{
+        self.screen.lock().unwrap().world_to_screen((x, y))
+    }
+
+    /// The cursor in logical pixels from the window's top left.
+    pub fn mouse_position(&self) -> (f32, f32){
+        self.screen.lock().unwrap().mouse_position()
+    }
+
+    /// The world point under the cursor, see `screen_to_world`.
+    pub fn mouse_world_position(&self) -> Option<(f32, f32)>{
+        self.screen.lock().unwrap().mouse_world_position()
+    }
+
+    /// The cursor in the virtual resolution's pixels, `None` over the letterbox bars.
+    pub fn mouse_virtual_position(&self) -> Option<(f32, f32)>{
+        self.screen.lock().unwrap().mouse_virtual_position()
+    }
+
+    /// Sets the size the game is designed for and how it's fitted into the window, `None` to follow the window.
+    /// Takes effect from the next frame.
+    pub fn set_virtual_resolution(&mut self, resolution: Option<VirtualResolution>){
+        self.virtual_resolution = resolution;
+        self.resolution_changed = true;
+    }
+
+    pub fn virtual_resolution(&self) -> Option<VirtualResolution>{
+        self.virtual_resolution
+    }
+
+    /// Loads a sound through the asset manager and makes it playable under `name`.
+    pub fn load_sound(&mut self, name: &str, path: &str) -> anyhow::Result<()>{
+        let sound = self.assets.load(path)?;
+        self.audio_manager.add_sound(name, sound);
+        Ok(())
+    }
+
+    /// Loads a TTF or OTF font through the asset manager. Once it's loaded it becomes
+    /// the first choice for proportional text in the game and editor UI.
+    pub fn load_font(&mut self, path: &str) -> anyhow::Result<()>{
+        self.fonts.push(self.assets.load(path)?);
+        Ok(())
+    }
+
+    /// Hands fonts that finished loading to egui.
+    fn install_fonts(&mut self, ctx: &egui::Context){
+        self.fonts.retain(|font|{
+            let Some(data) = font.get() else{
+                return !font.is_failed();
+            };
+            ctx.add_font(egui::epaint::text::FontInsert::new(
+                font.path(),
+                egui::FontData::from_owned(data.data.clone()),
+                vec![egui::epaint::text::InsertFontFamily { family: egui::FontFamily::Proportional, priority: egui::epaint::text::FontPriority::Highest }]
+            ));
+            false
+        });
+    }
+
+    /// A texture filled from raw RGBA8 pixels, for procedural content like a minimap.
+    /// Change it later with `update_texture`.
+    pub fn create_texture(&mut self, name: &str, width: u32, height: u32, pixels: &[u8], settings: TextureSettings) -> anyhow::Result<Handle<Texture>>{
+        let texture = self.state.borrow().create_texture_from_pixels(width, height, pixels, settings, name)?;
+        Ok(Handle::from_asset(name, texture))
+    }
+
+    /// Replaces the `width` x `height` rectangle at `x`, `y` (from the top left) of a texture made with `create_texture`.
+    pub fn update_texture(&mut self, texture: &Handle<Texture>, x: u32, y: u32, width: u32, height: u32, pixels: &[u8]) -> anyhow::Result<()>{
+        let texture = texture.get().with_context(|| format!("{} is not loaded", texture.path()))?;
+        self.state.borrow().update_texture(&texture, x, y, width, height, pixels)
+    }
+
+    /// Renders the world seen from `position` into a texture every frame, before the window.
+    /// The handle can go on a `Sprite` or be shown in egui through its `view`;
+    /// the target stops rendering once every handle to it is dropped.
+    pub fn create_render_target(&mut self, name: &str, width: u32, height: u32, position: (f32, f32), scale: f32) -> anyhow::Result<Handle<Texture>>{
+        self.state.borrow_mut().add_render_target(name, width, height, position, scale)
+    }
+
+    /// Moves a render target's camera; `scale` is the half height of the view in world units.
+    pub fn set_render_target_view(&mut self, target: &Handle<Texture>, position: (f32, f32), scale: f32) -> anyhow::Result<()>{
+        if !self.state.borrow_mut().set_render_target_view(target, position, scale){
+            anyhow::bail!("{} is not a render target", target.path());
+        }
+        Ok(())
+    }
+
+    /// `false` when audio goes to the null output.
+    pub fn has_audio_device(&self) -> bool{
+        !self.audio_output.is_null()
+    }
+
+    fn snapshot_transforms(&mut self){
+        for (_id, transform) in &mut self.world.query::<&TransformComponent>(){
+            transform.lock().unwrap().snapshot();
+        }
+    }
+
+    /// Marks where the fixed steps left every transform, so only what they moved gets interpolated.
+    fn finish_transform_steps(&mut self){
+        for (_id, transform) in &mut self.world.query::<&TransformComponent>(){
+            transform.lock().unwrap().finish_steps();
         }
     }
 }
 
 struct ScriptEditting{
     entity: Entity,
-    script: String
+    script: String,
+    /// Line and column (1-based) to move the cursor to on the next frame.
+    goto: Option<(usize, usize)>
+}
+
+impl ScriptEditting{
+    fn new(entity: Entity, script: &Script) -> Self{
+        let goto = match &script.state{
+            components::ScriptState::Err(e) => e.line.map(|line| (line, e.column.unwrap_or(1))),
+            components::ScriptState::Ok => None
+        };
+        Self { entity, script: script.get_script(), goto }
+    }
+}
+
+/// Live editing of a `ParticleEmitter` in the objects window.
+fn particles_inspector(ui: &mut egui::Ui, emitter: &mut components::ParticleEmitter){
+    ui.label(format!("{} / {} particles", emitter.particles().len(), emitter.max_particles));
+    ui.checkbox(&mut emitter.emitting, "emitting");
+    let range = |ui: &mut egui::Ui, name: &str, (min, max): &mut (f32, f32)|{
+        ui.horizontal(|ui|{
+            ui.label(name);
+            ui.add(egui::DragValue::new(min).speed(0.01).range(0.0..=f32::MAX));
+            ui.add(egui::DragValue::new(max).speed(0.01).range(0.0..=f32::MAX));
+        });
+    };
+    ui.horizontal(|ui|{
+        ui.label("rate: ");
+        ui.add(egui::DragValue::new(&mut emitter.rate).speed(0.1).range(0.0..=f32::MAX));
+        ui.label("max: ");
+        ui.add(egui::DragValue::new(&mut emitter.max_particles).range(0..=100_000));
+    });
+    range(ui, "lifetime: ", &mut emitter.lifetime);
+    range(ui, "speed: ", &mut emitter.speed);
+    ui.horizontal(|ui|{
+        ui.label("direction: ");
+        ui.drag_angle(&mut emitter.direction);
+        ui.label("spread: ");
+        ui.drag_angle(&mut emitter.spread);
+    });
+    ui.horizontal(|ui|{
+        ui.label("gravity: ");
+        ui.add(egui::DragValue::new(&mut emitter.gravity.0).speed(0.01));
+        ui.add(egui::DragValue::new(&mut emitter.gravity.1).speed(0.01));
+    });
+    ui.horizontal(|ui|{
+        ui.label("color: ");
+        for (_, color) in &mut emitter.color.keys{
+            ui.color_edit_button_rgba_unmultiplied(color);
+        }
+    });
+    ui.horizontal(|ui|{
+        ui.label("size: ");
+        for (_, size) in &mut emitter.size.keys{
+            ui.add(egui::DragValue::new(size).speed(0.01).range(0.0..=f32::MAX));
+        }
+    });
+    ui.horizontal(|ui|{
+        ui.label("rotation: ");
+        for (_, rotation) in &mut emitter.rotation.keys{
+            ui.drag_angle(rotation);
+        }
+    });
+    ui.horizontal(|ui|{
+        ui.label("blend: ");
+        ui.selectable_value(&mut emitter.blend, components::ParticleBlend::Alpha, "alpha");
+        ui.selectable_value(&mut emitter.blend, components::ParticleBlend::Additive, "additive");
+    });
+    ui.horizontal(|ui|{
+        ui.label("layer: ");
+        ui.add(egui::DragValue::new(&mut emitter.layer).range(0..=31));
+    });
+    ui.horizontal(|ui|{
+        let mut seed = emitter.seed();
+        ui.label("seed: ");
+        let changed = ui.add(egui::DragValue::new(&mut seed)).changed();
+        if changed || ui.button("Restart").clicked(){
+            emitter.restart(seed);
+        }
+    });
+}
+
+/// Picking the virtual resolution in the objects window, and where the cursor is in each space.
+fn resolution_inspector(ui: &mut egui::Ui, game_manager: &mut GameManager){
+    let current = game_manager.virtual_resolution();
+    let mut mode = current.map(|resolution| resolution.mode);
+    ui.horizontal(|ui|{
+        ui.label("mode: ");
+        ui.selectable_value(&mut mode, None, "window");
+        ui.selectable_value(&mut mode, Some(ScaleMode::Stretch), "stretch");
+        ui.selectable_value(&mut mode, Some(ScaleMode::Letterbox), "letterbox");
+        ui.selectable_value(&mut mode, Some(ScaleMode::Integer), "integer");
+        ui.selectable_value(&mut mode, Some(ScaleMode::Expand), "expand");
+    });
+    let mut resolution = mode.map(|mode| VirtualResolution { mode, ..current.unwrap_or(VirtualResolution::new(640, 360, mode)) });
+    if let Some(resolution) = &mut resolution{
+        ui.horizontal(|ui|{
+            ui.label("size: ");
+            ui.add(egui::DragValue::new(&mut resolution.width).range(1..=4096));
+            ui.add(egui::DragValue::new(&mut resolution.height).range(1..=4096));
+        });
+        ui.checkbox(&mut resolution.pixel_perfect, "pixel perfect");
+    }
+    if resolution != current{
+        game_manager.set_virtual_resolution(resolution);
+    }
+
+    let (x, y) = game_manager.mouse_position();
+    ui.label(format!("mouse: {:.0}, {:.0}", x, y));
+    if let Some((x, y)) = game_manager.mouse_virtual_position(){
+        ui.label(format!("virtual: {:.1}, {:.1}", x, y));
+    }
+    if let Some((x, y)) = game_manager.mouse_world_position(){
+        ui.label(format!("world: {:.2}, {:.2}", x, y));
+    }
+}
+
+/// Toggling and tweaking the post-processing passes in the objects window.
+fn post_processing_inspector(ui: &mut egui::Ui, passes: &mut [PostPass]){
+    if passes.is_empty(){
+        ui.label(RichText::new("no passes").color(Color32::GRAY));
+    }
+    for (index, pass) in passes.iter_mut().enumerate(){
+        ui.push_id(index, |ui|{
+            ui.checkbox(&mut pass.enabled, &pass.name);
+            let drag = |ui: &mut egui::Ui, name: &str, value: &mut f32, speed: f64|{
+                ui.label(name);
+                ui.add(egui::DragValue::new(value).speed(speed).range(0.0..=f32::MAX));
+            };
+            ui.horizontal(|ui|{
+                match &mut pass.effect{
+                    PostEffect::Bloom { threshold, intensity, radius } => {
+                        drag(ui, "threshold: ", threshold, 0.01);
+                        drag(ui, "intensity: ", intensity, 0.01);
+                        drag(ui, "radius: ", radius, 0.1);
+                    },
+                    PostEffect::ColorGrading { lut, strength } => {
+                        if lut.get().is_none(){
+                            ui.label(RichText::new("LUT not loaded").color(Color32::GRAY));
+                        }
+                        ui.label("strength: ");
+                        ui.add(egui::Slider::new(strength, 0.0..=1.0));
+                    },
+                    PostEffect::Vignette { intensity, radius, softness } => {
+                        ui.label("intensity: ");
+                        ui.add(egui::Slider::new(intensity, 0.0..=1.0));
+                        drag(ui, "radius: ", radius, 0.01);
+                        drag(ui, "softness: ", softness, 0.01);
+                    },
+                    PostEffect::Crt { scanlines, count, curvature } => {
+                        ui.label("scanlines: ");
+                        ui.add(egui::Slider::new(scanlines, 0.0..=1.0));
+                        drag(ui, "count: ", count, 1.0);
+                        drag(ui, "curvature: ", curvature, 0.001);
+                    },
+                    PostEffect::Custom { path, params } => {
+                        ui.label(path.as_str());
+                        for param in params{
+                            ui.add(egui::DragValue::new(param).speed(0.01));
+                        }
+                    }
+                }
+            });
+        });
+    }
 }
 
 pub struct App<T>
@@ -62,12 +584,19 @@ pub struct App<T>
 {
     state: Option<Rc<RefCell<State>>>,
     last_frame_time: Instant,
+    frame_dt: f32,
+    timestep: FixedTimestep,
     game: T,
     game_manager: Option<GameManager>,
     /*Script*/
     script_editting: Option<ScriptEditting>,
-    game_paused: bool,
-    show_debug_window: bool
+    show_debug_window: bool,
+    show_console: bool,
+    console_view: ConsoleView,
+    show_repl: bool,
+    repl: Repl,
+    show_tile_painter: bool,
+    tile_painter: TilePainter
 }
 
 
@@ -78,19 +607,92 @@ impl<T> App<T>
         Self { state: None,
             game_manager: None,
             last_frame_time: Instant::now(),
+            frame_dt: 0.0,
+            timestep: FixedTimestep::default(),
             game,
             script_editting: None,
-            game_paused: true,
-            show_debug_window: false
+            show_debug_window: false,
+            show_console: false,
+            console_view: ConsoleView::new(),
+            show_repl: false,
+            repl: Repl::new(),
+            show_tile_painter: false,
+            tile_painter: TilePainter::new()
          }
     }
 
+    /// Sets how many fixed simulation steps run per second.
+    pub fn set_fixed_rate(&mut self, rate: f32){
+        self.timestep.set_rate(rate);
+    }
+
+    /// Sets the directory asset paths are relative to, instead of the working directory.
+    pub fn set_asset_root(&mut self, root: impl Into<std::path::PathBuf>){
+        vfs::vfs().write().unwrap().set_root(root);
+    }
+
+    /// Mounts a directory or archive over the asset root; higher priorities win.
+    /// Use a positive priority for overlay and mod directories.
+    pub fn mount(&mut self, path: impl AsRef<std::path::Path>, priority: i32) -> std::io::Result<()>{
+        let path = path.as_ref();
+        let mut vfs = vfs::vfs().write().unwrap();
+        if path.is_dir(){
+            vfs.mount_dir(path, priority);
+            Ok(())
+        } else{
+            vfs.mount_archive(path, priority)
+        }
+    }
+
+    /// Caps the fixed steps run in one frame; remaining time is dropped.
+    pub fn set_max_fixed_steps(&mut self, max_steps: u32){
+        self.timestep.set_max_steps(max_steps);
+    }
+
     fn get_dt(&mut self) -> f32 {
         let now = Instant::now();
         let delta_time = now.duration_since(self.last_frame_time);
         self.last_frame_time = now;
         delta_time.as_secs_f32()
     }
+
+    /// Advances the simulation by one frame and returns the render interpolation factor.
+    /// Gameplay runs on the scaled clock, the camera and editor on the unscaled one.
+    fn step_frame(&mut self) -> f32 {
+        let dt = self.get_dt();
+        self.frame_dt = dt;
+
+        let gm = self.game_manager.as_mut().unwrap();
+        let state = self.state.as_ref().unwrap();
+
+        gm.time.advance(dt);
+        gm.time.set_fixed_delta(self.timestep.step());
+        gm.assets.update();
+        gm.hot_reload();
+
+        let steps = self.timestep.advance(gm.time.delta());
+        let step = self.timestep.step();
+        for _ in 0..steps{
+            gm.snapshot_transforms();
+            if gm.time.is_running(System::Simulation){
+                self.game.fixed_update(gm, step);
+            }
+            gm.fixed_update(step);
+        }
+        if steps > 0{
+            gm.finish_transform_steps();
+        }
+
+        self.game.update(gm, dt);
+        gm.update();
+        let alpha = if gm.time.is_paused() { 1.0 } else { self.timestep.alpha() };
+
+        gm.update_cameras(alpha);
+        state.borrow_mut().update(dt);
+        gm.sync_screen_space();
+        gm.update_audio();
+        alpha
+    }
 }
 
     
@@ -108,6 +710,7 @@ impl<T> ApplicationHandler for App<T>
         
         self.game_manager = Some(GameManager::new(state.clone()));
         let gm = self.game_manager.as_mut().unwrap();
+        gm.time.set_paused(true);
         self.game.on_start(gm);
         
         self.state = Some(state);
@@ -117,38 +720,31 @@ impl<T> ApplicationHandler for App<T>
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
-        let dt = self.get_dt();
+        let alpha = if let WindowEvent::RedrawRequested = event {
+            self.step_frame()
+        } else {
+            1.0
+        };
+        let dt = self.frame_dt;
 
         let gm = self.game_manager.as_mut().unwrap();
-        self.game.update(gm, dt);
-
         let state = self.state.as_mut().unwrap();
         let mut state = state.borrow_mut();
         state.input(&event);
-        state.update(dt);
-
-
-        if !self.game_paused{
-            gm.update(dt);
-        }
 
         match event {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             },
-            WindowEvent::MouseInput { device_id:_, state: state_event, button } =>{
-                if button == MouseButton::Left && state_event == ElementState::Pressed{
-                    println!("{}", state.pick());
-                }
+            WindowEvent::MouseInput { device_id:_, state: state_event, button } if button == MouseButton::Left && state_event == ElementState::Pressed =>{
+                println!("{}", state.pick());
             },
-            WindowEvent::KeyboardInput { device_id: _dt, event, is_synthetic: _ } => {
-                // println!("{:?} {:?}", event.physical_key, event.logical_key);
-                if event.physical_key == PhysicalKey::Code(winit::keyboard::KeyCode::Backquote) && event.state == ElementState::Pressed{
-                    self.show_debug_window = !self.show_debug_window;
-                }
+            WindowEvent::KeyboardInput { device_id: _dt, event, is_synthetic: _ } if event.physical_key == PhysicalKey::Code(winit::keyboard::KeyCode::Backquote) && event.state == ElementState::Pressed => {
+                self.show_debug_window = !self.show_debug_window;
             },
             WindowEvent::RedrawRequested => {
                 state.render(|game_mananger: &mut GameManager, renderer| {
+                    game_mananger.install_fonts(renderer.context());
                     if self.show_debug_window{
                     egui::Window::new("Objects").frame(
                         Frame::window(&egui::Style::default()).fill(Color32::from_rgba_premultiplied(0, 0, 0, 100))
@@ -158,52 +754,120 @@ impl<T> ApplicationHandler for App<T>
                     .default_open(true)
                     .show(&renderer.context().clone(), |ui| {
                             ui.label(format!("fps: {:.2}", 1.0/dt));
+                            ui.label(format!("assets: {}", game_mananger.assets.loaded_count()));
+                            if game_mananger.assets.pending_count() > 0{
+                                ui.label(format!("loading assets: {:.0}%", game_mananger.assets.progress() * 100.0));
+                            }
                             
-                            if ui.button(if self.game_paused {"Start"} else {"Stop"}).clicked(){
-                                    self.game_paused = !self.game_paused;
+                            ui.checkbox(&mut game_mananger.editor_camera, "Editor camera (WASD)");
+
+                            let paused = game_mananger.time.is_paused();
+                            if ui.button(if paused {"Start"} else {"Stop"}).clicked(){
+                                    game_mananger.time.set_paused(!paused);
+                            }
+                            ui.horizontal(|ui|{
+                                ui.label("run: ");
+                                for system in SYSTEMS{
+                                    let mut running = !game_mananger.time.is_system_paused(system);
+                                    if ui.checkbox(&mut running, system.name()).changed(){
+                                        game_mananger.time.set_system_paused(system, !running);
+                                    }
+                                }
+                            });
+
+                            ui.horizontal(|ui|{
+                                let mut time_scale = game_mananger.time.time_scale();
+                                ui.add(egui::Label::new("time scale: "));
+                                if ui.add(egui::Slider::new(&mut time_scale, 0.0..=2.0)).changed(){
+                                    game_mananger.time.set_time_scale(time_scale);
+                                }
+                            });
+
+                            ui.collapsing("Audio", |ui|{
+                                if !game_mananger.has_audio_device(){
+                                    ui.label(RichText::new("no output device").color(Color32::GRAY));
+                                }
+                                for bus in [audio::MASTER, audio::SFX, audio::MUSIC]{
+                                    let mut volume = game_mananger.audio_manager.bus_volume(bus).unwrap_or(1.0);
+                                    if ui.add(egui::Slider::new(&mut volume, 0.0..=1.0).text(bus)).changed(){
+                                        game_mananger.audio_manager.set_bus_volume(bus, volume);
+                                    }
+                                }
+                            });
+
+                            ui.collapsing("Asset mounts", |ui|{
+                                for (name, priority) in vfs::vfs().read().unwrap().mounts(){
+                                    ui.label(format!("{}: {}", priority, name));
+                                }
+                            });
+
+                            ui.collapsing("Resolution", |ui| resolution_inspector(ui, game_mananger));
+
+                            ui.collapsing("Post-processing", |ui| post_processing_inspector(ui, &mut game_mananger.post_passes));
+
+                            ui.horizontal(|ui|{
+                                ui.checkbox(&mut game_mananger.file_watcher.enabled, "hot reload");
+                                ui.checkbox(&mut self.show_console, "console");
+                                ui.checkbox(&mut self.show_repl, "lua");
+                                ui.checkbox(&mut self.show_tile_painter, "tiles");
+                            });
+                            if !game_mananger.reload_errors.is_empty(){
+                                for e in &game_mananger.reload_errors{
+                                    ui.label(RichText::new(e).color(Color32::RED));
+                                }
+                                if ui.button("Clear errors").clicked(){
+                                    game_mananger.reload_errors.clear();
+                                }
                             }
 
                             for (id, label) in &mut game_mananger.world.query::<&components::Label>(){
                                 ui.collapsing(format!("id: {}, label: {}", label.id, label.label), |ui|{
-                                    let transform = game_mananger.world.get::<&TransformComponent>(id);
-                                    match transform{
-                                        Ok(transform) => {
-                                            let mut transform = transform.lock().unwrap();
-                                            ui.collapsing("Transform", |ui|{
-                                                ui.horizontal(|ui|{
-                                                    ui.add(egui::Label::new("x: "));
-                                                    ui.add(egui::DragValue::new(&mut transform.position.x).speed(0.01));
-                                                    ui.add(egui::Label::new("y: "));
-                                                    ui.add(egui::DragValue::new(&mut transform.position.y).speed(0.01));
-                                                    ui.add(egui::Label::new("rotation: "));
-                                                    ui.add(egui::DragValue::new(&mut transform.rotation.angle).speed(0.01));
-                                                });
+                                    if let Ok(transform) = game_mananger.world.get::<&TransformComponent>(id){
+                                        let mut transform = transform.lock().unwrap();
+                                        ui.collapsing("Transform", |ui|{
+                                            ui.horizontal(|ui|{
+                                                ui.add(egui::Label::new("x: "));
+                                                ui.add(egui::DragValue::new(&mut transform.position.x).speed(0.01));
+                                                ui.add(egui::Label::new("y: "));
+                                                ui.add(egui::DragValue::new(&mut transform.position.y).speed(0.01));
+                                                ui.add(egui::Label::new("rotation: "));
+                                                ui.add(egui::DragValue::new(&mut transform.rotation.angle).speed(0.01));
                                             });
-                                            
-                                        },
-                                        Err(e) => {}
+                                        });
                                     }
-                                    let sprite = game_mananger.world.get::<&components::Sprite>(id);
-                                    match sprite{
-                                        Ok(sprite) => {
-                                            ui.collapsing("Sprite", |ui|{
-                                                let texture_id = renderer.register_texture(&sprite.texture.view);
+                                    if let Ok(sprite) = game_mananger.world.get::<&components::Sprite>(id){
+                                        ui.collapsing("Sprite", |ui|{
+                                            ui.label(sprite.texture.path());
+                                            if let assets::LoadState::Failed(e) = sprite.texture.state(){
+                                                ui.label(RichText::new(e).color(Color32::RED));
+                                            }
+                                            if let Some(texture) = sprite.texture.get(){
+                                                let texture_id = renderer.register_texture(&texture.view);
                                                 ui.image((texture_id, egui::vec2(100.0, 100.0)));
-                                            });
-                                        }
-                                        _ => {}
+                                            }
+                                            if let Some(material) = &sprite.material{
+                                                ui.label(format!("material: {}", material.path()));
+                                                if let assets::LoadState::Failed(e) = material.state(){
+                                                    ui.label(RichText::new(e).color(Color32::RED));
+                                                }
+                                            }
+                                        });
                                     }
 
-                                    let script = game_mananger.world.get::<&components::Script>(id);
-                                    match script {
-                                        Ok(script)=>{
-                                            ui.collapsing("Script", |ui|{
-                                                if ui.button("Edit").clicked(){
-                                                    self.script_editting = Some(ScriptEditting { entity: id, script: script.get_script() });
-                                                }
-                                            });
-                                        },
-                                        _ => {}
+                                    if let Ok(mut emitter) = game_mananger.world.get::<&mut components::ParticleEmitter>(id){
+                                        ui.collapsing("Particles", |ui| particles_inspector(ui, &mut emitter));
+                                    }
+
+                                    if let Ok(script) = game_mananger.world.get::<&components::Script>(id){
+                                        ui.collapsing("Script", |ui|{
+                                            if ui.button("Edit").clicked(){
+                                                self.script_editting = Some(ScriptEditting::new(id, &script));
+                                            }
+                                            if let components::ScriptState::Err(e) = &script.state
+                                                && ui.link(RichText::new(e.to_string()).color(Color32::RED)).clicked(){
+                                                self.script_editting = Some(ScriptEditting::new(id, &script));
+                                            }
+                                        });
                                     }
                                 });
                             }
@@ -211,8 +875,7 @@ impl<T> ApplicationHandler for App<T>
 
                     let mut close_clicked = false;
 
-                    match &mut self.script_editting {
-                        Some(script_editting)=>{
+                    if let Some(script_editting) = &mut self.script_editting {
                         egui::Window::new("Script")
                         .frame(
         Frame::window(&egui::Style::default()).fill(Color32::from_rgba_premultiplied(0, 0, 0, 100))
@@ -221,7 +884,7 @@ impl<T> ApplicationHandler for App<T>
                         .vscroll(true)
                         .default_open(true)
                         .show(&renderer.context().clone(), |ui| {
-                            CodeEditor::default()
+                            let output = CodeEditor::default()
                                 .id_source("code editor")
                                 .with_rows(12)
                                 .with_fontsize(11.0)
@@ -229,6 +892,17 @@ impl<T> ApplicationHandler for App<T>
                                 .with_syntax(Syntax::lua())
                                 .with_numlines(true)
                                 .show(ui, &mut script_editting.script);
+
+                            if let Some((line, column)) = script_editting.goto.take(){
+                                let index: usize = script_editting.script.lines().take(line.saturating_sub(1)).map(|l| l.chars().count() + 1).sum::<usize>() + column.saturating_sub(1);
+                                let cursor = egui::text::CCursor::new(index);
+                                let mut text_state = output.state;
+                                text_state.cursor.set_char_range(Some(egui::text::CCursorRange::one(cursor)));
+                                text_state.store(ui.ctx(), output.response.id);
+                                output.response.request_focus();
+                                let rect = output.galley.pos_from_ccursor(cursor).translate(output.galley_pos.to_vec2());
+                                ui.scroll_to_rect(rect, Some(egui::Align::Center));
+                            }
                             let mut script = game_mananger.world.get::<&mut components::Script>(script_editting.entity).unwrap();
 
                             if ui.button("Save").clicked(){
@@ -236,30 +910,35 @@ impl<T> ApplicationHandler for App<T>
                             }
 
                             if ui.button("Reload").clicked(){
-                                script.reload();
+                                if let Some(path) = script.path(){
+                                    for e in game_mananger.assets.reload_file(&path).1{
+                                        game_mananger.reload_errors.push(format!("{}: {:#}", path.display(), e));
+                                    }
+                                }
+                                script.sync_source();
                                 script_editting.script = script.get_script();
                             }
 
                             close_clicked = ui.button("Close").clicked();
                             
-                            match &script.state{
-                                components::ScriptState::Ok => {},
-                                components::ScriptState::Err(e) =>{
-                                    ui.label(RichText::new(e).color(Color32::RED));
-                                }
+                            if let components::ScriptState::Err(e) = &script.state
+                                && ui.link(RichText::new(e.to_string()).color(Color32::RED)).clicked(){
+                                script_editting.goto = e.line.map(|line| (line, e.column.unwrap_or(1)));
                             }
                         });
-                        },
-                        _ => {}
                     }
 
                     if close_clicked{
                         self.script_editting = None;
                     }
 
+                    self.console_view.show(&renderer.context().clone(), &mut self.show_console);
+                    self.repl.show(&renderer.context().clone(), game_mananger, &mut self.show_repl);
+                    self.tile_painter.show(&renderer.context().clone(), renderer, game_mananger, &mut self.show_tile_painter);
+
                     }
                     self.game.on_ui(game_mananger, renderer);
-                }, gm);
+                }, gm, alpha);
                 state.get_window().request_redraw();
             }
             WindowEvent::Resized(size) => {
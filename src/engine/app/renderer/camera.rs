@@ -1,8 +1,27 @@
+use egui_wgpu::wgpu;
+use wgpu::util::DeviceExt;
+
+use crate::engine::app::game::components::Viewport;
+
+#[derive(Clone, Copy)]
 pub struct Camera {
     pub position: cgmath::Point3<f32>,
     pub speed: f32,
     pub aspect: f32,
-    pub scale: f32
+    pub scale: f32,
+    /// Radians, counterclockwise.
+    pub rotation: f32
+}
+
+/// What the game camera shows this frame, set by `GameManager` from the active `Camera2D`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraView {
+    pub position: (f32, f32),
+    pub zoom: f32,
+    pub rotation: f32,
+    pub viewport: Viewport,
+    /// Bit `n` set shows sprites on layer `n`.
+    pub layer_mask: u32
 }
 
 #[rustfmt::skip]
@@ -16,8 +35,32 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 
 impl Camera {
     fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let proj = cgmath::ortho((-1.0*self.aspect)*self.scale  + self.position.x, (1.0*self.aspect)*self.scale  + self.position.x, (-1.0*self.scale) + self.position.y, (1.0*self.scale) + self.position.y, -1.0, 1.0);
-        return OPENGL_TO_WGPU_MATRIX * proj;
+        let proj = cgmath::ortho(-self.aspect*self.scale, self.aspect*self.scale, -self.scale, self.scale, -1.0, 1.0);
+        let view = cgmath::Matrix4::from_angle_z(cgmath::Rad(-self.rotation))
+            * cgmath::Matrix4::from_translation(cgmath::vec3(-self.position.x, -self.position.y, 0.0));
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+
+    /// The world point drawn at `point`, both in physical pixels, for a camera drawn into
+    /// `viewport` (x, y, width, height from the window's top left). The camera's `aspect`
+    /// is expected to match the viewport's.
+    pub fn screen_to_world(&self, point: (f32, f32), viewport: (f32, f32, f32, f32)) -> (f32, f32) {
+        use cgmath::SquareMatrix;
+        let ndc_x = (point.0 - viewport.0) / viewport.2 * 2.0 - 1.0;
+        let ndc_y = 1.0 - (point.1 - viewport.1) / viewport.3 * 2.0;
+        let matrix = self.build_view_projection_matrix();
+        // The depth the z = 0 plane ends up at, whatever the projection does with z.
+        let plane = matrix * cgmath::vec4(self.position.x, self.position.y, 0.0, 1.0);
+        let inverse = matrix.invert().unwrap_or(cgmath::Matrix4::identity());
+        let world = inverse * cgmath::vec4(ndc_x, ndc_y, plane.z / plane.w, 1.0);
+        (world.x / world.w, world.y / world.w)
+    }
+
+    /// Where `point` in world units is drawn, in physical pixels; see `screen_to_world`.
+    pub fn world_to_screen(&self, point: (f32, f32), viewport: (f32, f32, f32, f32)) -> (f32, f32) {
+        let clip = self.build_view_projection_matrix() * cgmath::vec4(point.0, point.1, 0.0, 1.0);
+        let (ndc_x, ndc_y) = (clip.x / clip.w, clip.y / clip.w);
+        (viewport.0 + (ndc_x + 1.0) * 0.5 * viewport.2, viewport.1 + (1.0 - ndc_y) * 0.5 * viewport.3)
     }
 }
 
@@ -40,6 +83,45 @@ impl CameraUniform {
     }
 }
 
+/// A camera uniform buffer and the bind group the sprite shader reads it through.
+pub struct CameraBinding {
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl CameraBinding {
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, camera: &Camera) -> Self {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(camera);
+
+        let buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Camera Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("camera_bind_group"),
+        });
+
+        Self { uniform, buffer, bind_group }
+    }
+
+    pub fn write(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        self.uniform.update_view_proj(camera);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
 use winit::{
     event::{ElementState, KeyEvent, WindowEvent},
     keyboard::{KeyCode, PhysicalKey}
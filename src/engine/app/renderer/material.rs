@@ -0,0 +1,415 @@
+use std::sync::{Arc, Weak};
+
+use anyhow::{bail, Context, Result};
+use egui_wgpu::wgpu;
+use wgpu::util::DeviceExt;
+
+use super::{render_data::Vertex, shaders, texture::Texture};
+use crate::engine::app::vfs;
+
+/// How a material is blended over what's behind it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MaterialBlend{
+    #[default]
+    Alpha,
+    /// For shaders that output premultiplied color.
+    Premultiplied,
+    Additive
+}
+
+impl MaterialBlend{
+    /// The blending for sprites whose texture is `premultiplied`: their color is already multiplied by alpha.
+    pub fn for_texture(self, premultiplied: bool) -> Self{
+        match self{
+            MaterialBlend::Alpha if premultiplied => MaterialBlend::Premultiplied,
+            blend => blend
+        }
+    }
+
+    fn state(self) -> wgpu::BlendState{
+        match self{
+            MaterialBlend::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            MaterialBlend::Premultiplied => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            MaterialBlend::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::SrcAlpha, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+                alpha: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::Zero, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+            }
+        }
+    }
+}
+
+/// A `.material` file: the fragment shader, blending, and the uniforms and textures it reads.
+///
+/// ```text
+/// shader = dissolve.wgsl
+/// blend = alpha             # alpha, premultiplied or additive
+/// param amount = 0.5        # up to four numbers, read as material.amount.x
+/// param edge = 1 0.5 0 1
+/// texture noise = noise.png # read with textureSample(noise, noise_sampler, uv)
+/// ```
+///
+/// Paths are relative to the material file. The shader only has to define
+/// `fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>`; `VertexOutput` has `tex_coords`
+/// and the sprite's texture is `t_diffuse` and `s_diffuse`, see `PRELUDE`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialSource{
+    pub shader: String,
+    pub blend: MaterialBlend,
+    pub params: Vec<(String, [f32; 4])>,
+    pub textures: Vec<(String, String)>
+}
+
+impl MaterialSource{
+    pub fn parse(path: &str, text: &str) -> Result<Self>{
+        let mut shader = None;
+        let mut source = Self { shader: String::new(), blend: MaterialBlend::Alpha, params: Vec::new(), textures: Vec::new() };
+        for (number, line) in text.lines().enumerate(){
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty(){
+                continue;
+            }
+            let (key, value) = line.split_once('=').with_context(|| format!("line {}: expected key = value", number + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key.split_once(char::is_whitespace).map(|(kind, name)| (kind, name.trim())){
+                Some(("param", name)) => {
+                    check_name(&source, name, number)?;
+                    let numbers = value.split_whitespace()
+                        .map(|number| number.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .with_context(|| format!("line {}: {} is not a list of numbers", number + 1, value))?;
+                    if numbers.is_empty() || numbers.len() > 4{
+                        bail!("line {}: a param has one to four numbers", number + 1);
+                    }
+                    let mut param = [0.0; 4];
+                    param[..numbers.len()].copy_from_slice(&numbers);
+                    source.params.push((name.to_string(), param));
+                },
+                Some(("texture", name)) => {
+                    check_name(&source, name, number)?;
+                    source.textures.push((name.to_string(), vfs::resolve(path, value)));
+                },
+                _ => match key{
+                    "shader" => shader = Some(vfs::resolve(path, value)),
+                    "blend" => source.blend = match value{
+                        "alpha" => MaterialBlend::Alpha,
+                        "premultiplied" => MaterialBlend::Premultiplied,
+                        "additive" => MaterialBlend::Additive,
+                        _ => bail!("line {}: unknown blend {}", number + 1, value)
+                    },
+                    _ => bail!("line {}: unknown setting {}", number + 1, key)
+                }
+            }
+        }
+        source.shader = shader.context("no shader = ... line")?;
+        Ok(source)
+    }
+
+    /// The shader and textures, so changing them reloads the material.
+    pub fn files(&self) -> Vec<String>{
+        std::iter::once(self.shader.clone()).chain(self.textures.iter().map(|(_, path)| path.clone())).collect()
+    }
+
+    /// The full shader: the sprite vertex stage and the material bindings, then `fragment`.
+    pub fn wgsl(&self, fragment: &str) -> String{
+        let mut wgsl = String::from(PRELUDE);
+        wgsl.push_str("struct MaterialParams {\n");
+        for (name, _) in &self.params{
+            wgsl.push_str(&format!("    {}: vec4<f32>,\n", name));
+        }
+        if self.params.is_empty(){
+            // Structs can't be empty.
+            wgsl.push_str("    unused: vec4<f32>,\n");
+        }
+        wgsl.push_str("};\n\n@group(3) @binding(0)\nvar<uniform> material: MaterialParams;\n");
+        for (index, (name, _)) in self.textures.iter().enumerate(){
+            let binding = 1 + index * 2;
+            wgsl.push_str(&format!("@group(3) @binding({})\nvar {}: texture_2d<f32>;\n", binding, name));
+            wgsl.push_str(&format!("@group(3) @binding({})\nvar {}_sampler: sampler;\n", binding + 1, name));
+        }
+        wgsl.push_str("\n// Material\n");
+        wgsl.push_str(fragment);
+        wgsl
+    }
+}
+
+/// Names become WGSL identifiers, so they have to be valid and unique.
+fn check_name(source: &MaterialSource, name: &str, number: usize) -> Result<()>{
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid{
+        bail!("line {}: {} is not a valid name", number + 1, name);
+    }
+    let taken = source.params.iter().map(|(name, _)| name).chain(source.textures.iter().map(|(name, _)| name)).any(|taken| taken == name);
+    if taken || matches!(name, "material" | "t_diffuse" | "s_diffuse" | "camera" | "modelMat"){
+        bail!("line {}: the name {} is already used", number + 1, name);
+    }
+    Ok(())
+}
+
+/// Put before `shader.wgsl` and every material's shader: the sprite vertex stage,
+/// the camera and model matrix it uses, and the sprite's texture.
+pub const PRELUDE: &str = "\
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+struct ModelMatrixUniform {
+    model_matrix: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var<uniform> modelMat: ModelMatrixUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = camera.view_proj * modelMat.model_matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+";
+
+/// A compiled material, drawn in place of the sprite shader by sprites that reference it.
+pub struct Material{
+    pub blend: MaterialBlend,
+    params: Vec<(String, [f32; 4])>,
+    textures: Vec<Texture>,
+    module: wgpu::ShaderModule,
+    layout: wgpu::BindGroupLayout,
+    /// With the params from the file.
+    bind_group: wgpu::BindGroup
+}
+
+impl Material{
+    /// Compiles the shader; WGSL errors come back as the error instead of a panic.
+    pub fn new(device: &wgpu::Device, source: &MaterialSource, fragment: &str, textures: Vec<Texture>, label: &str) -> Result<Self>{
+        let module = shaders::compile(device, label, &source.wgsl(fragment))?;
+
+        let mut entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        for index in 0..textures.len() as u32{
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 1 + index * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + index * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("material_bind_group_layout"),
+        });
+
+        let (_, bind_group) = params_bind_group(device, &layout, &param_values(&source.params, &[]), &textures);
+        Ok(Self { blend: source.blend, params: source.params.clone(), textures, module, layout, bind_group })
+    }
+
+    pub fn params(&self) -> &[(String, [f32; 4])]{
+        &self.params
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup{
+        &self.bind_group
+    }
+
+    /// The params of a sprite that overrides some of them, in their own buffer and bind group.
+    pub fn create_sprite_params(self: &Arc<Self>, device: &wgpu::Device, overrides: &[(String, [f32; 4])]) -> SpriteParams{
+        let values = param_values(&self.params, overrides);
+        let (buffer, bind_group) = params_bind_group(device, &self.layout, &values, &self.textures);
+        SpriteParams { material: Arc::downgrade(self), values, buffer, bind_group }
+    }
+
+    /// The sprite pipeline with this material's shader; `base_layouts` are the texture, camera and model matrix layouts.
+    /// Errors, e.g. a missing or mistyped `fs_main`, come back instead of panicking.
+    pub fn create_pipeline(&self, device: &wgpu::Device, base_layouts: [&wgpu::BindGroupLayout; 3], format: wgpu::TextureFormat, blend: MaterialBlend) -> Result<wgpu::RenderPipeline>{
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Material Pipeline Layout"),
+            bind_group_layouts: &[base_layouts[0], base_layouts[1], base_layouts[2], &self.layout],
+            push_constant_ranges: &[],
+        });
+        shaders::validated(device, || device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Material Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &self.module,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.module,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(blend.state()),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        }))
+    }
+}
+
+/// A sprite's material params, kept from frame to frame and written again when they change.
+pub struct SpriteParams{
+    material: Weak<Material>,
+    values: Vec<[f32; 4]>,
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup
+}
+
+impl SpriteParams{
+    /// True if these were made for `material`, which hasn't been reloaded since.
+    pub fn is_for(&self, material: &Arc<Material>) -> bool{
+        std::ptr::eq(self.material.as_ptr(), Arc::as_ptr(material))
+    }
+
+    /// Uploads the params again if `overrides` changed them.
+    pub fn update(&mut self, queue: &wgpu::Queue, material: &Material, overrides: &[(String, [f32; 4])]){
+        let values = param_values(&material.params, overrides);
+        if values != self.values{
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&values));
+            self.values = values;
+        }
+    }
+}
+
+/// The values of `params` with `overrides` applied, one `vec4` each, for the uniform buffer.
+fn param_values(params: &[(String, [f32; 4])], overrides: &[(String, [f32; 4])]) -> Vec<[f32; 4]>{
+    let mut values: Vec<[f32; 4]> = params.iter()
+        .map(|(name, value)| overrides.iter().find(|(other, _)| other == name).map_or(*value, |(_, value)| *value))
+        .collect();
+    if values.is_empty(){
+        values.push([0.0; 4]);
+    }
+    values
+}
+
+/// The uniform buffer of the param `values`, and the bind group with it and the textures.
+fn params_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, values: &[[f32; 4]], textures: &[Texture]) -> (wgpu::Buffer, wgpu::BindGroup){
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("material params buffer"),
+        contents: bytemuck::cast_slice(values),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }];
+    for (index, texture) in textures.iter().enumerate(){
+        let binding = 1 + index as u32 * 2;
+        entries.push(wgpu::BindGroupEntry { binding, resource: wgpu::BindingResource::TextureView(&texture.view) });
+        entries.push(wgpu::BindGroupEntry { binding: binding + 1, resource: wgpu::BindingResource::Sampler(&texture.sampler) });
+    }
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &entries,
+        label: Some("material bind group"),
+    });
+    (buffer, bind_group)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const DISSOLVE: &str = "\
+shader = dissolve.wgsl
+blend = additive
+param amount = 0.5   # how much is gone
+param edge = 1 0.5 0 1
+texture noise = ../noise.png
+";
+
+    #[test]
+    fn parses_materials(){
+        let source = MaterialSource::parse("materials/dissolve.material", DISSOLVE).unwrap();
+        assert_eq!(source.shader, "materials/dissolve.wgsl");
+        assert_eq!(source.blend, MaterialBlend::Additive);
+        assert_eq!(source.params, vec![("amount".to_string(), [0.5, 0.0, 0.0, 0.0]), ("edge".to_string(), [1.0, 0.5, 0.0, 1.0])]);
+        assert_eq!(source.textures, vec![("noise".to_string(), "noise.png".to_string())]);
+        assert_eq!(source.files(), vec!["materials/dissolve.wgsl".to_string(), "noise.png".to_string()]);
+
+        let wgsl = source.wgsl("// fragment");
+        assert!(wgsl.contains("    amount: vec4<f32>,\n    edge: vec4<f32>,\n"));
+        assert!(wgsl.contains("@group(3) @binding(1)\nvar noise: texture_2d<f32>;"));
+        assert!(wgsl.contains("@group(3) @binding(2)\nvar noise_sampler: sampler;"));
+        assert!(wgsl.ends_with("// fragment"));
+    }
+
+    #[test]
+    fn bundled_materials_compile(){
+        let materials = [
+            (include_str!("../../../../resources/materials/dissolve.material"), include_str!("../../../../resources/materials/dissolve.wgsl")),
+            (include_str!("../../../../resources/materials/outline.material"), include_str!("../../../../resources/materials/outline.wgsl"))
+        ];
+        for (material, fragment) in materials{
+            let wgsl = MaterialSource::parse("resources/materials/a.material", material).unwrap().wgsl(fragment);
+            let module = wgpu::naga::front::wgsl::parse_str(&wgsl).unwrap();
+            wgpu::naga::valid::Validator::new(Default::default(), Default::default()).validate(&module).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_bad_materials(){
+        assert!(MaterialSource::parse("a.material", "blend = alpha").is_err());
+        assert!(MaterialSource::parse("a.material", "shader = a.wgsl\nparam 2fast = 1").is_err());
+        assert!(MaterialSource::parse("a.material", "shader = a.wgsl\nparam a = 1\ntexture a = a.png").is_err());
+        assert!(MaterialSource::parse("a.material", "shader = a.wgsl\nparam a = 1 2 3 4 5").is_err());
+        assert!(MaterialSource::parse("a.material", "shader = a.wgsl\nblend = multiply").is_err());
+    }
+
+    #[test]
+    fn overrides_replace_params_by_name(){
+        let params = vec![("amount".to_string(), [0.5, 0.0, 0.0, 0.0]), ("edge".to_string(), [1.0, 0.5, 0.0, 1.0])];
+        assert_eq!(param_values(&params, &[("edge".to_string(), [0.0; 4]), ("other".to_string(), [2.0; 4])]), vec![[0.5, 0.0, 0.0, 0.0], [0.0; 4]]);
+        assert_eq!(param_values(&[], &[]), vec![[0.0; 4]]);
+    }
+
+    #[test]
+    fn premultiplied_textures_blend_premultiplied(){
+        assert_eq!(MaterialBlend::Alpha.for_texture(true), MaterialBlend::Premultiplied);
+        assert_eq!(MaterialBlend::Alpha.for_texture(false), MaterialBlend::Alpha);
+        assert_eq!(MaterialBlend::Additive.for_texture(true), MaterialBlend::Additive);
+    }
+}
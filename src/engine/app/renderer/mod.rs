@@ -1,9 +1,19 @@
 pub mod texture;
+pub mod material;
+pub mod shaders;
 pub mod egui_tools;
 mod render_data;
 mod camera;
-
-use std::{env, sync::Arc};
+mod render_target;
+mod tilemap_mesh;
+mod particles;
+pub mod post;
+pub mod resolution;
+pub mod screen;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
 use egui_winit::EventResponse;
 use winit::{
     dpi::PhysicalPosition, event::WindowEvent, window::Window
@@ -14,8 +24,16 @@ use egui_wgpu::{wgpu, ScreenDescriptor};
 
 use egui_tools::EguiRenderer;
 use render_data::{Instance, Vertex, RECTANGLE_INDICES, RECTANGLE_VERTICES};
+use render_target::RenderTarget;
+use resolution::{Layout, VirtualResolution};
+use tilemap_mesh::ChunkMesh;
+use particles::ParticleRenderer;
+use post::PostChain;
+use material::{Material, MaterialBlend, SpriteParams};
+pub use camera::CameraView;
 
-use crate::engine::app::{game::components, GameManager};
+use anyhow::Context;
+use crate::engine::app::{assets::Handle, game::components::{self, Viewport}, vfs, GameManager};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -39,9 +57,19 @@ struct ObjectIdUniform {
 }
 
 
+/// What shows where nothing is drawn, behind the world and between viewports.
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {r: 10.0/255.0, g: 10.0/255.0, b: 10.0/255.0, a: 1.0};
+
 const NUM_INSTANCES_PER_ROW: u32 = 1;
 const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0);
 
+/// One camera's part of the frame.
+struct View {
+    camera: camera::Camera,
+    viewport: Viewport,
+    layer_mask: u32
+}
+
 pub struct State {
     window: Arc<Window>,
     device: Arc<wgpu::Device>,
@@ -50,28 +78,54 @@ pub struct State {
     surface: wgpu::Surface<'static>,
     surface_format: wgpu::TextureFormat,
     render_pipeline: wgpu::RenderPipeline,
+    premultiplied_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer, 
     num_indices: u32,
     texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// Drawn for sprites whose texture is still loading.
+    pending_texture: texture::Texture,
+    /// Drawn for sprites whose texture failed to load.
+    missing_texture: texture::Texture,
+    /// One pixel of `CLEAR_COLOR`, drawn over a viewport before the world so views on top hide the ones below.
+    clear_texture: texture::Texture,
+    /// The free WASD camera, used when `editor_camera` is on or no `Camera2D` exists.
     camera: camera::Camera,
-    camera_uniform: camera::CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
+    /// Set every frame from the `Camera2D`s, in drawing order.
+    game_cameras: Vec<CameraView>,
+    editor_camera: bool,
+    /// What gets drawn this frame, one per camera, with a binding each at the same index.
+    views: Vec<View>,
+    camera_bindings: Vec<camera::CameraBinding>,
+    resolution: Option<VirtualResolution>,
+    /// The low-res texture the world is drawn into when `resolution` is pixel perfect.
+    pixel_target: Option<texture::Texture>,
+    /// Shows the quad over the whole viewport, for drawing `pixel_target` to the window.
+    blit_camera: camera::CameraBinding,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera_controller: camera::CameraController,
+    /// Camera views drawn into textures before the window, in the order they were added.
+    render_targets: Vec<RenderTarget>,
+    /// Tilemap chunk meshes by tilemap id, layer and chunk, rebuilt when a chunk changes.
+    tilemap_meshes: HashMap<(u64, usize, u32, u32), ChunkMesh>,
+    particles: ParticleRenderer,
+    /// The scene is drawn into its HDR target and goes through `GameManager::post_passes` into the window.
+    post: PostChain,
+    /// Sprite pipelines of materials by material and blend mode, `None` if the pipeline failed to build.
+    material_pipelines: HashMap<(usize, MaterialBlend), (Weak<Material>, Option<wgpu::RenderPipeline>)>,
+    /// Material params of the sprites that override some, by entity.
+    sprite_params: HashMap<hecs::Entity, SpriteParams>,
     egui_renderer: EguiRenderer,
     scale_factor: f32,
-    model_matrix_uniform: ModelMatrixUniform,
-    model_matrix_buffer: wgpu::Buffer,
     model_matrix_bind_group: wgpu::BindGroup,
     model_matrix_bind_group_layout: wgpu::BindGroupLayout,
-    picking_texture: wgpu::Texture,
-    picking_view: wgpu::TextureView,
     picking_buffer: wgpu::Buffer,
-    object_id_bind_group: wgpu::BindGroup,
     object_id_bind_group_layout: wgpu::BindGroupLayout,
-    object_id_buffer: wgpu::Buffer,
     picking_pipeline: wgpu::RenderPipeline,
+    picking_pipeline_layout: wgpu::PipelineLayout,
+    /// Shader errors not yet shown, see `take_shader_errors`.
+    shader_errors: Vec<String>,
     mouse_pos: PhysicalPosition<f64>
 
 }
@@ -130,6 +184,14 @@ impl State {
                 label: Some("texture_bind_group_layout"),
             });
 
+        let pending_image = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 128, 96]));
+        let pending_texture = texture::Texture::from_image(&device, &queue, &pending_image.into(), &texture_bind_group_layout, Some("pending texture")).unwrap();
+        let missing_image = image::RgbaImage::from_fn(2, 2, |x, y| if (x + y) % 2 == 0 { image::Rgba([255, 0, 255, 255]) } else { image::Rgba([0, 0, 0, 255]) });
+        let missing_texture = texture::Texture::from_image(&device, &queue, &missing_image.into(), &texture_bind_group_layout, Some("missing texture")).unwrap();
+        let clear_pixel = [CLEAR_COLOR.r, CLEAR_COLOR.g, CLEAR_COLOR.b, CLEAR_COLOR.a].map(|channel| (channel * 255.0).round() as u8);
+        let clear_settings = texture::TextureSettings { srgb: false, ..Default::default() };
+        let clear_texture = texture::Texture::from_pixels(&device, &queue, (1, 1), &clear_pixel, clear_settings, &texture_bind_group_layout, Some("clear texture")).unwrap();
+
            
         let vertex_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
@@ -149,12 +211,6 @@ impl State {
 
         let num_indices = RECTANGLE_INDICES.len() as u32;
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../../../../resources/shaders/shader.wgsl").into()),
-        });
-
-
         /////////////////////////////////////////
         // Camera
         /////////////////////////////////////////
@@ -163,20 +219,10 @@ impl State {
             position: cgmath::Point3 { x: 0.0, y: 0.0, z: 0.0 },
             speed: 2.0,
             aspect: size.width as f32 / size.height as f32,
-            scale: 1.0
+            scale: 1.0,
+            rotation: 0.0
         };
 
-        let mut camera_uniform = camera::CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
-
-        let camera_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Camera Buffer"),
-                contents: bytemuck::cast_slice(&[camera_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            }
-        );
-        
         let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
@@ -193,15 +239,13 @@ impl State {
             label: Some("camera_bind_group_layout"),
         });
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                }
-            ],
-            label: Some("camera_bind_group"),
+        let camera_bindings = vec![camera::CameraBinding::new(&device, &camera_bind_group_layout, &camera)];
+        let blit_camera = camera::CameraBinding::new(&device, &camera_bind_group_layout, &camera::Camera {
+            position: cgmath::Point3 { x: 0.0, y: 0.0, z: 0.0 },
+            speed: 0.0,
+            aspect: 1.0,
+            scale: 0.5,
+            rotation: 0.0
         });
 
         let camera_controller = camera::CameraController::new();
@@ -261,60 +305,16 @@ impl State {
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[
-                    Vertex::desc()
-                ], // 2.
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState { // 3.
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState { // 4.
-                    format: surface_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: None,
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None, // 1.
-            multisample: wgpu::MultisampleState {
-                count: 1, // 2.
-                mask: !0, // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-            cache: None, // 6.
-        });
+        let mut shader_errors = Vec::new();
+        let ((render_pipeline, premultiplied_pipeline), error) = shaders::SPRITE.build(|source| create_sprite_pipelines(&device, &render_pipeline_layout, post::HDR_FORMAT, source));
+        shader_errors.extend(error);
+        let (particles, error) = ParticleRenderer::new(&device, &texture_bind_group_layout, &camera_bind_group_layout, post::HDR_FORMAT);
+        shader_errors.extend(error);
 
         /////////////////////////////////////////
         /////////////////////////////////////////
         /////////////////////////////////////////
 
-        let object_id = ObjectIdUniform { id: 42 };
-        let object_id_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Object ID Buffer"),
-            contents: bytemuck::cast_slice(&[object_id]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
         let object_id_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
@@ -330,32 +330,6 @@ impl State {
                 label: Some("Object ID Bind Group Layout"),
             });
 
-        let object_id_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &object_id_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: object_id_buffer.as_entire_binding(),
-            }],
-            label: Some("Object ID Bind Group"),
-        });
-        
-        let picking_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Picking Texture"),
-            size: wgpu::Extent3d {
-                width: size.width,
-                height: size.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Uint,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
-            view_formats: &[],
-        });
-
-        let picking_view = picking_texture.create_view(&Default::default());
-
         // Buffer to read back picking
         let bytes_per_pixel = 4u32; // Rgba8Uint: 4 байта
         let aligned_bytes_per_row = 256; // минимальное выравнивание
@@ -368,11 +342,6 @@ impl State {
             mapped_at_creation: false,
         });
         
-        let picking_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Picking Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../../../../resources/shaders/picking_shader.wgsl").into()),
-        });
-
         let picking_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
@@ -385,45 +354,11 @@ impl State {
             push_constant_ranges: &[],
         });
         
-        let picking_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Picking Pipeline"),
-            layout: Some(&picking_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &picking_shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[
-                    Vertex::desc()
-                ], // 2.
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState { // 3.
-                module: &picking_shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState { // 4.
-                    format: wgpu::TextureFormat::Rgba8Uint,
-                    blend: None,
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None, // 1.
-            multisample: wgpu::MultisampleState {
-                count: 1, // 2.
-                mask: !0, // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-            cache: None, // 6.
-        });
+        let (picking_pipeline, error) = shaders::PICKING.build(|source| create_picking_pipeline(&device, &picking_pipeline_layout, source));
+        shader_errors.extend(error);
+
+        let mut post = PostChain::new(&device, surface_format.add_srgb_suffix(), (size.width, size.height));
+        shader_errors.extend(post.take_errors());
 
         let state = State {
             window,
@@ -433,28 +368,40 @@ impl State {
             surface,
             surface_format,
             render_pipeline,
+            premultiplied_pipeline,
+            render_pipeline_layout,
             vertex_buffer,
             index_buffer,
             num_indices,
             camera,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
+            views: vec![View { camera, viewport: Viewport::FULL, layer_mask: components::ALL_LAYERS }],
+            camera_bindings,
+            resolution: None,
+            pixel_target: None,
+            blit_camera,
+            camera_bind_group_layout,
             camera_controller,
+            render_targets: Vec::new(),
+            tilemap_meshes: HashMap::new(),
+            particles,
+            post,
+            material_pipelines: HashMap::new(),
+            sprite_params: HashMap::new(),
+            game_cameras: Vec::new(),
+            editor_camera: false,
             texture_bind_group_layout,
+            pending_texture,
+            missing_texture,
+            clear_texture,
             egui_renderer,
             scale_factor: 1.0,
-            model_matrix_uniform,
-            model_matrix_buffer,
             model_matrix_bind_group,
             model_matrix_bind_group_layout,
-            picking_texture,
-            picking_view,
             picking_buffer,
-            object_id_bind_group,
             object_id_bind_group_layout,
-            object_id_buffer,
             picking_pipeline,
+            picking_pipeline_layout,
+            shader_errors,
             mouse_pos: PhysicalPosition { x: 0.0, y: 0.0 }
         };
 
@@ -464,6 +411,11 @@ impl State {
         state
     }
 
+    /// Window size in physical pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.size.width, self.size.height)
+    }
+
     pub fn get_window(&self) -> &Window {
         &self.window
     }
@@ -486,14 +438,22 @@ impl State {
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.camera.aspect = self.size.width as f32 / self.size.height as f32;
+        self.update_pixel_target();
+        self.post.resize(&self.device, (new_size.width, new_size.height));
         // reconfigure the surface
         self.configure_surface();
     }
 
-    pub fn render<T>(&mut self, mut egui_render_func: T, gm: &mut GameManager)
-    where T: FnMut(&mut GameManager, &mut EguiRenderer) -> ()
+    /// `alpha` blends sprite transforms between the last two fixed simulation steps.
+    pub fn render<T>(&mut self, mut egui_render_func: T, gm: &mut GameManager, alpha: f32)
+    where T: FnMut(&mut GameManager, &mut EguiRenderer)
     {
         let world = &gm.world;
+        self.prepare_tilemaps(world);
+        self.prepare_materials(world);
+        self.particles.prepare(&self.device, &self.queue, world, alpha);
+        self.post.prepare(&self.device, &gm.post_passes);
+        self.shader_errors.extend(self.post.take_errors());
         let surface_texture = self
             .surface
             .get_current_texture()
@@ -508,13 +468,37 @@ impl State {
             });
 
         let mut encoder = self.device.create_command_encoder(&Default::default());
+
+        // Nobody can see a target whose texture only we still hold.
+        self.render_targets.retain(|target| !target.texture.is_unique());
+        for target in &self.render_targets{
+            let Some(texture) = target.texture.get() else{
+                continue;
+            };
+            let mut renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Target Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.draw_world(&mut renderpass, world, alpha, &target.binding.bind_group, components::ALL_LAYERS, Some(&target.texture));
+        }
+
         let mut renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: None,
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &texture_view,
+                view: self.post.scene_view(),
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {r: 10.0/255.0, g: 10.0/255.0, b: 10.0/255.0, a: 1.0}),
+                    load: wgpu::LoadOp::Clear(CLEAR_COLOR),
                     store: wgpu::StoreOp::Store,
                 },
             })],
@@ -522,40 +506,57 @@ impl State {
             timestamp_writes: None,
             occlusion_query_set: None,
         });
-
-        renderpass.set_pipeline(&self.render_pipeline); 
-        renderpass.set_bind_group(1, &self.camera_bind_group, &[]);
-        renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        renderpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16); 
-        
-        for (_id, (_label, sprite, transform_arc)) in &mut world.query::<(&components::Label, &components::Sprite, &components::TransformComponent)>(){
-            let transform = transform_arc.lock().unwrap();
-            
-            let model_matrix_uniform = ModelMatrixUniform {
-                view_proj: transform.to_mat().into(),
-            };
-
-            let matrix_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("model matrix buffer"),
-                contents: bytemuck::cast_slice(&[model_matrix_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
-
-            let model_matrix_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &self.model_matrix_bind_group_layout, // тот layout, что ты создавал
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: matrix_buffer.as_entire_binding(),
-                }],
-                label: Some("model matrix bind group"),
-            });
-            renderpass.set_bind_group(2, &model_matrix_bind_group, &[]);
-            renderpass.set_bind_group(0, &sprite.texture.bind_group, &[]);
-            renderpass.draw_indexed(0..self.num_indices, 0, 0..1 as _);
-
+        match &self.pixel_target{
+            Some(pixel_target) => {
+                drop(renderpass);
+                let mut pixel_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Pixel Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &pixel_target.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(CLEAR_COLOR),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                let frame = (0.0, 0.0, pixel_target.width() as f32, pixel_target.height() as f32);
+                self.draw_views(&mut pixel_pass, world, alpha, frame);
+                drop(pixel_pass);
+
+                renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Upscale Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: self.post.scene_view(),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                let (x, y, width, height) = self.layout().area;
+                renderpass.set_viewport(x, y, width, height, 0.0, 1.0);
+                renderpass.set_pipeline(&self.premultiplied_pipeline);
+                renderpass.set_bind_group(0, &pixel_target.bind_group, &[]);
+                renderpass.set_bind_group(1, &self.blit_camera.bind_group, &[]);
+                renderpass.set_bind_group(2, &self.model_matrix_bind_group, &[]);
+                renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                renderpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                renderpass.draw_indexed(0..self.num_indices, 0, 0..1);
+            },
+            None => self.draw_views(&mut renderpass, world, alpha, self.layout().area)
         }
 
         drop(renderpass);
+        self.post.run(&self.queue, &mut encoder, &gm.post_passes, &texture_view);
+
         let size = surface_texture.texture.size();
         let picking_texture = self.device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Picking Texture"),
@@ -590,16 +591,29 @@ impl State {
         });
         
 
+        // Only the camera under the cursor is drawn, a mask of 0 draws nothing.
+        let picked_view = self.view_at(self.mouse_pos.x as f32, self.mouse_pos.y as f32);
+        let layer_mask = match picked_view{
+            Some(index) => {
+                let (x, y, width, height) = self.viewport_rect(&self.views[index].viewport).unwrap();
+                renderpass.set_viewport(x, y, width, height, 0.0, 1.0);
+                self.views[index].layer_mask
+            },
+            None => 0
+        };
         renderpass.set_pipeline(&self.picking_pipeline); 
-        renderpass.set_bind_group(1, &self.camera_bind_group, &[]);
+        renderpass.set_bind_group(1, &self.camera_bindings[picked_view.unwrap_or(0)].bind_group, &[]);
         renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         renderpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
 
         for (_id, (label, sprite, transform_arc)) in &mut world.query::<(&components::Label, &components::Sprite, &components::TransformComponent)>(){
+            if !sprite.is_on(layer_mask){
+                continue;
+            }
             let transform = transform_arc.lock().unwrap();
             
             let model_matrix_uniform = ModelMatrixUniform {
-                view_proj: transform.to_mat().into(),
+                view_proj: transform.interpolated_mat(alpha).into(),
             };
 
             let matrix_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -634,7 +648,8 @@ impl State {
             });
 
             renderpass.set_bind_group(2, &model_matrix_bind_group, &[]);
-            renderpass.set_bind_group(0, &sprite.texture.bind_group, &[]);
+            let texture = sprite.texture.get();
+            renderpass.set_bind_group(0, &self.sprite_texture(&sprite.texture, &texture).bind_group, &[]);
             renderpass.set_bind_group(3, &object_id_bind_group, &[]);
             renderpass.draw_indexed(0..self.num_indices, 0, 0..1 as _);
 
@@ -708,14 +723,272 @@ impl State {
         surface_texture.present();
     }
 
-    pub fn load_texture(&self, name: &str, path: &str) -> texture::Texture{
-        let exe_path = env::current_exe().expect("Failed to get executable path");
-        let exe_dir = exe_path.parent().expect("Executable has no parent directory");
-        let path = exe_dir.join(path);
-        let data = std::fs::read(path).unwrap();
-        let texture_bytes = data.as_slice();
-        let texture = texture::Texture::from_bytes(&self.device, &self.queue, texture_bytes, &self.texture_bind_group_layout, name).unwrap();
-        return texture;
+    /// Draws what every camera sees into its viewport, placed within `frame` (x, y, width, height in pixels).
+    fn draw_views(&self, renderpass: &mut wgpu::RenderPass, world: &hecs::World, alpha: f32, frame: (f32, f32, f32, f32)){
+        for (view, binding) in self.views.iter().zip(&self.camera_bindings){
+            let Some((x, y, width, height)) = viewport_rect(&view.viewport, frame) else{
+                continue;
+            };
+            renderpass.set_viewport(x, y, width, height, 0.0, 1.0);
+            renderpass.set_pipeline(&self.premultiplied_pipeline);
+            renderpass.set_bind_group(0, &self.clear_texture.bind_group, &[]);
+            renderpass.set_bind_group(1, &self.blit_camera.bind_group, &[]);
+            renderpass.set_bind_group(2, &self.model_matrix_bind_group, &[]);
+            renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            renderpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            renderpass.draw_indexed(0..self.num_indices, 0, 0..1);
+            self.draw_world(renderpass, world, alpha, &binding.bind_group, view.layer_mask, None);
+        }
+    }
+
+    /// Rebuilds the meshes of tilemap chunks that changed since the last frame and drops the ones of removed tilemaps.
+    fn prepare_tilemaps(&mut self, world: &hecs::World){
+        let mut seen = HashSet::new();
+        for (_id, tilemap) in &mut world.query::<&components::Tilemap>(){
+            let Some(texture) = tilemap.tileset.texture.get() else{
+                continue;
+            };
+            let texture_size = (texture.width(), texture.height());
+            let (columns, rows) = tilemap.chunk_counts();
+            for layer in 0..tilemap.layers().len(){
+                for chunk_y in 0..rows{
+                    for chunk_x in 0..columns{
+                        let key = (tilemap.id(), layer, chunk_x, chunk_y);
+                        seen.insert(key);
+                        let revision = tilemap.chunk_revision(layer, chunk_x, chunk_y);
+                        if self.tilemap_meshes.get(&key).is_some_and(|mesh| mesh.revision == revision && mesh.texture_size == texture_size){
+                            continue;
+                        }
+                        let quads = tilemap.chunk_quads(layer, chunk_x, chunk_y, texture_size);
+                        self.tilemap_meshes.insert(key, ChunkMesh::new(&self.device, &quads, revision, texture_size));
+                    }
+                }
+            }
+        }
+        self.tilemap_meshes.retain(|key, _| seen.contains(key));
+    }
+
+    /// Builds the pipelines of materials sprites started using and drops the ones of unloaded materials.
+    /// Sprites that override params get their own bind group, kept until they stop.
+    fn prepare_materials(&mut self, world: &hecs::World){
+        self.material_pipelines.retain(|_, (material, _)| material.strong_count() > 0);
+        let mut overriding = HashSet::new();
+        for (id, sprite) in &mut world.query::<&components::Sprite>(){
+            let Some(material) = sprite.material.as_ref().and_then(|material| material.get()) else{
+                continue;
+            };
+            if !sprite.params.is_empty(){
+                overriding.insert(id);
+                match self.sprite_params.get_mut(&id){
+                    Some(params) if params.is_for(&material) => params.update(&self.queue, &material, &sprite.params),
+                    _ => {
+                        self.sprite_params.insert(id, material.create_sprite_params(&self.device, &sprite.params));
+                    }
+                }
+            }
+            let texture = sprite.texture.get();
+            let key = material_key(&material, self.sprite_texture(&sprite.texture, &texture));
+            if self.material_pipelines.contains_key(&key){
+                continue;
+            }
+            let layouts = [&self.texture_bind_group_layout, &self.camera_bind_group_layout, &self.model_matrix_bind_group_layout];
+            let pipeline = material.create_pipeline(&self.device, layouts, post::HDR_FORMAT, key.1)
+                .inspect_err(|e| log::error!("Could not build the pipeline of {}: {:#}", sprite.material.as_ref().unwrap().path(), e))
+                .ok();
+            self.material_pipelines.insert(key, (Arc::downgrade(&material), pipeline));
+        }
+        self.sprite_params.retain(|id, _| overriding.contains(id));
+    }
+
+    /// Compiles a material's shader for sprites; see `material::MaterialSource` for what it can use.
+    /// Fails if it doesn't compile or its pipeline can't be built, so a reload keeps the last working material.
+    pub fn create_material(&self, source: &material::MaterialSource, fragment: &str, textures: Vec<texture::Texture>, name: &str) -> anyhow::Result<Material>{
+        let material = Material::new(&self.device, source, fragment, textures, name)?;
+        let layouts = [&self.texture_bind_group_layout, &self.camera_bind_group_layout, &self.model_matrix_bind_group_layout];
+        material.create_pipeline(&self.device, layouts, post::HDR_FORMAT, material.blend)?;
+        Ok(material)
+    }
+
+    /// Files of the engine's own shaders and of the post-processing passes, for the file watcher.
+    pub fn shader_paths(&self) -> Vec<PathBuf>{
+        let mut paths: Vec<PathBuf> = shaders::ENGINE_SHADERS.iter().filter_map(|shader| vfs::real_path(shader.path)).collect();
+        paths.extend(self.post.paths());
+        paths
+    }
+
+    /// Rebuilds the pipelines that use `file` if it's one of the engine's shaders.
+    /// On an error the pipelines that were working stay in use. `Ok(false)` if nothing uses the file.
+    pub fn reload_shader(&mut self, file: &Path) -> anyhow::Result<bool>{
+        if shaders::SPRITE.is_file(file){
+            let source = shaders::SPRITE.source();
+            let (alpha, premultiplied) = create_sprite_pipelines(&self.device, &self.render_pipeline_layout, post::HDR_FORMAT, &source)
+                .with_context(|| format!("Could not reload {}", shaders::SPRITE.path))?;
+            self.render_pipeline = alpha;
+            self.premultiplied_pipeline = premultiplied;
+            // Material pipelines draw sprites too; `prepare_materials` builds them again next frame.
+            self.material_pipelines.clear();
+        } else if shaders::PICKING.is_file(file){
+            self.picking_pipeline = create_picking_pipeline(&self.device, &self.picking_pipeline_layout, &shaders::PICKING.source())
+                .with_context(|| format!("Could not reload {}", shaders::PICKING.path))?;
+        } else if shaders::PARTICLE.is_file(file){
+            self.particles.reload(&self.device).with_context(|| format!("Could not reload {}", shaders::PARTICLE.path))?;
+        } else{
+            return self.post.reload(&self.device, file);
+        }
+        Ok(true)
+    }
+
+    /// Errors of engine shaders that fell back to their built-in source at startup,
+    /// and of custom post-processing passes that didn't compile.
+    pub fn take_shader_errors(&mut self) -> Vec<String>{
+        std::mem::take(&mut self.shader_errors)
+    }
+
+    fn model_matrix_bind_group(&self, matrix: cgmath::Matrix4<f32>) -> wgpu::BindGroup{
+        let model_matrix_uniform = ModelMatrixUniform {
+            view_proj: matrix.into(),
+        };
+
+        let matrix_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("model matrix buffer"),
+            contents: bytemuck::cast_slice(&[model_matrix_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.model_matrix_bind_group_layout, // тот layout, что ты создавал
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: matrix_buffer.as_entire_binding(),
+            }],
+            label: Some("model matrix bind group"),
+        })
+    }
+
+    /// Draws the tilemaps, then the sprites, then the particles, on `layer_mask` seen through `camera_bind_group`, leaving out the ones showing `skip`.
+    fn draw_world(&self, renderpass: &mut wgpu::RenderPass, world: &hecs::World, alpha: f32, camera_bind_group: &wgpu::BindGroup, layer_mask: u32, skip: Option<&Handle<texture::Texture>>){
+        renderpass.set_bind_group(1, camera_bind_group, &[]);
+        for (_id, (tilemap, transform_arc)) in &mut world.query::<(&components::Tilemap, &components::TransformComponent)>(){
+            if tilemap.layer >= 32 || layer_mask & (1 << tilemap.layer) == 0{
+                continue;
+            }
+            if skip.is_some_and(|skip| skip.ptr_eq(&tilemap.tileset.texture)){
+                continue;
+            }
+            let Some(texture) = tilemap.tileset.texture.get() else{
+                continue;
+            };
+            renderpass.set_pipeline(if texture.settings.premultiply_alpha { &self.premultiplied_pipeline } else { &self.render_pipeline });
+            renderpass.set_bind_group(0, &texture.bind_group, &[]);
+            let model_matrix_bind_group = self.model_matrix_bind_group(transform_arc.lock().unwrap().interpolated_mat(alpha));
+            renderpass.set_bind_group(2, &model_matrix_bind_group, &[]);
+
+            let (columns, rows) = tilemap.chunk_counts();
+            for (layer, tile_layer) in tilemap.layers().iter().enumerate(){
+                if !tile_layer.visible{
+                    continue;
+                }
+                for chunk_y in 0..rows{
+                    for chunk_x in 0..columns{
+                        let Some(mesh) = self.tilemap_meshes.get(&(tilemap.id(), layer, chunk_x, chunk_y)) else{
+                            continue;
+                        };
+                        if mesh.index_count == 0{
+                            continue;
+                        }
+                        renderpass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                        renderpass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                        renderpass.draw_indexed(0..mesh.index_count, 0, 0..1);
+                    }
+                }
+            }
+        }
+
+        // Which sprite pipeline is set, `None` after a material's.
+        let mut premultiplied = None;
+        renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        renderpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16); 
+        
+        for (id, (_label, sprite, transform_arc)) in &mut world.query::<(&components::Label, &components::Sprite, &components::TransformComponent)>(){
+            if !sprite.is_on(layer_mask){
+                continue;
+            }
+            // A texture can't be sampled while it's being drawn into.
+            if skip.is_some_and(|skip| skip.ptr_eq(&sprite.texture)){
+                continue;
+            }
+            let model_matrix_bind_group = self.model_matrix_bind_group(transform_arc.lock().unwrap().interpolated_mat(alpha));
+            renderpass.set_bind_group(2, &model_matrix_bind_group, &[]);
+            let texture = sprite.texture.get();
+            let texture = self.sprite_texture(&sprite.texture, &texture);
+            let material = sprite.material.as_ref().and_then(|material| material.get());
+            let material_pipeline = material.as_ref().and_then(|material|{
+                let (_, pipeline) = self.material_pipelines.get(&material_key(material, texture))?;
+                pipeline.as_ref().map(|pipeline| (material, pipeline))
+            });
+            match material_pipeline{
+                Some((material, pipeline)) => {
+                    renderpass.set_pipeline(pipeline);
+                    match self.sprite_params.get(&id){
+                        Some(params) if !sprite.params.is_empty() && params.is_for(material) => renderpass.set_bind_group(3, &params.bind_group, &[]),
+                        _ => renderpass.set_bind_group(3, material.bind_group(), &[])
+                    }
+                    premultiplied = None;
+                },
+                None => if premultiplied != Some(texture.settings.premultiply_alpha){
+                    premultiplied = Some(texture.settings.premultiply_alpha);
+                    renderpass.set_pipeline(if texture.settings.premultiply_alpha { &self.premultiplied_pipeline } else { &self.render_pipeline });
+                }
+            }
+            renderpass.set_bind_group(0, &texture.bind_group, &[]);
+            renderpass.draw_indexed(0..self.num_indices, 0, 0..1 as _);
+
+        }
+        self.particles.draw(renderpass, layer_mask, skip, self.num_indices);
+    }
+
+    /// The sprite's texture, or a placeholder while it's loading or if it failed.
+    fn sprite_texture<'a>(&'a self, handle: &Handle<texture::Texture>, texture: &'a Option<Arc<texture::Texture>>) -> &'a texture::Texture{
+        match texture{
+            Some(texture) => texture,
+            None if handle.is_failed() => &self.missing_texture,
+            None => &self.pending_texture
+        }
+    }
+
+    /// Uploads images prepared by `TextureSettings::prepare`.
+    pub fn create_texture(&self, levels: &[image::RgbaImage], settings: texture::TextureSettings, name: &str) -> anyhow::Result<texture::Texture>{
+        texture::Texture::from_levels(&self.device, &self.queue, levels, settings, &self.texture_bind_group_layout, Some(name))
+    }
+
+    /// A texture from raw RGBA8 pixels, see `Texture::from_pixels`.
+    pub fn create_texture_from_pixels(&self, width: u32, height: u32, pixels: &[u8], settings: texture::TextureSettings, name: &str) -> anyhow::Result<texture::Texture>{
+        texture::Texture::from_pixels(&self.device, &self.queue, (width, height), pixels, settings, &self.texture_bind_group_layout, Some(name))
+    }
+
+    /// Replaces part of a texture made from pixels; the upload happens with the next frame.
+    pub fn update_texture(&self, texture: &texture::Texture, x: u32, y: u32, width: u32, height: u32, pixels: &[u8]) -> anyhow::Result<()>{
+        texture.write_region(&self.queue, x, y, width, height, pixels)
+    }
+
+    /// Starts drawing the world seen from `position` into a new `width` x `height` texture every frame.
+    /// `scale` is the half height of the view in world units.
+    pub fn add_render_target(&mut self, name: &str, width: u32, height: u32, position: (f32, f32), scale: f32) -> anyhow::Result<Handle<texture::Texture>>{
+        let texture = texture::Texture::render_target(&self.device, (width, height), post::HDR_FORMAT, texture::Filter::Linear, &self.texture_bind_group_layout, Some(name))?;
+        let handle = Handle::from_asset(name, texture);
+        self.render_targets.push(RenderTarget::new(&self.device, &self.camera_bind_group_layout, handle.clone(), position, scale));
+        Ok(handle)
+    }
+
+    /// Moves the camera of a render target. Returns false if `target` isn't one.
+    pub fn set_render_target_view(&mut self, target: &Handle<texture::Texture>, position: (f32, f32), scale: f32) -> bool{
+        match self.render_targets.iter_mut().find(|render_target| render_target.texture.ptr_eq(target)){
+            Some(render_target) => {
+                render_target.set_view(&self.queue, position, scale);
+                true
+            },
+            None => false
+        }
     }
 
     pub fn pick(&self) -> u8{
@@ -733,24 +1006,287 @@ impl State {
 
     pub fn input(&mut self, event: &WindowEvent) -> bool{
         let response = self.egui_renderer
-            .handle_input(&self.window, &event);
+            .handle_input(&self.window, event);
         if response.consumed{
             return true;
         }
-        match event{
-            WindowEvent::CursorMoved { device_id: _, position } => {
-                self.mouse_pos = *position;
-            },
-            _ => {}
-        };
+        if let WindowEvent::CursorMoved { device_id: _, position } = event{
+            self.mouse_pos = *position;
+        }
         self.camera_controller.process_events(event);
         false
     }
 
+    /// Position of the main camera in world units.
+    pub fn camera_position(&self) -> (f32, f32){
+        let camera = self.main_camera();
+        (camera.position.x, camera.position.y)
+    }
+
+    /// Shows `views`, in order, instead of the editor camera, unless the editor camera is turned on.
+    pub fn set_game_cameras(&mut self, views: Vec<CameraView>){
+        self.game_cameras = views;
+    }
+
+    /// Switches to the free WASD camera, starting from what the main camera shows.
+    pub fn set_editor_camera(&mut self, enabled: bool){
+        if enabled && !self.editor_camera{
+            let camera = self.main_camera();
+            self.camera.position = camera.position;
+            self.camera.scale = camera.scale;
+            self.camera.rotation = camera.rotation;
+        }
+        self.editor_camera = enabled;
+    }
+
+    /// The camera drawn last, which is on top.
+    fn main_camera(&self) -> camera::Camera{
+        self.views.last().map_or(self.camera, |view| view.camera)
+    }
+
+    /// The game cameras as seen on screen, or the editor camera over the whole game area.
+    fn active_views(&self) -> Vec<View>{
+        let layout = self.layout();
+        let (virtual_width, virtual_height) = layout.virtual_size;
+        if self.editor_camera || self.game_cameras.is_empty(){
+            let camera = camera::Camera { aspect: virtual_width / virtual_height, ..self.camera };
+            return vec![View { camera, viewport: Viewport::FULL, layer_mask: components::ALL_LAYERS }];
+        }
+        // With `Expand` the extra virtual pixels show more of the world instead of making it bigger.
+        let expansion = self.resolution.map_or(1.0, |resolution| virtual_height / resolution.height.max(1) as f32);
+        self.game_cameras.iter().map(|view|{
+            let camera = camera::Camera {
+                position: cgmath::Point3 { x: view.position.0, y: view.position.1, z: 0.0 },
+                speed: 0.0,
+                aspect: (virtual_width * view.viewport.width) / (virtual_height * view.viewport.height).max(f32::EPSILON),
+                scale: expansion / view.zoom.max(f32::EPSILON),
+                rotation: view.rotation
+            };
+            View { camera, viewport: view.viewport, layer_mask: view.layer_mask }
+        }).collect()
+    }
+
+    /// Where the game is drawn in the window.
+    fn layout(&self) -> Layout{
+        let window = (self.size.width, self.size.height);
+        match &self.resolution{
+            Some(resolution) => resolution.layout(window),
+            None => Layout::window(window)
+        }
+    }
+
+    /// Sets the size the game is designed for, `None` to follow the window.
+    pub fn set_virtual_resolution(&mut self, resolution: Option<VirtualResolution>){
+        self.resolution = resolution;
+        self.update_pixel_target();
+    }
+
+    /// Creates, resizes or drops the low-res texture to match the resolution settings.
+    fn update_pixel_target(&mut self){
+        let size = match &self.resolution{
+            Some(resolution) if resolution.pixel_perfect => {
+                let (width, height) = self.layout().virtual_size;
+                (width.round().max(1.0) as u32, height.round().max(1.0) as u32)
+            },
+            _ => {
+                self.pixel_target = None;
+                return;
+            }
+        };
+        if self.pixel_target.as_ref().is_some_and(|target| (target.width(), target.height()) == size){
+            return;
+        }
+        self.pixel_target = texture::Texture::render_target(&self.device, size, post::HDR_FORMAT, texture::Filter::Nearest, &self.texture_bind_group_layout, Some("Pixel Target"))
+            .inspect_err(|e| log::error!("Could not create the low-res target: {}", e))
+            .ok();
+    }
+
+    /// A camera viewport in physical window pixels, clipped to the game area; `None` if nothing of it is visible.
+    fn viewport_rect(&self, viewport: &Viewport) -> Option<(f32, f32, f32, f32)>{
+        viewport_rect(viewport, self.layout().area)
+    }
+
+    /// This frame's cameras, for converting window coordinates.
+    pub fn screen_space(&self) -> screen::ScreenSpace{
+        let views = self.views.iter()
+            .filter_map(|view| Some((view.camera, self.viewport_rect(&view.viewport)?)))
+            .collect();
+        screen::ScreenSpace::new(views, self.layout(), self.window.scale_factor() as f32, (self.mouse_pos.x as f32, self.mouse_pos.y as f32))
+    }
+
+    /// Index of the topmost view whose viewport contains the pixel.
+    fn view_at(&self, x: f32, y: f32) -> Option<usize>{
+        view_at(self.views.iter().map(|view| &view.viewport), self.layout().area, x, y)
+    }
+
     pub fn update(&mut self, dt: f32) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        self.post.advance(dt);
+        if self.editor_camera{
+            self.camera_controller.update_camera(&mut self.camera, dt);
+        }
+        self.views = self.active_views();
+        while self.camera_bindings.len() < self.views.len(){
+            self.camera_bindings.push(camera::CameraBinding::new(&self.device, &self.camera_bind_group_layout, &self.camera));
+        }
+        for (view, binding) in self.views.iter().zip(&mut self.camera_bindings){
+            binding.write(&self.queue, &view.camera);
+        }
+    }
+}
+
+/// `viewport` in the pixels of `frame` (x, y, width, height), clipped to it; `None` if nothing of it is visible.
+fn viewport_rect(viewport: &Viewport, frame: (f32, f32, f32, f32)) -> Option<(f32, f32, f32, f32)>{
+    let (left, top, width, height) = frame;
+    let x = viewport.x.clamp(0.0, 1.0) * width;
+    let y = viewport.y.clamp(0.0, 1.0) * height;
+    let w = (viewport.width * width).min(width - x);
+    let h = (viewport.height * height).min(height - y);
+    (w > 0.0 && h > 0.0).then_some((left + x, top + y, w, h))
+}
+
+/// Index of the last of `viewports` that contains the pixel, when placed within `frame`.
+fn view_at<'a>(mut viewports: impl DoubleEndedIterator<Item = &'a Viewport> + ExactSizeIterator, frame: (f32, f32, f32, f32), x: f32, y: f32) -> Option<usize>{
+    viewports.rposition(|viewport|{
+        viewport_rect(viewport, frame).is_some_and(|(left, top, width, height)|{
+            x >= left && x < left + width && y >= top && y < top + height
+        })
+    })
+}
+
+/// The key of the pipeline that draws `material` over a sprite with `texture`.
+fn material_key(material: &Arc<Material>, texture: &texture::Texture) -> (usize, MaterialBlend){
+    (Arc::as_ptr(material) as usize, material.blend.for_texture(texture.settings.premultiply_alpha))
+}
+
+/// The sprite pipelines with alpha and premultiplied alpha blending, from the source of `shader.wgsl` after `material::PRELUDE`.
+fn create_sprite_pipelines(device: &wgpu::Device, layout: &wgpu::PipelineLayout, format: wgpu::TextureFormat, source: &str) -> anyhow::Result<(wgpu::RenderPipeline, wgpu::RenderPipeline)>{
+    let shader = shaders::compile(device, "Shader", &format!("{}{}", material::PRELUDE, source))?;
+    shaders::validated(device, ||{
+        let alpha = create_sprite_pipeline(device, layout, &shader, format, wgpu::BlendState::ALPHA_BLENDING, "Render Pipeline");
+        let premultiplied = create_sprite_pipeline(device, layout, &shader, format, wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING, "Premultiplied Render Pipeline");
+        (alpha, premultiplied)
+    })
+}
+
+/// The pipeline that draws object ids for picking.
+fn create_picking_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, source: &str) -> anyhow::Result<wgpu::RenderPipeline>{
+    let shader = shaders::compile(device, "Picking Shader", source)?;
+    shaders::validated(device, ||{
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Picking Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"), // 1.
+                buffers: &[
+                    Vertex::desc()
+                ], // 2.
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState { // 3.
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState { // 4.
+                    format: wgpu::TextureFormat::Rgba8Uint,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw, // 2.
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None, // 1.
+            multisample: wgpu::MultisampleState {
+                count: 1, // 2.
+                mask: !0, // 3.
+                alpha_to_coverage_enabled: false, // 4.
+            },
+            multiview: None, // 5.
+            cache: None, // 6.
+        })
+    })
+}
+
+/// The sprite pipeline with the given blending; sprites with premultiplied
+/// textures need `PREMULTIPLIED_ALPHA_BLENDING`.
+fn create_sprite_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, format: wgpu::TextureFormat, blend: wgpu::BlendState, label: &str) -> wgpu::RenderPipeline{
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"), // 1.
+            buffers: &[
+                Vertex::desc()
+            ], // 2.
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState { // 3.
+            module: shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState { // 4.
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList, // 1.
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw, // 2.
+            cull_mode: None,
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: None, // 1.
+        multisample: wgpu::MultisampleState {
+            count: 1, // 2.
+            mask: !0, // 3.
+            alpha_to_coverage_enabled: false, // 4.
+        },
+        multiview: None, // 5.
+        cache: None, // 6.
+    })
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn places_viewports_within_the_frame(){
+        let frame = (10.0, 20.0, 200.0, 100.0);
+        assert_eq!(viewport_rect(&Viewport::FULL, frame), Some((10.0, 20.0, 200.0, 100.0)));
+        let corner = Viewport { x: 0.75, y: 0.5, width: 0.25, height: 0.5 };
+        assert_eq!(viewport_rect(&corner, frame), Some((160.0, 70.0, 50.0, 50.0)));
+        let overhanging = Viewport { x: 0.5, y: 0.0, width: 1.0, height: 1.0 };
+        assert_eq!(viewport_rect(&overhanging, frame), Some((110.0, 20.0, 100.0, 100.0)));
+        let outside = Viewport { x: 1.0, y: 0.0, width: 0.5, height: 1.0 };
+        assert_eq!(viewport_rect(&outside, frame), None);
+        let empty = Viewport { x: 0.0, y: 0.0, width: 0.0, height: 1.0 };
+        assert_eq!(viewport_rect(&empty, frame), None);
     }
 
+    #[test]
+    fn finds_the_topmost_view_under_a_pixel(){
+        let frame = (0.0, 0.0, 200.0, 100.0);
+        let picture_in_picture = Viewport { x: 0.75, y: 0.0, width: 0.25, height: 0.25 };
+        let viewports = [Viewport::FULL, picture_in_picture];
+        assert_eq!(view_at(viewports.iter(), frame, 10.0, 10.0), Some(0));
+        assert_eq!(view_at(viewports.iter(), frame, 160.0, 10.0), Some(1));
+        assert_eq!(view_at(viewports.iter(), frame, 160.0, 25.0), Some(0));
+        assert_eq!(view_at(viewports.iter(), frame, 200.0, 50.0), None);
+    }
 }
@@ -0,0 +1,176 @@
+use std::{collections::HashMap, ops::Range};
+
+use egui_wgpu::wgpu;
+
+use crate::engine::app::{assets::Handle, game::components::{ParticleBlend, ParticleEmitter}};
+use super::{render_data::Vertex, shaders, texture::Texture};
+
+/// One particle as the shader sees it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleInstance{
+    center: [f32; 2],
+    size_rotation: [f32; 2],
+    color: [f32; 4],
+    uv: [f32; 4]
+}
+
+impl ParticleInstance{
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![2 => Float32x2, 3 => Float32x2, 4 => Float32x4, 5 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static>{
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES
+        }
+    }
+}
+
+/// The particles of one emitter, drawn with one instanced call.
+struct Batch{
+    texture: Handle<Texture>,
+    blend: ParticleBlend,
+    layer: u32,
+    instances: Range<u32>
+}
+
+/// Draws every `ParticleEmitter` from one instance buffer, refilled each frame.
+pub struct ParticleRenderer{
+    layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    /// By blend mode and whether the texture is premultiplied.
+    pipelines: HashMap<(ParticleBlend, bool), wgpu::RenderPipeline>,
+    instance_buffer: wgpu::Buffer,
+    batches: Vec<Batch>
+}
+
+impl ParticleRenderer{
+    /// The renderer, with the error of `particle.wgsl` if the built-in shader had to be used.
+    pub fn new(device: &wgpu::Device, texture_layout: &wgpu::BindGroupLayout, camera_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat) -> (Self, Option<String>){
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let (pipelines, error) = shaders::PARTICLE.build(|source| create_pipelines(device, &layout, format, source));
+        let renderer = Self { layout, format, pipelines, instance_buffer: create_instance_buffer(device, 256), batches: Vec::new() };
+        (renderer, error)
+    }
+
+    /// Rebuilds the pipelines from `particle.wgsl`, keeping the current ones if it doesn't compile.
+    pub fn reload(&mut self, device: &wgpu::Device) -> anyhow::Result<()>{
+        self.pipelines = create_pipelines(device, &self.layout, self.format, &shaders::PARTICLE.source())?;
+        Ok(())
+    }
+
+    /// Uploads the particles of every emitter, `alpha` between their last two fixed steps.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, world: &hecs::World, alpha: f32){
+        self.batches.clear();
+        let mut instances = Vec::new();
+        for (_id, emitter) in &mut world.query::<&ParticleEmitter>(){
+            let start = instances.len() as u32;
+            instances.extend(emitter.particles().iter().map(|particle|{
+                let life = particle.life();
+                let (x, y) = particle.interpolated_position(alpha);
+                ParticleInstance {
+                    center: [x, y],
+                    size_rotation: [emitter.size.sample(life).unwrap_or(1.0), emitter.rotation.sample(life).unwrap_or(0.0)],
+                    color: emitter.color.sample(life).unwrap_or([1.0; 4]),
+                    uv: emitter.region
+                }
+            }));
+            let end = instances.len() as u32;
+            if end > start{
+                self.batches.push(Batch { texture: emitter.texture.clone(), blend: emitter.blend, layer: emitter.layer, instances: start..end });
+            }
+        }
+        if instances.is_empty(){
+            return;
+        }
+
+        let size = std::mem::size_of_val(instances.as_slice()) as wgpu::BufferAddress;
+        if size > self.instance_buffer.size(){
+            self.instance_buffer = create_instance_buffer(device, instances.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+    }
+
+    /// Draws the batches on `layer_mask`, leaving out the ones showing `skip` and the ones still loading.
+    /// Expects the camera at group 1 and the quad in vertex buffer 0 and the index buffer.
+    pub fn draw(&self, renderpass: &mut wgpu::RenderPass, layer_mask: u32, skip: Option<&Handle<Texture>>, num_indices: u32){
+        renderpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for batch in &self.batches{
+            if batch.layer >= 32 || layer_mask & (1 << batch.layer) == 0{
+                continue;
+            }
+            if skip.is_some_and(|skip| skip.ptr_eq(&batch.texture)){
+                continue;
+            }
+            let Some(texture) = batch.texture.get() else{
+                continue;
+            };
+            renderpass.set_pipeline(&self.pipelines[&(batch.blend, texture.settings.premultiply_alpha)]);
+            renderpass.set_bind_group(0, &texture.bind_group, &[]);
+            renderpass.draw_indexed(0..num_indices, 0, batch.instances.clone());
+        }
+    }
+}
+
+fn create_pipelines(device: &wgpu::Device, layout: &wgpu::PipelineLayout, format: wgpu::TextureFormat, source: &str) -> anyhow::Result<HashMap<(ParticleBlend, bool), wgpu::RenderPipeline>>{
+    let shader = shaders::compile(device, "Particle Shader", source)?;
+    let additive = wgpu::BlendState {
+        color: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::One, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+        alpha: wgpu::BlendComponent { src_factor: wgpu::BlendFactor::Zero, dst_factor: wgpu::BlendFactor::One, operation: wgpu::BlendOperation::Add },
+    };
+    let mut pipelines = HashMap::new();
+    for (mode, blend) in [(ParticleBlend::Alpha, wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING), (ParticleBlend::Additive, additive)]{
+        for premultiplied in [false, true]{
+            let entry_point = if premultiplied { "fs_premultiplied" } else { "fs_main" };
+            let pipeline = shaders::validated(device, || create_particle_pipeline(device, layout, &shader, format, blend, entry_point))?;
+            pipelines.insert((mode, premultiplied), pipeline);
+        }
+    }
+    Ok(pipelines)
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer{
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Particle Instance Buffer"),
+        size: (capacity * std::mem::size_of::<ParticleInstance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_particle_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, format: wgpu::TextureFormat, blend: wgpu::BlendState, entry_point: &str) -> wgpu::RenderPipeline{
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Particle Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[Vertex::desc(), ParticleInstance::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(entry_point),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
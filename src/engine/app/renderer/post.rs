@@ -0,0 +1,587 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{Arc, Weak}};
+
+use anyhow::{Context, Result};
+use egui_wgpu::wgpu;
+use crate::engine::app::{assets::Handle, vfs};
+use super::{shaders::{self, EngineShader}, texture::Texture};
+
+/// The scene is drawn in this format so colors can go over 1 until the chain is done, e.g. for bloom.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Put before every post-processing shader, built-in or custom. A pass reads the frame so far
+/// from `source` and writes `fs_main`'s output to the next one.
+pub const PRELUDE: &str = "\
+struct Post {
+    params: vec4<f32>,
+    extra: vec4<f32>,
+    // width and height in pixels, seconds since start
+    frame: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+// A second input, e.g. the LUT or the blurred bloom; the frame again for passes without one.
+@group(0) @binding(2)
+var second: texture_2d<f32>;
+@group(0) @binding(3)
+var second_sampler: sampler;
+@group(0) @binding(4)
+var<uniform> post: Post;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle over the whole target.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+";
+
+#[derive(Clone)]
+pub enum PostEffect{
+    /// Makes colors brighter than `threshold` glow, blurred over `radius` pixels.
+    Bloom{ threshold: f32, intensity: f32, radius: f32 },
+    /// Looks colors up in a LUT: `size` slices of `size` x `size` pixels side by side, blue growing to the right.
+    /// Load it with `srgb = false` so the table isn't converted.
+    ColorGrading{ lut: Handle<Texture>, strength: f32 },
+    /// Darkens the corners; `radius` and `softness` are in screen heights.
+    Vignette{ intensity: f32, radius: f32, softness: f32 },
+    /// Scanlines and a curved picture.
+    Crt{ scanlines: f32, count: f32, curvature: f32 },
+    /// `fs_main` of a WGSL file from the asset root, compiled after `PRELUDE`,
+    /// with `params` as `post.params` and `post.extra`. Reloaded when the file changes.
+    Custom{ path: String, params: [f32; 8] }
+}
+
+/// One step of the post-processing chain, run in order while `enabled`.
+#[derive(Clone)]
+pub struct PostPass{
+    pub name: String,
+    pub enabled: bool,
+    pub effect: PostEffect
+}
+
+impl PostPass{
+    pub fn new(name: &str, effect: PostEffect) -> Self{
+        Self { name: name.to_string(), enabled: true, effect }
+    }
+
+    pub fn bloom() -> Self{
+        Self::new("bloom", PostEffect::Bloom { threshold: 0.8, intensity: 1.0, radius: 8.0 })
+    }
+
+    pub fn color_grading(lut: Handle<Texture>) -> Self{
+        Self::new("color grading", PostEffect::ColorGrading { lut, strength: 1.0 })
+    }
+
+    pub fn vignette() -> Self{
+        Self::new("vignette", PostEffect::Vignette { intensity: 0.5, radius: 0.8, softness: 0.5 })
+    }
+
+    pub fn crt() -> Self{
+        Self::new("crt", PostEffect::Crt { scanlines: 0.3, count: 240.0, curvature: 0.05 })
+    }
+
+    pub fn custom(name: &str, path: &str) -> Self{
+        Self::new(name, PostEffect::Custom { path: path.to_string(), params: [0.0; 8] })
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform{
+    params: [f32; 4],
+    extra: [f32; 4],
+    frame: [f32; 4]
+}
+
+/// An HDR texture the chain draws into.
+struct Target{
+    view: wgpu::TextureView
+}
+
+/// Draws the scene's HDR target through the enabled `PostPass`es into the window.
+pub struct PostChain{
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    surface_format: wgpu::TextureFormat,
+    /// Built-in pipelines by shader path and entry point.
+    pipelines: HashMap<(&'static str, &'static str), wgpu::RenderPipeline>,
+    /// Pipelines of custom passes by path, `None` if the file never compiled.
+    custom: HashMap<String, Option<wgpu::RenderPipeline>>,
+    /// The scene is drawn into the first; passes go back and forth between them.
+    targets: Vec<Target>,
+    /// Bind groups reading target `source` and target `second`, at `source * 3 + second`.
+    target_bind_groups: Vec<wgpu::BindGroup>,
+    /// Bind groups reading a target and a color grading LUT, by target and LUT.
+    lut_bind_groups: HashMap<(usize, usize), (Weak<Texture>, wgpu::BindGroup)>,
+    /// The `PostUniform` of every draw of a frame, `uniform_stride` apart.
+    uniforms: wgpu::Buffer,
+    uniform_stride: u64,
+    uniform_slots: usize,
+    size: (u32, u32),
+    time: f32,
+    /// Shader errors not yet shown.
+    errors: Vec<String>
+}
+
+/// The entry points of each built-in shader and whether they draw to the window instead of an HDR target.
+const BUILTIN_PASSES: [(&EngineShader, &[&str], bool); 5] = [
+    (&shaders::POST_COPY, &["fs_main"], true),
+    (&shaders::POST_BLOOM, &["fs_extract", "fs_blur", "fs_combine"], false),
+    (&shaders::POST_COLOR_GRADING, &["fs_main"], false),
+    (&shaders::POST_VIGNETTE, &["fs_main"], false),
+    (&shaders::POST_CRT, &["fs_main"], false)
+];
+
+impl PostChain{
+    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat, size: (u32, u32)) -> Self{
+        let texture_entry = |binding|{
+            wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            }
+        };
+        let sampler_entry = |binding|{
+            wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            }
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                sampler_entry(1),
+                texture_entry(2),
+                sampler_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PostUniform>() as u64),
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("post_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniform_stride = (std::mem::size_of::<PostUniform>() as u64).div_ceil(alignment) * alignment;
+
+        let mut chain = Self {
+            layout,
+            pipeline_layout,
+            sampler,
+            surface_format,
+            pipelines: HashMap::new(),
+            custom: HashMap::new(),
+            targets: Vec::new(),
+            target_bind_groups: Vec::new(),
+            lut_bind_groups: HashMap::new(),
+            uniforms: create_uniform_buffer(device, uniform_stride, 1),
+            uniform_stride,
+            uniform_slots: 1,
+            size: (0, 0),
+            time: 0.0,
+            errors: Vec::new()
+        };
+        for (shader, entry_points, to_window) in BUILTIN_PASSES{
+            let format = if to_window { surface_format } else { HDR_FORMAT };
+            let (pipelines, error) = shader.build(|source| chain.create_pipelines(device, source, entry_points, format));
+            chain.errors.extend(error);
+            for (entry_point, pipeline) in entry_points.iter().zip(pipelines){
+                chain.pipelines.insert((shader.path, entry_point), pipeline);
+            }
+        }
+        chain.resize(device, size);
+        chain
+    }
+
+    fn create_pipelines(&self, device: &wgpu::Device, source: &str, entry_points: &[&str], format: wgpu::TextureFormat) -> Result<Vec<wgpu::RenderPipeline>>{
+        let shader = shaders::compile(device, "Post Shader", &format!("{}{}", PRELUDE, source))?;
+        entry_points.iter()
+            .map(|entry_point| shaders::validated(device, || create_post_pipeline(device, &self.pipeline_layout, &shader, entry_point, format)))
+            .collect()
+    }
+
+    /// Makes the targets match the window.
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)){
+        let size = (size.0.max(1), size.1.max(1));
+        if size == self.size{
+            return;
+        }
+        self.size = size;
+        self.targets = (0..3).map(|_|{
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Post Target"),
+                size: wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            Target { view: texture.create_view(&Default::default()) }
+        }).collect();
+        self.create_bind_groups(device);
+    }
+
+    /// The bind groups of every pair of targets; the ones with LUTs are made again by `prepare`.
+    fn create_bind_groups(&mut self, device: &wgpu::Device){
+        self.target_bind_groups = (0..9).map(|index| self.create_bind_group(device, &self.targets[index / 3].view, &self.targets[index % 3].view)).collect();
+        self.lut_bind_groups.clear();
+    }
+
+    fn create_bind_group(&self, device: &wgpu::Device, source: &wgpu::TextureView, second: &wgpu::TextureView) -> wgpu::BindGroup{
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(source) },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+                wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::TextureView(second) },
+                wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+                wgpu::BindGroupEntry { binding: 4, resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &self.uniforms,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<PostUniform>() as u64),
+                }) }
+            ],
+            label: Some("post bind group"),
+        })
+    }
+
+    /// Where the scene is drawn.
+    pub fn scene_view(&self) -> &wgpu::TextureView{
+        &self.targets[0].view
+    }
+
+    pub fn advance(&mut self, dt: f32){
+        self.time += dt;
+    }
+
+    /// Compiles the shaders of custom passes that are new since the last frame,
+    /// makes room for the uniforms of this frame's draws and binds the LUTs that loaded.
+    pub fn prepare(&mut self, device: &wgpu::Device, passes: &[PostPass]){
+        let slots = draw_count(passes);
+        if slots > self.uniform_slots{
+            self.uniform_slots = slots.next_power_of_two();
+            self.uniforms = create_uniform_buffer(device, self.uniform_stride, self.uniform_slots);
+            self.create_bind_groups(device);
+        }
+
+        self.lut_bind_groups.retain(|_, (lut, _)| lut.strong_count() > 0);
+        for pass in passes.iter().filter(|pass| pass.enabled){
+            let PostEffect::ColorGrading { lut, .. } = &pass.effect else{
+                continue;
+            };
+            let Some(lut) = lut.get() else{
+                continue;
+            };
+            for source in 0..3{
+                let key = (source, Arc::as_ptr(&lut) as usize);
+                if !self.lut_bind_groups.contains_key(&key){
+                    let bind_group = self.create_bind_group(device, &self.targets[source].view, &lut.view);
+                    self.lut_bind_groups.insert(key, (Arc::downgrade(&lut), bind_group));
+                }
+            }
+        }
+
+        for pass in passes{
+            let PostEffect::Custom { path, .. } = &pass.effect else{
+                continue;
+            };
+            if self.custom.contains_key(path){
+                continue;
+            }
+            let pipeline = self.create_custom_pipeline(device, path)
+                .inspect_err(|e| self.errors.push(format!("{}: {:#}", path, e)))
+                .ok();
+            self.custom.insert(path.clone(), pipeline);
+        }
+    }
+
+    fn create_custom_pipeline(&self, device: &wgpu::Device, path: &str) -> Result<wgpu::RenderPipeline>{
+        let source = vfs::read_to_string(path).with_context(|| format!("Could not read {}", path))?;
+        let mut pipelines = self.create_pipelines(device, &source, &["fs_main"], HDR_FORMAT)?;
+        Ok(pipelines.remove(0))
+    }
+
+    /// Files of the built-in and custom pass shaders, for the file watcher.
+    pub fn paths(&self) -> Vec<PathBuf>{
+        shaders::POST_SHADERS.iter().map(|shader| shader.path)
+            .chain(self.custom.keys().map(String::as_str))
+            .filter_map(vfs::real_path)
+            .collect()
+    }
+
+    /// Rebuilds the pipelines of the pass shaders read from `file`, keeping the old ones on an error.
+    pub fn reload(&mut self, device: &wgpu::Device, file: &Path) -> Result<bool>{
+        let mut reloaded = false;
+        for (shader, entry_points, to_window) in BUILTIN_PASSES{
+            if !shader.is_file(file){
+                continue;
+            }
+            let format = if to_window { self.surface_format } else { HDR_FORMAT };
+            let pipelines = self.create_pipelines(device, &shader.source(), entry_points, format).with_context(|| format!("Could not reload {}", shader.path))?;
+            for (entry_point, pipeline) in entry_points.iter().zip(pipelines){
+                self.pipelines.insert((shader.path, entry_point), pipeline);
+            }
+            reloaded = true;
+        }
+        let custom: Vec<String> = self.custom.keys().filter(|path| vfs::real_path(path).is_some_and(|path| path == file)).cloned().collect();
+        for path in custom{
+            let pipeline = self.create_custom_pipeline(device, &path).with_context(|| format!("Could not reload {}", path))?;
+            self.custom.insert(path, Some(pipeline));
+            reloaded = true;
+        }
+        Ok(reloaded)
+    }
+
+    pub fn take_errors(&mut self) -> Vec<String>{
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Runs the enabled passes on the scene target and writes the result to `output`.
+    /// `prepare` has to be called with the same passes first.
+    pub fn run(&self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, passes: &[PostPass], output: &wgpu::TextureView){
+        let mut uniforms = Vec::new();
+        let mut current = 0;
+        for pass in passes.iter().filter(|pass| pass.enabled){
+            let (next, spare) = others(current);
+            match &pass.effect{
+                PostEffect::Bloom { threshold, intensity, radius } => {
+                    let params = [*threshold, *intensity, *radius, 0.0];
+                    let mut draw = |entry_point, source: usize, second: usize, target: usize, direction: [f32; 2]|{
+                        let pipeline = &self.pipelines[&(shaders::POST_BLOOM.path, entry_point)];
+                        let bind_group = &self.target_bind_groups[source * 3 + second];
+                        let uniform = self.uniform(params, [direction[0], direction[1], 0.0, 0.0]);
+                        self.draw(encoder, &mut uniforms, pipeline, bind_group, &self.targets[target].view, uniform);
+                    };
+                    draw("fs_extract", current, current, next, [0.0; 2]);
+                    draw("fs_blur", next, next, spare, [1.0, 0.0]);
+                    draw("fs_blur", spare, spare, next, [0.0, 1.0]);
+                    draw("fs_combine", current, next, spare, [0.0; 2]);
+                    current = spare;
+                    continue;
+                },
+                PostEffect::ColorGrading { lut, strength } => {
+                    let Some(lut) = lut.get() else{
+                        continue;
+                    };
+                    let Some((_, bind_group)) = self.lut_bind_groups.get(&(current, Arc::as_ptr(&lut) as usize)) else{
+                        continue;
+                    };
+                    let pipeline = &self.pipelines[&(shaders::POST_COLOR_GRADING.path, "fs_main")];
+                    let uniform = self.uniform([*strength, lut.height() as f32, 0.0, 0.0], [0.0; 4]);
+                    self.draw(encoder, &mut uniforms, pipeline, bind_group, &self.targets[next].view, uniform);
+                },
+                PostEffect::Vignette { intensity, radius, softness } => {
+                    let pipeline = &self.pipelines[&(shaders::POST_VIGNETTE.path, "fs_main")];
+                    let uniform = self.uniform([*intensity, *radius, *softness, 0.0], [0.0; 4]);
+                    self.draw(encoder, &mut uniforms, pipeline, self.read_only(current), &self.targets[next].view, uniform);
+                },
+                PostEffect::Crt { scanlines, count, curvature } => {
+                    let pipeline = &self.pipelines[&(shaders::POST_CRT.path, "fs_main")];
+                    let uniform = self.uniform([*scanlines, *count, *curvature, 0.0], [0.0; 4]);
+                    self.draw(encoder, &mut uniforms, pipeline, self.read_only(current), &self.targets[next].view, uniform);
+                },
+                PostEffect::Custom { path, params } => {
+                    let Some(Some(pipeline)) = self.custom.get(path) else{
+                        continue;
+                    };
+                    let uniform = self.uniform([params[0], params[1], params[2], params[3]], [params[4], params[5], params[6], params[7]]);
+                    self.draw(encoder, &mut uniforms, pipeline, self.read_only(current), &self.targets[next].view, uniform);
+                }
+            }
+            current = next;
+        }
+
+        let pipeline = &self.pipelines[&(shaders::POST_COPY.path, "fs_main")];
+        let uniform = self.uniform([0.0; 4], [0.0; 4]);
+        self.draw(encoder, &mut uniforms, pipeline, self.read_only(current), output, uniform);
+        queue.write_buffer(&self.uniforms, 0, &uniforms);
+    }
+
+    fn uniform(&self, params: [f32; 4], extra: [f32; 4]) -> PostUniform{
+        PostUniform { params, extra, frame: [self.size.0 as f32, self.size.1 as f32, self.time, 0.0] }
+    }
+
+    /// The bind group of passes that only read the frame so far.
+    fn read_only(&self, target: usize) -> &wgpu::BindGroup{
+        &self.target_bind_groups[target * 4]
+    }
+
+    /// Draws a pass; its uniform goes at the end of `uniforms`, which `run` uploads once all are recorded.
+    fn draw(&self, encoder: &mut wgpu::CommandEncoder, uniforms: &mut Vec<u8>, pipeline: &wgpu::RenderPipeline, bind_group: &wgpu::BindGroup, target: &wgpu::TextureView, uniform: PostUniform){
+        let offset = uniforms.len();
+        uniforms.extend_from_slice(bytemuck::bytes_of(&uniform));
+        uniforms.resize(offset + self.uniform_stride as usize, 0);
+
+        let mut renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        renderpass.set_pipeline(pipeline);
+        renderpass.set_bind_group(0, bind_group, &[offset as u32]);
+        renderpass.draw(0..3, 0..1);
+    }
+}
+
+/// The two targets other than `current`: the one a pass draws into and a spare for passes that need a third, like bloom.
+fn others(current: usize) -> (usize, usize){
+    ((current + 1) % 3, (current + 2) % 3)
+}
+
+/// How many draws `PostChain::run` makes at most with `passes`, the copy to the window included.
+fn draw_count(passes: &[PostPass]) -> usize{
+    1 + passes.iter()
+        .filter(|pass| pass.enabled)
+        .map(|pass| if matches!(pass.effect, PostEffect::Bloom { .. }) { 4 } else { 1 })
+        .sum::<usize>()
+}
+
+fn create_uniform_buffer(device: &wgpu::Device, stride: u64, slots: usize) -> wgpu::Buffer{
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("post uniform buffer"),
+        size: stride * slots as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_post_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, entry_point: &str, format: wgpu::TextureFormat) -> wgpu::RenderPipeline{
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Post Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(entry_point),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn builtin_passes_are_valid(){
+        for (shader, entry_points, _) in BUILTIN_PASSES{
+            let source = format!("{}{}", PRELUDE, shader.builtin());
+            let module = wgpu::naga::front::wgsl::parse_str(&source).unwrap_or_else(|e| panic!("{}: {}", shader.path, e.emit_to_string(&source)));
+            wgpu::naga::valid::Validator::new(Default::default(), Default::default()).validate(&module).unwrap();
+            for entry_point in entry_points{
+                assert!(module.entry_points.iter().any(|entry| entry.name == *entry_point), "{} has no {}", shader.path, entry_point);
+            }
+        }
+    }
+
+    #[test]
+    fn bundled_custom_passes_compile(){
+        let source = format!("{}{}", PRELUDE, include_str!("../../../../resources/post/desaturate.wgsl"));
+        let module = wgpu::naga::front::wgsl::parse_str(&source).unwrap_or_else(|e| panic!("{}", e.emit_to_string(&source)));
+        wgpu::naga::valid::Validator::new(Default::default(), Default::default()).validate(&module).unwrap();
+    }
+
+    #[test]
+    fn passes_read_one_target_and_draw_into_another(){
+        for current in 0..3{
+            let (next, spare) = others(current);
+            let mut all = [current, next, spare];
+            all.sort();
+            assert_eq!(all, [0, 1, 2]);
+        }
+        assert_eq!(others(others(0).0).0, 2);
+
+        let mut crt = PostPass::crt();
+        crt.enabled = false;
+        assert_eq!(draw_count(&[PostPass::bloom(), PostPass::vignette(), crt]), 6);
+        assert_eq!(draw_count(&[]), 1);
+    }
+
+    /// As in `color_grading.wgsl`.
+    fn lut_uv(color: [f32; 3], slice: f32, size: f32) -> (f32, f32){
+        let x = (slice + (color[0] * (size - 1.0) + 0.5) / size) / size;
+        let y = (color[1] * (size - 1.0) + 0.5) / size;
+        (x, y)
+    }
+
+    #[test]
+    fn the_neutral_lut_gives_colors_back(){
+        let lut = image::load_from_memory(include_bytes!("../../../../resources/luts/neutral.png")).unwrap().to_rgba8();
+        let size = lut.height() as f32;
+        assert_eq!(lut.width(), lut.height() * lut.height());
+        for color in [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 0.0], [0.2, 0.6, 1.0], [1.0 / 15.0, 14.0 / 15.0, 7.0 / 15.0]]{
+            let slice = (color[2] * (size - 1.0)).round();
+            let (x, y) = lut_uv(color, slice, size);
+            // Half a texel in from the slice's edges, so filtering doesn't bleed into the next slice.
+            let within = x * size - slice;
+            assert!(within >= 0.5 / size - 1e-6 && within <= 1.0 - 0.5 / size + 1e-6);
+            let pixel = lut.get_pixel((x * lut.width() as f32) as u32, (y * lut.height() as f32) as u32);
+            for channel in 0..3{
+                assert!((pixel[channel] as f32 - color[channel] * 255.0).abs() <= 1.0, "{:?} became {:?}", color, pixel);
+            }
+        }
+    }
+}
@@ -8,6 +8,10 @@ pub struct Vertex {
 }
 
 impl Vertex {
+    pub fn new(position: [f32; 3], tex_coords: [f32; 2]) -> Self {
+        Self { position, tex_coords }
+    }
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
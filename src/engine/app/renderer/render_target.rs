@@ -0,0 +1,30 @@
+use egui_wgpu::wgpu;
+
+use crate::engine::app::assets::Handle;
+use super::camera::{Camera, CameraBinding};
+use super::texture::Texture;
+
+/// A camera view drawn into a texture every frame, before the window.
+/// The texture is a normal handle, so a `Sprite` can show it and egui can register its view.
+pub struct RenderTarget{
+    pub texture: Handle<Texture>,
+    camera: Camera,
+    pub binding: CameraBinding
+}
+
+impl RenderTarget{
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: Handle<Texture>, position: (f32, f32), scale: f32) -> Self{
+        let aspect = texture.get().map_or(1.0, |texture| texture.width() as f32 / texture.height() as f32);
+        let camera = Camera { position: (position.0, position.1, 0.0).into(), speed: 0.0, aspect, scale, rotation: 0.0 };
+        let binding = CameraBinding::new(device, layout, &camera);
+        Self { texture, camera, binding }
+    }
+
+    /// Moves the camera; `scale` is the half height of the view in world units, like the main camera's.
+    pub fn set_view(&mut self, queue: &wgpu::Queue, position: (f32, f32), scale: f32){
+        self.camera.position.x = position.0;
+        self.camera.position.y = position.1;
+        self.camera.scale = scale;
+        self.binding.write(queue, &self.camera);
+    }
+}
@@ -0,0 +1,110 @@
+/// How the virtual resolution is fitted into the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleMode{
+    /// Fills the window, distorting the picture if the shapes differ.
+    Stretch,
+    /// Scales as much as fits and centers the picture, leaving bars.
+    Letterbox,
+    /// Like letterbox, but only by whole multiples so pixels stay square and equal.
+    Integer,
+    /// Scales as much as fits and shows more of the world on the longer side instead of bars.
+    Expand
+}
+
+/// The size the game is designed for, independent of the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VirtualResolution{
+    pub width: u32,
+    pub height: u32,
+    pub mode: ScaleMode,
+    /// Draws into a texture of the virtual size and upscales it with nearest filtering, for pixel art.
+    pub pixel_perfect: bool
+}
+
+impl VirtualResolution{
+    pub fn new(width: u32, height: u32, mode: ScaleMode) -> Self{
+        Self { width, height, mode, pixel_perfect: false }
+    }
+
+    /// Where the game is drawn in a window of `window` physical pixels.
+    pub fn layout(&self, window: (u32, u32)) -> Layout{
+        let (window_width, window_height) = (window.0.max(1) as f32, window.1.max(1) as f32);
+        let (width, height) = (self.width.max(1) as f32, self.height.max(1) as f32);
+        let fit = (window_width / width).min(window_height / height);
+
+        let (scale, virtual_size) = match self.mode{
+            ScaleMode::Stretch => return Layout { area: (0.0, 0.0, window_width, window_height), virtual_size: (width, height) },
+            ScaleMode::Letterbox => (fit, (width, height)),
+            // Below 1x it can't stay whole, so it shrinks like letterbox.
+            ScaleMode::Integer => (if fit >= 1.0 { fit.floor() } else { fit }, (width, height)),
+            ScaleMode::Expand => (fit, (window_width / fit, window_height / fit))
+        };
+        let (area_width, area_height) = (virtual_size.0 * scale, virtual_size.1 * scale);
+        let x = ((window_width - area_width) * 0.5).floor();
+        let y = ((window_height - area_height) * 0.5).floor();
+        Layout { area: (x, y, area_width, area_height), virtual_size }
+    }
+}
+
+/// Where the game ends up in the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout{
+    /// The part of the window the game is drawn to, in physical pixels: x, y, width, height.
+    pub area: (f32, f32, f32, f32),
+    /// The size of `area` in virtual pixels; bigger than the virtual resolution with `Expand`.
+    pub virtual_size: (f32, f32)
+}
+
+impl Layout{
+    /// The whole window, for games without a virtual resolution.
+    pub fn window(window: (u32, u32)) -> Self{
+        let size = (window.0.max(1) as f32, window.1.max(1) as f32);
+        Self { area: (0.0, 0.0, size.0, size.1), virtual_size: size }
+    }
+
+    /// A physical window position in virtual pixels, `None` outside the game area.
+    pub fn to_virtual(self, point: (f32, f32)) -> Option<(f32, f32)>{
+        let (x, y, width, height) = self.area;
+        if point.0 < x || point.0 >= x + width || point.1 < y || point.1 >= y + height{
+            return None;
+        }
+        Some(((point.0 - x) / width * self.virtual_size.0, (point.1 - y) / height * self.virtual_size.1))
+    }
+
+    /// A position in virtual pixels as physical window pixels.
+    pub fn to_window(self, point: (f32, f32)) -> (f32, f32){
+        let (x, y, width, height) = self.area;
+        (x + point.0 / self.virtual_size.0 * width, y + point.1 / self.virtual_size.1 * height)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn fits_each_mode(){
+        let window = (1000, 600);
+        let layout = VirtualResolution::new(320, 180, ScaleMode::Stretch).layout(window);
+        assert_eq!(layout, Layout { area: (0.0, 0.0, 1000.0, 600.0), virtual_size: (320.0, 180.0) });
+
+        let layout = VirtualResolution::new(320, 180, ScaleMode::Letterbox).layout(window);
+        assert_eq!(layout.area, (0.0, 18.0, 1000.0, 562.5));
+
+        let layout = VirtualResolution::new(320, 180, ScaleMode::Integer).layout(window);
+        assert_eq!(layout.area, (20.0, 30.0, 960.0, 540.0));
+
+        let layout = VirtualResolution::new(320, 180, ScaleMode::Expand).layout(window);
+        assert_eq!(layout.area, (0.0, 0.0, 1000.0, 600.0));
+        assert_eq!(layout.virtual_size, (320.0, 192.0));
+    }
+
+    #[test]
+    fn maps_the_mouse_into_virtual_pixels(){
+        let layout = VirtualResolution::new(320, 180, ScaleMode::Integer).layout((1000, 600));
+        assert_eq!(layout.to_virtual((20.0, 30.0)), Some((0.0, 0.0)));
+        assert_eq!(layout.to_virtual((500.0, 300.0)), Some((160.0, 90.0)));
+        assert_eq!(layout.to_virtual((10.0, 300.0)), None);
+        assert_eq!(layout.to_window((160.0, 90.0)), (500.0, 300.0));
+    }
+}
@@ -0,0 +1,189 @@
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+
+use super::camera::Camera;
+use super::resolution::Layout;
+
+/// The cameras on screen during a frame, for converting between window and world coordinates.
+/// Window coordinates are logical pixels from the top left, like egui's; the DPI scale
+/// factor turns them into the physical pixels the viewports are laid out in.
+#[derive(Clone)]
+pub struct ScreenSpace{
+    /// In drawing order, each with its viewport in physical pixels.
+    views: Vec<(Camera, (f32, f32, f32, f32))>,
+    layout: Layout,
+    scale_factor: f32,
+    /// In physical pixels.
+    mouse: (f32, f32)
+}
+
+pub type SharedScreenSpace = Arc<Mutex<ScreenSpace>>;
+
+impl ScreenSpace{
+    pub fn new(views: Vec<(Camera, (f32, f32, f32, f32))>, layout: Layout, scale_factor: f32, mouse: (f32, f32)) -> Self{
+        Self { views, layout, scale_factor: if scale_factor > 0.0 { scale_factor } else { 1.0 }, mouse }
+    }
+
+    /// The world point under `point`, seen through the topmost camera whose viewport contains it.
+    pub fn screen_to_world(&self, point: (f32, f32)) -> Option<(f32, f32)>{
+        let physical = (point.0 * self.scale_factor, point.1 * self.scale_factor);
+        let (camera, viewport) = self.views.iter().rev().find(|(_, (x, y, width, height))|{
+            physical.0 >= *x && physical.0 < x + width && physical.1 >= *y && physical.1 < y + height
+        })?;
+        Some(camera.screen_to_world(physical, *viewport))
+    }
+
+    /// Where a world point is drawn by the main camera, the one on top.
+    /// The result can be outside the window or the camera's viewport.
+    pub fn world_to_screen(&self, point: (f32, f32)) -> Option<(f32, f32)>{
+        let (camera, viewport) = self.views.last()?;
+        let (x, y) = camera.world_to_screen(point, *viewport);
+        Some((x / self.scale_factor, y / self.scale_factor))
+    }
+
+    pub fn mouse_position(&self) -> (f32, f32){
+        (self.mouse.0 / self.scale_factor, self.mouse.1 / self.scale_factor)
+    }
+
+    /// A window position in the game's virtual pixels, `None` over the letterbox bars.
+    pub fn screen_to_virtual(&self, point: (f32, f32)) -> Option<(f32, f32)>{
+        self.layout.to_virtual((point.0 * self.scale_factor, point.1 * self.scale_factor))
+    }
+
+    pub fn virtual_to_screen(&self, point: (f32, f32)) -> (f32, f32){
+        let (x, y) = self.layout.to_window(point);
+        (x / self.scale_factor, y / self.scale_factor)
+    }
+
+    /// The cursor in virtual pixels, `None` outside the game area.
+    pub fn mouse_virtual_position(&self) -> Option<(f32, f32)>{
+        self.screen_to_virtual(self.mouse_position())
+    }
+
+    /// The world point under the cursor, `None` if no camera is drawn there.
+    pub fn mouse_world_position(&self) -> Option<(f32, f32)>{
+        self.screen_to_world(self.mouse_position())
+    }
+}
+
+impl Default for ScreenSpace{
+    fn default() -> Self{
+        Self::new(Vec::new(), Layout::window((1, 1)), 1.0, (0.0, 0.0))
+    }
+}
+
+/// Builds the `screen` table scripts use: `toWorld(x, y)` and `toScreen(x, y)`, which return
+/// nothing where no camera is drawn, `toVirtual(x, y)`, `fromVirtual(x, y)`, `mouse()`,
+/// `mouseWorld()` and `mouseVirtual()`.
+pub fn create_screen_table(lua: &Lua, screen: &SharedScreenSpace) -> LuaResult<LuaTable>{
+    let table = lua.create_table()?;
+
+    let screen_clone = screen.clone();
+    table.set("toWorld", lua.create_function(move |_, (x, y): (f32, f32)|{
+        Ok(screen_clone.lock().unwrap().screen_to_world((x, y)).unzip())
+    })?)?;
+
+    let screen_clone = screen.clone();
+    table.set("toScreen", lua.create_function(move |_, (x, y): (f32, f32)|{
+        Ok(screen_clone.lock().unwrap().world_to_screen((x, y)).unzip())
+    })?)?;
+
+    let screen_clone = screen.clone();
+    table.set("toVirtual", lua.create_function(move |_, (x, y): (f32, f32)|{
+        Ok(screen_clone.lock().unwrap().screen_to_virtual((x, y)).unzip())
+    })?)?;
+
+    let screen_clone = screen.clone();
+    table.set("fromVirtual", lua.create_function(move |_, (x, y): (f32, f32)|{
+        Ok(screen_clone.lock().unwrap().virtual_to_screen((x, y)))
+    })?)?;
+
+    let screen_clone = screen.clone();
+    table.set("mouseVirtual", lua.create_function(move |_, ()|{
+        Ok(screen_clone.lock().unwrap().mouse_virtual_position().unzip())
+    })?)?;
+
+    let screen_clone = screen.clone();
+    table.set("mouse", lua.create_function(move |_, ()|{
+        Ok(screen_clone.lock().unwrap().mouse_position())
+    })?)?;
+
+    let screen_clone = screen.clone();
+    table.set("mouseWorld", lua.create_function(move |_, ()|{
+        Ok(screen_clone.lock().unwrap().mouse_world_position().unzip())
+    })?)?;
+
+    Ok(table)
+}
+
+/// Sets the `screen` global of a script's Lua state unless it's already there.
+pub fn register(lua: &Lua, screen: &SharedScreenSpace) -> LuaResult<()>{
+    let globals = lua.globals();
+    if globals.contains_key("screen")?{
+        return Ok(());
+    }
+    globals.set("screen", create_screen_table(lua, screen)?)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn camera(position: (f32, f32), zoom: f32, aspect: f32) -> Camera{
+        Camera { position: cgmath::Point3 { x: position.0, y: position.1, z: 0.0 }, speed: 0.0, aspect, scale: 1.0 / zoom, rotation: 0.0 }
+    }
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32)){
+        assert!((actual.0 - expected.0).abs() < 1e-3 && (actual.1 - expected.1).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn converts_through_wide_and_tall_windows(){
+        // 16:9, zoom 1: the view is 2 units high and 3.56 wide.
+        let screen = ScreenSpace::new(vec![(camera((0.0, 0.0), 1.0, 16.0 / 9.0), (0.0, 0.0, 1920.0, 1080.0))], Layout::window((1, 1)), 1.0, (0.0, 0.0));
+        assert_near(screen.screen_to_world((960.0, 540.0)).unwrap(), (0.0, 0.0));
+        assert_near(screen.screen_to_world((0.0, 0.0)).unwrap(), (-16.0 / 9.0, 1.0));
+        assert_near(screen.screen_to_world((1919.99, 1079.99)).unwrap(), (16.0 / 9.0, -1.0));
+
+        // Portrait 1:2.
+        let screen = ScreenSpace::new(vec![(camera((0.0, 0.0), 1.0, 0.5), (0.0, 0.0, 500.0, 1000.0))], Layout::window((1, 1)), 1.0, (0.0, 0.0));
+        assert_near(screen.screen_to_world((499.99, 0.0)).unwrap(), (0.5, 1.0));
+        assert_near(screen.world_to_screen((-0.5, -1.0)).unwrap(), (0.0, 1000.0));
+    }
+
+    #[test]
+    fn zoom_and_position_scale_the_view(){
+        let screen = ScreenSpace::new(vec![(camera((10.0, -4.0), 4.0, 1.0), (0.0, 0.0, 800.0, 800.0))], Layout::window((1, 1)), 1.0, (0.0, 0.0));
+        // Zoom 4 shows half a unit vertically in total.
+        assert_near(screen.screen_to_world((400.0, 400.0)).unwrap(), (10.0, -4.0));
+        assert_near(screen.screen_to_world((799.99, 0.0)).unwrap(), (10.25, -3.75));
+
+        let screen = ScreenSpace::new(vec![(camera((0.0, 0.0), 0.5, 2.0), (0.0, 0.0, 1000.0, 500.0))], Layout::window((1, 1)), 1.0, (0.0, 0.0));
+        assert_near(screen.world_to_screen((2.0, 1.0)).unwrap(), (750.0, 125.0));
+    }
+
+    #[test]
+    fn accounts_for_dpi_and_viewports(){
+        // Right half of a 1600x900 physical window at 2x: logical 800x450.
+        let views = vec![
+            (camera((0.0, 0.0), 1.0, 800.0 / 900.0), (0.0, 0.0, 800.0, 900.0)),
+            (camera((100.0, 0.0), 1.0, 800.0 / 900.0), (800.0, 0.0, 800.0, 900.0))
+        ];
+        let screen = ScreenSpace::new(views, Layout::window((1600, 900)), 2.0, (1200.0, 450.0));
+        assert_eq!(screen.mouse_position(), (600.0, 225.0));
+        assert_near(screen.mouse_world_position().unwrap(), (100.0, 0.0));
+        assert_near(screen.screen_to_world((200.0, 225.0)).unwrap(), (0.0, 0.0));
+        // The main camera is the last one.
+        assert_near(screen.world_to_screen((100.0, 0.0)).unwrap(), (600.0, 225.0));
+        assert!(screen.screen_to_world((900.0, 100.0)).is_none());
+    }
+
+    #[test]
+    fn round_trips_with_rotation(){
+        let camera = Camera { rotation: 0.7, ..camera((3.0, 2.0), 1.5, 4.0 / 3.0) };
+        let viewport = (100.0, 50.0, 640.0, 480.0);
+        let world = camera.screen_to_world((300.0, 200.0), viewport);
+        assert_near(camera.world_to_screen(world, viewport), (300.0, 200.0));
+    }
+}
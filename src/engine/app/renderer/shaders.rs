@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use egui_wgpu::wgpu;
+
+use crate::engine::app::vfs;
+
+/// A shader of the engine itself, read from the asset root so it can be edited while the game runs.
+pub struct EngineShader{
+    pub path: &'static str,
+    /// The copy built into the binary, for when the file isn't there, e.g. in a packed game.
+    builtin: &'static str
+}
+
+pub const SPRITE: EngineShader = EngineShader { path: "resources/shaders/shader.wgsl", builtin: include_str!("../../../../resources/shaders/shader.wgsl") };
+pub const PICKING: EngineShader = EngineShader { path: "resources/shaders/picking_shader.wgsl", builtin: include_str!("../../../../resources/shaders/picking_shader.wgsl") };
+pub const PARTICLE: EngineShader = EngineShader { path: "resources/shaders/particle.wgsl", builtin: include_str!("../../../../resources/shaders/particle.wgsl") };
+
+pub const ENGINE_SHADERS: [&EngineShader; 3] = [&SPRITE, &PICKING, &PARTICLE];
+
+/// Fragment shaders of the post-processing passes, compiled after `post::PRELUDE`.
+pub const POST_COPY: EngineShader = EngineShader { path: "resources/shaders/post/copy.wgsl", builtin: include_str!("../../../../resources/shaders/post/copy.wgsl") };
+pub const POST_BLOOM: EngineShader = EngineShader { path: "resources/shaders/post/bloom.wgsl", builtin: include_str!("../../../../resources/shaders/post/bloom.wgsl") };
+pub const POST_COLOR_GRADING: EngineShader = EngineShader { path: "resources/shaders/post/color_grading.wgsl", builtin: include_str!("../../../../resources/shaders/post/color_grading.wgsl") };
+pub const POST_VIGNETTE: EngineShader = EngineShader { path: "resources/shaders/post/vignette.wgsl", builtin: include_str!("../../../../resources/shaders/post/vignette.wgsl") };
+pub const POST_CRT: EngineShader = EngineShader { path: "resources/shaders/post/crt.wgsl", builtin: include_str!("../../../../resources/shaders/post/crt.wgsl") };
+
+pub const POST_SHADERS: [&EngineShader; 5] = [&POST_COPY, &POST_BLOOM, &POST_COLOR_GRADING, &POST_VIGNETTE, &POST_CRT];
+
+impl EngineShader{
+    /// The file's source, or the built-in one if it can't be read.
+    pub fn source(&self) -> String{
+        vfs::read_to_string(self.path).unwrap_or_else(|_| self.builtin.to_string())
+    }
+
+    /// The source built into the binary.
+    #[cfg(test)]
+    pub fn builtin(&self) -> &'static str{
+        self.builtin
+    }
+
+    pub fn is_file(&self, file: &Path) -> bool{
+        vfs::real_path(self.path).is_some_and(|path| path == file)
+    }
+
+    /// Builds something from the file's source with `build`, falling back to the built-in source if that fails.
+    /// The error of the file is returned with the result, for the overlay.
+    pub fn build<T>(&self, mut build: impl FnMut(&str) -> Result<T>) -> (T, Option<String>){
+        match build(&self.source()){
+            Ok(built) => (built, None),
+            Err(e) => {
+                log::error!("{}: {:#}, using the built-in shader", self.path, e);
+                let built = build(self.builtin).unwrap_or_else(|e| panic!("built-in {} is invalid: {:#}", self.path, e));
+                (built, Some(format!("{}: {:#}", self.path, e)))
+            }
+        }
+    }
+}
+
+/// Compiles WGSL; validation errors come back as the error instead of a panic.
+pub fn compile(device: &wgpu::Device, label: &str, source: &str) -> Result<wgpu::ShaderModule>{
+    validated(device, ||{
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })
+    })
+}
+
+/// Runs `create`, e.g. building a pipeline, and returns the validation error it caused if any.
+pub fn validated<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T>{
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    match pollster::block_on(device.pop_error_scope()){
+        Some(e) => bail!("{}", e),
+        None => Ok(created)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::renderer::material;
+
+    #[test]
+    fn builtin_shaders_are_valid(){
+        for shader in ENGINE_SHADERS{
+            let source = match shader.path == SPRITE.path{
+                true => format!("{}{}", material::PRELUDE, shader.builtin),
+                false => shader.builtin.to_string()
+            };
+            let module = wgpu::naga::front::wgsl::parse_str(&source).unwrap_or_else(|e| panic!("{}: {}", shader.path, e));
+            wgpu::naga::valid::Validator::new(Default::default(), Default::default()).validate(&module).unwrap();
+        }
+    }
+}
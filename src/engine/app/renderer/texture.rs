@@ -1,36 +1,177 @@
-use image::GenericImageView;
+use image::RgbaImage;
 use anyhow::*;
 use egui_wgpu::{wgpu};
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Filter{
+    /// Sharp pixels, for pixel art.
+    #[default]
+    Nearest,
+    Linear
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Wrap{
+    #[default]
+    Clamp,
+    Repeat,
+    Mirror
+}
+
+/// How an image is turned into a texture.
+/// Read from a `<image>.meta` sidecar next to the image, or passed to the loader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureSettings{
+    pub filter: Filter,
+    pub wrap: Wrap,
+    /// Generates the full mip chain on load.
+    pub mipmaps: bool,
+    /// Multiplies colors by alpha on load; such sprites are drawn with premultiplied blending.
+    pub premultiply_alpha: bool,
+    /// Color data is sRGB encoded. Turn off for data textures like normal maps.
+    pub srgb: bool
+}
+
+impl Default for TextureSettings{
+    fn default() -> Self{
+        Self { filter: Filter::Nearest, wrap: Wrap::Clamp, mipmaps: false, premultiply_alpha: false, srgb: true }
+    }
+}
+
+impl TextureSettings{
+    /// Smooth filtering with mipmaps, for scaled artwork.
+    pub fn smooth() -> Self{
+        Self { filter: Filter::Linear, mipmaps: true, ..Self::default() }
+    }
+
+    /// Parses a sidecar file made of `key = value` lines:
+    /// `filter` (nearest, linear), `wrap` (clamp, repeat, mirror),
+    /// `mipmaps`, `premultiply_alpha` and `srgb` (true, false).
+    /// `#` starts a comment; missing keys keep their defaults.
+    pub fn parse(text: &str) -> Result<Self>{
+        let mut settings = Self::default();
+        for (number, line) in text.lines().enumerate(){
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty(){
+                continue;
+            }
+            let (key, value) = line.split_once('=').with_context(|| format!("line {}: expected key = value", number + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key{
+                "filter" => settings.filter = match value{
+                    "nearest" => Filter::Nearest,
+                    "linear" => Filter::Linear,
+                    _ => bail!("line {}: unknown filter {}", number + 1, value)
+                },
+                "wrap" => settings.wrap = match value{
+                    "clamp" => Wrap::Clamp,
+                    "repeat" => Wrap::Repeat,
+                    "mirror" => Wrap::Mirror,
+                    _ => bail!("line {}: unknown wrap {}", number + 1, value)
+                },
+                "mipmaps" => settings.mipmaps = parse_bool(value, number)?,
+                "premultiply_alpha" => settings.premultiply_alpha = parse_bool(value, number)?,
+                "srgb" => settings.srgb = parse_bool(value, number)?,
+                _ => bail!("line {}: unknown setting {}", number + 1, key)
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The pixel work done before upload: premultiplying and building the mip chain.
+    /// Cheap enough to run on a loading thread.
+    pub fn prepare(&self, image: &image::DynamicImage) -> Vec<RgbaImage>{
+        let mut base = image.to_rgba8();
+        if self.premultiply_alpha{
+            for pixel in base.pixels_mut(){
+                let alpha = pixel[3] as u32;
+                for channel in &mut pixel.0[..3]{
+                    *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+                }
+            }
+        }
+
+        let mut levels = vec![base];
+        if self.mipmaps{
+            loop{
+                let last = levels.last().unwrap();
+                let (width, height) = last.dimensions();
+                if width == 1 && height == 1{
+                    break;
+                }
+                let next = image::imageops::resize(last, (width / 2).max(1), (height / 2).max(1), image::imageops::FilterType::Triangle);
+                levels.push(next);
+            }
+        }
+        levels
+    }
+
+    fn format(&self) -> wgpu::TextureFormat{
+        if self.srgb { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm }
+    }
+
+    fn sampler(&self) -> wgpu::SamplerDescriptor<'static>{
+        let filter = match self.filter{
+            Filter::Nearest => wgpu::FilterMode::Nearest,
+            Filter::Linear => wgpu::FilterMode::Linear
+        };
+        let address_mode = match self.wrap{
+            Wrap::Clamp => wgpu::AddressMode::ClampToEdge,
+            Wrap::Repeat => wgpu::AddressMode::Repeat,
+            Wrap::Mirror => wgpu::AddressMode::MirrorRepeat
+        };
+        wgpu::SamplerDescriptor {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            ..Default::default()
+        }
+    }
+}
+
+fn parse_bool(value: &str, number: usize) -> Result<bool>{
+    match value{
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => bail!("line {}: expected true or false, got {}", number + 1, value)
+    }
+}
+
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
-    pub bind_group: wgpu::BindGroup
+    pub bind_group: wgpu::BindGroup,
+    pub settings: TextureSettings
 }
 
 impl Texture {
-    pub fn from_bytes(
+    pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        bytes: &[u8], 
+        img: &image::DynamicImage,
         layout: &wgpu::BindGroupLayout,
-        label: &str
+        label: Option<&str>
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, layout, Some(label))
+        let settings = TextureSettings::default();
+        Self::from_levels(device, queue, &settings.prepare(img), settings, layout, label)
     }
 
-    pub fn from_image(
+    /// Uploads images prepared by `TextureSettings::prepare`, the first one being the full size.
+    pub fn from_levels(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        img: &image::DynamicImage,
+        levels: &[RgbaImage],
+        settings: TextureSettings,
         layout: &wgpu::BindGroupLayout,
         label: Option<&str>
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let base = levels.first().context("No image data")?;
+        let dimensions = base.dimensions();
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -41,43 +182,129 @@ impl Texture {
             &wgpu::TextureDescriptor {
                 label,
                 size,
-                mip_level_count: 1,
+                mip_level_count: levels.len() as u32,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format: settings.format(),
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
                 view_formats: &[],
             }
         );
 
+        for (mip_level, level) in levels.iter().enumerate(){
+            let (width, height) = level.dimensions();
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                level,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            );
+        }
+
+        Ok(Self::from_texture(device, texture, settings, layout))
+    }
+
+    /// A texture filled from raw RGBA8 pixels, `width * height * 4` bytes, that
+    /// can be changed later with `write_region`. Pixels are uploaded as they are:
+    /// no mipmaps are generated and premultiplying them is up to the caller.
+    pub fn from_pixels(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        (width, height): (u32, u32),
+        pixels: &[u8],
+        settings: TextureSettings,
+        layout: &wgpu::BindGroupLayout,
+        label: Option<&str>
+    ) -> Result<Self> {
+        let image = RgbaImage::from_raw(width, height, pixels.to_vec())
+            .with_context(|| format!("Expected {} bytes of pixels for {}x{}, got {}", width as usize * height as usize * 4, width, height, pixels.len()))?;
+        Self::from_levels(device, queue, &[image], TextureSettings { mipmaps: false, ..settings }, layout, label)
+    }
+
+    /// A texture the sprite pipeline can draw into, in the surface `format`.
+    /// What's drawn on a cleared target is premultiplied, so it's marked as such.
+    pub fn render_target(
+        device: &wgpu::Device,
+        (width, height): (u32, u32),
+        format: wgpu::TextureFormat,
+        filter: Filter,
+        layout: &wgpu::BindGroupLayout,
+        label: Option<&str>
+    ) -> Result<Self> {
+        if width == 0 || height == 0{
+            bail!("A render target can't be {}x{}", width, height);
+        }
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            }
+        );
+        let settings = TextureSettings { filter, premultiply_alpha: true, ..Default::default() };
+        Ok(Self::from_texture(device, texture, settings, layout))
+    }
+
+    /// Replaces the `width` x `height` rectangle at `x`, `y` with raw RGBA8 pixels.
+    /// Only the full-size level is written, so use it on textures without mipmaps.
+    pub fn write_region(&self, queue: &wgpu::Queue, x: u32, y: u32, width: u32, height: u32, pixels: &[u8]) -> Result<()> {
+        if !matches!(self.texture.format(), wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb){
+            bail!("Only RGBA8 textures can be written to, this one is {:?}", self.texture.format());
+        }
+        let size = self.texture.size();
+        if x.checked_add(width).is_none_or(|right| right > size.width) || y.checked_add(height).is_none_or(|bottom| bottom > size.height){
+            bail!("Region {}x{} at {},{} is outside the {}x{} texture", width, height, x, y, size.width, size.height);
+        }
+        if pixels.len() != width as usize * height as usize * 4{
+            bail!("Expected {} bytes of pixels for {}x{}, got {}", width as usize * height as usize * 4, width, height, pixels.len());
+        }
+        if width == 0 || height == 0{
+            return Ok(());
+        }
+
         queue.write_texture(
             wgpu::TexelCopyTextureInfo {
                 aspect: wgpu::TextureAspect::All,
-                texture: &texture,
+                texture: &self.texture,
                 mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                origin: wgpu::Origin3d { x, y, z: 0 },
             },
-            &rgba,
+            pixels,
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
             },
-            size,
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
         );
+        Ok(())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.texture.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.texture.height()
+    }
 
+    fn from_texture(device: &wgpu::Device, texture: wgpu::Texture, settings: TextureSettings, layout: &wgpu::BindGroupLayout) -> Self {
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Nearest,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
-        );
+        let sampler = device.create_sampler(&settings.sampler());
 
         let bind_group = device.create_bind_group(
                 &wgpu::BindGroupDescriptor {
@@ -96,6 +323,42 @@ impl Texture {
                 }
             );
 
-        Ok(Self { texture, view, sampler, bind_group})
+        Self { texture, view, sampler, bind_group, settings }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parses_sidecars(){
+        let settings = TextureSettings::parse("
+            # smooth background
+            filter = linear
+            wrap=repeat
+            mipmaps = true   # full chain
+            srgb = false
+        ").unwrap();
+        assert_eq!(settings, TextureSettings { filter: Filter::Linear, wrap: Wrap::Repeat, mipmaps: true, premultiply_alpha: false, srgb: false });
+        assert_eq!(TextureSettings::parse("").unwrap(), TextureSettings::default());
+        assert_eq!(TextureSettings::parse("premultiply_alpha = true\nwrap = mirror").unwrap().wrap, Wrap::Mirror);
+    }
+
+    #[test]
+    fn reports_the_bad_line(){
+        let error = |text: &str| TextureSettings::parse(text).unwrap_err().to_string();
+        assert_eq!(error("filter = linear\nfilter = cubic"), "line 2: unknown filter cubic");
+        assert_eq!(error("mipmaps = yes"), "line 1: expected true or false, got yes");
+        assert_eq!(error("\n\nsharpen = true"), "line 3: unknown setting sharpen");
+        assert_eq!(error("linear"), "line 1: expected key = value");
+    }
+
+    #[test]
+    fn premultiplies_and_builds_mips(){
+        let image = image::DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 2, image::Rgba([200, 100, 0, 128])));
+        let levels = TextureSettings { premultiply_alpha: true, mipmaps: true, ..TextureSettings::default() }.prepare(&image);
+        assert_eq!(levels.iter().map(|level| level.dimensions()).collect::<Vec<_>>(), [(4, 2), (2, 1), (1, 1)]);
+        assert_eq!(levels[0].get_pixel(0, 0).0, [100, 50, 0, 128]);
     }
 }
@@ -0,0 +1,52 @@
+use egui_wgpu::wgpu;
+use wgpu::util::DeviceExt;
+
+use crate::engine::app::game::components::TileQuad;
+use super::render_data::Vertex;
+
+/// The GPU mesh of one chunk of one tilemap layer.
+pub struct ChunkMesh{
+    /// The `Tilemap::chunk_revision` and texture size it was built from.
+    pub revision: u64,
+    pub texture_size: (u32, u32),
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_count: u32
+}
+
+impl ChunkMesh{
+    pub fn new(device: &wgpu::Device, quads: &[TileQuad], revision: u64, texture_size: (u32, u32)) -> Self{
+        let mut vertices = Vec::with_capacity(quads.len() * 4);
+        let mut indices: Vec<u16> = Vec::with_capacity(quads.len() * 6);
+        for quad in quads{
+            let [left, bottom, right, top] = quad.rect;
+            let [u_left, v_top, u_right, v_bottom] = quad.uv;
+            let first = vertices.len() as u16;
+            vertices.push(Vertex::new([left, bottom, 0.0], [u_left, v_bottom]));
+            vertices.push(Vertex::new([right, bottom, 0.0], [u_right, v_bottom]));
+            vertices.push(Vertex::new([right, top, 0.0], [u_right, v_top]));
+            vertices.push(Vertex::new([left, top, 0.0], [u_left, v_top]));
+            indices.extend([first, first + 1, first + 2, first + 2, first + 3, first]);
+        }
+        // Empty buffers aren't allowed; an empty chunk just draws nothing.
+        if vertices.is_empty(){
+            vertices.push(Vertex::new([0.0; 3], [0.0; 2]));
+        }
+        if indices.is_empty(){
+            indices.extend([0, 0]);
+        }
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tilemap Chunk Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tilemap Chunk Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self { revision, texture_size, vertex_buffer, index_buffer, index_count: quads.len() as u32 * 6 }
+    }
+}
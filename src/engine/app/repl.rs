@@ -0,0 +1,459 @@
+use std::{cell::RefCell, sync::{Arc, Mutex}};
+
+use egui::{Color32, Key, Modifiers, RichText};
+use hecs::{Entity, World};
+use mlua::prelude::*;
+
+use crate::engine::app::{assets::AssetManager, game::components::{self, Label, Script, TransformComponent, ENTITY_TABLES}, renderer::texture::Texture, GameManager};
+
+const HELP: &str = "\
+entities()                      list objects as {id, label}
+position(id)                    x, y, rotation of an object
+set_position(id, x, y)          move an object
+set_rotation(id, angle)         rotate an object
+spawn(label, x, y [, texture])  create an object with an optional texture path, returns its id
+despawn(id)                     remove an object
+call(id, name, ...)             call a function of the object's script
+eval(id, code)                  run code inside the object's script";
+
+/// Functions the REPL binds on every evaluation, for autocompletion.
+const REPL_API: &[&str] = &["entities", "position", "set_position", "set_rotation", "spawn", "despawn", "call", "eval", "help"];
+
+/// Lua console evaluating commands against the live world.
+/// Objects are addressed by the id the editor shows (`Label::id`).
+pub struct Repl{
+    lua: Lua,
+    input: String,
+    output: Arc<Mutex<Vec<(Color32, String)>>>,
+    history: Vec<String>,
+    history_index: Option<usize>
+}
+
+impl Repl{
+    pub fn new() -> Self{
+        let lua = Lua::new();
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        let output_clone = output.clone();
+        let print_func = lua.create_function(move |_, args: LuaMultiValue|{
+            let parts: Vec<String> = args.iter().map(describe).collect();
+            output_clone.lock().unwrap().push((Color32::LIGHT_GRAY, parts.join("\t")));
+            Ok(())
+        }).unwrap();
+        lua.globals().set("print", print_func).unwrap();
+
+        Self { lua, input: String::new(), output, history: Vec::new(), history_index: None }
+    }
+
+    /// Runs `code` as an expression if it parses as one, as a statement otherwise.
+    pub fn eval(&mut self, code: &str, gm: &mut GameManager){
+        self.output.lock().unwrap().push((Color32::GRAY, format!("> {}", code)));
+        if self.history.last().map(String::as_str) != Some(code){
+            self.history.push(code.to_string());
+        }
+        self.history_index = None;
+
+        let gm = RefCell::new(gm);
+        let result = self.lua.scope(|scope|{
+            bind_world(&self.lua, scope, &gm)?;
+            run_chunk(&self.lua, code)
+        });
+
+        let mut output = self.output.lock().unwrap();
+        match result{
+            Ok(values) if !values.is_empty() => {
+                let parts: Vec<String> = values.iter().map(describe).collect();
+                output.push((Color32::WHITE, parts.join("\t")));
+            },
+            Ok(_) => {},
+            Err(e) => output.push((Color32::RED, e.to_string()))
+        }
+    }
+
+    /// Names matching the identifier at the end of the input.
+    /// After `gameObject.` or `name.`/`name:` the fields of that table are offered.
+    pub fn completions(&self) -> (usize, Vec<String>){
+        let start = self.input
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == ':'))
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        let word = &self.input[start..];
+
+        let (base, prefix, offset) = match word.rfind(['.', ':']){
+            Some(index) => (Some(&word[..index]), &word[index + 1..], start + index + 1),
+            None => (None, word, start)
+        };
+
+        let entity_table = base.and_then(|base| ENTITY_TABLES.iter().find(|(name, _)| *name == base));
+        let mut names: Vec<String> = match (base, entity_table){
+            (_, Some((_, api))) => api.iter().map(|name| name.to_string()).collect(),
+            (Some(base), None) => match self.lua.globals().get::<LuaValue>(base){
+                Ok(LuaValue::Table(table)) => table_keys(&table),
+                _ => Vec::new()
+            },
+            (None, _) => {
+                let mut names = table_keys(&self.lua.globals());
+                names.extend(REPL_API.iter().map(|name| name.to_string()));
+                names.extend(ENTITY_TABLES.iter().map(|(name, _)| name.to_string()));
+                names
+            }
+        };
+        names.retain(|name| name.starts_with(prefix));
+        names.sort();
+        names.dedup();
+        (offset, names)
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, gm: &mut GameManager, open: &mut bool){
+        egui::Window::new("Lua")
+            .open(open)
+            .resizable(true)
+            .default_height(250.0)
+            .show(ctx, |ui|{
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .auto_shrink(false)
+                    .max_height(ui.available_height() - 30.0)
+                    .show(ui, |ui|{
+                        for (color, line) in self.output.lock().unwrap().iter(){
+                            ui.label(RichText::new(line).color(*color).monospace());
+                        }
+                    });
+                ui.separator();
+
+                let input_id = ui.make_persistent_id("repl input");
+                if ui.memory(|memory| memory.has_focus(input_id)){
+                    if ui.input_mut(|input| input.consume_key(Modifiers::NONE, Key::Tab)){
+                        self.complete();
+                    }
+                    if ui.input_mut(|input| input.consume_key(Modifiers::NONE, Key::ArrowUp)){
+                        self.browse_history(-1);
+                    }
+                    if ui.input_mut(|input| input.consume_key(Modifiers::NONE, Key::ArrowDown)){
+                        self.browse_history(1);
+                    }
+                }
+
+                let response = ui.add(egui::TextEdit::singleline(&mut self.input)
+                    .id(input_id)
+                    .font(egui::TextStyle::Monospace)
+                    .desired_width(f32::INFINITY)
+                    .hint_text("Lua, help() for commands"));
+
+                if response.lost_focus() && ui.input(|input| input.key_pressed(Key::Enter)){
+                    let code = std::mem::take(&mut self.input);
+                    if !code.trim().is_empty(){
+                        self.eval(&code, gm);
+                    }
+                    response.request_focus();
+                }
+            });
+    }
+
+    fn complete(&mut self){
+        let (offset, names) = self.completions();
+        match names.as_slice(){
+            [] => {},
+            [name] => {
+                self.input.truncate(offset);
+                self.input.push_str(name);
+            },
+            names => {
+                let common = common_prefix(names);
+                self.input.truncate(offset);
+                self.input.push_str(&common);
+                self.output.lock().unwrap().push((Color32::GRAY, names.join("  ")));
+            }
+        }
+    }
+
+    fn browse_history(&mut self, direction: isize){
+        if self.history.is_empty(){
+            return;
+        }
+        let last = self.history.len() as isize - 1;
+        let index = match self.history_index{
+            Some(index) => index as isize + direction,
+            None if direction < 0 => last,
+            None => return
+        };
+        if index > last{
+            self.history_index = None;
+            self.input.clear();
+        } else {
+            let index = index.max(0) as usize;
+            self.history_index = Some(index);
+            self.input = self.history[index].clone();
+        }
+    }
+}
+
+impl Default for Repl{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+fn bind_world<'scope, 'env>(lua: &Lua, scope: &'scope mlua::Scope<'scope, 'env>, gm: &'env RefCell<&mut GameManager>) -> LuaResult<()>{
+    let globals = lua.globals();
+
+    globals.set("help", scope.create_function(|_, ()| Ok(HELP))?)?;
+
+    globals.set("entities", scope.create_function(|lua, ()|{
+        let gm = gm.borrow();
+        let list = lua.create_table()?;
+        for (_, label) in gm.world.query::<&Label>().iter(){
+            let entry = lua.create_table()?;
+            entry.set("id", label.id)?;
+            entry.set("label", label.label.clone())?;
+            list.push(entry)?;
+        }
+        Ok(list)
+    })?)?;
+
+    globals.set("position", scope.create_function(|_, id: u32|{
+        let gm = gm.borrow();
+        let transform = gm.world.get::<&TransformComponent>(find(&gm, id)?).map_err(LuaError::external)?;
+        let transform = transform.lock().unwrap();
+        Ok((transform.position.x, transform.position.y, transform.rotation.angle))
+    })?)?;
+
+    globals.set("set_position", scope.create_function(|_, (id, x, y): (u32, f32, f32)|{
+        let gm = gm.borrow();
+        let transform = gm.world.get::<&TransformComponent>(find(&gm, id)?).map_err(LuaError::external)?;
+        let mut transform = transform.lock().unwrap();
+        transform.position.x = x;
+        transform.position.y = y;
+        Ok(())
+    })?)?;
+
+    globals.set("set_rotation", scope.create_function(|_, (id, angle): (u32, f32)|{
+        let gm = gm.borrow();
+        let transform = gm.world.get::<&TransformComponent>(find(&gm, id)?).map_err(LuaError::external)?;
+        transform.lock().unwrap().rotation.angle = angle;
+        Ok(())
+    })?)?;
+
+    globals.set("spawn", scope.create_function(|_, (label, x, y, texture): (String, Option<f32>, Option<f32>, Option<String>)|{
+        let mut gm = gm.borrow_mut();
+        let gm = &mut **gm;
+        spawn(&mut gm.world, &mut gm.assets, &label, x.unwrap_or(0.0), y.unwrap_or(0.0), texture.as_deref())
+    })?)?;
+
+    globals.set("despawn", scope.create_function(|_, id: u32|{
+        let mut gm = gm.borrow_mut();
+        let entity = find(&gm, id)?;
+        gm.remove_object(entity);
+        Ok(())
+    })?)?;
+
+    globals.set("call", scope.create_function(|lua, (id, name, args): (u32, String, LuaMultiValue)|{
+        let gm = gm.borrow();
+        let entity = find(&gm, id)?;
+        let script = gm.world.get::<&Script>(entity).map_err(LuaError::external)?;
+        let values = script.run(&gm.world, &entity, |script_lua|{
+            let args = args.into_iter().map(|value| transfer(value, script_lua)).collect::<LuaResult<Vec<_>>>()?;
+            let func = script_lua.globals().get::<LuaFunction>(name)?;
+            func.call::<LuaMultiValue>(LuaMultiValue::from_iter(args))
+        })?;
+        values.into_iter().map(|value| transfer(value, lua)).collect::<LuaResult<LuaMultiValue>>()
+    })?)?;
+
+    globals.set("eval", scope.create_function(|lua, (id, code): (u32, String)|{
+        let gm = gm.borrow();
+        let entity = find(&gm, id)?;
+        let script = gm.world.get::<&Script>(entity).map_err(LuaError::external)?;
+        let values = script.run(&gm.world, &entity, |script_lua|{
+            script_lua.load(code).set_name("=repl").eval::<LuaMultiValue>()
+        })?;
+        values.into_iter().map(|value| transfer(value, lua)).collect::<LuaResult<LuaMultiValue>>()
+    })?)?;
+
+    Ok(())
+}
+
+/// Evaluates `code` as an expression if it parses as one, as a statement otherwise.
+/// Creates an object for `spawn`. The texture loads in the background and the sprite
+/// shows up once it's ready; this runs inside the frame, where a blocking load can't finish.
+fn spawn(world: &mut World, assets: &mut AssetManager, label: &str, x: f32, y: f32, texture: Option<&str>) -> LuaResult<u32>{
+    let sprite = match texture{
+        Some(path) => Some(assets.load::<Texture>(path).map_err(|e| LuaError::runtime(format!("{:#}", e)))?),
+        None => None
+    };
+    let entity = world.spawn((Label::from_str(label), components::Transform::new(x, y, 0.0)));
+    if let Some(texture) = sprite{
+        world.insert_one(entity, components::Sprite::new(texture)).map_err(LuaError::external)?;
+    }
+    let id = world.get::<&Label>(entity).map_err(LuaError::external)?.id;
+    Ok(id)
+}
+
+fn run_chunk(lua: &Lua, code: &str) -> LuaResult<LuaMultiValue>{
+    let chunk = match lua.load(format!("return {}", code)).set_name("=repl").into_function(){
+        Ok(func) => func,
+        Err(_) => lua.load(code).set_name("=repl").into_function()?
+    };
+    chunk.call::<LuaMultiValue>(())
+}
+
+fn find(gm: &GameManager, id: u32) -> LuaResult<Entity>{
+    gm.find_object(id).ok_or_else(|| LuaError::runtime(format!("no object with id {}", id)))
+}
+
+/// Copies a value between Lua states. Tables and functions can't be shared, they arrive as text.
+fn transfer(value: LuaValue, to: &Lua) -> LuaResult<LuaValue>{
+    Ok(match value{
+        LuaValue::Nil => LuaValue::Nil,
+        LuaValue::Boolean(value) => LuaValue::Boolean(value),
+        LuaValue::Integer(value) => LuaValue::Integer(value),
+        LuaValue::Number(value) => LuaValue::Number(value),
+        LuaValue::String(value) => LuaValue::String(to.create_string(&*value.as_bytes())?),
+        value => LuaValue::String(to.create_string(describe(&value))?)
+    })
+}
+
+/// Human readable value; tables are shown one level deep.
+fn describe(value: &LuaValue) -> String{
+    match value{
+        LuaValue::String(value) => value.to_string_lossy(),
+        LuaValue::Table(table) => {
+            let fields: Vec<String> = table.pairs::<LuaValue, LuaValue>().flatten().take(32).map(|(key, value)|{
+                let value = match value{
+                    LuaValue::Table(_) => "{...}".to_string(),
+                    LuaValue::String(value) => format!("{:?}", value.to_string_lossy()),
+                    value => value.to_string().unwrap_or_default()
+                };
+                match key{
+                    LuaValue::String(key) => format!("{} = {}", key.to_string_lossy(), value),
+                    _ => value
+                }
+            }).collect();
+            format!("{{{}}}", fields.join(", "))
+        },
+        value => value.to_string().unwrap_or_default()
+    }
+}
+
+fn table_keys(table: &LuaTable) -> Vec<String>{
+    table.pairs::<LuaValue, LuaValue>().flatten().filter_map(|(key, _)| match key{
+        LuaValue::String(key) => Some(key.to_string_lossy()),
+        _ => None
+    }).collect()
+}
+
+fn common_prefix(names: &[String]) -> String{
+    let first = &names[0];
+    let mut length = first.chars().count();
+    for name in &names[1..]{
+        length = first.chars().zip(name.chars()).take_while(|(a, b)| a == b).count().min(length);
+    }
+    first.chars().take(length).collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn complete(repl: &mut Repl, input: &str) -> Vec<String>{
+        repl.input = input.to_string();
+        repl.completions().1
+    }
+
+    #[test]
+    fn completes_entity_tables_and_globals(){
+        let mut repl = Repl::new();
+        assert_eq!(complete(&mut repl, "camera:set"), ["setBounds", "setPosition", "setRotation", "setZoom"]);
+        assert_eq!(complete(&mut repl, "x = gameObject.g"), ["getPosition"]);
+        assert_eq!(complete(&mut repl, "spa"), ["spawn"]);
+        assert!(complete(&mut repl, "").contains(&"particles".to_string()));
+
+        repl.lua.load("enemies = { boss = 1, bat = 2 }").exec().unwrap();
+        assert_eq!(complete(&mut repl, "print(enemies.b"), ["bat", "boss"]);
+        assert_eq!(repl.completions().0, "print(enemies.".len());
+    }
+
+    #[test]
+    fn tab_fills_in_the_common_prefix(){
+        let mut repl = Repl::new();
+        repl.input = "set_".to_string();
+        repl.complete();
+        assert_eq!(repl.input, "set_");
+        repl.input = "set_p".to_string();
+        repl.complete();
+        assert_eq!(repl.input, "set_position");
+        assert_eq!(common_prefix(&["string".to_string(), "strong".to_string()]), "str");
+    }
+
+    #[test]
+    fn browses_history(){
+        let mut repl = Repl::new();
+        repl.history = vec!["a".to_string(), "b".to_string()];
+        repl.browse_history(-1);
+        assert_eq!(repl.input, "b");
+        repl.browse_history(-1);
+        repl.browse_history(-1);
+        assert_eq!(repl.input, "a");
+        repl.browse_history(1);
+        assert_eq!(repl.input, "b");
+        repl.browse_history(1);
+        assert_eq!(repl.input, "");
+        assert_eq!(repl.history_index, None);
+    }
+
+    #[test]
+    fn runs_expressions_and_statements(){
+        let lua = Lua::new();
+        let values = run_chunk(&lua, "1 + 2").unwrap();
+        assert_eq!(values.iter().map(describe).collect::<Vec<_>>(), ["3"]);
+        assert!(run_chunk(&lua, "x = 5").unwrap().is_empty());
+        assert_eq!(lua.globals().get::<i32>("x").unwrap(), 5);
+        assert!(run_chunk(&lua, "x = = 5").is_err());
+    }
+
+    #[test]
+    fn describes_tables_one_level_deep(){
+        let lua = Lua::new();
+        let value: LuaValue = lua.load("{ name = 'bat', inner = {} }").eval().unwrap();
+        let text = describe(&value);
+        assert!(text.contains("name = \"bat\"") && text.contains("inner = {...}"), "{}", text);
+        let value = transfer(value, &Lua::new()).unwrap();
+        assert!(matches!(value, LuaValue::String(_)));
+    }
+
+    #[test]
+    fn spawn_leaves_the_texture_loading(){
+        /// Stands in for the texture loader, which needs a GPU to finish.
+        struct PendingTextures;
+
+        impl crate::engine::app::assets::AssetLoader for PendingTextures{
+            type Asset = Texture;
+            type Decoded = ();
+            type Settings = ();
+
+            fn decoder(&self) -> impl Fn(&str, Option<&()>) -> anyhow::Result<()> + Send + Sync + 'static{
+                |_: &str, _: Option<&()>| Ok(())
+            }
+
+            fn finish(&self, _: ()) -> anyhow::Result<Texture>{
+                anyhow::bail!("no GPU in tests")
+            }
+        }
+
+        let mut world = World::new();
+        let mut assets = AssetManager::new();
+        assert!(spawn(&mut world, &mut assets, "bat", 1.0, 2.0, Some("bat.png")).is_err());
+        assets.register(PendingTextures);
+
+        let id = spawn(&mut world, &mut assets, "bat", 1.0, 2.0, Some("bat.png")).unwrap();
+        let entity = world.query::<&Label>().iter().find(|(_, label)| label.id == id).map(|(entity, _)| entity).unwrap();
+        {
+            let sprite = world.get::<&components::Sprite>(entity).unwrap();
+            assert!(sprite.texture.ptr_eq(&assets.get::<Texture>("bat.png").unwrap()));
+            assert!(!sprite.texture.is_ready());
+        }
+        assert_eq!(world.get::<&TransformComponent>(entity).unwrap().lock().unwrap().position.x, 1.0);
+
+        spawn(&mut world, &mut assets, "plain", 0.0, 0.0, None).unwrap();
+        assert_eq!(world.query::<&components::Sprite>().iter().count(), 1);
+    }
+}
@@ -0,0 +1,421 @@
+use std::sync::Arc;
+
+use egui::{Color32, Key, Modifiers, RichText};
+use hecs::Entity;
+
+use crate::engine::app::{game::components::{Label, Tile, TileFlags, Tilemap, TransformComponent}, renderer::{egui_tools::EguiRenderer, texture::Texture}, GameManager};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool{
+    Paint,
+    Erase,
+    /// Replaces the connected area of equal tiles under the cursor.
+    Fill,
+    /// Fills the rectangle dragged out with the mouse.
+    Rectangle
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Change{
+    x: u32,
+    y: u32,
+    before: Option<Tile>,
+    after: Option<Tile>
+}
+
+/// One undo step: every tile a stroke, fill or rectangle changed.
+struct Edit{
+    entity: Entity,
+    layer: usize,
+    changes: Vec<Change>
+}
+
+impl Edit{
+    fn new(entity: Entity, layer: usize) -> Self{
+        Self { entity, layer, changes: Vec::new() }
+    }
+
+    /// Sets a tile and remembers what it was before the edit started.
+    fn set(&mut self, tilemap: &mut Tilemap, x: u32, y: u32, tile: Option<Tile>){
+        let before = tilemap.get(self.layer, x, y);
+        if before == tile || !tilemap.set(self.layer, x, y, tile){
+            return;
+        }
+        match self.changes.iter_mut().find(|change| change.x == x && change.y == y){
+            Some(change) => change.after = tile,
+            None => self.changes.push(Change { x, y, before, after: tile })
+        }
+    }
+
+    fn undo(&self, tilemap: &mut Tilemap){
+        for change in self.changes.iter().rev(){
+            tilemap.set(self.layer, change.x, change.y, change.before);
+        }
+    }
+
+    fn redo(&self, tilemap: &mut Tilemap){
+        for change in &self.changes{
+            tilemap.set(self.layer, change.x, change.y, change.after);
+        }
+    }
+}
+
+/// The tiles connected to `start` through edges that are the same as it.
+fn flood_fill(tilemap: &Tilemap, layer: usize, start: (u32, u32)) -> Vec<(u32, u32)>{
+    let target = tilemap.get(layer, start.0, start.1);
+    let mut seen = vec![false; (tilemap.width() * tilemap.height()) as usize];
+    let mut stack = vec![start];
+    let mut area = Vec::new();
+    while let Some((x, y)) = stack.pop(){
+        let index = (y * tilemap.width() + x) as usize;
+        if seen[index] || tilemap.get(layer, x, y) != target{
+            continue;
+        }
+        seen[index] = true;
+        area.push((x, y));
+        if x > 0{
+            stack.push((x - 1, y));
+        }
+        if y > 0{
+            stack.push((x, y - 1));
+        }
+        if x + 1 < tilemap.width(){
+            stack.push((x + 1, y));
+        }
+        if y + 1 < tilemap.height(){
+            stack.push((x, y + 1));
+        }
+    }
+    area
+}
+
+/// Tilemap editing in the debug overlay: pick a tile from the tileset and paint over the scene.
+/// Changes to tilemaps imported from Tiled can be saved back into the map file.
+pub struct TilePainter{
+    tilemap: Option<Entity>,
+    layer: usize,
+    tile: u32,
+    flags: TileFlags,
+    tool: Tool,
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// The edit of the stroke being painted.
+    stroke: Option<Edit>,
+    rectangle_start: Option<(u32, u32)>,
+    palette: Option<(Arc<Texture>, egui::TextureId)>,
+    status: Option<(Color32, String)>
+}
+
+impl TilePainter{
+    pub fn new() -> Self{
+        Self {
+            tilemap: None,
+            layer: 0,
+            tile: 0,
+            flags: TileFlags::NONE,
+            tool: Tool::Paint,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            stroke: None,
+            rectangle_start: None,
+            palette: None,
+            status: None
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, renderer: &mut EguiRenderer, gm: &mut GameManager, open: &mut bool){
+        egui::Window::new("Tiles")
+            .open(open)
+            .resizable(true)
+            .default_width(260.0)
+            .show(ctx, |ui| self.ui(ui, renderer, gm));
+        if !*open{
+            self.stroke = None;
+            self.rectangle_start = None;
+            return;
+        }
+
+        if !ctx.wants_keyboard_input(){
+            if ctx.input_mut(|input| input.consume_key(Modifiers::COMMAND, Key::Z)){
+                self.undo(gm);
+            }
+            if ctx.input_mut(|input| input.consume_key(Modifiers::COMMAND, Key::Y)){
+                self.redo(gm);
+            }
+        }
+        self.paint(ctx, gm);
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, renderer: &mut EguiRenderer, gm: &mut GameManager){
+        let tilemaps: Vec<(Entity, String)> = gm.world.query::<(&Label, &Tilemap)>().iter()
+            .map(|(entity, (label, _))| (entity, label.label.clone()))
+            .collect();
+        if self.tilemap.is_some_and(|entity| !tilemaps.iter().any(|(tilemap, _)| *tilemap == entity)){
+            self.tilemap = None;
+        }
+        let selected = self.tilemap.and_then(|entity| tilemaps.iter().find(|(tilemap, _)| *tilemap == entity)).map_or("none", |(_, label)| label.as_str());
+        egui::ComboBox::from_label("tilemap").selected_text(selected).show_ui(ui, |ui|{
+            for (entity, label) in &tilemaps{
+                if ui.selectable_label(self.tilemap == Some(*entity), label).clicked() && self.tilemap != Some(*entity){
+                    self.tilemap = Some(*entity);
+                    self.layer = 0;
+                }
+            }
+        });
+        let Some(entity) = self.tilemap else{
+            ui.label(RichText::new("no tilemap selected").color(Color32::GRAY));
+            return;
+        };
+        let Ok(mut tilemap) = gm.world.get::<&mut Tilemap>(entity) else{
+            return;
+        };
+
+        let layers: Vec<String> = tilemap.layers().iter().map(|layer| layer.name.clone()).collect();
+        self.layer = self.layer.min(layers.len().saturating_sub(1));
+        ui.horizontal(|ui|{
+            egui::ComboBox::from_label("layer").selected_text(layers.get(self.layer).map_or("", String::as_str)).show_ui(ui, |ui|{
+                for (index, name) in layers.iter().enumerate(){
+                    ui.selectable_value(&mut self.layer, index, name);
+                }
+            });
+            let mut visible = tilemap.layers().get(self.layer).is_some_and(|layer| layer.visible);
+            if ui.checkbox(&mut visible, "visible").changed(){
+                tilemap.set_layer_visible(self.layer, visible);
+            }
+        });
+
+        ui.horizontal(|ui|{
+            for (tool, name) in [(Tool::Paint, "paint"), (Tool::Erase, "erase"), (Tool::Fill, "fill"), (Tool::Rectangle, "rectangle")]{
+                ui.selectable_value(&mut self.tool, tool, name);
+            }
+        });
+        ui.horizontal(|ui|{
+            for (flag, name) in [(TileFlags::SOLID, "solid"), (TileFlags::FLIP_X, "flip x"), (TileFlags::FLIP_Y, "flip y")]{
+                let mut on = self.flags.contains(flag);
+                if ui.checkbox(&mut on, name).changed(){
+                    self.flags = TileFlags(if on { self.flags.0 | flag.0 } else { self.flags.0 & !flag.0 });
+                }
+            }
+        });
+
+        self.palette_ui(ui, renderer, &tilemap);
+        drop(tilemap);
+
+        ui.horizontal(|ui|{
+            if ui.add_enabled(!self.undo.is_empty(), egui::Button::new("Undo")).clicked(){
+                self.undo(gm);
+            }
+            if ui.add_enabled(!self.redo.is_empty(), egui::Button::new("Redo")).clicked(){
+                self.redo(gm);
+            }
+            if ui.button("Save").clicked(){
+                self.status = Some(match gm.save_tiled_map(entity){
+                    Ok(path) => (Color32::LIGHT_GREEN, format!("saved {}", path)),
+                    Err(e) => (Color32::RED, format!("{:#}", e))
+                });
+            }
+        });
+        if let Some((color, status)) = &self.status{
+            ui.label(RichText::new(status).color(*color));
+        }
+    }
+
+    /// The tileset as a grid of buttons selecting the tile to paint with.
+    fn palette_ui(&mut self, ui: &mut egui::Ui, renderer: &mut EguiRenderer, tilemap: &Tilemap){
+        let tileset = &tilemap.tileset;
+        let Some(texture) = tileset.texture.get() else{
+            ui.label(RichText::new(format!("{} is not loaded", tileset.texture.path())).color(Color32::GRAY));
+            return;
+        };
+        let texture_id = match &self.palette{
+            Some((registered, id)) if Arc::ptr_eq(registered, &texture) => *id,
+            _ => {
+                let id = renderer.register_texture(&texture.view);
+                self.palette = Some((texture.clone(), id));
+                id
+            }
+        };
+
+        let texture_size = (texture.width(), texture.height());
+        let count = tileset.tiles_in(texture_size);
+        egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui|{
+            egui::Grid::new("tile palette").spacing(egui::vec2(2.0, 2.0)).show(ui, |ui|{
+                for index in 0..count{
+                    let [left, top, right, bottom] = tileset.uv(index, texture_size);
+                    let image = egui::Image::new((texture_id, egui::vec2(32.0, 32.0)))
+                        .uv(egui::Rect::from_min_max(egui::pos2(left, top), egui::pos2(right, bottom)));
+                    if ui.add(egui::Button::image(image).selected(self.tile == index)).on_hover_text(index.to_string()).clicked(){
+                        self.tile = index;
+                        if self.tool == Tool::Erase{
+                            self.tool = Tool::Paint;
+                        }
+                    }
+                    if (index + 1) % tileset.columns == 0{
+                        ui.end_row();
+                    }
+                }
+            });
+        });
+    }
+
+    /// Applies the current tool under the mouse unless it's over a window.
+    fn paint(&mut self, ctx: &egui::Context, gm: &mut GameManager){
+        let Some(entity) = self.tilemap else{
+            return;
+        };
+        let (pointer, pressed, down, released) = ctx.input(|input|{
+            (input.pointer.hover_pos(), input.pointer.primary_pressed(), input.pointer.primary_down(), input.pointer.primary_released())
+        });
+        let over_window = ctx.is_pointer_over_area();
+        let Some((origin, tile_size)) = tilemap_placement(gm, entity) else{
+            return;
+        };
+        let cell = pointer.filter(|_| !over_window || self.stroke.is_some() || self.rectangle_start.is_some())
+            .and_then(|pointer| gm.screen_to_world(pointer.x, pointer.y))
+            .and_then(|(x, y)| gm.world.get::<&Tilemap>(entity).ok()?.tile_at(x - origin.0, y - origin.1));
+
+        let area = match (self.rectangle_start, cell){
+            (Some(start), Some(end)) => Some((start.0.min(end.0), start.1.min(end.1), start.0.max(end.0), start.1.max(end.1))),
+            (None, Some((x, y))) => Some((x, y, x, y)),
+            _ => None
+        };
+        if let Some(area) = area{
+            draw_cursor(ctx, gm, origin, tile_size, area);
+        }
+
+        let tile = Some(Tile { index: self.tile, flags: self.flags });
+        let Ok(mut tilemap) = gm.world.get::<&mut Tilemap>(entity) else{
+            return;
+        };
+        if pressed && !over_window && let Some((x, y)) = cell{
+            let mut edit = Edit::new(entity, self.layer);
+            match self.tool{
+                Tool::Paint => edit.set(&mut tilemap, x, y, tile),
+                Tool::Erase => edit.set(&mut tilemap, x, y, None),
+                Tool::Fill => {
+                    for (x, y) in flood_fill(&tilemap, self.layer, (x, y)){
+                        edit.set(&mut tilemap, x, y, tile);
+                    }
+                },
+                Tool::Rectangle => self.rectangle_start = Some((x, y))
+            }
+            self.stroke = Some(edit);
+        }
+        if down && let (Some(stroke), Some((x, y))) = (&mut self.stroke, cell){
+            match self.tool{
+                Tool::Paint => stroke.set(&mut tilemap, x, y, tile),
+                Tool::Erase => stroke.set(&mut tilemap, x, y, None),
+                _ => {}
+            }
+        }
+        if released && let Some(mut stroke) = self.stroke.take(){
+            if let (Some(_), Some((left, top, right, bottom))) = (self.rectangle_start.take(), area){
+                for y in top..=bottom{
+                    for x in left..=right{
+                        stroke.set(&mut tilemap, x, y, tile);
+                    }
+                }
+            }
+            if !stroke.changes.is_empty(){
+                self.undo.push(stroke);
+                self.redo.clear();
+            }
+        }
+    }
+
+    fn undo(&mut self, gm: &mut GameManager){
+        if let Some(edit) = self.undo.pop(){
+            match gm.world.get::<&mut Tilemap>(edit.entity){
+                Ok(mut tilemap) => edit.undo(&mut tilemap),
+                // Reimported or despawned; its history is gone with it.
+                Err(_) => return self.forget(edit.entity)
+            }
+            self.redo.push(edit);
+        }
+    }
+
+    fn redo(&mut self, gm: &mut GameManager){
+        if let Some(edit) = self.redo.pop(){
+            match gm.world.get::<&mut Tilemap>(edit.entity){
+                Ok(mut tilemap) => edit.redo(&mut tilemap),
+                Err(_) => return self.forget(edit.entity)
+            }
+            self.undo.push(edit);
+        }
+    }
+
+    fn forget(&mut self, entity: Entity){
+        self.undo.retain(|edit| edit.entity != entity);
+        self.redo.retain(|edit| edit.entity != entity);
+        self.status = Some((Color32::GRAY, "the edited tilemap is gone, its history was dropped".to_string()));
+    }
+}
+
+impl Default for TilePainter{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+/// Where a tilemap's origin is in the world and its tile size.
+fn tilemap_placement(gm: &GameManager, entity: Entity) -> Option<((f32, f32), f32)>{
+    let tile_size = gm.world.get::<&Tilemap>(entity).ok()?.tile_size;
+    let origin = gm.world.get::<&TransformComponent>(entity).map_or((0.0, 0.0), |transform|{
+        let transform = transform.lock().unwrap();
+        (transform.position.x, transform.position.y)
+    });
+    Some((origin, tile_size))
+}
+
+/// Outlines the tiles from `left`, `top` to `right`, `bottom` over the scene.
+fn draw_cursor(ctx: &egui::Context, gm: &GameManager, origin: (f32, f32), tile_size: f32, (left, top, right, bottom): (u32, u32, u32, u32)){
+    let (min_x, max_x) = (origin.0 + left as f32 * tile_size, origin.0 + (right + 1) as f32 * tile_size);
+    let (max_y, min_y) = (origin.1 - top as f32 * tile_size, origin.1 - (bottom + 1) as f32 * tile_size);
+    let corners: Option<Vec<egui::Pos2>> = [(min_x, max_y), (max_x, max_y), (max_x, min_y), (min_x, min_y)].into_iter()
+        .map(|(x, y)| gm.world_to_screen(x, y).map(|(x, y)| egui::pos2(x, y)))
+        .collect();
+    if let Some(corners) = corners{
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("tile painter cursor")));
+        painter.add(egui::Shape::closed_line(corners, egui::Stroke::new(2.0, Color32::YELLOW)));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::{assets::Handle, game::components::Tileset};
+
+    fn tilemap() -> Tilemap{
+        Tilemap::new(Tileset::new(Handle::pending("tiles.png"), 8, 8, 4), 4, 3, 1.0)
+    }
+
+    #[test]
+    fn fills_the_connected_area(){
+        let mut tilemap = tilemap();
+        // A wall splitting the left column from the rest.
+        for y in 0..3{
+            tilemap.set(0, 1, y, Some(Tile::solid(1)));
+        }
+        let mut area = flood_fill(&tilemap, 0, (3, 0));
+        area.sort();
+        assert_eq!(area, vec![(2, 0), (2, 1), (2, 2), (3, 0), (3, 1), (3, 2)]);
+        assert_eq!(flood_fill(&tilemap, 0, (1, 1)).len(), 3);
+    }
+
+    #[test]
+    fn undoes_a_stroke_at_once(){
+        let mut tilemap = tilemap();
+        tilemap.set(0, 0, 0, Some(Tile::new(5)));
+        let mut edit = Edit::new(Entity::DANGLING, 0);
+        edit.set(&mut tilemap, 0, 0, Some(Tile::new(1)));
+        edit.set(&mut tilemap, 1, 0, Some(Tile::new(1)));
+        // Painting over a tile twice keeps what it was before the stroke.
+        edit.set(&mut tilemap, 0, 0, Some(Tile::new(2)));
+        assert_eq!(edit.changes.len(), 2);
+
+        edit.undo(&mut tilemap);
+        assert_eq!((tilemap.get(0, 0, 0), tilemap.get(0, 1, 0)), (Some(Tile::new(5)), None));
+        edit.redo(&mut tilemap);
+        assert_eq!((tilemap.get(0, 0, 0), tilemap.get(0, 1, 0)), (Some(Tile::new(2)), Some(Tile::new(1))));
+    }
+}
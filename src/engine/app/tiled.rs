@@ -0,0 +1,1025 @@
+//! Imports maps made with Tiled (https://www.mapeditor.org), in its JSON (`.tmj`, `.json`)
+//! or XML (`.tmx`) format, with embedded or external (`.tsj`, `.tsx`) tilesets.
+//!
+//! Tile layers become `Tilemap`s, one per tileset the layer uses. Objects become entities with a
+//! `Label` (the object's name, else its class) and a `Transform`; tile objects get a `Sprite`, or a
+//! one tile `Tilemap` for tiles cut from an atlas, and shapes get a `Collider` from their bounding box.
+//! The custom properties `sprite` and `script` (file paths from the asset root) add a `Sprite` and a
+//! `Script`, and `layer` sets the camera layer. Tiles with collision shapes in the tileset are solid.
+//!
+//! One map tile is one world unit. Only orthogonal, finite maps without rotated tiles are supported.
+
+use std::{collections::HashMap, io::Read};
+
+use anyhow::{anyhow, bail, Context};
+use hecs::{Entity, World};
+
+use crate::engine::app::{assets::{AssetManager, Handle}, game::components::{self, Collider, Label, Tile, TileFlags, Tilemap, Tileset}, renderer::texture::Texture, vfs};
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const ROTATED_HEXAGONAL: u32 = 0x1000_0000;
+const GID_MASK: u32 = !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL);
+
+pub struct TiledMap{
+    /// In tiles.
+    pub width: u32,
+    pub height: u32,
+    /// In pixels.
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub tilesets: Vec<TiledTileset>,
+    /// Group layers are flattened into their children.
+    pub layers: Vec<TiledLayer>,
+    /// External tileset files the map was read from, for reloading.
+    pub dependencies: Vec<String>
+}
+
+pub struct TiledTileset{
+    pub first_gid: u32,
+    pub name: String,
+    /// The atlas, `None` for a collection of images.
+    pub image: Option<String>,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub columns: u32,
+    pub margin: u32,
+    pub spacing: u32,
+    /// Tiles in the atlas; the last row may be partly empty.
+    pub tile_count: Option<u32>,
+    /// Tiles with their own image or collision shapes, by index.
+    pub tiles: HashMap<u32, TiledTile>
+}
+
+#[derive(Default)]
+pub struct TiledTile{
+    pub image: Option<String>,
+    pub solid: bool
+}
+
+pub enum TiledLayer{
+    Tiles{
+        name: String,
+        visible: bool,
+        /// In pixels.
+        offset: (f32, f32),
+        /// Global tile ids row by row, 0 for empty, with the flip bits.
+        data: Vec<u32>
+    },
+    Objects{
+        name: String,
+        visible: bool,
+        offset: (f32, f32),
+        objects: Vec<TiledObject>
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TiledShape{
+    Rectangle,
+    Ellipse,
+    Point,
+    /// Points relative to the object's position.
+    Polygon(Vec<(f32, f32)>)
+}
+
+/// Positions and sizes in pixels, y down.
+pub struct TiledObject{
+    pub id: u32,
+    pub name: String,
+    pub class: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Degrees, clockwise.
+    pub rotation: f32,
+    pub visible: bool,
+    pub gid: Option<u32>,
+    pub shape: TiledShape,
+    pub properties: HashMap<String, String>
+}
+
+impl TiledMap{
+    /// Reads a map and its external tilesets through the VFS.
+    pub fn load(path: &str) -> anyhow::Result<Self>{
+        let text = vfs::read_to_string(path).with_context(|| format!("Could not read {}", path))?;
+        Self::parse(path, &text).with_context(|| format!("Invalid Tiled map {}", path))
+    }
+
+    /// Parses a map; `path` picks the format and is where relative paths start from.
+    pub fn parse(path: &str, text: &str) -> anyhow::Result<Self>{
+        let map = if is_xml(path){
+            xml::parse_map(path, text)?
+        } else{
+            json::parse_map(path, text)?
+        };
+        map.check_tiles()?;
+        Ok(map)
+    }
+
+    /// Tilemaps can flip tiles but not rotate them, which Tiled stores as a diagonal flip.
+    fn check_tiles(&self) -> anyhow::Result<()>{
+        for layer in &self.layers{
+            if let TiledLayer::Tiles { name, data, .. } = layer
+                && let Some(i) = data.iter().position(|gid| gid & FLIPPED_DIAGONALLY != 0){
+                let (x, y) = (i as u32 % self.width.max(1), i as u32 / self.width.max(1));
+                bail!("layer '{}' rotates the tile at {}, {}; rotated (diagonally flipped) tiles are not supported", name, x, y);
+            }
+        }
+        Ok(())
+    }
+
+    /// The tileset a global tile id (without flip bits) belongs to.
+    pub fn tileset(&self, gid: u32) -> Option<&TiledTileset>{
+        self.tilesets.iter().rev().find(|tileset| tileset.first_gid <= gid)
+    }
+
+    /// The tile ids of the `index`th tile layer, counting the layers of groups.
+    pub fn tile_layer(&self, index: usize) -> Option<&[u32]>{
+        self.layers.iter().filter_map(|layer| match layer{
+            TiledLayer::Tiles { data, .. } => Some(data.as_slice()),
+            TiledLayer::Objects { .. } => None
+        }).nth(index)
+    }
+
+    /// The ids of a tile layer after editing its tilemaps, given with the first id of their tileset.
+    /// Cells of those tilesets come from the tilemaps, the others keep their id from the file.
+    pub fn edited_tile_layer(&self, index: usize, tilemaps: &[(u32, &Tilemap)]) -> anyhow::Result<Vec<u32>>{
+        let original = self.tile_layer(index).ok_or_else(|| anyhow!("the map has no tile layer {}", index))?;
+        let mut data: Vec<u32> = original.iter().map(|&gid|{
+            let edited = self.tileset(gid & GID_MASK).is_some_and(|tileset| tilemaps.iter().any(|(first_gid, _)| *first_gid == tileset.first_gid));
+            if edited { 0 } else { gid }
+        }).collect();
+        for (first_gid, tilemap) in tilemaps{
+            for y in 0..tilemap.height().min(self.height){
+                for x in 0..tilemap.width().min(self.width){
+                    if let Some(tile) = tilemap.get(0, x, y){
+                        data[(y * self.width + x) as usize] = tile_gid(*first_gid, tile);
+                    }
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// Creates the entities of the map read from `path`. On failure nothing is left behind.
+    pub fn spawn(&self, path: &str, world: &mut World, assets: &mut AssetManager) -> anyhow::Result<ImportedMap>{
+        let mut imported = ImportedMap { path: path.to_string(), dependencies: self.dependencies.clone(), entities: Vec::new(), tile_layers: Vec::new() };
+        if let Err(e) = self.spawn_layers(world, assets, &mut imported){
+            for entity in imported.entities{
+                let _ = world.despawn(entity);
+            }
+            return Err(e);
+        }
+        Ok(imported)
+    }
+
+    fn spawn_layers(&self, world: &mut World, assets: &mut AssetManager, imported: &mut ImportedMap) -> anyhow::Result<()>{
+        let entities = &mut imported.entities;
+        let mut tile_layer = 0;
+        for layer in &self.layers{
+            match layer{
+                TiledLayer::Tiles { name, visible, offset, data } => {
+                    for (entity, first_gid) in self.spawn_tiles(world, assets, (name, *visible, *offset), data)?{
+                        entities.push(entity);
+                        imported.tile_layers.push(TileLayerEntity { entity, layer: tile_layer, first_gid });
+                    }
+                    tile_layer += 1;
+                },
+                TiledLayer::Objects { name, visible, offset, objects } => {
+                    for object in objects{
+                        self.spawn_object(world, assets, (name, *offset, *visible && object.visible), object, entities)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Spawns a tilemap for each tileset the layer uses and returns them with the tileset's first id.
+    /// An empty layer gets an empty tilemap of the first atlas, to paint on.
+    fn spawn_tiles(&self, world: &mut World, assets: &mut AssetManager, (name, visible, offset): (&str, bool, (f32, f32)), data: &[u32]) -> anyhow::Result<Vec<(Entity, u32)>>{
+        if data.len() != (self.width * self.height) as usize{
+            bail!("layer '{}' has {} tiles instead of {}", name, data.len(), self.width * self.height);
+        }
+        let mut tilemaps: Vec<(u32, Tilemap)> = Vec::new();
+        let mut skipped = Vec::new();
+        for (i, &gid) in data.iter().enumerate(){
+            let Some(tileset) = self.tileset(gid & GID_MASK) else{
+                continue;
+            };
+            let Some(image) = &tileset.image else{
+                if !skipped.contains(&tileset.first_gid){
+                    log::warn!("Tile layer '{}' uses the image collection '{}', which tilemaps can't draw", name, tileset.name);
+                    skipped.push(tileset.first_gid);
+                }
+                continue;
+            };
+            if !tilemaps.iter().any(|(first_gid, _)| *first_gid == tileset.first_gid){
+                tilemaps.push((tileset.first_gid, self.empty_tilemap(tileset, image, visible, assets)?));
+            }
+            let (_, tilemap) = tilemaps.iter_mut().find(|(first_gid, _)| *first_gid == tileset.first_gid).unwrap();
+            let x = i as u32 % self.width;
+            let y = i as u32 / self.width;
+            tilemap.set(0, x, y, Some(tileset.tile(gid)));
+        }
+
+        if tilemaps.is_empty()
+            && let Some((tileset, image)) = self.tilesets.iter().find_map(|tileset| tileset.image.as_ref().map(|image| (tileset, image))){
+            tilemaps.push((tileset.first_gid, self.empty_tilemap(tileset, image, visible, assets)?));
+        }
+
+        let several = tilemaps.len() > 1;
+        let (x, y) = self.to_world(offset);
+        Ok(tilemaps.into_iter().map(|(first_gid, tilemap)|{
+            let label = match several{
+                true => format!("{} ({})", name, self.tileset(first_gid).map_or("", |tileset| &tileset.name)),
+                false => name.to_string()
+            };
+            (world.spawn((Label::new(label), tilemap, components::Transform::new(x, y, 0.0))), first_gid)
+        }).collect())
+    }
+
+    fn empty_tilemap(&self, tileset: &TiledTileset, image: &str, visible: bool, assets: &mut AssetManager) -> anyhow::Result<Tilemap>{
+        let mut tilemap = Tilemap::new(tileset.engine_tileset(assets.load::<Texture>(image)?), self.width, self.height, 1.0);
+        tilemap.set_layer_visible(0, visible);
+        Ok(tilemap)
+    }
+
+    fn spawn_object(&self, world: &mut World, assets: &mut AssetManager, (layer, offset, visible): (&str, (f32, f32), bool), object: &TiledObject, entities: &mut Vec<Entity>) -> anyhow::Result<()>{
+        let label = [&object.name, &object.class].into_iter().find(|label| !label.is_empty()).cloned()
+            .unwrap_or_else(|| format!("{} {}", layer, object.id));
+        let angle = -object.rotation.to_radians();
+        let camera_layer = match object.properties.get("layer"){
+            Some(layer) => layer.parse::<u32>().with_context(|| format!("object {}: layer must be a number from 0 to 31", object.id))?.min(31),
+            None => 0
+        };
+
+        let entity = world.spawn((Label::new(label),));
+        entities.push(entity);
+        let tile = object.gid.and_then(|gid| self.tileset(gid & GID_MASK).map(|tileset| (gid, tileset)));
+        let atlas_tile = tile.filter(|(gid, tileset)| tileset.image.is_some() && tileset.tiles.get(&tileset.index(*gid)).is_none_or(|tile| tile.image.is_none()));
+
+        // Tiled anchors tile objects at the bottom left and everything else at the top left,
+        // and rotates around that point.
+        let (pivot_x, pivot_y) = (offset.0 + object.x, offset.1 + object.y);
+        let (min, max) = object.bounds();
+        let corner = if object.gid.is_some(){ (0.0, -object.height) } else{ (0.0, 0.0) };
+        let local = match atlas_tile{
+            // A tilemap's origin is its top left corner.
+            Some(_) => corner,
+            None => (corner.0 + (min.0 + max.0) * 0.5, corner.1 + (min.1 + max.1) * 0.5)
+        };
+        let (sin, cos) = object.rotation.to_radians().sin_cos();
+        let position = (pivot_x + local.0 * cos - local.1 * sin, pivot_y + local.0 * sin + local.1 * cos);
+        let (x, y) = self.to_world(position);
+        world.insert_one(entity, components::Transform::new(x, y, angle))?;
+
+        if let Some((gid, tileset)) = atlas_tile{
+            let texture = assets.load::<Texture>(tileset.image.as_ref().unwrap())?;
+            let mut tilemap = Tilemap::new(tileset.engine_tileset(texture), 1, 1, object.width / self.tile_width as f32);
+            tilemap.set(0, 0, 0, Some(tileset.tile(gid)));
+            tilemap.set_layer_visible(0, visible);
+            tilemap.layer = camera_layer;
+            world.insert_one(entity, tilemap)?;
+        }
+
+        let image = object.properties.get("sprite").cloned()
+            .or_else(|| tile.and_then(|(gid, tileset)| tileset.tiles.get(&tileset.index(gid)).and_then(|tile| tile.image.clone())));
+        if let Some(image) = image.filter(|_| visible){
+            let mut sprite = components::Sprite::new(assets.load::<Texture>(&image)?);
+            sprite.layer = camera_layer;
+            world.insert_one(entity, sprite)?;
+        }
+
+        if object.gid.is_none() && object.shape != TiledShape::Point{
+            let size = ((max.0 - min.0) / self.tile_width as f32, (max.1 - min.1) / self.tile_height as f32);
+            world.insert_one(entity, Collider::new(size.0, size.1))?;
+        }
+
+        if let Some(script) = object.properties.get("script"){
+            world.insert_one(entity, components::Script::new(assets.load(script)?))?;
+        }
+        Ok(())
+    }
+
+    /// A position in map pixels, y down, in world units, y up.
+    fn to_world(&self, (x, y): (f32, f32)) -> (f32, f32){
+        (x / self.tile_width as f32, -y / self.tile_height as f32)
+    }
+}
+
+impl TiledTileset{
+    fn engine_tileset(&self, texture: Handle<Texture>) -> Tileset{
+        Tileset { margin: self.margin, spacing: self.spacing, tile_count: self.tile_count, ..Tileset::new(texture, self.tile_width, self.tile_height, self.columns) }
+    }
+
+    fn index(&self, gid: u32) -> u32{
+        (gid & GID_MASK) - self.first_gid
+    }
+
+    fn tile(&self, gid: u32) -> Tile{
+        let index = self.index(gid);
+        let mut flags = TileFlags::NONE;
+        if gid & FLIPPED_HORIZONTALLY != 0{
+            flags = flags | TileFlags::FLIP_X;
+        }
+        if gid & FLIPPED_VERTICALLY != 0{
+            flags = flags | TileFlags::FLIP_Y;
+        }
+        if self.tiles.get(&index).is_some_and(|tile| tile.solid){
+            flags = flags | TileFlags::SOLID;
+        }
+        Tile { index, flags }
+    }
+}
+
+fn tile_gid(first_gid: u32, tile: Tile) -> u32{
+    let mut gid = first_gid + tile.index;
+    if tile.flags.contains(TileFlags::FLIP_X){
+        gid |= FLIPPED_HORIZONTALLY;
+    }
+    if tile.flags.contains(TileFlags::FLIP_Y){
+        gid |= FLIPPED_VERTICALLY;
+    }
+    gid
+}
+
+/// Replaces the ids of tile layers in the text of a map, by their position among the tile layers.
+/// The replaced layers are written uncompressed; the rest of the file is kept as it was.
+pub fn replace_tile_data(path: &str, text: &str, layers: &HashMap<usize, Vec<u32>>) -> anyhow::Result<String>{
+    if is_xml(path){
+        xml::replace_tile_data(text, layers)
+    } else{
+        json::replace_tile_data(text, layers)
+    }
+}
+
+impl TiledObject{
+    /// The shape's bounding box relative to the object's position, unrotated.
+    fn bounds(&self) -> ((f32, f32), (f32, f32)){
+        match &self.shape{
+            TiledShape::Polygon(points) if !points.is_empty() => {
+                let min = points.iter().fold((f32::MAX, f32::MAX), |min, point| (min.0.min(point.0), min.1.min(point.1)));
+                let max = points.iter().fold((f32::MIN, f32::MIN), |max, point| (max.0.max(point.0), max.1.max(point.1)));
+                (min, max)
+            },
+            _ => ((0.0, 0.0), (self.width, self.height))
+        }
+    }
+}
+
+fn is_xml(path: &str) -> bool{
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".tmx") || path.ends_with(".tsx")
+}
+
+/// Tile ids stored as base64, optionally zlib or gzip compressed, four little endian bytes each.
+fn decode_tile_data(data: &str, compression: &str) -> anyhow::Result<Vec<u32>>{
+    let bytes = decode_base64(data)?;
+    let bytes = match compression{
+        "" => bytes,
+        "zlib" => {
+            let mut out = Vec::new();
+            flate2::read::ZlibDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+            out
+        },
+        "gzip" => {
+            let mut out = Vec::new();
+            flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+            out
+        },
+        other => bail!("{} compressed tile data is not supported", other)
+    };
+    if bytes.len() % 4 != 0{
+        bail!("tile data is {} bytes, not a multiple of 4", bytes.len());
+    }
+    Ok(bytes.chunks_exact(4).map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]])).collect())
+}
+
+fn decode_base64(text: &str) -> anyhow::Result<Vec<u8>>{
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'='){
+        let value = match c{
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => bail!("invalid base64 character '{}'", c as char)
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8{
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+fn load_tileset(map_path: &str, source: &str, first_gid: u32, dependencies: &mut Vec<String>) -> anyhow::Result<TiledTileset>{
+    let path = vfs::resolve(map_path, source);
+    let text = vfs::read_to_string(&path).with_context(|| format!("Could not read tileset {}", path))?;
+    dependencies.push(path.clone());
+    let tileset = if is_xml(&path){
+        xml::parse_tileset(&path, roxmltree::Document::parse(&text)?.root_element(), first_gid)
+    } else{
+        json::parse_tileset(&path, &serde_json::from_str(&text)?, first_gid)
+    };
+    tileset.with_context(|| format!("Invalid tileset {}", path))
+}
+
+mod json{
+    use std::ops::Range;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    fn field<'a>(value: &'a Value, name: &str) -> anyhow::Result<&'a Value>{
+        value.get(name).ok_or_else(|| anyhow!("missing '{}'", name))
+    }
+
+    fn uint(value: &Value, name: &str) -> anyhow::Result<u32>{
+        field(value, name)?.as_u64().map(|value| value as u32).ok_or_else(|| anyhow!("'{}' is not a whole number", name))
+    }
+
+    fn float_or(value: &Value, name: &str, default: f32) -> f32{
+        value.get(name).and_then(Value::as_f64).map_or(default, |value| value as f32)
+    }
+
+    fn string(value: &Value, name: &str) -> String{
+        value.get(name).and_then(Value::as_str).unwrap_or_default().to_string()
+    }
+
+    fn properties(value: &Value) -> HashMap<String, String>{
+        let Some(properties) = value.get("properties").and_then(Value::as_array) else{
+            return HashMap::new();
+        };
+        properties.iter().filter_map(|property|{
+            let value = match property.get("value")?{
+                Value::String(text) => text.clone(),
+                other => other.to_string()
+            };
+            Some((property.get("name")?.as_str()?.to_string(), value))
+        }).collect()
+    }
+
+    pub(super) fn parse_map(path: &str, text: &str) -> anyhow::Result<TiledMap>{
+        let map: Value = serde_json::from_str(text)?;
+        check_map(&string(&map, "orientation"), map.get("infinite").and_then(Value::as_bool).unwrap_or(false))?;
+        let mut dependencies = Vec::new();
+        let mut tilesets = Vec::new();
+        for tileset in field(&map, "tilesets")?.as_array().into_iter().flatten(){
+            let first_gid = uint(tileset, "firstgid")?;
+            tilesets.push(match tileset.get("source").and_then(Value::as_str){
+                Some(source) => load_tileset(path, source, first_gid, &mut dependencies)?,
+                None => parse_tileset(path, tileset, first_gid)?
+            });
+        }
+        let mut layers = Vec::new();
+        parse_layers(field(&map, "layers")?, (0.0, 0.0), true, &mut layers)?;
+        Ok(TiledMap {
+            width: uint(&map, "width")?,
+            height: uint(&map, "height")?,
+            tile_width: uint(&map, "tilewidth")?.max(1),
+            tile_height: uint(&map, "tileheight")?.max(1),
+            tilesets,
+            layers,
+            dependencies
+        })
+    }
+
+    pub(super) fn parse_tileset(path: &str, tileset: &Value, first_gid: u32) -> anyhow::Result<TiledTileset>{
+        let mut tiles = HashMap::new();
+        for tile in tileset.get("tiles").and_then(Value::as_array).into_iter().flatten(){
+            let image = tile.get("image").and_then(Value::as_str).map(|image| vfs::resolve(path, image));
+            let solid = tile.get("objectgroup").and_then(|group| group.get("objects")).and_then(Value::as_array).is_some_and(|objects| !objects.is_empty());
+            tiles.insert(uint(tile, "id")?, TiledTile { image, solid });
+        }
+        Ok(TiledTileset {
+            first_gid,
+            name: string(tileset, "name"),
+            image: tileset.get("image").and_then(Value::as_str).map(|image| vfs::resolve(path, image)),
+            tile_width: uint(tileset, "tilewidth")?,
+            tile_height: uint(tileset, "tileheight")?,
+            columns: uint(tileset, "columns")?,
+            margin: uint(tileset, "margin").unwrap_or(0),
+            spacing: uint(tileset, "spacing").unwrap_or(0),
+            tile_count: uint(tileset, "tilecount").ok(),
+            tiles
+        })
+    }
+
+    fn parse_layers(layers: &Value, parent_offset: (f32, f32), parent_visible: bool, out: &mut Vec<TiledLayer>) -> anyhow::Result<()>{
+        for layer in layers.as_array().into_iter().flatten(){
+            let name = string(layer, "name");
+            let visible = parent_visible && layer.get("visible").and_then(Value::as_bool).unwrap_or(true);
+            let offset = (parent_offset.0 + float_or(layer, "offsetx", 0.0), parent_offset.1 + float_or(layer, "offsety", 0.0));
+            match layer.get("type").and_then(Value::as_str){
+                Some("tilelayer") => {
+                    let data = field(layer, "data").with_context(|| format!("layer '{}'", name))?;
+                    let data = match data{
+                        Value::String(text) => decode_tile_data(text, &string(layer, "compression"))?,
+                        Value::Array(gids) => gids.iter().map(|gid| gid.as_u64().unwrap_or(0) as u32).collect(),
+                        _ => bail!("layer '{}' has invalid data", name)
+                    };
+                    out.push(TiledLayer::Tiles { name, visible, offset, data });
+                },
+                Some("objectgroup") => {
+                    let objects = field(layer, "objects")?.as_array().into_iter().flatten().map(parse_object).collect::<anyhow::Result<_>>()?;
+                    out.push(TiledLayer::Objects { name, visible, offset, objects });
+                },
+                Some("group") => parse_layers(field(layer, "layers")?, offset, visible, out)?,
+                // Image layers.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn replace_tile_data(text: &str, layers: &HashMap<usize, Vec<u32>>) -> anyhow::Result<String>{
+        let map = scan(text)?;
+        let mut replacements = Vec::new();
+        if let Some(children) = map.member("layers"){
+            find_tile_data(text, children, &mut 0, layers, &mut replacements);
+        }
+
+        let mut text = text.to_string();
+        // From the end, so the earlier ranges stay valid.
+        replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        for (range, value) in replacements{
+            text.replace_range(range, &value);
+        }
+        Ok(text)
+    }
+
+    /// The text ranges to replace in the layers in `layers`, walked like `parse_layers`:
+    /// the `data` array, and `encoding` and `compression` set back to plain csv.
+    fn find_tile_data(text: &str, parent: &Span, tile_layer: &mut usize, layers: &HashMap<usize, Vec<u32>>, out: &mut Vec<(Range<usize>, String)>){
+        for layer in &parent.items{
+            match layer.member("type").and_then(|kind| serde_json::from_str::<String>(&text[kind.range.clone()]).ok()).as_deref(){
+                Some("tilelayer") => {
+                    if let Some(gids) = layers.get(tile_layer)
+                        && let Some(data) = layer.member("data"){
+                        let gids: Vec<String> = gids.iter().map(u32::to_string).collect();
+                        out.push((data.range.clone(), format!("[{}]", gids.join(", "))));
+                        if let Some(encoding) = layer.member("encoding"){
+                            out.push((encoding.range.clone(), "\"csv\"".to_string()));
+                        }
+                        if let Some(compression) = layer.member("compression"){
+                            out.push((compression.range.clone(), "\"\"".to_string()));
+                        }
+                    }
+                    *tile_layer += 1;
+                },
+                Some("group") => {
+                    if let Some(children) = layer.member("layers"){
+                        find_tile_data(text, children, tile_layer, layers, out);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// Where a value is in the text, with the members of an object or the items of an array.
+    #[derive(Default)]
+    struct Span{
+        range: Range<usize>,
+        members: Vec<(String, Span)>,
+        items: Vec<Span>
+    }
+
+    impl Span{
+        fn member(&self, name: &str) -> Option<&Span>{
+            self.members.iter().find(|(key, _)| key == name).map(|(_, value)| value)
+        }
+    }
+
+    /// The spans of a JSON document, which must be valid.
+    fn scan(text: &str) -> anyhow::Result<Span>{
+        serde_json::from_str::<Value>(text)?;
+        let mut position = 0;
+        scan_value(text, &mut position)
+    }
+
+    fn scan_value(text: &str, position: &mut usize) -> anyhow::Result<Span>{
+        let bytes = text.as_bytes();
+        skip_whitespace(bytes, position);
+        let start = *position;
+        let mut span = Span::default();
+        match bytes.get(start){
+            Some(b'{') | Some(b'[') => {
+                let object = bytes[start] == b'{';
+                let end = if object { b'}' } else { b']' };
+                *position += 1;
+                loop{
+                    skip_whitespace(bytes, position);
+                    match bytes.get(*position){
+                        Some(&byte) if byte == end => break,
+                        Some(b',') => *position += 1,
+                        None => bail!("unexpected end of JSON"),
+                        _ if object => {
+                            let key = scan_value(text, position)?;
+                            let key = serde_json::from_str::<String>(&text[key.range])?;
+                            skip_whitespace(bytes, position);
+                            *position += 1;
+                            span.members.push((key, scan_value(text, position)?));
+                        },
+                        _ => span.items.push(scan_value(text, position)?)
+                    }
+                }
+                *position += 1;
+            },
+            Some(b'"') => {
+                *position += 1;
+                while let Some(&byte) = bytes.get(*position) && byte != b'"'{
+                    *position += if byte == b'\\' { 2 } else { 1 };
+                }
+                *position += 1;
+            },
+            Some(_) => {
+                while bytes.get(*position).is_some_and(|byte| !b",]} \t\r\n".contains(byte)){
+                    *position += 1;
+                }
+            },
+            None => bail!("unexpected end of JSON")
+        }
+        span.range = start..*position;
+        Ok(span)
+    }
+
+    fn skip_whitespace(bytes: &[u8], position: &mut usize){
+        while bytes.get(*position).is_some_and(u8::is_ascii_whitespace){
+            *position += 1;
+        }
+    }
+
+    fn parse_object(object: &Value) -> anyhow::Result<TiledObject>{
+        let points = object.get("polygon").or_else(|| object.get("polyline")).and_then(Value::as_array);
+        let shape = if let Some(points) = points{
+            TiledShape::Polygon(points.iter().map(|point| (float_or(point, "x", 0.0), float_or(point, "y", 0.0))).collect())
+        } else if object.get("ellipse").and_then(Value::as_bool).unwrap_or(false){
+            TiledShape::Ellipse
+        } else if object.get("point").and_then(Value::as_bool).unwrap_or(false){
+            TiledShape::Point
+        } else{
+            TiledShape::Rectangle
+        };
+        let class = match string(object, "class"){
+            class if class.is_empty() => string(object, "type"),
+            class => class
+        };
+        Ok(TiledObject {
+            id: uint(object, "id")?,
+            name: string(object, "name"),
+            class,
+            x: float_or(object, "x", 0.0),
+            y: float_or(object, "y", 0.0),
+            width: float_or(object, "width", 0.0),
+            height: float_or(object, "height", 0.0),
+            rotation: float_or(object, "rotation", 0.0),
+            visible: object.get("visible").and_then(Value::as_bool).unwrap_or(true),
+            gid: object.get("gid").and_then(Value::as_u64).map(|gid| gid as u32),
+            shape,
+            properties: properties(object)
+        })
+    }
+}
+
+mod xml{
+    use roxmltree::Node;
+
+    use super::*;
+
+    fn uint(node: Node, name: &str) -> anyhow::Result<u32>{
+        let value = node.attribute(name).ok_or_else(|| anyhow!("<{}> is missing '{}'", node.tag_name().name(), name))?;
+        value.parse().with_context(|| format!("'{}' is not a whole number", name))
+    }
+
+    fn float_or(node: Node, name: &str, default: f32) -> f32{
+        node.attribute(name).and_then(|value| value.parse().ok()).unwrap_or(default)
+    }
+
+    fn string(node: Node, name: &str) -> String{
+        node.attribute(name).unwrap_or_default().to_string()
+    }
+
+    fn children<'a, 'input>(node: Node<'a, 'input>, tag: &'static str) -> impl Iterator<Item = Node<'a, 'input>>{
+        node.children().filter(move |child| child.has_tag_name(tag))
+    }
+
+    fn child<'a, 'input>(node: Node<'a, 'input>, tag: &'static str) -> Option<Node<'a, 'input>>{
+        children(node, tag).next()
+    }
+
+    fn properties(node: Node) -> HashMap<String, String>{
+        let Some(properties) = child(node, "properties") else{
+            return HashMap::new();
+        };
+        children(properties, "property").filter_map(|property|{
+            let value = property.attribute("value").or_else(|| property.text()).unwrap_or_default();
+            Some((property.attribute("name")?.to_string(), value.to_string()))
+        }).collect()
+    }
+
+    pub(super) fn parse_map(path: &str, text: &str) -> anyhow::Result<TiledMap>{
+        let document = roxmltree::Document::parse(text)?;
+        let map = document.root_element();
+        if !map.has_tag_name("map"){
+            bail!("the root element is <{}>, not <map>", map.tag_name().name());
+        }
+        check_map(&string(map, "orientation"), map.attribute("infinite") == Some("1"))?;
+        let mut dependencies = Vec::new();
+        let mut tilesets = Vec::new();
+        for tileset in children(map, "tileset"){
+            let first_gid = uint(tileset, "firstgid")?;
+            tilesets.push(match tileset.attribute("source"){
+                Some(source) => load_tileset(path, source, first_gid, &mut dependencies)?,
+                None => parse_tileset(path, tileset, first_gid)?
+            });
+        }
+        let width = uint(map, "width")?;
+        let mut layers = Vec::new();
+        parse_layers(map, (0.0, 0.0), true, &mut layers)?;
+        Ok(TiledMap {
+            width,
+            height: uint(map, "height")?,
+            tile_width: uint(map, "tilewidth")?.max(1),
+            tile_height: uint(map, "tileheight")?.max(1),
+            tilesets,
+            layers,
+            dependencies
+        })
+    }
+
+    pub(super) fn parse_tileset(path: &str, tileset: Node, first_gid: u32) -> anyhow::Result<TiledTileset>{
+        let mut tiles = HashMap::new();
+        for tile in children(tileset, "tile"){
+            let image = child(tile, "image").and_then(|image| image.attribute("source")).map(|image| vfs::resolve(path, image));
+            let solid = child(tile, "objectgroup").is_some_and(|group| children(group, "object").next().is_some());
+            tiles.insert(uint(tile, "id")?, TiledTile { image, solid });
+        }
+        Ok(TiledTileset {
+            first_gid,
+            name: string(tileset, "name"),
+            image: child(tileset, "image").and_then(|image| image.attribute("source")).map(|image| vfs::resolve(path, image)),
+            tile_width: uint(tileset, "tilewidth")?,
+            tile_height: uint(tileset, "tileheight")?,
+            columns: uint(tileset, "columns")?,
+            margin: uint(tileset, "margin").unwrap_or(0),
+            spacing: uint(tileset, "spacing").unwrap_or(0),
+            tile_count: uint(tileset, "tilecount").ok(),
+            tiles
+        })
+    }
+
+    fn parse_layers(parent: Node, parent_offset: (f32, f32), parent_visible: bool, out: &mut Vec<TiledLayer>) -> anyhow::Result<()>{
+        for layer in parent.children().filter(Node::is_element){
+            let name = string(layer, "name");
+            let visible = parent_visible && layer.attribute("visible") != Some("0");
+            let offset = (parent_offset.0 + float_or(layer, "offsetx", 0.0), parent_offset.1 + float_or(layer, "offsety", 0.0));
+            match layer.tag_name().name(){
+                "layer" => {
+                    let data = child(layer, "data").ok_or_else(|| anyhow!("layer '{}' has no <data>", name))?;
+                    let text = data.text().unwrap_or_default();
+                    let data = match data.attribute("encoding"){
+                        Some("csv") => text.split(',').map(|gid| gid.trim().parse::<u32>()).collect::<Result<_, _>>()
+                            .with_context(|| format!("layer '{}' has invalid CSV data", name))?,
+                        Some("base64") => decode_tile_data(text, data.attribute("compression").unwrap_or_default())?,
+                        Some(other) => bail!("layer '{}' uses the unknown encoding {}", name, other),
+                        None => children(data, "tile").map(|tile| float_or(tile, "gid", 0.0) as u32).collect()
+                    };
+                    out.push(TiledLayer::Tiles { name, visible, offset, data });
+                },
+                "objectgroup" => {
+                    let objects = children(layer, "object").map(parse_object).collect::<anyhow::Result<_>>()?;
+                    out.push(TiledLayer::Objects { name, visible, offset, objects });
+                },
+                "group" => parse_layers(layer, offset, visible, out)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn replace_tile_data(text: &str, layers: &HashMap<usize, Vec<u32>>) -> anyhow::Result<String>{
+        let document = roxmltree::Document::parse(text)?;
+        let map = document.root_element();
+        let width = uint(map, "width")?.max(1) as usize;
+        let mut replacements = Vec::new();
+        find_tile_data(map, &mut 0, layers, &mut replacements);
+
+        let mut text = text.to_string();
+        // From the end, so the earlier ranges stay valid.
+        replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        for (range, gids) in replacements{
+            let rows: Vec<String> = gids.chunks(width).map(|row| row.iter().map(u32::to_string).collect::<Vec<_>>().join(",")).collect();
+            text.replace_range(range, &format!("<data encoding=\"csv\">\n{}\n</data>", rows.join(",\n")));
+        }
+        Ok(text)
+    }
+
+    /// The text ranges of the `<data>` elements of the layers in `layers`, walked like `parse_layers`.
+    fn find_tile_data<'a>(parent: Node, tile_layer: &mut usize, layers: &'a HashMap<usize, Vec<u32>>, out: &mut Vec<(std::ops::Range<usize>, &'a [u32])>){
+        for layer in parent.children().filter(Node::is_element){
+            match layer.tag_name().name(){
+                "layer" => {
+                    if let Some(gids) = layers.get(tile_layer)
+                        && let Some(data) = child(layer, "data"){
+                        out.push((data.range(), gids));
+                    }
+                    *tile_layer += 1;
+                },
+                "group" => find_tile_data(layer, tile_layer, layers, out),
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_object(object: Node) -> anyhow::Result<TiledObject>{
+        let points = child(object, "polygon").or_else(|| child(object, "polyline")).and_then(|points| points.attribute("points"));
+        let shape = if let Some(points) = points{
+            TiledShape::Polygon(points.split_whitespace().filter_map(|point|{
+                let (x, y) = point.split_once(',')?;
+                Some((x.parse().ok()?, y.parse().ok()?))
+            }).collect())
+        } else if child(object, "ellipse").is_some(){
+            TiledShape::Ellipse
+        } else if child(object, "point").is_some(){
+            TiledShape::Point
+        } else{
+            TiledShape::Rectangle
+        };
+        Ok(TiledObject {
+            id: uint(object, "id")?,
+            name: string(object, "name"),
+            class: object.attribute("class").or_else(|| object.attribute("type")).unwrap_or_default().to_string(),
+            x: float_or(object, "x", 0.0),
+            y: float_or(object, "y", 0.0),
+            width: float_or(object, "width", 0.0),
+            height: float_or(object, "height", 0.0),
+            rotation: float_or(object, "rotation", 0.0),
+            visible: object.attribute("visible") != Some("0"),
+            gid: object.attribute("gid").and_then(|gid| gid.parse().ok()),
+            shape,
+            properties: properties(object)
+        })
+    }
+}
+
+fn check_map(orientation: &str, infinite: bool) -> anyhow::Result<()>{
+    if orientation != "orthogonal"{
+        bail!("{} maps are not supported, only orthogonal ones", orientation);
+    }
+    if infinite{
+        bail!("infinite maps are not supported");
+    }
+    Ok(())
+}
+
+/// The entities spawned from a map, respawned when the map or one of its tilesets changes.
+pub struct ImportedMap{
+    pub path: String,
+    pub dependencies: Vec<String>,
+    pub entities: Vec<Entity>,
+    /// Where the tilemaps among `entities` came from, for saving them back.
+    pub tile_layers: Vec<TileLayerEntity>
+}
+
+pub struct TileLayerEntity{
+    pub entity: Entity,
+    /// Position among the map's tile layers, with groups flattened.
+    pub layer: usize,
+    /// First global id of the tileset the tilemap draws.
+    pub first_gid: u32
+}
+
+impl ImportedMap{
+    /// Files whose changes reimport the map.
+    pub fn files(&self) -> impl Iterator<Item = &String>{
+        std::iter::once(&self.path).chain(&self.dependencies)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const TMJ: &str = r#"{
+        "orientation": "orthogonal", "width": 3, "height": 2, "tilewidth": 16, "tileheight": 16, "infinite": false,
+        "tilesets": [{
+            "firstgid": 1, "name": "terrain", "image": "../images/terrain.png", "tilewidth": 16, "tileheight": 16,
+            "columns": 4, "margin": 1, "spacing": 2,
+            "tiles": [{ "id": 2, "objectgroup": { "objects": [{ "id": 1, "x": 0, "y": 0, "width": 16, "height": 16 }] } }]
+        }],
+        "layers": [
+            { "type": "tilelayer", "name": "ground", "visible": true, "data": [1, 0, 3, 2147483650, 0, 0] },
+            { "type": "group", "name": "things", "offsetx": 8, "layers": [
+                { "type": "objectgroup", "name": "actors", "objects": [
+                    { "id": 7, "name": "door", "type": "", "x": 32, "y": 16, "width": 16, "height": 32, "rotation": 0,
+                      "properties": [{ "name": "script", "type": "file", "value": "scripts/door.lua" }, { "name": "locked", "type": "bool", "value": true }] },
+                    { "id": 8, "name": "", "class": "spawn", "x": 4, "y": 4, "point": true }
+                ]}
+            ]}
+        ]
+    }"#;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <map version="1.10" orientation="orthogonal" width="2" height="2" tilewidth="8" tileheight="8" infinite="0">
+            <tileset firstgid="1" name="tiles" tilewidth="8" tileheight="8" tilecount="4" columns="2">
+                <image source="tiles.png" width="16" height="16"/>
+            </tileset>
+            <layer id="1" name="walls" width="2" height="2">
+                <data encoding="base64">AQAAAAAAAAAAAABABAAAAA==</data>
+            </layer>
+            <objectgroup id="2" name="zones" visible="0">
+                <object id="3" name="pit" x="0" y="8">
+                    <polygon points="0,0 8,0 8,4 0,4"/>
+                    <properties><property name="layer" type="int" value="2"/></properties>
+                </object>
+            </objectgroup>
+        </map>"#;
+
+    #[test]
+    fn parses_json_maps(){
+        let map = TiledMap::parse("maps/level.tmj", TMJ).unwrap();
+        assert_eq!((map.width, map.height, map.tile_width), (3, 2, 16));
+        let tileset = &map.tilesets[0];
+        assert_eq!(tileset.image.as_deref(), Some("images/terrain.png"));
+        assert_eq!((tileset.margin, tileset.spacing), (1, 2));
+        assert_eq!(tileset.tile(3), Tile { index: 2, flags: TileFlags::SOLID });
+        assert_eq!(tileset.tile(2147483650), Tile { index: 1, flags: TileFlags::FLIP_X });
+
+        let TiledLayer::Objects { name, offset, objects, .. } = &map.layers[1] else{
+            panic!("the group should be flattened into its object layer");
+        };
+        assert_eq!((name.as_str(), *offset), ("actors", (8.0, 0.0)));
+        assert_eq!(objects[0].properties["script"], "scripts/door.lua");
+        assert_eq!(objects[0].properties["locked"], "true");
+        assert_eq!((objects[1].class.as_str(), &objects[1].shape), ("spawn", &TiledShape::Point));
+    }
+
+    #[test]
+    fn parses_xml_maps_with_base64_data(){
+        let map = TiledMap::parse("level.tmx", TMX).unwrap();
+        let TiledLayer::Tiles { data, .. } = &map.layers[0] else{
+            panic!("expected a tile layer");
+        };
+        assert_eq!(data, &[1, 0, FLIPPED_VERTICALLY, 4]);
+        let TiledLayer::Objects { visible, objects, .. } = &map.layers[1] else{
+            panic!("expected an object layer");
+        };
+        assert!(!visible);
+        assert_eq!(objects[0].bounds(), ((0.0, 0.0), (8.0, 4.0)));
+        assert_eq!(objects[0].properties["layer"], "2");
+    }
+
+    #[test]
+    fn writes_edited_layers_back(){
+        let map = TiledMap::parse("level.tmx", TMX).unwrap();
+        let mut tilemap = Tilemap::new(Tileset::new(Handle::pending("tiles.png"), 8, 8, 2), 2, 2, 1.0);
+        tilemap.set(0, 1, 0, Some(Tile { index: 2, flags: TileFlags::FLIP_X }));
+        let data = map.edited_tile_layer(0, &[(1, &tilemap)]).unwrap();
+        // The flipped empty cell belongs to no tileset, so it's kept as it was.
+        assert_eq!(data, vec![0, 3 | FLIPPED_HORIZONTALLY, FLIPPED_VERTICALLY, 0]);
+
+        let text = replace_tile_data("level.tmx", TMX, &HashMap::from([(0, data.clone())])).unwrap();
+        let saved = TiledMap::parse("level.tmx", &text).unwrap();
+        assert!(matches!(&saved.layers[0], TiledLayer::Tiles { data: saved, .. } if *saved == data));
+        assert!(matches!(&saved.layers[1], TiledLayer::Objects { objects, .. } if objects.len() == 1));
+    }
+
+    #[test]
+    fn saves_json_tile_data_in_place(){
+        let text = replace_tile_data("level.tmj", TMJ, &HashMap::from([(0, vec![5; 6])])).unwrap();
+        assert_eq!(text, TMJ.replace("[1, 0, 3, 2147483650, 0, 0]", "[5, 5, 5, 5, 5, 5]"));
+        let saved = TiledMap::parse("maps/level.tmj", &text).unwrap();
+        assert_eq!(saved.tile_layer(0), Some(&[5; 6][..]));
+
+        let encoded = r#"{ "orientation": "orthogonal", "width": 1, "height": 1, "tilewidth": 8, "tileheight": 8, "tilesets": [],
+            "layers": [{ "type": "group", "name": "\"quoted\" {group}", "layers": [
+                { "type": "tilelayer", "name": "a", "encoding": "base64", "compression": "", "data": "AQAAAA==" }
+            ]}]
+        }"#;
+        let text = replace_tile_data("level.tmj", encoded, &HashMap::from([(0, vec![2])])).unwrap();
+        assert!(text.contains(r#""encoding": "csv", "compression": "", "data": [2] }"#));
+        assert_eq!(TiledMap::parse("level.tmj", &text).unwrap().tile_layer(0), Some(&[2][..]));
+    }
+
+    #[test]
+    fn rejects_unsupported_maps(){
+        let isometric = TMX.replace("orthogonal", "isometric");
+        assert!(TiledMap::parse("level.tmx", &isometric).is_err());
+        let rotated = TMJ.replace("2147483650", &(FLIPPED_DIAGONALLY | 1).to_string());
+        let error = TiledMap::parse("level.tmj", &rotated).err().unwrap();
+        assert!(format!("{:#}", error).contains("rotates the tile at 0, 1"));
+        assert_eq!(vfs::resolve("maps/a/level.tmx", "../../tiles.png"), "tiles.png");
+    }
+}
@@ -0,0 +1,177 @@
+/// Gameplay systems that can be paused on their own, see `Time::set_system_paused`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum System{
+    /// Fixed updates of the game and of scripts.
+    Simulation,
+    /// Script `update`, timers and coroutines.
+    Scripts,
+    Particles,
+    /// Voices of `AudioSource`s.
+    Audio
+}
+
+pub const SYSTEMS: [System; 4] = [System::Simulation, System::Scripts, System::Particles, System::Audio];
+
+impl System{
+    pub fn name(self) -> &'static str{
+        match self{
+            System::Simulation => "simulation",
+            System::Scripts => "scripts",
+            System::Particles => "particles",
+            System::Audio => "audio"
+        }
+    }
+}
+
+/// Frame timing shared by the engine and game code.
+///
+/// Gameplay systems (scripts, fixed updates, timers) read the scaled `delta`,
+/// which is zero while paused, and skip their work while `is_running` is false.
+/// Rendering, the camera controller and the editor read `unscaled_delta`
+/// so they keep running in pause and slow-motion.
+pub struct Time{
+    delta: f32,
+    unscaled_delta: f32,
+    fixed_delta: f32,
+    elapsed: f64,
+    unscaled_elapsed: f64,
+    frame_count: u64,
+    time_scale: f32,
+    paused: bool,
+    paused_systems: Vec<System>
+}
+
+impl Time{
+    pub fn new() -> Self{
+        Self {
+            delta: 0.0,
+            unscaled_delta: 0.0,
+            fixed_delta: 0.0,
+            elapsed: 0.0,
+            unscaled_elapsed: 0.0,
+            frame_count: 0,
+            time_scale: 1.0,
+            paused: false,
+            paused_systems: Vec::new()
+        }
+    }
+
+    pub(crate) fn advance(&mut self, unscaled_dt: f32){
+        self.unscaled_delta = unscaled_dt;
+        self.delta = if self.paused { 0.0 } else { unscaled_dt * self.time_scale };
+        self.unscaled_elapsed += unscaled_dt as f64;
+        self.elapsed += self.delta as f64;
+        self.frame_count += 1;
+    }
+
+    pub(crate) fn set_fixed_delta(&mut self, fixed_dt: f32){
+        self.fixed_delta = fixed_dt;
+    }
+
+    /// Scaled frame time in seconds, zero while paused.
+    pub fn delta(&self) -> f32{
+        self.delta
+    }
+
+    /// Real frame time in seconds, unaffected by pause and time scale.
+    pub fn unscaled_delta(&self) -> f32{
+        self.unscaled_delta
+    }
+
+    /// Length of one fixed simulation step in seconds.
+    pub fn fixed_delta(&self) -> f32{
+        self.fixed_delta
+    }
+
+    /// Scaled gameplay time since start in seconds.
+    pub fn elapsed(&self) -> f64{
+        self.elapsed
+    }
+
+    /// Real time since start in seconds.
+    pub fn unscaled_elapsed(&self) -> f64{
+        self.unscaled_elapsed
+    }
+
+    pub fn frame_count(&self) -> u64{
+        self.frame_count
+    }
+
+    pub fn time_scale(&self) -> f32{
+        self.time_scale
+    }
+
+    /// `1.0` is normal speed, values below one give slow-motion.
+    pub fn set_time_scale(&mut self, scale: f32){
+        self.time_scale = scale.max(0.0);
+    }
+
+    /// Whether all of gameplay is paused.
+    pub fn is_paused(&self) -> bool{
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool){
+        self.paused = paused;
+    }
+
+    /// Whether `system` was paused on its own, regardless of `is_paused`.
+    pub fn is_system_paused(&self, system: System) -> bool{
+        self.paused_systems.contains(&system)
+    }
+
+    pub fn set_system_paused(&mut self, system: System, paused: bool){
+        self.paused_systems.retain(|other| *other != system);
+        if paused{
+            self.paused_systems.push(system);
+        }
+    }
+
+    /// Whether `system` should do its work this frame: neither gameplay nor the system is paused.
+    pub fn is_running(&self, system: System) -> bool{
+        !self.paused && !self.is_system_paused(system)
+    }
+}
+
+impl Default for Time{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn pauses_systems_on_their_own(){
+        let mut time = Time::new();
+        time.set_system_paused(System::Particles, true);
+        assert!(!time.is_running(System::Particles));
+        assert!(time.is_running(System::Scripts));
+
+        time.advance(0.5);
+        assert_eq!(time.delta(), 0.5);
+
+        time.set_paused(true);
+        assert!(!time.is_running(System::Scripts));
+        time.set_paused(false);
+        time.set_system_paused(System::Particles, false);
+        assert!(SYSTEMS.iter().all(|system| time.is_running(*system)));
+    }
+
+    #[test]
+    fn pause_and_scale_only_affect_the_scaled_clock(){
+        let mut time = Time::new();
+        time.set_time_scale(0.5);
+        time.advance(0.2);
+        assert_eq!(time.delta(), 0.1);
+        time.set_paused(true);
+        time.advance(0.2);
+        assert_eq!(time.delta(), 0.0);
+        assert_eq!(time.unscaled_delta(), 0.2);
+        assert!((time.elapsed() - 0.1).abs() < 1e-6);
+        assert!((time.unscaled_elapsed() - 0.4).abs() < 1e-6);
+        assert_eq!(time.frame_count(), 2);
+    }
+}
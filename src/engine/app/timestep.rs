@@ -0,0 +1,87 @@
+/// Accumulator for running the simulation at a fixed rate independent of the frame rate.
+pub struct FixedTimestep{
+    step: f32,
+    accumulator: f32,
+    max_steps: u32
+}
+
+impl FixedTimestep{
+    pub fn new(rate: f32, max_steps: u32) -> Self{
+        Self { step: 1.0 / rate, accumulator: 0.0, max_steps }
+    }
+
+    pub fn step(&self) -> f32{
+        self.step
+    }
+
+    pub fn set_rate(&mut self, rate: f32){
+        self.step = 1.0 / rate;
+        self.accumulator = 0.0;
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: u32){
+        self.max_steps = max_steps;
+    }
+
+    /// Adds frame time and returns how many fixed steps should run this frame.
+    /// Time that does not fit into `max_steps` is dropped so a slow frame
+    /// can't snowball into an ever growing backlog.
+    pub fn advance(&mut self, dt: f32) -> u32{
+        self.accumulator += dt;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps{
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps == self.max_steps{
+            self.accumulator = self.accumulator.min(self.step);
+        }
+        steps
+    }
+
+    /// How far the current frame is between the last and the next fixed step.
+    pub fn alpha(&self) -> f32{
+        (self.accumulator / self.step).clamp(0.0, 1.0)
+    }
+}
+
+impl Default for FixedTimestep{
+    fn default() -> Self{
+        Self::new(60.0, 5)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn runs_whole_steps_and_keeps_the_rest(){
+        let mut timestep = FixedTimestep::new(4.0, 5);
+        assert_eq!(timestep.advance(0.1), 0);
+        assert_eq!(timestep.advance(0.2), 1);
+        assert!((timestep.alpha() - 0.2).abs() < 1e-5);
+        assert_eq!(timestep.advance(0.5), 2);
+        assert!((timestep.alpha() - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn caps_steps_and_drops_the_backlog(){
+        let mut timestep = FixedTimestep::new(10.0, 3);
+        assert_eq!(timestep.advance(2.0), 3);
+        assert_eq!(timestep.alpha(), 1.0);
+        // At most one step is carried over to the next frame.
+        assert_eq!(timestep.advance(0.0), 1);
+        assert_eq!(timestep.advance(0.0), 0);
+    }
+
+    #[test]
+    fn set_rate_resets_the_accumulator(){
+        let mut timestep = FixedTimestep::new(10.0, 5);
+        timestep.advance(0.05);
+        timestep.set_rate(20.0);
+        assert_eq!(timestep.step(), 0.05);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(0.05), 1);
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    collections::HashMap, env, fs::{self, File}, io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf}, sync::{Mutex, OnceLock, RwLock}
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+/// A source of files inside the virtual filesystem.
+/// Paths are relative and use `/`, e.g. `resources/script.lua`.
+pub trait Mount: Send + Sync{
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+    fn exists(&self, path: &str) -> bool;
+    /// The file on disk backing `path`, if there is one; used for watching and saving.
+    fn real_path(&self, _path: &str) -> Option<PathBuf>{
+        None
+    }
+    /// Every file in the mount.
+    fn files(&self) -> Vec<String>;
+    /// The directory on disk, for directory mounts.
+    fn dir(&self) -> Option<&Path>{
+        None
+    }
+}
+
+/// A directory on disk.
+pub struct DirMount{
+    root: PathBuf
+}
+
+impl DirMount{
+    pub fn new(root: impl Into<PathBuf>) -> Self{
+        Self { root: root.into() }
+    }
+}
+
+impl Mount for DirMount{
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>{
+        fs::read(self.root.join(path))
+    }
+
+    fn exists(&self, path: &str) -> bool{
+        self.root.join(path).is_file()
+    }
+
+    fn real_path(&self, path: &str) -> Option<PathBuf>{
+        Some(self.root.join(path))
+    }
+
+    fn files(&self) -> Vec<String>{
+        let mut files = Vec::new();
+        collect_files(&self.root, &self.root, &mut files);
+        files.sort();
+        files
+    }
+
+    fn dir(&self) -> Option<&Path>{
+        Some(&self.root)
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>){
+    let Ok(entries) = fs::read_dir(dir) else{
+        return;
+    };
+    for entry in entries.flatten(){
+        let path = entry.path();
+        if path.is_dir(){
+            collect_files(root, &path, files);
+        } else if let Ok(relative) = path.strip_prefix(root){
+            files.push(normalize(&relative.to_string_lossy()));
+        }
+    }
+}
+
+/// Magic bytes at the start of an archive.
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"EPAK";
+pub const ARCHIVE_VERSION: u32 = 1;
+/// Archive mounted below the asset root when it's found there or next to the executable.
+pub const DEFAULT_ARCHIVE: &str = "game.epak";
+
+pub const COMPRESSION_NONE: u8 = 0;
+pub const COMPRESSION_ZLIB: u8 = 1;
+
+/// An entry of an archive's index.
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry{
+    pub offset: u64,
+    /// Size of the file once read.
+    pub size: u64,
+    /// Size of the data in the archive.
+    pub stored_size: u64,
+    /// `COMPRESSION_NONE` or `COMPRESSION_ZLIB`.
+    pub compression: u8
+}
+
+/// A read-only archive: `EPAK`, version, entry count, then per entry the path
+/// (u16 length + UTF-8), offset, size, stored size and compression, then the data.
+/// Numbers are little endian, offsets count from the start of the file.
+pub struct ArchiveMount{
+    file: Mutex<File>,
+    entries: HashMap<String, ArchiveEntry>
+}
+
+impl ArchiveMount{
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self>{
+        let mut file = File::open(path)?;
+        let entries = read_index(&mut file)?;
+        Ok(Self { file: Mutex::new(file), entries })
+    }
+}
+
+impl Mount for ArchiveMount{
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>{
+        let entry = self.entries.get(path).ok_or_else(|| not_found(path))?;
+        let mut data = vec![0; entry.stored_size as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut data)?;
+        }
+        match entry.compression{
+            COMPRESSION_NONE => Ok(data),
+            COMPRESSION_ZLIB => {
+                let mut decompressed = Vec::with_capacity(entry.size as usize);
+                ZlibDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            },
+            compression => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{}: unknown compression {}", path, compression)))
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool{
+        self.entries.contains_key(path)
+    }
+
+    fn files(&self) -> Vec<String>{
+        let mut files: Vec<String> = self.entries.keys().cloned().collect();
+        files.sort();
+        files
+    }
+}
+
+fn read_index(reader: &mut impl Read) -> io::Result<HashMap<String, ArchiveEntry>>{
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != ARCHIVE_MAGIC{
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an archive"));
+    }
+    let version = read_u32(reader)?;
+    if version != ARCHIVE_VERSION{
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported archive version {}", version)));
+    }
+    let count = read_u32(reader)?;
+    let mut entries = HashMap::new();
+    for _ in 0..count{
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let mut path = vec![0; u16::from_le_bytes(length) as usize];
+        reader.read_exact(&mut path)?;
+        let path = String::from_utf8(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let offset = read_u64(reader)?;
+        let size = read_u64(reader)?;
+        let stored_size = read_u64(reader)?;
+        let mut compression = [0; 1];
+        reader.read_exact(&mut compression)?;
+        entries.insert(path, ArchiveEntry { offset, size, stored_size, compression: compression[0] });
+    }
+    Ok(entries)
+}
+
+/// Writes an archive holding `files` (path, contents). With `compress` every file is
+/// deflated, unless that doesn't make it smaller.
+pub fn write_archive(writer: &mut impl Write, files: &[(String, Vec<u8>)], compress: bool) -> io::Result<()>{
+    let mut stored = Vec::with_capacity(files.len());
+    for (path, data) in files{
+        let compressed = if compress{
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            Some(encoder.finish()?).filter(|compressed| compressed.len() < data.len())
+        } else{
+            None
+        };
+        match compressed{
+            Some(compressed) => stored.push((path, data.len(), compressed, COMPRESSION_ZLIB)),
+            None => stored.push((path, data.len(), data.clone(), COMPRESSION_NONE))
+        }
+    }
+
+    let index_size: usize = stored.iter().map(|(path, ..)| 2 + path.len() + 8 * 3 + 1).sum();
+    let mut offset = (ARCHIVE_MAGIC.len() + 4 + 4 + index_size) as u64;
+    writer.write_all(ARCHIVE_MAGIC)?;
+    writer.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+    writer.write_all(&(stored.len() as u32).to_le_bytes())?;
+    for (path, size, data, compression) in &stored{
+        let length = u16::try_from(path.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Path too long: {}", path)))?;
+        writer.write_all(&length.to_le_bytes())?;
+        writer.write_all(path.as_bytes())?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&(*size as u64).to_le_bytes())?;
+        writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        writer.write_all(&[*compression])?;
+        offset += data.len() as u64;
+    }
+    for (_, _, data, _) in &stored{
+        writer.write_all(data)?;
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32>{
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64>{
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn not_found(path: &str) -> io::Error{
+    io::Error::new(io::ErrorKind::NotFound, format!("{} not found in any mount", path))
+}
+
+/// Uses `/` and drops leading `./` so the same file always has the same key.
+pub fn normalize(path: &str) -> String{
+    let path = path.replace('\\', "/");
+    let mut parts = Vec::new();
+    for part in path.split('/'){
+        match part{
+            "" | "." => {},
+            // Clamped at the root, so no path leaves a directory mount.
+            ".." => { parts.pop(); },
+            part => parts.push(part)
+        }
+    }
+    parts.join("/")
+}
+
+/// `relative` as seen from the directory of `file`, with `..` resolved.
+pub fn resolve(file: &str, relative: &str) -> String{
+    let file = normalize(file);
+    let mut parts: Vec<&str> = file.split('/').collect();
+    parts.pop();
+    let relative = relative.replace('\\', "/");
+    for part in relative.split('/'){
+        match part{
+            "" | "." => {},
+            ".." if parts.last().is_some_and(|last| *last != "..") => { parts.pop(); },
+            part => parts.push(part)
+        }
+    }
+    parts.join("/")
+}
+
+struct Mounted{
+    mount: Box<dyn Mount>,
+    priority: i32,
+    name: String
+}
+
+/// Layers of mounts searched from the highest priority down, so overlay and
+/// mod directories can replace files of the base game.
+#[derive(Default)]
+pub struct Vfs{
+    mounts: Vec<Mounted>,
+    /// Name of the mount `set_root` added.
+    root: Option<String>
+}
+
+impl Vfs{
+    /// Mounts `ASSET_ROOT` if set, otherwise the working directory. Below it go
+    /// `game.epak` and then the executable's directory, for installed builds.
+    pub fn with_default_root() -> Self{
+        let mut vfs = Self::default();
+        let exe_dir = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf));
+        let root = env::var_os("ASSET_ROOT").map(PathBuf::from).or_else(|| env::current_dir().ok());
+        if let Some(exe_dir) = &exe_dir{
+            vfs.mount_dir(exe_dir, -2);
+        }
+        let archive = [&root, &exe_dir].into_iter().flatten().map(|dir| dir.join(DEFAULT_ARCHIVE)).find(|file| file.is_file());
+        if let Some(archive) = archive
+            && let Err(e) = vfs.mount_archive(&archive, -1){
+            log::error!("Could not mount {}: {}", archive.display(), e);
+        }
+        if let Some(root) = root
+            && Some(&root) != exe_dir.as_ref(){
+            vfs.set_root(root);
+        }
+        vfs
+    }
+
+    /// The directory asset paths are relative to.
+    pub fn root(&self) -> Option<PathBuf>{
+        let root = self.root.as_ref()?;
+        self.mounts.iter()
+            .find(|mounted| &mounted.name == root)
+            .and_then(|mounted| mounted.mount.dir().map(Path::to_path_buf))
+    }
+
+    /// Mounts `root` at priority 0 in place of the previous root. Other mounts stay.
+    pub fn set_root(&mut self, root: impl Into<PathBuf>){
+        if let Some(previous) = self.root.take(){
+            self.unmount(&previous);
+        }
+        let root = root.into();
+        self.root = Some(root.display().to_string());
+        self.mount_dir(root, 0);
+    }
+
+    pub fn mount(&mut self, name: impl Into<String>, mount: Box<dyn Mount>, priority: i32){
+        // Later mounts win over earlier ones with the same priority.
+        let index = self.mounts.iter().position(|mounted| mounted.priority <= priority).unwrap_or(self.mounts.len());
+        self.mounts.insert(index, Mounted { mount, priority, name: name.into() });
+    }
+
+    pub fn mount_dir(&mut self, dir: impl Into<PathBuf>, priority: i32){
+        let dir = dir.into();
+        self.mount(dir.display().to_string(), Box::new(DirMount::new(dir)), priority);
+    }
+
+    pub fn mount_archive(&mut self, file: impl AsRef<Path>, priority: i32) -> io::Result<()>{
+        let archive = ArchiveMount::open(&file)?;
+        self.mount(file.as_ref().display().to_string(), Box::new(archive), priority);
+        Ok(())
+    }
+
+    pub fn unmount(&mut self, name: &str){
+        self.mounts.retain(|mounted| mounted.name != name);
+    }
+
+    /// Names and priorities of the mounts, highest priority first.
+    pub fn mounts(&self) -> Vec<(String, i32)>{
+        self.mounts.iter().map(|mounted| (mounted.name.clone(), mounted.priority)).collect()
+    }
+
+    fn find(&self, path: &str) -> Option<&dyn Mount>{
+        self.mounts.iter().map(|mounted| mounted.mount.as_ref()).find(|mount| mount.exists(path))
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>>{
+        let path = normalize(path);
+        self.find(&path).ok_or_else(|| not_found(&path))?.read(&path)
+    }
+
+    pub fn exists(&self, path: &str) -> bool{
+        self.find(&normalize(path)).is_some()
+    }
+
+    /// The file on disk that `path` currently resolves to.
+    pub fn real_path(&self, path: &str) -> Option<PathBuf>{
+        let path = normalize(path);
+        self.find(&path)?.real_path(&path)
+    }
+
+    /// The file on disk `path` resolves to, or where `write` would create it in the root if it
+    /// isn't on disk yet.
+    pub fn writable_path(&self, path: &str) -> Option<PathBuf>{
+        let path = normalize(path);
+        self.real_path(&path).or_else(|| {
+            let root = self.root.as_ref()?;
+            self.mounts.iter().find(|mounted| &mounted.name == root)?.mount.real_path(&path)
+        })
+    }
+
+    /// Writes to the file `path` resolves to, or into the root if it's new.
+    pub fn write(&self, path: &str, data: &[u8]) -> io::Result<()>{
+        let file = self.writable_path(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, format!("No writable mount for {}", normalize(path))))?;
+        if let Some(parent) = file.parent(){
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, data)
+    }
+
+    /// Opens `path` for streaming: from disk if possible, otherwise from memory.
+    pub fn open(&self, path: &str) -> io::Result<VfsFile>{
+        match self.real_path(path){
+            Some(file) => Ok(VfsFile::Disk(File::open(file)?)),
+            None => Ok(VfsFile::Memory(Cursor::new(self.read(path)?)))
+        }
+    }
+}
+
+/// A readable, seekable file opened through the VFS.
+pub enum VfsFile{
+    Disk(File),
+    Memory(Cursor<Vec<u8>>)
+}
+
+impl VfsFile{
+    pub fn len(&self) -> Option<u64>{
+        match self{
+            VfsFile::Disk(file) => file.metadata().ok().map(|metadata| metadata.len()),
+            VfsFile::Memory(cursor) => Some(cursor.get_ref().len() as u64)
+        }
+    }
+}
+
+impl Read for VfsFile{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>{
+        match self{
+            VfsFile::Disk(file) => file.read(buf),
+            VfsFile::Memory(cursor) => cursor.read(buf)
+        }
+    }
+}
+
+impl Seek for VfsFile{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64>{
+        match self{
+            VfsFile::Disk(file) => file.seek(pos),
+            VfsFile::Memory(cursor) => cursor.seek(pos)
+        }
+    }
+}
+
+/// The filesystem every loader goes through.
+pub fn vfs() -> &'static RwLock<Vfs>{
+    static VFS: OnceLock<RwLock<Vfs>> = OnceLock::new();
+    VFS.get_or_init(|| RwLock::new(Vfs::with_default_root()))
+}
+
+pub fn read(path: &str) -> io::Result<Vec<u8>>{
+    vfs().read().unwrap().read(path)
+}
+
+pub fn read_to_string(path: &str) -> io::Result<String>{
+    String::from_utf8(read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write(path: &str, data: &[u8]) -> io::Result<()>{
+    vfs().read().unwrap().write(path, data)
+}
+
+pub fn real_path(path: &str) -> Option<PathBuf>{
+    vfs().read().unwrap().real_path(path)
+}
+
+pub fn writable_path(path: &str) -> Option<PathBuf>{
+    vfs().read().unwrap().writable_path(path)
+}
+
+pub fn open(path: &str) -> io::Result<VfsFile>{
+    vfs().read().unwrap().open(path)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// An empty directory under the system temp directory.
+    fn temp_dir(name: &str) -> PathBuf{
+        let dir = env::temp_dir().join(format!("eng-rs-vfs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn archive(dir: &Path, files: &[(&str, &str)], compress: bool) -> PathBuf{
+        let file = dir.join(DEFAULT_ARCHIVE);
+        let files: Vec<(String, Vec<u8>)> = files.iter().map(|(path, data)| (path.to_string(), data.as_bytes().to_vec())).collect();
+        write_archive(&mut File::create(&file).unwrap(), &files, compress).unwrap();
+        file
+    }
+
+    #[test]
+    fn normalizes_and_resolves_paths(){
+        assert_eq!(normalize("./resources\\maps/level.tmx"), "resources/maps/level.tmx");
+        assert_eq!(normalize("maps/../tiles/./ground.tsx"), "tiles/ground.tsx");
+        assert_eq!(normalize("../../secret.txt"), "secret.txt");
+        assert_eq!(resolve("maps/level.tmx", "../tiles/ground.tsx"), "tiles/ground.tsx");
+        assert_eq!(resolve("maps/level.tmx", "./props.png"), "maps/props.png");
+        assert_eq!(resolve("level.tmx", "../shared.png"), "../shared.png");
+    }
+
+    #[test]
+    fn higher_priorities_win(){
+        let (base, overlay) = (temp_dir("base"), temp_dir("overlay"));
+        fs::write(base.join("a.txt"), "base").unwrap();
+        fs::write(base.join("b.txt"), "base").unwrap();
+        fs::write(overlay.join("a.txt"), "overlay").unwrap();
+
+        let mut vfs = Vfs::default();
+        vfs.mount_dir(&overlay, 1);
+        vfs.set_root(&base);
+        assert_eq!(vfs.read("./a.txt").unwrap(), b"overlay");
+        assert_eq!(vfs.read("b.txt").unwrap(), b"base");
+        assert_eq!(vfs.real_path("a.txt"), Some(overlay.join("a.txt")));
+        assert_eq!(vfs.real_path("new.txt"), None);
+        assert_eq!(vfs.writable_path("a.txt"), Some(overlay.join("a.txt")));
+        assert_eq!(vfs.writable_path("b.txt"), Some(base.join("b.txt")));
+        assert_eq!(vfs.writable_path("new.txt"), Some(base.join("new.txt")));
+        assert!(vfs.read("c.txt").is_err());
+
+        vfs.unmount(&overlay.display().to_string());
+        assert_eq!(vfs.read("a.txt").unwrap(), b"base");
+        fs::write(overlay.join("new.txt"), "outside").unwrap();
+        assert!(!vfs.exists(&format!("../{}/new.txt", overlay.file_name().unwrap().to_str().unwrap())));
+    }
+
+    #[test]
+    fn reads_from_archives(){
+        let dir = temp_dir("archive");
+        for compress in [false, true]{
+            let text = "hello ".repeat(100);
+            let file = archive(&dir, &[("scripts/main.lua", text.as_str()), ("empty.txt", "")], compress);
+            let mut vfs = Vfs::default();
+            vfs.mount_archive(&file, 0).unwrap();
+            assert_eq!(vfs.read("scripts/main.lua").unwrap(), text.as_bytes());
+            assert_eq!(vfs.read("empty.txt").unwrap(), b"");
+            assert_eq!(vfs.real_path("scripts/main.lua"), None);
+
+            let mut streamed = String::new();
+            vfs.open("scripts/main.lua").unwrap().read_to_string(&mut streamed).unwrap();
+            assert_eq!(streamed, text);
+        }
+    }
+
+    #[test]
+    fn set_root_keeps_other_mounts(){
+        let (first, second, mods) = (temp_dir("first-root"), temp_dir("second-root"), temp_dir("mods"));
+        fs::write(mods.join("mod.txt"), "mod").unwrap();
+        let mut vfs = Vfs::default();
+        vfs.mount_archive(archive(&mods, &[("packed.txt", "packed")], false), 0).unwrap();
+        vfs.mount_dir(&mods, 1);
+        vfs.set_root(&first);
+        vfs.set_root(&second);
+
+        assert_eq!(vfs.root(), Some(second.clone()));
+        assert_eq!(vfs.mounts().len(), 3);
+        assert!(!vfs.mounts().iter().any(|(name, _)| *name == first.display().to_string()));
+        assert_eq!(vfs.read("packed.txt").unwrap(), b"packed");
+        assert_eq!(vfs.read("mod.txt").unwrap(), b"mod");
+    }
+}
@@ -1 +1,2 @@
-pub mod app;
\ No newline at end of file
+pub mod app;
+pub mod pack;
\ No newline at end of file
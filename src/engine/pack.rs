@@ -0,0 +1,194 @@
+use std::{collections::BTreeSet, fs, io::BufWriter, path::{Path, PathBuf}};
+
+use anyhow::{bail, Context};
+
+use crate::engine::app::vfs::{self, DirMount, Mount};
+
+/// Files that end up in the pack.
+const PACKED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "bmp", "tga", "gif",
+    "lua",
+    "scene", "json", "tmx", "tmj", "tsx", "tsj",
+    "wgsl", "material",
+    "wav", "ogg", "flac",
+    "ttf", "otf"
+];
+
+/// Files whose quoted asset paths must exist in the pack.
+const SCENE_EXTENSIONS: &[&str] = &["scene", "json", "tmx", "tmj", "tsx", "tsj"];
+
+/// Directories that never hold assets, besides hidden ones.
+const SKIPPED_DIRS: &[&str] = &["target"];
+
+pub struct PackOptions{
+    pub root: PathBuf,
+    pub output: PathBuf,
+    pub compress: bool
+}
+
+const USAGE: &str = "usage: pack [--root DIR] [--output FILE] [--compress]";
+
+/// Entry point of the `pack` subcommand.
+pub fn run(args: &[String]) -> anyhow::Result<()>{
+    let (mut root, mut output, mut compress) = (None, PathBuf::from(vfs::DEFAULT_ARCHIVE), false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next(){
+        match arg.as_str(){
+            "--root" => root = Some(args.next().context(USAGE)?.into()),
+            "--output" | "-o" => output = args.next().context(USAGE)?.into(),
+            "--compress" => compress = true,
+            _ => bail!("unknown argument {}\n{}", arg, USAGE)
+        }
+    }
+    // The asset root is only looked up when `--root` isn't given.
+    let root = match root{
+        Some(root) => root,
+        None => vfs::vfs().read().unwrap().root().context("No asset root")?
+    };
+    let options = PackOptions { root, output, compress };
+
+    let files = pack(&options)?;
+    println!("packed {} files from {} into {}", files, options.root.display(), options.output.display());
+    Ok(())
+}
+
+/// Writes every asset under `options.root` into one archive and returns the file count.
+/// Fails without writing if a scene references a file that isn't packed.
+pub fn pack(options: &PackOptions) -> anyhow::Result<usize>{
+    let files: Vec<String> = DirMount::new(&options.root).files().into_iter()
+        .filter(|file| !file.split('/').any(|part| part.starts_with('.') || SKIPPED_DIRS.contains(&part)))
+        .filter(|file| has_extension(file, PACKED_EXTENSIONS))
+        .collect();
+
+    let mut contents = Vec::with_capacity(files.len());
+    for file in &files{
+        let data = fs::read(options.root.join(file)).with_context(|| format!("Could not read {}", file))?;
+        contents.push((file.clone(), data));
+    }
+
+    let missing = missing_references(&contents);
+    if !missing.is_empty(){
+        let lines: Vec<String> = missing.iter().map(|(scene, path)| format!("  {} references {}", scene, path)).collect();
+        bail!("missing files referenced by scenes:\n{}", lines.join("\n"));
+    }
+
+    let output = fs::File::create(&options.output).with_context(|| format!("Could not create {}", options.output.display()))?;
+    vfs::write_archive(&mut BufWriter::new(output), &contents, options.compress)?;
+    Ok(contents.len())
+}
+
+/// `(scene, reference)` for every quoted asset path in a scene that isn't in `files`.
+/// References are looked up next to the scene first, then from the root.
+pub fn missing_references(files: &[(String, Vec<u8>)]) -> Vec<(String, String)>{
+    let packed: BTreeSet<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    let mut missing = Vec::new();
+    for (scene, data) in files.iter().filter(|(path, _)| has_extension(path, SCENE_EXTENSIONS)){
+        let text = String::from_utf8_lossy(data);
+        let dir = Path::new(scene).parent().unwrap_or(Path::new(""));
+        for reference in quoted_paths(&text){
+            let next_to_scene = vfs::normalize(&collapse(&dir.join(&reference)));
+            if !packed.contains(next_to_scene.as_str()) && !packed.contains(vfs::normalize(&reference).as_str()){
+                missing.push((scene.clone(), reference));
+            }
+        }
+    }
+    missing
+}
+
+/// Strings in double or single quotes that end with a packed extension.
+fn quoted_paths(text: &str) -> Vec<String>{
+    let mut paths = Vec::new();
+    for quote in ['"', '\'']{
+        let mut parts = text.split(quote);
+        parts.next();
+        while let Some(inside) = parts.next(){
+            if has_extension(inside, PACKED_EXTENSIONS) && !inside.contains(char::is_whitespace){
+                paths.push(inside.to_string());
+            }
+            parts.next();
+        }
+    }
+    paths
+}
+
+/// Resolves `..` so references like `../tiles.png` can be compared with packed paths.
+fn collapse(path: &Path) -> String{
+    let mut parts: Vec<String> = Vec::new();
+    for part in path.components(){
+        match part{
+            std::path::Component::ParentDir => {
+                parts.pop();
+            },
+            std::path::Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+fn has_extension(path: &str, extensions: &[&str]) -> bool{
+    path.rsplit_once('.').is_some_and(|(_, extension)| extensions.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::engine::app::vfs::ArchiveMount;
+
+    fn temp_dir(name: &str) -> PathBuf{
+        let dir = std::env::temp_dir().join(format!("eng-rs-pack-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file(path: &str, data: &str) -> (String, Vec<u8>){
+        (path.to_string(), data.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn packs_assets_into_a_mountable_archive(){
+        let root = temp_dir("root");
+        fs::create_dir_all(root.join("maps")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let level = r#"{"tileset": "../tiles.png", "script": "maps/level.lua"}"#;
+        fs::write(root.join("maps/level.json"), level).unwrap();
+        fs::write(root.join("maps/level.lua"), "print('hi')\n".repeat(50)).unwrap();
+        fs::write(root.join("tiles.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        fs::write(root.join("notes.md"), "not an asset").unwrap();
+        fs::write(root.join(".git/config.json"), "{}").unwrap();
+
+        for compress in [false, true]{
+            let output = root.with_extension(if compress { "zlib.epak" } else { "epak" });
+            let options = PackOptions { root: root.clone(), output: output.clone(), compress };
+            assert_eq!(pack(&options).unwrap(), 3);
+
+            let archive = ArchiveMount::open(&output).unwrap();
+            assert_eq!(archive.files(), ["maps/level.json", "maps/level.lua", "tiles.png"]);
+            assert_eq!(archive.read("maps/level.json").unwrap(), level.as_bytes());
+            assert_eq!(archive.read("maps/level.lua").unwrap(), "print('hi')\n".repeat(50).as_bytes());
+            assert_eq!(archive.read("tiles.png").unwrap(), [0x89, b'P', b'N', b'G']);
+        }
+    }
+
+    #[test]
+    fn reports_missing_references(){
+        let files = [
+            file("maps/level.tmx", r#"<image source="../tiles.png"/><property value='level.lua'/><image source="gone.png"/>"#),
+            file("maps/level.lua", ""),
+            file("tiles.png", ""),
+            file("scene.json", r#"{"sprite": "maps/level.lua", "music": "music/theme.ogg", "name": "a b.png"}"#)
+        ];
+        assert_eq!(missing_references(&files), [
+            ("maps/level.tmx".to_string(), "gone.png".to_string()),
+            ("scene.json".to_string(), "music/theme.ogg".to_string())
+        ]);
+
+        let root = temp_dir("missing");
+        fs::write(root.join("scene.json"), r#"{"sprite": "player.png"}"#).unwrap();
+        let output = root.join("out.epak");
+        let e = pack(&PackOptions { root, output: output.clone(), compress: false }).unwrap_err();
+        assert!(e.to_string().contains("scene.json references player.png"));
+        assert!(!output.exists());
+    }
+}
@@ -1,31 +1,52 @@
 mod engine;
-use egui::Ui;
 use engine::app::{App, GameManager};
 use engine::app::game::GameHandler;
 use engine::app::game::components;
-use engine::app::renderer::egui_tools::EguiRenderer;
-use egui_code_editor::{CodeEditor, ColorTheme, Syntax};
+use engine::app::audio::spatial::Attenuation;
+use engine::app::renderer::post::{PostEffect, PostPass};
+use engine::app::assets::Handle;
+use engine::app::renderer::texture::{Texture, TextureSettings};
 
 use hecs::Entity;
 use winit::{
     event_loop::{ControlFlow, EventLoop},
 };
 
-use crate::engine::app::game::components::{Script, ScriptState, Sprite, TransformComponent};
+use crate::engine::app::game::components::TransformComponent;
 
 struct Game{
-    player: Option<Entity>
+    player: Option<Entity>,
+    /// Follows the player, shown on a sprite in the corner.
+    minimap: Option<Handle<Texture>>,
+    /// The procedural tileset, its grass is animated in `update`.
+    tiles: Option<Handle<Texture>>,
+    elapsed: f32,
+    /// A Tiled map to import on start, from `--map PATH`.
+    map: Option<String>
 }
 
 impl Game{
     fn new() -> Self {
-        Self {player: None}
+        Self {player: None, minimap: None, tiles: None, elapsed: 0.0, map: None}
     }
 }
 
+/// An 8x8 grass tile; `phase` sways the blades.
+fn grass_tile(phase: f32) -> Vec<u8>{
+    let mut pixels = Vec::with_capacity(8 * 8 * 4);
+    for y in 0..8{
+        for x in 0..8{
+            let sway = ((x as f32 + phase).sin() * 1.5) as i32;
+            let blade = (x + y + sway).rem_euclid(2) as u8;
+            pixels.extend([60, 160 - blade * 20, 60, 255]);
+        }
+    }
+    pixels
+}
+
 impl GameHandler for Game{
     fn on_start(&mut self, gm: &mut GameManager) {
-        let texture = gm.texture_manager.load_texture("player", "resources/2.png").unwrap();
+        let texture = gm.assets.load("resources/2.png").unwrap();
         let sprite = components::Sprite::new(texture.clone());
         let player = gm.add_object("Player");
         gm.add_component_to_object(player, sprite);
@@ -37,33 +58,157 @@ impl GameHandler for Game{
         gm.add_component_to_object(player, components::Transform::new(-1.0, 0.0, 0.0));
 
 
-        let texture = gm.texture_manager.load_texture("happy-tree", "resources/happy-tree.png").unwrap();
-        let sprite = components::Sprite::new(texture);
-        let script = components::Script::new("resources/script.lua".to_string());
+        let texture = gm.assets.load_with("resources/happy-tree.png", TextureSettings::smooth()).unwrap();
+        let outline = gm.assets.load("resources/materials/outline.material").unwrap();
+        let sprite = components::Sprite::with_material(texture, outline);
+        // Ready before the first frame, so its `start` runs along with the rest of the scene.
+        let script = components::Script::new(gm.assets.load_blocking("resources/script.lua").unwrap());
         let player = gm.add_object("Player 2");
         gm.add_component_to_object(player, sprite);
         gm.add_component_to_object(player, script);
         gm.add_component_to_object(player, components::Transform::new(1.0, 1.0, 0.0));
+        // Scripts ask it what they touch with `collider.overlapping()` and `collider.touchesSolid()`.
+        gm.add_component_to_object(player, components::Collider::new(1.0, 1.0));
         self.player = Some(player);
-    }
 
-    fn update(&mut self, _gm: &mut GameManager, _dt: f32) {
+        let mut camera = components::Camera2D::new();
+        camera.follow = Some(components::Follow { deadzone: (0.5, 0.5), smoothing: 5.0, ..components::Follow::new(player) });
+        camera.bounds = Some(components::Bounds { min_x: -5.0, min_y: -5.0, max_x: 5.0, max_y: 5.0 });
+        let camera_object = gm.add_object("Camera");
+        gm.add_component_to_object(camera_object, camera);
+        gm.add_component_to_object(camera_object, components::Transform::new(0.0, 0.0, 0.0));
+
+        // Two 8x8 tiles side by side: grass and stone.
+        let mut pixels = Vec::with_capacity(16 * 8 * 4);
+        for y in 0..8{
+            for x in 0..16{
+                let color = if x < 8 { [60, 160 - (x + y) % 2 * 20, 60, 255] } else { [120 + (x ^ y) % 3 * 10, 120, 130, 255] };
+                pixels.extend(color);
+            }
+        }
+        let tiles = gm.create_texture("demo tiles", 16, 8, &pixels, Default::default()).unwrap();
+        self.tiles = Some(tiles.clone());
+        let mut tilemap = components::Tilemap::new(components::Tileset::new(tiles, 8, 8, 2), 24, 3, 0.5);
+        tilemap.fill(0, Some(components::Tile::solid(1)));
+        for x in 0..24{
+            tilemap.set(0, x, 0, Some(components::Tile::new(0)));
+        }
+        let ground = gm.add_object("Ground");
+        gm.add_component_to_object(ground, tilemap);
+        gm.add_component_to_object(ground, components::Transform::new(-6.0, -2.0, 0.0));
+
+        let minimap = gm.create_render_target("minimap", 128, 128, (1.0, 1.0), 6.0).unwrap();
+        let minimap_object = gm.add_object("Minimap");
+        gm.add_component_to_object(minimap_object, components::Sprite::new(minimap.clone()));
+        gm.add_component_to_object(minimap_object, components::Transform::new(4.0, 3.0, 0.0));
+        self.minimap = Some(minimap);
 
+        // A soft round dot for sparks rising off the ground.
+        let mut pixels = Vec::with_capacity(16 * 16 * 4);
+        for y in 0..16{
+            for x in 0..16{
+                let distance = ((x as f32 - 7.5).powi(2) + (y as f32 - 7.5).powi(2)).sqrt() / 8.0;
+                pixels.extend([255, 255, 255, ((1.0 - distance).max(0.0) * 255.0) as u8]);
+            }
+        }
+        let dot = gm.create_texture("demo particle", 16, 16, &pixels, Default::default()).unwrap();
+        let mut sparks = components::ParticleEmitter::new(dot);
+        sparks.blend = components::ParticleBlend::Additive;
+        sparks.rate = 20.0;
+        sparks.bursts.push(components::Burst { time: 0.0, count: 30, interval: Some(3.0) });
+        sparks.lifetime = (0.8, 1.6);
+        sparks.speed = (1.0, 2.5);
+        sparks.gravity = (0.0, -2.0);
+        sparks.color = components::Curve::linear([1.0, 0.8, 0.3, 1.0], [1.0, 0.2, 0.1, 0.0]);
+        sparks.size = components::Curve::linear(0.25, 0.05);
+        let emitter = gm.add_object("Sparks");
+        gm.add_component_to_object(emitter, sparks);
+        gm.add_component_to_object(emitter, components::Transform::new(2.0, -0.5, 0.0));
+        for (name, path) in [("crackle", "resources/sounds/crackle.ogg"), ("jump", "resources/sounds/jump.wav")]{
+            if let Err(e) = gm.load_sound(name, path){
+                log::warn!("{:#}", e);
+            }
+        }
+        if let Err(e) = gm.load_font("resources/fonts/ui.ttf"){
+            log::warn!("{:#}", e);
+        }
+        gm.add_component_to_object(emitter, components::AudioSource::new("crackle", Attenuation::Inverse { min_distance: 1.0, rolloff: 1.5 }));
+        // Played by the script with `audioSource.play()`.
+        gm.add_component_to_object(player, components::AudioSource::one_shot("jump", Attenuation::default()));
+
+        gm.post_passes.push(PostPass::bloom());
+        // Swap the LUT for a graded copy of it to change the look.
+        let mut grading = PostPass::color_grading(gm.assets.load("resources/luts/neutral.png").unwrap());
+        grading.enabled = false;
+        gm.post_passes.push(grading);
+        gm.post_passes.push(PostPass::vignette());
+        let mut crt = PostPass::crt();
+        crt.enabled = false;
+        gm.post_passes.push(crt);
+        let mut desaturate = PostPass::custom("desaturate", "resources/post/desaturate.wgsl");
+        desaturate.enabled = false;
+        if let PostEffect::Custom { params, .. } = &mut desaturate.effect{
+            params[0] = 1.0;
+        }
+        gm.post_passes.push(desaturate);
+
+        if let Some(map) = &self.map
+            && let Err(e) = gm.import_tiled_map(map){
+            log::error!("Could not import {}: {:#}", map, e);
+        }
     }
 
-    fn on_ui(&mut self, gm: &mut GameManager, egui_renderer: &mut EguiRenderer) {
-       
+    fn update(&mut self, gm: &mut GameManager, dt: f32) {
+        self.elapsed += dt;
+        if let Some(tiles) = &self.tiles
+            && let Err(e) = gm.update_texture(tiles, 0, 0, 8, 8, &grass_tile(self.elapsed * 2.0)){
+            log::error!("{:#}", e);
+        }
+
+        let player = self.player.and_then(|player| gm.world.get::<&TransformComponent>(player).ok().map(|transform|{
+            let position = transform.lock().unwrap().position;
+            (position.x, position.y)
+        }));
+        if let (Some(minimap), Some(position)) = (&self.minimap, player)
+            && let Err(e) = gm.set_render_target_view(minimap, position, 6.0){
+            log::error!("{:#}", e);
+        }
     }
 }
 fn main() {
-    let game = Game::new();
+    let mut game = Game::new();
 
-    env_logger::init();
+    engine::app::console::init_logger();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("pack"){
+        if let Err(e) = engine::pack::run(&args[2..]){
+            eprintln!("pack failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    game.map = args.iter().skip_while(|arg| *arg != "--map").nth(1).cloned();
 
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
     let mut app: App<Game> = App::new(game);
+    app.set_fixed_rate(60.0);
+    app.set_max_fixed_steps(5);
+    // `--assets DIR` replaces the asset root, every `--mount PATH` adds an overlay directory or archive
+    // and `--map PATH` imports a Tiled map.
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next(){
+        match (arg.as_str(), args.next()){
+            ("--assets", Some(root)) => app.set_asset_root(root),
+            ("--mount", Some(path)) => if let Err(e) = app.mount(path, 1){
+                eprintln!("Could not mount {}: {}", path, e);
+            },
+            ("--map", Some(_)) => {},
+            _ => eprintln!("usage: eng-rs [--assets DIR] [--mount PATH]... [--map PATH] | pack ...")
+        }
+    }
     
     event_loop.run_app(&mut app).unwrap();
 }
\ No newline at end of file